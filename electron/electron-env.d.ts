@@ -148,7 +148,7 @@ interface Window {
     }) => Promise<{ success: boolean; session?: Record<string, unknown>; message?: string; error?: string }>
     startInputTracking: (payload: import('../src/types/inputTelemetry').StartInputTrackingPayload) => Promise<{ success: boolean; message?: string }>
     stopInputTracking: () => Promise<{ success: boolean; telemetry?: import('../src/types/inputTelemetry').InputTelemetryFileV1; message?: string }>
-    nativeCaptureStart: (payload: import('../src/types/nativeCapture').NativeCaptureStartPayload) => Promise<{ success: boolean; message?: string }>
+    nativeCaptureStart: (payload: import('../src/types/nativeCapture').NativeCaptureStartPayload) => Promise<{ success: boolean; message?: string; encoderUsed?: import('../src/types/nativeCapture').RecordingEncoder; encoderFallbacks?: import('../src/types/nativeCapture').NativeCaptureEncoderFallback[]; rateControl?: import('../src/types/nativeCapture').NativeCaptureRateControl; preflight?: import('../src/types/nativeCapture').NativeCapturePreflight; inputTelemetryUnavailable?: string }>
     nativeCaptureStop: (payload: import('../src/types/nativeCapture').NativeCaptureStopPayload) => Promise<{ success: boolean; result?: import('../src/types/nativeCapture').NativeCaptureSessionResult; message?: string }>
     nativeCaptureStatus: (sessionId?: string) => Promise<{ success: boolean; status: import('../src/types/nativeCapture').NativeCaptureStatus; message?: string; sessionId?: string; startedAtMs?: number }>
     getRecordedVideoPath: () => Promise<{ success: boolean; path?: string; message?: string }>
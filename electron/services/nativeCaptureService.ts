@@ -2,26 +2,79 @@ import { app } from "electron";
 import { spawn, spawnSync, type ChildProcessWithoutNullStreams } from "node:child_process";
 import fs from "node:fs";
 import path from "node:path";
+import type { AutoZoomGeneratedPayload, AutoZoomIntensity } from "@/types/inputTelemetry";
 import type {
+  NativeCaptureAdaptation,
+  NativeCaptureBenchmarkPayload,
+  NativeCaptureBenchmarkProgress,
+  NativeCaptureBenchmarkResult,
+  NativeCaptureDiagnosticsResult,
+  NativeCaptureFfmpegDiscovery,
+  NativeCaptureSidecarStatus,
+  NativeCaptureEncoderFallback,
+  NativeCaptureError,
+  NativeCaptureErrorCode,
+  NativeCaptureFfmpegExit,
+  NativeCaptureFrameTiming,
+  NativeCaptureInputTelemetryStats,
+  NativeCapturePreflight,
+  NativeCaptureRateControl,
+  NativeCaptureRecoverableSession,
+  NativeCaptureRecoveryResult,
   NativeCaptureSessionResult,
   NativeCaptureStartPayload,
   NativeCaptureStatus,
+  NativeCaptureStaticSegment,
   NativeCaptureStatusResult,
   NativeCaptureStopPayload,
+  NativeCaptureWarning,
+  RecordingEncoder,
 } from "@/types/nativeCapture";
 
 type SidecarRequest =
-  | { id: string; cmd: "init"; payload: { platform: NodeJS.Platform } }
+  | { id: string; cmd: "init"; payload: { platform: NodeJS.Platform; ffmpegPath?: string } }
   | { id: string; cmd: "get_encoder_options"; payload: { platform: NodeJS.Platform; ffmpegPath?: string } }
   | { id: string; cmd: "start_capture"; payload: NativeCaptureStartPayload }
-  | { id: string; cmd: "stop_capture"; payload: NativeCaptureStopPayload };
+  | { id: string; cmd: "stop_capture"; payload: NativeCaptureStopPayload }
+  | { id: string; cmd: "generate_auto_zoom"; payload: { telemetryPath: string; intensity: AutoZoomIntensity; durationMs: number } }
+  | { id: string; cmd: "list_recoverable_sessions"; payload: Record<string, never> }
+  | { id: string; cmd: "recover_session"; payload: { sessionId: string; ffmpegPath?: string } }
+  | { id: string; cmd: "benchmark_encoders"; payload: NativeCaptureBenchmarkPayload & { platform: NodeJS.Platform; ffmpegPath?: string } }
+  | { id: string; cmd: "cancel_benchmark"; payload: { benchmarkId?: string } }
+  | { id: string; cmd: "collect_diagnostics"; payload: { ffmpegPath?: string; outputDir?: string; journals?: number } }
+  | { id: string; cmd: "get_status"; payload: Record<string, never> }
+  | { id: string; cmd: "cancel"; payload: { requestId: string } };
+
+// Each encoder may run for three times the planned duration (capped at 30 s) before the
+// sidecar cuts it off, plus the flush.
+const BENCHMARK_MAX_RUN_MS = 30_000;
+const BENCHMARK_ENCODER_OVERHEAD_MS = 15_000;
+
+// Closing stdin makes the sidecar finalize any running capture (bounded at 10 s on its side).
+const SIDECAR_SHUTDOWN_GRACE_MS = 12_000;
 
 type SidecarResponse = {
   id?: string;
   event?: string;
   ok?: boolean;
   payload?: Record<string, unknown>;
-  error?: string;
+  error?: NativeCaptureError;
+};
+
+type CaptureWarningEvent = Omit<NativeCaptureWarning, "firstAtMs" | "lastAtMs"> & {
+  event: "capture_warning";
+  sessionId: string;
+  newCount: number;
+  atMs: number;
+};
+
+type QualityAdaptedEvent = NativeCaptureAdaptation & {
+  event: "quality_adapted";
+  sessionId: string;
+};
+
+type BenchmarkProgressEvent = NativeCaptureBenchmarkProgress & {
+  event: "benchmark_progress";
 };
 
 type Pending = {
@@ -36,11 +89,25 @@ export class NativeCaptureService {
   private pending = new Map<string, Pending>();
   private status: NativeCaptureStatus = "idle";
   private statusMessage = "";
+  private errorCode: NativeCaptureErrorCode | null = null;
   private currentSessionId: string | null = null;
   private startedAtMs: number | null = null;
+  private warnings: NativeCaptureWarning[] = [];
+  private adaptations: NativeCaptureAdaptation[] = [];
+  private benchmarkProgress: ((progress: NativeCaptureBenchmarkProgress) => void) | null = null;
   private sequence = 0;
 
-  async start(payload: NativeCaptureStartPayload): Promise<{ success: boolean; message?: string }> {
+  async start(payload: NativeCaptureStartPayload): Promise<{
+    success: boolean;
+    message?: string;
+    code?: NativeCaptureErrorCode;
+    encoderUsed?: RecordingEncoder;
+    encoderFallbacks?: NativeCaptureEncoderFallback[];
+    rateControl?: NativeCaptureRateControl;
+    preflight?: NativeCapturePreflight;
+    // Why input telemetry was requested but is not being recorded.
+    inputTelemetryUnavailable?: string;
+  }> {
     if (this.status === "recording" || this.status === "starting") {
       return { success: false, message: "Native capture already in progress" };
     }
@@ -52,8 +119,11 @@ export class NativeCaptureService {
 
     this.status = "starting";
     this.statusMessage = "";
+    this.errorCode = null;
     this.currentSessionId = payload.sessionId;
     this.startedAtMs = Date.now();
+    this.warnings = [];
+    this.adaptations = [];
     try {
       const response = await this.sendRequest({
         id: this.nextId("start"),
@@ -62,12 +132,37 @@ export class NativeCaptureService {
       }, 10_000);
       if (!response.ok) {
         this.status = "error";
-        this.statusMessage = response.error || "Failed to start native capture";
-        return { success: false, message: this.statusMessage };
+        this.statusMessage = response.error?.message || "Failed to start native capture";
+        this.errorCode = response.error?.code ?? null;
+        return { success: false, message: this.statusMessage, code: response.error?.code };
       }
       this.status = "recording";
       this.statusMessage = "";
-      return { success: true };
+      this.errorCode = null;
+      const preflight = response.payload?.preflight
+        ? (response.payload.preflight as NativeCapturePreflight)
+        : undefined;
+      if (preflight?.warning) {
+        console.warn("[native-capture][main] lossless preflight", preflight.warning);
+      }
+      const inputTelemetryUnavailable = stringOrUndefined(response.payload?.inputTelemetryUnavailable);
+      if (inputTelemetryUnavailable) {
+        console.warn("[native-capture][main] input telemetry unavailable", inputTelemetryUnavailable);
+      }
+      return {
+        success: true,
+        encoderUsed: typeof response.payload?.encoderUsed === "string"
+          ? (response.payload.encoderUsed as RecordingEncoder)
+          : undefined,
+        encoderFallbacks: Array.isArray(response.payload?.encoderFallbacks)
+          ? (response.payload.encoderFallbacks as NativeCaptureEncoderFallback[])
+          : undefined,
+        rateControl: response.payload?.rateControl
+          ? (response.payload.rateControl as NativeCaptureRateControl)
+          : undefined,
+        preflight,
+        inputTelemetryUnavailable,
+      };
     } catch (error) {
       this.status = "error";
       this.statusMessage = error instanceof Error ? error.message : "Failed to start native capture";
@@ -75,7 +170,7 @@ export class NativeCaptureService {
     }
   }
 
-  async stop(payload: NativeCaptureStopPayload): Promise<{ success: boolean; result?: NativeCaptureSessionResult; message?: string }> {
+  async stop(payload: NativeCaptureStopPayload): Promise<{ success: boolean; result?: NativeCaptureSessionResult; message?: string; code?: NativeCaptureErrorCode }> {
     if (this.status !== "recording" && this.status !== "starting") {
       return { success: false, message: "Native capture is not active" };
     }
@@ -93,8 +188,9 @@ export class NativeCaptureService {
       }, 120_000);
       if (!response.ok) {
         this.status = "error";
-        this.statusMessage = response.error || "Failed to stop native capture";
-        return { success: false, message: this.statusMessage };
+        this.statusMessage = response.error?.message || "Failed to stop native capture";
+        this.errorCode = response.error?.code ?? null;
+        return { success: false, message: this.statusMessage, code: response.error?.code };
       }
       const outputPath = typeof response.payload?.outputPath === "string" ? response.payload.outputPath : "";
       if (!outputPath) {
@@ -102,8 +198,10 @@ export class NativeCaptureService {
         this.statusMessage = "Native capture did not return output path";
         return { success: false, message: this.statusMessage };
       }
-      const stats = fs.existsSync(outputPath) ? fs.statSync(outputPath) : undefined;
+      const aborted = response.payload?.status === "aborted";
+      const stats = !aborted && fs.existsSync(outputPath) ? fs.statSync(outputPath) : undefined;
       const result: NativeCaptureSessionResult = {
+        status: aborted ? "aborted" : "finalized",
         outputPath,
         durationMs: numberOrUndefined(response.payload?.durationMs),
         width: numberOrUndefined(response.payload?.width),
@@ -111,9 +209,43 @@ export class NativeCaptureService {
         fpsActual: numberOrUndefined(response.payload?.fpsActual),
         bytes: numberOrUndefined(response.payload?.bytes) ?? stats?.size,
         sourceBounds: parseSourceBounds(response.payload?.sourceBounds),
+        frameRateMode: response.payload?.frameRateMode === "vfr"
+          ? "vfr"
+          : response.payload?.frameRateMode === "cfr" ? "cfr" : undefined,
+        faststart: typeof response.payload?.faststart === "boolean" ? response.payload.faststart : undefined,
+        framesEncoded: numberOrUndefined(response.payload?.framesEncoded),
+        framesDuplicated: numberOrUndefined(response.payload?.framesDuplicated),
+        frameTiming: response.payload?.frameTiming
+          ? (response.payload.frameTiming as NativeCaptureFrameTiming)
+          : undefined,
+        timestampsPath: stringOrUndefined(response.payload?.timestampsPath),
+        frame0EpochMs: numberOrUndefined(response.payload?.frame0EpochMs),
+        firstFrameLatencyMs: numberOrUndefined(response.payload?.firstFrameLatencyMs),
+        staticSegments: Array.isArray(response.payload?.staticSegments)
+          ? (response.payload.staticSegments as NativeCaptureStaticSegment[])
+          : undefined,
+        inputTelemetryPath: stringOrUndefined(response.payload?.inputTelemetryPath),
+        inputTelemetryStats: response.payload?.inputTelemetryStats
+          ? (response.payload.inputTelemetryStats as NativeCaptureInputTelemetryStats)
+          : undefined,
+        cursorTrackPath: stringOrUndefined(response.payload?.cursorTrackPath),
+        cursorShapes: numberOrUndefined(response.payload?.cursorShapes),
+        ffmpegExit: response.payload?.ffmpegExit
+          ? (response.payload.ffmpegExit as NativeCaptureFfmpegExit)
+          : undefined,
+        warnings: Array.isArray(response.payload?.warnings)
+          ? (response.payload.warnings as NativeCaptureWarning[])
+          : undefined,
+        adaptations: Array.isArray(response.payload?.adaptations)
+          ? (response.payload.adaptations as NativeCaptureAdaptation[])
+          : undefined,
+        deletedFiles: Array.isArray(response.payload?.deletedFiles)
+          ? (response.payload.deletedFiles as string[])
+          : undefined,
       };
       this.status = "idle";
       this.statusMessage = "";
+      this.errorCode = null;
       this.currentSessionId = null;
       this.startedAtMs = null;
       return { success: true, result };
@@ -124,6 +256,228 @@ export class NativeCaptureService {
     }
   }
 
+  async generateAutoZoom(
+    telemetryPath: string,
+    intensity: AutoZoomIntensity,
+    durationMs: number
+  ): Promise<{ success: boolean; result?: AutoZoomGeneratedPayload; message?: string }> {
+    const boot = await this.ensureProcess();
+    if (!boot.success) {
+      return boot;
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("generate-auto-zoom"),
+        cmd: "generate_auto_zoom",
+        payload: {
+          telemetryPath,
+          intensity,
+          durationMs,
+        },
+      }, 30_000);
+      if (!response.ok || !response.payload) {
+        return { success: false, message: response.error?.message || "Failed to generate auto zoom regions" };
+      }
+      return { success: true, result: response.payload as unknown as AutoZoomGeneratedPayload };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to generate auto zoom regions",
+      };
+    }
+  }
+
+  async listRecoverableSessions(): Promise<{ success: boolean; sessions?: NativeCaptureRecoverableSession[]; message?: string }> {
+    const boot = await this.ensureProcess();
+    if (!boot.success) {
+      return boot;
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("list-recoverable"),
+        cmd: "list_recoverable_sessions",
+        payload: {},
+      }, 5_000);
+      if (!response.ok || !response.payload) {
+        return { success: false, message: response.error?.message || "Failed to list recoverable sessions" };
+      }
+      return { success: true, sessions: response.payload.sessions as NativeCaptureRecoverableSession[] };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to list recoverable sessions",
+      };
+    }
+  }
+
+  // Stops a leftover ffmpeg (up to 8 s) and remuxes the output, so this gets a long timeout.
+  async recoverSession(sessionId: string, ffmpegPath?: string): Promise<{ success: boolean; result?: NativeCaptureRecoveryResult; message?: string; code?: NativeCaptureErrorCode }> {
+    const boot = await this.ensureProcess();
+    if (!boot.success) {
+      return boot;
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("recover-session"),
+        cmd: "recover_session",
+        payload: { sessionId, ...(ffmpegPath ? { ffmpegPath } : {}) },
+      }, 120_000);
+      if (!response.ok || !response.payload) {
+        return { success: false, message: response.error?.message || "Failed to recover session", code: response.error?.code };
+      }
+      return { success: true, result: response.payload as unknown as NativeCaptureRecoveryResult };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to recover session",
+      };
+    }
+  }
+
+  // Resolves once every encoder has run or the benchmark was cancelled; `onProgress` gets the
+  // sidecar's progress events meanwhile.
+  async benchmarkEncoders(
+    payload: NativeCaptureBenchmarkPayload,
+    ffmpegPath?: string,
+    onProgress?: (progress: NativeCaptureBenchmarkProgress) => void
+  ): Promise<{ success: boolean; result?: NativeCaptureBenchmarkResult; message?: string; code?: NativeCaptureErrorCode }> {
+    const boot = await this.ensureProcess();
+    if (!boot.success) {
+      return boot;
+    }
+
+    const runMs = Math.min(payload.durationMs ?? 4_000, BENCHMARK_MAX_RUN_MS) * 3 + BENCHMARK_ENCODER_OVERHEAD_MS;
+    const encoderCount = payload.encoders?.length ?? 5;
+    this.benchmarkProgress = onProgress ?? null;
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("benchmark"),
+        cmd: "benchmark_encoders",
+        payload: {
+          ...payload,
+          platform: process.platform,
+          ...(ffmpegPath ? { ffmpegPath } : {}),
+        },
+      }, runMs * encoderCount);
+      if (!response.ok || !response.payload) {
+        return { success: false, message: response.error?.message || "Failed to benchmark encoders", code: response.error?.code };
+      }
+      return { success: true, result: response.payload as unknown as NativeCaptureBenchmarkResult };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to benchmark encoders",
+      };
+    } finally {
+      this.benchmarkProgress = null;
+    }
+  }
+
+  // The running benchmarkEncoders call then resolves with the encoders finished so far.
+  async cancelBenchmark(benchmarkId?: string): Promise<{ success: boolean; message?: string; code?: NativeCaptureErrorCode }> {
+    if (!this.process || this.process.killed) {
+      return { success: false, message: "Native capture process is not running" };
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("cancel-benchmark"),
+        cmd: "cancel_benchmark",
+        payload: benchmarkId ? { benchmarkId } : {},
+      }, 5_000);
+      if (!response.ok) {
+        return { success: false, message: response.error?.message || "Failed to cancel benchmark", code: response.error?.code };
+      }
+      return { success: true };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to cancel benchmark",
+      };
+    }
+  }
+
+  // Live view from the sidecar: sessions with elapsed time, bytes written, backend and
+  // encoder health, plus the requests it is still working on. Answered even while a
+  // start or stop is in progress.
+  async getSidecarStatus(): Promise<{ success: boolean; status?: NativeCaptureSidecarStatus; message?: string }> {
+    if (!this.process || this.process.killed) {
+      return { success: false, message: "Native capture process is not running" };
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("get-status"),
+        cmd: "get_status",
+        payload: {},
+      }, 5_000);
+      if (!response.ok || !response.payload) {
+        return { success: false, message: response.error?.message || "Failed to get sidecar status" };
+      }
+      return { success: true, status: response.payload as unknown as NativeCaptureSidecarStatus };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to get sidecar status",
+      };
+    }
+  }
+
+  // Withdraws an in-flight request by id; it then fails with CANCELLED. stop_capture and
+  // recover_session cannot be cancelled. `pending` means a cancelled start_capture is still
+  // winding down, and a new start is refused with ALREADY_RUNNING until it has.
+  async cancelRequest(requestId: string): Promise<{ success: boolean; pending?: boolean; message?: string; code?: NativeCaptureErrorCode }> {
+    if (!this.process || this.process.killed) {
+      return { success: false, message: "Native capture process is not running" };
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("cancel"),
+        cmd: "cancel",
+        payload: { requestId },
+      }, 5_000);
+      if (!response.ok) {
+        return { success: false, message: response.error?.message || "Failed to cancel request", code: response.error?.code };
+      }
+      return { success: true, pending: response.payload?.pending === true };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to cancel request",
+      };
+    }
+  }
+
+  // Writes a support bundle (ffmpeg, GPU, displays, OS, journals and recent sidecar logs).
+  // Each probe may take up to 15 s on the sidecar side when a tool hangs.
+  async collectDiagnostics(options: { ffmpegPath?: string; outputDir?: string; journals?: number } = {}): Promise<{ success: boolean; result?: NativeCaptureDiagnosticsResult; message?: string; code?: NativeCaptureErrorCode }> {
+    const boot = await this.ensureProcess();
+    if (!boot.success) {
+      return boot;
+    }
+
+    try {
+      const response = await this.sendRequest({
+        id: this.nextId("collect-diagnostics"),
+        cmd: "collect_diagnostics",
+        payload: options,
+      }, 120_000);
+      if (!response.ok || !response.payload) {
+        return { success: false, message: response.error?.message || "Failed to collect diagnostics", code: response.error?.code };
+      }
+      return { success: true, result: response.payload as unknown as NativeCaptureDiagnosticsResult };
+    } catch (error) {
+      return {
+        success: false,
+        message: error instanceof Error ? error.message : "Failed to collect diagnostics",
+      };
+    }
+  }
+
   async getEncoderOptions(ffmpegPath?: string): Promise<{ success: boolean; options: Array<{ encoder: string; label: string; hardware: string }>; message?: string }> {
     const ffmpegFallback = this.getEncoderOptionsFromFfmpeg(ffmpegPath);
 
@@ -151,13 +505,13 @@ export class NativeCaptureService {
           return {
             success: true,
             options: ffmpegFallback.options,
-            message: response.error || ffmpegFallback.message || "Sidecar encoder options unavailable, used FFmpeg probe fallback",
+            message: response.error?.message || ffmpegFallback.message || "Sidecar encoder options unavailable, used FFmpeg probe fallback",
           };
         }
         return {
           success: false,
           options: ffmpegFallback.options,
-          message: response.error || "Failed to fetch encoder options",
+          message: response.error?.message || "Failed to fetch encoder options",
         };
       }
 
@@ -232,8 +586,11 @@ export class NativeCaptureService {
     return {
       status: this.status,
       message: this.statusMessage || undefined,
+      errorCode: this.errorCode ?? undefined,
       sessionId: sessionId || this.currentSessionId || undefined,
       startedAtMs: this.startedAtMs || undefined,
+      warnings: this.warnings.length > 0 ? [...this.warnings] : undefined,
+      adaptations: this.adaptations.length > 0 ? [...this.adaptations] : undefined,
     };
   }
 
@@ -243,13 +600,20 @@ export class NativeCaptureService {
       pending.reject(new Error("Native capture service disposed"));
     }
     this.pending.clear();
-    if (this.process && !this.process.killed) {
-      this.process.kill();
+    const child = this.process;
+    if (child && !child.killed) {
+      child.stdin.end();
+      setTimeout(() => {
+        if (child.exitCode === null && child.signalCode === null) {
+          child.kill();
+        }
+      }, SIDECAR_SHUTDOWN_GRACE_MS).unref();
     }
     this.process = null;
     this.buffer = "";
     this.status = "idle";
     this.statusMessage = "";
+    this.errorCode = null;
     this.currentSessionId = null;
     this.startedAtMs = null;
   }
@@ -282,6 +646,8 @@ export class NativeCaptureService {
       console.info("[native-capture][sidecar][stderr]", text);
     });
     child.on("exit", (code, signal) => {
+      // A disposed sidecar may still be finalizing after a new one has started.
+      if (this.process !== child) return;
       const message = `Native capture sidecar exited (code=${code ?? "null"}, signal=${signal ?? "null"})`;
       for (const [, pending] of this.pending.entries()) {
         clearTimeout(pending.timeout);
@@ -296,16 +662,19 @@ export class NativeCaptureService {
     });
 
     try {
+      // The sidecar probes every candidate ffmpeg binary before it replies.
       const init = await this.sendRequest({
         id: this.nextId("init"),
         cmd: "init",
         payload: { platform: process.platform },
-      }, 5_000);
+      }, 30_000);
       if (!init.ok) {
         this.status = "error";
         this.statusMessage = init.error || "Native capture sidecar init failed";
         return { success: false, message: this.statusMessage };
       }
+      const ffmpeg = init.payload?.ffmpeg as NativeCaptureFfmpegDiscovery | undefined;
+      console.info("[native-capture][main] ffmpeg", { path: ffmpeg?.path, source: ffmpeg?.source, reason: ffmpeg?.reason });
       return { success: true };
     } catch (error) {
       this.status = "error";
@@ -336,13 +705,68 @@ export class NativeCaptureService {
           continue;
         }
       }
+      if (parsed.event === "shutdown") {
+        console.info("[native-capture][main] sidecar shutdown", parsed);
+        continue;
+      }
+      if (parsed.event === "capture_warning") {
+        this.recordWarning(parsed as unknown as CaptureWarningEvent);
+        continue;
+      }
+      if (parsed.event === "quality_adapted") {
+        this.recordAdaptation(parsed as unknown as QualityAdaptedEvent);
+        continue;
+      }
+      if (parsed.event === "benchmark_progress") {
+        this.benchmarkProgress?.(parsed as unknown as BenchmarkProgressEvent);
+        continue;
+      }
       if (parsed.event === "capture_error") {
         this.status = "error";
-        this.statusMessage = parsed.error || "Native capture sidecar reported error";
+        this.statusMessage = parsed.error?.message || "Native capture sidecar reported error";
+        this.errorCode = parsed.error?.code ?? null;
       }
     }
   }
 
+  // Folds a capture_warning event into the per-kind summary of the current session; the
+  // sidecar throttles repeats, so `count` is the running total.
+  private recordWarning(event: CaptureWarningEvent) {
+    console.warn("[native-capture][main] capture warning", event);
+    if (event.sessionId !== this.currentSessionId) return;
+    const existing = this.warnings.find((warning) => warning.kind === event.kind);
+    if (existing) {
+      existing.count = event.count;
+      existing.lastAtMs = event.atMs;
+      existing.detail = event.detail ?? existing.detail;
+      return;
+    }
+    this.warnings.push({
+      kind: event.kind,
+      severity: event.severity,
+      message: event.message,
+      count: event.count,
+      firstAtMs: event.atMs,
+      lastAtMs: event.atMs,
+      detail: event.detail,
+    });
+  }
+
+  private recordAdaptation(event: QualityAdaptedEvent) {
+    console.info("[native-capture][main] quality adapted", event);
+    if (event.sessionId !== this.currentSessionId) return;
+    this.adaptations.push({
+      atMs: event.atMs,
+      change: event.change,
+      from: event.from,
+      to: event.to,
+      encodeFps: event.encodeFps,
+      targetFps: event.targetFps,
+      segment: event.segment,
+      gapMs: event.gapMs,
+    });
+  }
+
   private async sendRequest(request: SidecarRequest, timeoutMs: number): Promise<SidecarResponse> {
     if (!this.process || this.process.killed) {
       throw new Error("Native capture process is not running");
@@ -353,6 +777,10 @@ export class NativeCaptureService {
     const promise = new Promise<SidecarResponse>((resolve, reject) => {
       const timeout = setTimeout(() => {
         this.pending.delete(request.id);
+        // Let the sidecar stop working on it; a request it cannot cancel runs to the end.
+        if (request.cmd !== "cancel") {
+          this.process?.stdin.write(`${JSON.stringify({ id: this.nextId("cancel"), cmd: "cancel", payload: { requestId: request.id } })}\n`);
+        }
         reject(new Error(`Native capture request timed out (${request.cmd})`));
       }, timeoutMs);
       this.pending.set(request.id, { resolve, reject, timeout });
@@ -403,6 +831,10 @@ function numberOrUndefined(value: unknown): number | undefined {
   return typeof value === "number" && Number.isFinite(value) ? value : undefined;
 }
 
+function stringOrUndefined(value: unknown): string | undefined {
+  return typeof value === "string" && value ? value : undefined;
+}
+
 function parseSourceBounds(value: unknown): NativeCaptureSessionResult["sourceBounds"] | undefined {
   if (!value || typeof value !== "object") return undefined;
   const candidate = value as { x?: unknown; y?: unknown; width?: unknown; height?: unknown };
@@ -0,0 +1,849 @@
+use serde::Serialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::{CaptureError, ErrorCode};
+use crate::output::is_mp4_family;
+use crate::protocol::{write_event, AdaptiveQualityConfig};
+use crate::supervisor::{FfmpegExit, FfmpegProcess, Progress, Readiness, StopStep};
+use crate::warnings::{merge_warnings, CaptureWarning};
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+// The encoder is behind when it encodes less than this share of the target frame rate over
+// a whole window, so one slow second does not cost quality.
+const BEHIND_RATIO: f64 = 0.9;
+const BEHIND_WINDOW: Duration = Duration::from_secs(6);
+// A fresh segment first warms up its encoder and works off what queued during the switch.
+const SEGMENT_SETTLE: Duration = Duration::from_secs(4);
+// The outgoing segment only has to flush what it already received.
+const SEGMENT_STOP_GRACE: Duration = Duration::from_secs(8);
+// Joining is a stream copy at disk speed; this keeps it inside the app's stop timeout.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(90);
+// Codec headers in every keyframe let segments with different settings be joined by
+// stream copy.
+const SEGMENT_ARGS: [&str; 2] = ["-bsf:v", "dump_extra=freq=keyframe"];
+pub const FPS_STEPS: [u32; 6] = [60, 48, 30, 24, 15, 10];
+
+// Encoder speed option and its values from slowest to fastest. Unknown encoders are
+// recorded with libx264 by every backend.
+fn preset_ladder(encoder: &str) -> (&'static str, &'static [&'static str]) {
+    match encoder {
+        "h264_nvenc" | "hevc_nvenc" => ("-preset", &["p7", "p6", "p5", "p4", "p3", "p2", "p1"]),
+        "h264_amf" => ("-quality", &["quality", "balanced", "speed"]),
+        _ => (
+            "-preset",
+            &[
+                "medium",
+                "fast",
+                "faster",
+                "veryfast",
+                "superfast",
+                "ultrafast",
+            ],
+        ),
+    }
+}
+
+// Encoder arguments with the speed option set to `preset`, when one is given.
+pub fn with_preset<S: AsRef<str>>(encoder: &str, args: &[S], preset: Option<&str>) -> Vec<String> {
+    let (flag, _) = preset_ladder(encoder);
+    let mut args: Vec<String> = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+    if let Some(preset) = preset {
+        if let Some(at) = args.iter().position(|arg| arg == flag) {
+            if let Some(value) = args.get_mut(at + 1) {
+                *value = preset.to_string();
+            }
+        }
+    }
+    args
+}
+
+// Settings of one segment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EncodeStep {
+    // Value of the encoder's speed option; None when the encoder arguments have none.
+    pub preset: Option<String>,
+    pub fps: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl EncodeStep {
+    pub fn new(fps: u32, width: u32, height: u32) -> Self {
+        Self {
+            preset: None,
+            fps,
+            width,
+            height,
+        }
+    }
+}
+
+// How far a recording may step down from its starting settings.
+pub struct AdaptivePolicy {
+    // From the starting preset to the fastest one allowed.
+    presets: Vec<&'static str>,
+    min_fps: u32,
+    min_height: u32,
+    // MP4 sample entry for a join across resolution steps: with parameter sets in band
+    // (`avc3`/`hev1`) the decoder follows the size change in the keyframes.
+    in_band_tag: &'static str,
+}
+
+impl AdaptivePolicy {
+    // `base` is what the recording starts with; its preset is filled in from `encoder_args`.
+    pub fn new<S: AsRef<str>>(
+        config: &AdaptiveQualityConfig,
+        encoder: &str,
+        encoder_args: &[S],
+        base: &mut EncodeStep,
+    ) -> Self {
+        let (flag, ladder) = preset_ladder(encoder);
+        let current = encoder_args
+            .iter()
+            .position(|arg| arg.as_ref() == flag)
+            .and_then(|at| encoder_args.get(at + 1))
+            .map(|value| value.as_ref().to_string());
+        let start = current
+            .as_deref()
+            .and_then(|current| ladder.iter().position(|preset| *preset == current));
+        let end = config
+            .min_preset
+            .as_deref()
+            .and_then(|min| ladder.iter().position(|preset| *preset == min))
+            .unwrap_or(ladder.len() - 1);
+        let presets = match start {
+            Some(start) if start <= end => ladder[start..=end].to_vec(),
+            _ => Vec::new(),
+        };
+        base.preset = current;
+        Self {
+            presets,
+            in_band_tag: if encoder.starts_with("hevc") { "hev1" } else { "avc3" },
+            min_fps: config.min_fps.unwrap_or(base.fps).clamp(1, base.fps.max(1)),
+            min_height: config
+                .min_height
+                .unwrap_or(base.height)
+                .clamp(2, base.height.max(2)),
+        }
+    }
+
+    // The next step down and what it changes. The cheapest loss goes first: encoder preset,
+    // then frame rate, then resolution.
+    fn next(&self, step: &EncodeStep) -> Option<(&'static str, EncodeStep)> {
+        let index = step
+            .preset
+            .as_deref()
+            .and_then(|current| self.presets.iter().position(|preset| *preset == current));
+        if let Some(preset) = index.and_then(|index| self.presets.get(index + 1)) {
+            let next = EncodeStep {
+                preset: Some(preset.to_string()),
+                ..step.clone()
+            };
+            return Some(("preset", next));
+        }
+        let fps = FPS_STEPS
+            .iter()
+            .copied()
+            .find(|fps| *fps < step.fps)
+            .unwrap_or(step.fps / 2)
+            .max(self.min_fps);
+        if fps < step.fps {
+            return Some((
+                "fps",
+                EncodeStep {
+                    fps,
+                    ..step.clone()
+                },
+            ));
+        }
+        let height = ((step.height * 3 / 4).max(self.min_height) + 1) & !1;
+        if height < step.height {
+            let width =
+                ((u64::from(step.width) * u64::from(height) / u64::from(step.height)) as u32 + 1)
+                    & !1;
+            return Some((
+                "resolution",
+                EncodeStep {
+                    width: width.max(2),
+                    height,
+                    ..step.clone()
+                },
+            ));
+        }
+        None
+    }
+}
+
+// One step down, as reported in the `quality_adapted` event and the stop response.
+#[derive(Debug, Clone, Serialize)]
+pub struct Adaptation {
+    // Since the recording started.
+    #[serde(rename = "atMs")]
+    pub at_ms: u64,
+    pub change: &'static str,
+    pub from: EncodeStep,
+    pub to: EncodeStep,
+    #[serde(rename = "encodeFps")]
+    pub encode_fps: f64,
+    #[serde(rename = "targetFps")]
+    pub target_fps: u32,
+    // Index of the segment recorded with `to`.
+    pub segment: usize,
+    // Time not recorded while a device input restarted; piped frames queue instead.
+    #[serde(rename = "gapMs")]
+    pub gap_ms: u64,
+}
+
+// Builds the ffmpeg command for one segment, up to but excluding the output path.
+pub type SegmentCommand = Box<dyn Fn(&EncodeStep) -> Result<Command, CaptureError> + Send>;
+
+pub struct AdaptiveOptions {
+    pub session_id: String,
+    pub label: &'static str,
+    pub ffmpeg_exe: String,
+    pub readiness: Readiness,
+    pub policy: AdaptivePolicy,
+    pub base: EncodeStep,
+    // Frames are written to stdin through a `FramePipe` rather than read from a device.
+    pub piped: bool,
+    pub command: SegmentCommand,
+}
+
+// Stdin of whichever segment is current, for raw-frame writers. Whole frames are written
+// under the lock, so a switch never splits one between two segments.
+#[derive(Clone)]
+pub struct FramePipe(Arc<Mutex<Option<ChildStdin>>>);
+
+impl FramePipe {
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn write_frame(&self, data: &[u8]) -> io::Result<()> {
+        let mut guard = self
+            .0
+            .lock()
+            .map_err(|_| io::Error::other("frame pipe lock poisoned"))?;
+        match guard.as_mut() {
+            Some(stdin) => stdin.write_all(data),
+            None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+        }
+    }
+
+    // EOF for the current segment, which then finishes on its own.
+    pub fn close(&self) {
+        if let Ok(mut guard) = self.0.lock() {
+            guard.take();
+        }
+    }
+}
+
+struct Segments {
+    current: Option<FfmpegProcess>,
+    step: EncodeStep,
+    paths: Vec<String>,
+    adaptations: Vec<Adaptation>,
+    // Of the segments that already finished.
+    warnings: Vec<CaptureWarning>,
+    // The device input could not be restarted; the recording ended with the last segment.
+    failure: Option<CaptureError>,
+}
+
+// The monitor's view of the recording, refreshed every check for get_status.
+#[derive(Debug, Clone, Default)]
+pub struct AdaptiveStatus {
+    // Of the current segment.
+    pub progress: Option<Progress>,
+    // Of all segments so far.
+    pub warnings: Vec<CaptureWarning>,
+    pub segments: Vec<String>,
+}
+
+// Watches encode speed through ffmpeg's progress counters and, when the encoder keeps
+// falling behind the target frame rate, continues the recording in a new segment with the
+// next cheaper settings. Segments are joined into one file when the recording stops.
+pub struct AdaptiveEncoder {
+    label: &'static str,
+    session_id: String,
+    in_band_tag: &'static str,
+    ffmpeg_exe: String,
+    pid: Arc<AtomicU32>,
+    pipe: Option<FramePipe>,
+    stop_flag: Arc<AtomicBool>,
+    live: Arc<Mutex<AdaptiveStatus>>,
+    monitor: JoinHandle<Segments>,
+}
+
+pub struct AdaptiveExit {
+    // Of the last segment; None when it could not be restarted after a switch.
+    pub exit: Option<FfmpegExit>,
+    pub adaptations: Vec<Adaptation>,
+    // Of all segments.
+    pub warnings: Vec<CaptureWarning>,
+    failure: Option<CaptureError>,
+}
+
+impl AdaptiveExit {
+    // A restart or join failure; the last segment's exit is checked by the caller.
+    pub fn check(&self) -> Result<(), CaptureError> {
+        match &self.failure {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AdaptiveEncoder {
+    // Starts the first segment at `output_path` and the monitor. Startup failures are
+    // returned as they would be without adaptation.
+    pub fn start(options: AdaptiveOptions, output_path: &str) -> Result<Self, CaptureError> {
+        let mut command = (options.command)(&options.base)?;
+        command.args(SEGMENT_ARGS).arg(output_path);
+        let mut process = FfmpegProcess::spawn(command, options.label, &options.session_id)?;
+        let pipe = options
+            .piped
+            .then(|| FramePipe(Arc::new(Mutex::new(process.take_stdin()))));
+        process.wait_ready(options.readiness)?;
+
+        let label = options.label;
+        let session_id = options.session_id.clone();
+        let in_band_tag = options.policy.in_band_tag;
+        let ffmpeg_exe = options.ffmpeg_exe.clone();
+        let pid = Arc::new(AtomicU32::new(process.id()));
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let live = Arc::new(Mutex::new(AdaptiveStatus {
+            segments: vec![output_path.to_string()],
+            ..AdaptiveStatus::default()
+        }));
+        let segments = Segments {
+            current: Some(process),
+            step: options.base.clone(),
+            paths: vec![output_path.to_string()],
+            adaptations: Vec::new(),
+            warnings: Vec::new(),
+            failure: None,
+        };
+        let monitor = {
+            let pid = Arc::clone(&pid);
+            let pipe = pipe.clone();
+            let stop_flag = Arc::clone(&stop_flag);
+            let live = Arc::clone(&live);
+            thread::spawn(move || monitor(options, segments, pipe, pid, stop_flag, live))
+        };
+        Ok(Self {
+            label,
+            session_id,
+            in_band_tag,
+            ffmpeg_exe,
+            pid,
+            pipe,
+            stop_flag,
+            live,
+            monitor,
+        })
+    }
+
+    // Pid of the current segment's ffmpeg.
+    pub fn id(&self) -> u32 {
+        self.pid.load(Ordering::SeqCst)
+    }
+
+    // Follows the ffmpeg working for the recording through segment switches and the join
+    // at stop, for a shutdown that has to kill whichever one is running.
+    pub fn pid_watch(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.pid)
+    }
+
+    // False once the current segment's ffmpeg has exited on its own.
+    pub fn running(&self) -> bool {
+        !self.monitor.is_finished()
+    }
+
+    pub fn status(&self) -> AdaptiveStatus {
+        self.live.lock().map(|live| live.clone()).unwrap_or_default()
+    }
+
+    // Only WGC feeds raw frames; device inputs never have a pipe.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn frame_pipe(&self) -> Option<FramePipe> {
+        self.pipe.clone()
+    }
+
+    // Stops the current segment like `FfmpegProcess::stop` and joins all segments into the
+    // first one's path. Piped recordings close their `FramePipe` first.
+    pub fn stop(self, grace: Duration) -> AdaptiveExit {
+        let (label, ffmpeg_exe, pipe) = (self.label, self.ffmpeg_exe.clone(), self.pipe.clone());
+        let (session_id, pid, in_band_tag) =
+            (self.session_id.clone(), Arc::clone(&self.pid), self.in_band_tag);
+        let mut segments = self.join_monitor();
+        if let Some(pipe) = pipe {
+            pipe.close();
+        }
+        let exit = segments.current.take().map(|process| process.stop(grace));
+        if let Some(exit) = &exit {
+            merge_warnings(&mut segments.warnings, exit.warnings.clone());
+        }
+        let intact =
+            segments.failure.is_none() && exit.as_ref().is_some_and(|exit| exit.check().is_ok());
+        if intact && segments.paths.len() > 1 {
+            let resized = segments
+                .adaptations
+                .iter()
+                .any(|adaptation| adaptation.change == "resolution");
+            let tag = resized.then_some(in_band_tag);
+            let join = join_segments(&ffmpeg_exe, &segments.paths, tag, label, &session_id, &pid);
+            if let Err(err) = join {
+                segments.failure = Some(err);
+            } else {
+                log!(
+                    "[native-capture][{}][adaptive] joined {} segments into {}",
+                    label,
+                    segments.paths.len(),
+                    segments.paths[0]
+                );
+            }
+        }
+        AdaptiveExit {
+            exit,
+            adaptations: segments.adaptations,
+            warnings: segments.warnings,
+            failure: segments.failure,
+        }
+    }
+
+    // Kills the current segment and deletes every segment but the first, which the caller
+    // discards with the rest of the recording. Returns the deleted paths.
+    pub fn abort(self) -> Vec<String> {
+        let mut segments = self.join_monitor();
+        if let Some(process) = segments.current.take() {
+            process.abort();
+        }
+        segments
+            .paths
+            .split_off(1)
+            .into_iter()
+            .filter(|path| fs::remove_file(path).is_ok())
+            .collect()
+    }
+
+    fn join_monitor(self) -> Segments {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        self.monitor.join().unwrap_or_else(|_| {
+            // The monitor owns the processes; a panic there leaves nothing to stop.
+            Segments {
+                current: None,
+                step: EncodeStep::new(0, 0, 0),
+                paths: Vec::new(),
+                adaptations: Vec::new(),
+                warnings: Vec::new(),
+                failure: Some("adaptive quality monitor panicked".into()),
+            }
+        })
+    }
+}
+
+fn monitor(
+    options: AdaptiveOptions,
+    mut segments: Segments,
+    pipe: Option<FramePipe>,
+    pid: Arc<AtomicU32>,
+    stop_flag: Arc<AtomicBool>,
+    live: Arc<Mutex<AdaptiveStatus>>,
+) -> Segments {
+    let started = Instant::now();
+    let mut segment_started = started;
+    // (time, frames encoded) over the last window; the front is the window's start.
+    let mut samples: VecDeque<(Instant, u64)> = VecDeque::new();
+    let mut exhausted = false;
+
+    while !stop_flag.load(Ordering::SeqCst) {
+        thread::sleep(CHECK_INTERVAL);
+        let Some(process) = segments.current.as_mut() else {
+            break;
+        };
+        // An encoder that died is reported when the recording stops, not adapted around.
+        if !process.running() {
+            break;
+        }
+        if let Ok(mut live) = live.lock() {
+            live.progress = process.progress();
+            live.warnings = segments.warnings.clone();
+            merge_warnings(&mut live.warnings, process.warnings());
+            live.segments = segments.paths.clone();
+        }
+        let now = Instant::now();
+        if exhausted || now.duration_since(segment_started) < SEGMENT_SETTLE {
+            continue;
+        }
+        let frame = process.progress().map_or(0, |progress| progress.frame);
+        samples.push_back((now, frame));
+        while samples.len() > 1 && now.duration_since(samples[1].0) >= BEHIND_WINDOW {
+            samples.pop_front();
+        }
+        let (window_start, window_frames) = samples[0];
+        let span = now.duration_since(window_start);
+        if span < BEHIND_WINDOW {
+            continue;
+        }
+        let encode_fps = frame.saturating_sub(window_frames) as f64 / span.as_secs_f64();
+        let target_fps = segments.step.fps;
+        if encode_fps >= f64::from(target_fps) * BEHIND_RATIO {
+            continue;
+        }
+        let Some((change, next)) = options.policy.next(&segments.step) else {
+            log!(
+                "[native-capture][{}][adaptive] encoder behind at {:.1}/{} fps with no step left",
+                options.label, encode_fps, target_fps
+            );
+            exhausted = true;
+            continue;
+        };
+        let at_ms = now.duration_since(started).as_millis() as u64;
+        match switch_segment(&options, &mut segments, pipe.as_ref(), next.clone()) {
+            Ok(gap_ms) => {
+                let adaptation = Adaptation {
+                    at_ms,
+                    change,
+                    from: std::mem::replace(&mut segments.step, next),
+                    to: segments.step.clone(),
+                    encode_fps: (encode_fps * 10.0).round() / 10.0,
+                    target_fps,
+                    segment: segments.paths.len() - 1,
+                    gap_ms,
+                };
+                let mut event = json!(adaptation);
+                log!(
+                    "[native-capture][{}][adaptive] {} from={} to={} encode_fps={:.1} target_fps={} gap_ms={}",
+                    options.label, change, event["from"], event["to"], encode_fps, target_fps, gap_ms
+                );
+                event["event"] = json!("quality_adapted");
+                event["sessionId"] = json!(options.session_id);
+                write_event(event);
+                segments.adaptations.push(adaptation);
+            }
+            Err(err) => {
+                log!(
+                    "[native-capture][{}][adaptive] switch failed: {}",
+                    options.label, err
+                );
+                if segments.current.is_none() {
+                    segments.failure = Some(err);
+                    break;
+                }
+                // The old settings keep recording; retrying would only fail again.
+                exhausted = true;
+            }
+        }
+        if let Some(process) = segments.current.as_ref() {
+            pid.store(process.id(), Ordering::SeqCst);
+        }
+        segment_started = Instant::now();
+        samples.clear();
+    }
+    segments
+}
+
+// Moves the recording to a new segment with `next`. Piped frames go to the new ffmpeg once
+// it is up, so nothing is lost; a device input has to be released before it can be opened
+// again, which leaves a gap (returned in ms). When the new segment fails, a piped recording
+// keeps the old one and a device input is reopened with the old settings.
+fn switch_segment(
+    options: &AdaptiveOptions,
+    segments: &mut Segments,
+    pipe: Option<&FramePipe>,
+    next: EncodeStep,
+) -> Result<u64, CaptureError> {
+    let path = segment_path(&segments.paths[0], segments.paths.len());
+    let switch_started = Instant::now();
+
+    if let Some(pipe) = pipe {
+        let mut process = spawn_segment(options, &next, &path)?;
+        let stdin = process.take_stdin();
+        if let Err(err) = process.wait_ready(options.readiness) {
+            let _ = fs::remove_file(&path);
+            return Err(err);
+        }
+        let previous = match pipe.0.lock() {
+            Ok(mut guard) => std::mem::replace(&mut *guard, stdin),
+            Err(_) => {
+                process.abort();
+                let _ = fs::remove_file(&path);
+                return Err("frame pipe lock poisoned".into());
+            }
+        };
+        // EOF lets the old segment finish what it already received.
+        drop(previous);
+        segments.paths.push(path);
+        if let Some(old) = segments.current.replace(process) {
+            finish_segment(options.label, segments, old);
+        }
+        return Ok(0);
+    }
+
+    if let Some(old) = segments.current.take() {
+        finish_segment(options.label, segments, old);
+    }
+    match spawn_segment(options, &next, &path) {
+        Ok(process) => {
+            segments.paths.push(path);
+            segments.current = Some(process);
+            Ok(switch_started.elapsed().as_millis() as u64)
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&path);
+            let retry = segment_path(&segments.paths[0], segments.paths.len());
+            if let Ok(process) = spawn_segment(options, &segments.step, &retry) {
+                segments.paths.push(retry);
+                segments.current = Some(process);
+            }
+            Err(err)
+        }
+    }
+}
+
+fn spawn_segment(
+    options: &AdaptiveOptions,
+    step: &EncodeStep,
+    path: &str,
+) -> Result<FfmpegProcess, CaptureError> {
+    let mut command = (options.command)(step)?;
+    command.args(SEGMENT_ARGS).arg(path);
+    let mut process = FfmpegProcess::spawn(command, options.label, &options.session_id)?;
+    if !options.piped {
+        process.wait_ready(options.readiness)?;
+    }
+    Ok(process)
+}
+
+// A segment that fails to finish is kept on record but does not end the recording; its
+// warnings say what went wrong.
+fn finish_segment(label: &str, segments: &mut Segments, process: FfmpegProcess) {
+    let exit = process.stop(SEGMENT_STOP_GRACE);
+    if let Err(err) = exit.check() {
+        log!(
+            "[native-capture][{}][adaptive] segment did not finish cleanly: {}",
+            label, err
+        );
+    }
+    merge_warnings(&mut segments.warnings, exit.warnings);
+}
+
+// `<stem>.seg<index>.<ext>` next to the first segment.
+fn segment_path(first: &str, index: usize) -> String {
+    sibling_path(first, &format!("seg{index}"))
+}
+
+fn sibling_path(first: &str, tag: &str) -> String {
+    let path = Path::new(first);
+    let stem = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.{tag}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{tag}"),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+// Stream-copies all segments into the first one's path with the concat demuxer and deletes
+// the others. A join that overruns JOIN_TIMEOUT is stopped and the segments are kept.
+// `tag` is the in-band sample entry an MP4 needs when the segments differ in size.
+fn join_segments(
+    ffmpeg_exe: &str,
+    segments: &[String],
+    tag: Option<&str>,
+    label: &'static str,
+    session_id: &str,
+    pid: &AtomicU32,
+) -> Result<(), CaptureError> {
+    let first = &segments[0];
+    let list_path = format!("{first}.segments.txt");
+    let joined = sibling_path(first, "joined");
+    let list: String = segments
+        .iter()
+        .map(|segment| format!("file '{}'\n", segment.replace('\'', "'\\''")))
+        .collect();
+    fs::write(&list_path, list)
+        .map_err(|err| CaptureError::io("failed to write the segment list", &err))?;
+
+    let mut command = Command::new(ffmpeg_exe);
+    command
+        .args(["-hide_banner", "-nostdin", "-loglevel", "error", "-y"])
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list_path)
+        .args(["-map", "0", "-c", "copy"]);
+    if is_mp4_family(first) {
+        // The join runs at stop, so unlike the fragmented segments it can be a faststart file.
+        command.args(["-movflags", "+faststart"]);
+        if let Some(tag) = tag {
+            command.args(["-tag:v", tag]);
+        }
+    }
+    command
+        .arg(&joined)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let exit = FfmpegProcess::spawn(command, label, session_id).map(|process| {
+        pid.store(process.id(), Ordering::SeqCst);
+        process.stop(JOIN_TIMEOUT)
+    });
+    let _ = fs::remove_file(&list_path);
+
+    let failed = |message: String| {
+        let _ = fs::remove_file(&joined);
+        CaptureError::new(ErrorCode::FfmpegFailed, message)
+            .with_details(json!({ "segments": segments }))
+    };
+    let exit = match exit {
+        Ok(exit) => exit,
+        Err(err) => {
+            return Err(failed(format!(
+                "failed to run ffmpeg to join segments: {}",
+                err.message
+            )))
+        }
+    };
+    // Anything but exiting on its own means the join was cut short.
+    let checked = if exit.step == StopStep::Exited {
+        exit.check()
+    } else {
+        Err(exit.error(&format!(
+            "joining did not finish within {} s",
+            JOIN_TIMEOUT.as_secs()
+        )))
+    };
+    match checked {
+        Ok(()) => {
+            fs::rename(&joined, first)
+                .map_err(|err| CaptureError::io("failed to replace the first segment", &err))?;
+            for segment in &segments[1..] {
+                let _ = fs::remove_file(segment);
+            }
+            Ok(())
+        }
+        Err(err) => Err(failed(format!(
+            "failed to join {} adaptive quality segments: {}",
+            segments.len(),
+            err.message
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(min_fps: Option<u32>, min_height: Option<u32>) -> AdaptiveQualityConfig {
+        AdaptiveQualityConfig {
+            min_preset: None,
+            min_fps,
+            min_height,
+        }
+    }
+
+    fn ladder(policy: &AdaptivePolicy, base: &EncodeStep) -> Vec<(&'static str, EncodeStep)> {
+        let mut steps = Vec::new();
+        let mut step = base.clone();
+        while let Some((change, next)) = policy.next(&step) {
+            steps.push((change, next.clone()));
+            step = next;
+        }
+        steps
+    }
+
+    #[test]
+    fn steps_down_preset_then_fps_then_resolution() {
+        let mut base = EncodeStep::new(30, 1920, 1080);
+        let policy =
+            AdaptivePolicy::new(&config(Some(15), Some(540)), "libx264", &["-preset", "veryfast"], &mut base);
+        assert_eq!(base.preset.as_deref(), Some("veryfast"));
+        let steps: Vec<(&str, Option<String>, u32, u32, u32)> = ladder(&policy, &base)
+            .into_iter()
+            .map(|(change, step)| (change, step.preset, step.fps, step.width, step.height))
+            .collect();
+        let fast = |preset: &str| Some(preset.to_string());
+        assert_eq!(
+            steps,
+            vec![
+                ("preset", fast("superfast"), 30, 1920, 1080),
+                ("preset", fast("ultrafast"), 30, 1920, 1080),
+                ("fps", fast("ultrafast"), 24, 1920, 1080),
+                ("fps", fast("ultrafast"), 15, 1920, 1080),
+                ("resolution", fast("ultrafast"), 15, 1440, 810),
+                ("resolution", fast("ultrafast"), 15, 1080, 608),
+                ("resolution", fast("ultrafast"), 15, 960, 540),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_fps_and_resolution_without_bounds() {
+        let mut base = EncodeStep::new(60, 2560, 1440);
+        let policy = AdaptivePolicy::new(&config(None, None), "h264_nvenc", &["-preset", "p5"], &mut base);
+        let changes: Vec<&str> = ladder(&policy, &base).iter().map(|(change, _)| *change).collect();
+        assert_eq!(changes, ["preset"; 4]);
+    }
+
+    #[test]
+    fn stops_at_the_min_preset_and_skips_unknown_presets() {
+        let mut base = EncodeStep::new(30, 1280, 720);
+        let limited = AdaptiveQualityConfig {
+            min_preset: Some("faster".to_string()),
+            ..config(None, None)
+        };
+        let policy = AdaptivePolicy::new(&limited, "libx264", &["-preset", "medium"], &mut base);
+        assert_eq!(policy.presets, ["medium", "fast", "faster"]);
+
+        let mut base = EncodeStep::new(30, 1280, 720);
+        let policy = AdaptivePolicy::new(&config(None, None), "libx264", &["-preset", "slow"], &mut base);
+        assert!(policy.presets.is_empty());
+        assert_eq!(base.preset.as_deref(), Some("slow"));
+        assert!(policy.next(&base).is_none());
+
+        let mut base = EncodeStep::new(30, 1280, 720);
+        let policy = AdaptivePolicy::new(&config(None, None), "libx264", &["-crf", "23"], &mut base);
+        assert!(policy.presets.is_empty());
+        assert_eq!(base.preset, None);
+    }
+
+    #[test]
+    fn picks_the_in_band_tag_by_codec() {
+        let mut base = EncodeStep::new(30, 1280, 720);
+        let hevc = AdaptivePolicy::new(&config(None, None), "hevc_nvenc", &["-preset", "p4"], &mut base);
+        assert_eq!(hevc.in_band_tag, "hev1");
+        let h264 = AdaptivePolicy::new(&config(None, None), "h264_amf", &["-quality", "speed"], &mut base);
+        assert_eq!(h264.in_band_tag, "avc3");
+    }
+
+    #[test]
+    fn replaces_only_the_encoders_speed_option() {
+        assert_eq!(
+            with_preset("libx264", &["-preset", "veryfast", "-crf", "23"], Some("ultrafast")),
+            ["-preset", "ultrafast", "-crf", "23"]
+        );
+        assert_eq!(
+            with_preset("h264_amf", &["-quality", "balanced"], Some("speed")),
+            ["-quality", "speed"]
+        );
+        assert_eq!(with_preset("libx264", &["-preset", "fast"], None), ["-preset", "fast"]);
+        assert_eq!(with_preset("libx264", &["-crf", "23"], Some("ultrafast")), ["-crf", "23"]);
+        assert_eq!(with_preset("libx264", &["-preset"], Some("ultrafast")), ["-preset"]);
+    }
+
+    #[test]
+    fn names_segments_next_to_the_first() {
+        let dir = std::env::temp_dir();
+        let first = dir.join("rec.mp4").to_string_lossy().to_string();
+        assert_eq!(segment_path(&first, 2), dir.join("rec.seg2.mp4").to_string_lossy());
+        let bare = dir.join("rec").to_string_lossy().to_string();
+        assert_eq!(segment_path(&bare, 1), dir.join("rec.seg1").to_string_lossy());
+        assert_eq!(sibling_path(&first, "concat"), dir.join("rec.concat.mp4").to_string_lossy());
+    }
+}
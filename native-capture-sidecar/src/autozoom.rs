@@ -0,0 +1,418 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{CaptureError, ErrorCode};
+use crate::protocol::{GenerateAutoZoomPayload, Response};
+
+// Port of src/lib/autoZoom/generateAutoZoomRegions.ts; keep the two in step and bump
+// the version in both places when the output changes.
+const AUTO_ZOOM_ALGORITHM_VERSION: &str = "v1-balanced-plus";
+
+const CLICK_PRE_ROLL_MS: f64 = 420.0;
+const CLICK_TAIL_MS: f64 = 760.0;
+const CLICK_DEPTH: i32 = 2;
+
+const TYPE_BURST_GAP_MS: f64 = 350.0;
+const TYPE_PRE_ROLL_MS: f64 = 280.0;
+const TYPE_TAIL_MS: f64 = 920.0;
+
+const SCROLL_BURST_GAP_MS: f64 = 250.0;
+const SCROLL_PRE_ROLL_MS: f64 = 120.0;
+const SCROLL_TAIL_MS: f64 = 760.0;
+const SCROLL_DEPTH: i32 = 2;
+
+const DRAG_MIN_MS: f64 = 700.0;
+const DRAG_DEPTH: i32 = 1;
+const DRAG_PRE_ROLL_MS: f64 = 280.0;
+const DRAG_TAIL_MS: f64 = 700.0;
+
+const MERGE_GAP_MS: f64 = 180.0;
+const MERGE_FOCUS_DISTANCE: f64 = 0.08;
+const MIN_DURATION_MS: f64 = 220.0;
+
+// Only the parts of InputTelemetryFileV1 the algorithm reads.
+#[derive(Debug, Deserialize)]
+struct TelemetryFile {
+    #[serde(rename = "startedAtMs")]
+    started_at_ms: f64,
+    #[serde(rename = "sourceBounds")]
+    source_bounds: Option<Bounds>,
+    #[serde(default)]
+    events: Vec<TelemetryEvent>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct Bounds {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TelemetryEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    ts: f64,
+    x: Option<f64>,
+    y: Option<f64>,
+    category: Option<String>,
+}
+
+impl TelemetryEvent {
+    fn position(&self) -> Option<(f64, f64)> {
+        Some((self.x?, self.y?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Focus {
+    cx: f64,
+    cy: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ZoomRegion {
+    start_ms: f64,
+    end_ms: f64,
+    depth: i32,
+    focus: Focus,
+}
+
+pub fn handle_generate_auto_zoom(id: String, payload: serde_json::Value) -> Response {
+    let payload: GenerateAutoZoomPayload = match serde_json::from_value(payload) {
+        Ok(v) => v,
+        Err(err) => {
+            return Response::err(id, CaptureError::invalid_payload("generate_auto_zoom", err));
+        }
+    };
+    let intensity = payload.intensity.as_deref().unwrap_or("balanced");
+    if !matches!(intensity, "subtle" | "balanced" | "intense") {
+        return Response::err(
+            id,
+            CaptureError::new(
+                ErrorCode::InvalidPayload,
+                format!("unsupported intensity: {intensity} (expected subtle, balanced or intense)"),
+            ),
+        );
+    }
+
+    let telemetry = match std::fs::read(&payload.telemetry_path)
+        .map_err(|e| format!("failed to read {}: {e}", payload.telemetry_path))
+        .and_then(|bytes| {
+            serde_json::from_slice::<TelemetryFile>(&bytes)
+                .map_err(|e| format!("invalid telemetry file {}: {e}", payload.telemetry_path))
+        }) {
+        Ok(v) => v,
+        Err(err) => {
+            return Response::err(
+                id,
+                CaptureError::new(ErrorCode::InvalidPayload, err)
+                    .with_details(json!({ "telemetryPath": payload.telemetry_path })),
+            );
+        }
+    };
+
+    let duration_ms = payload.duration_ms;
+    let started = std::time::Instant::now();
+    let regions = generate_auto_zoom_regions(&telemetry, duration_ms, intensity);
+    log!(
+        "[native-capture][autozoom] events={} regions={} preset={} elapsed_ms={:.1}",
+        telemetry.events.len(),
+        regions.len(),
+        intensity,
+        started.elapsed().as_secs_f64() * 1000.0
+    );
+
+    let generated_at_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    Response::ok(
+        id,
+        json!({
+            "algorithmVersion": AUTO_ZOOM_ALGORITHM_VERSION,
+            "preset": intensity,
+            "generatedAtMs": generated_at_ms,
+            "regions": regions.iter().map(|region| json!({
+                "startMs": region.start_ms,
+                "endMs": region.end_ms,
+                "depth": region.depth,
+                "focus": { "cx": region.focus.cx, "cy": region.focus.cy },
+            })).collect::<Vec<_>>(),
+        }),
+    )
+}
+
+fn generate_auto_zoom_regions(
+    telemetry: &TelemetryFile,
+    duration_ms: f64,
+    intensity: &str,
+) -> Vec<ZoomRegion> {
+    if duration_ms <= 0.0 || telemetry.events.is_empty() {
+        return Vec::new();
+    }
+
+    let (depth_offset, tail_scale) = match intensity {
+        "subtle" => (-1, 1.2),
+        "intense" => (0, 1.5),
+        _ => (0, 1.35),
+    };
+    let with_depth_offset = |depth: i32| (depth + depth_offset).clamp(1, 6);
+    // Math.round: halves round up.
+    let scale_tail = |ms: f64| (ms * tail_scale + 0.5).floor();
+    let origin = telemetry.started_at_ms;
+    let bounds = telemetry.source_bounds;
+
+    // Stable, like Array.prototype.sort.
+    let mut events = telemetry.events.clone();
+    events.sort_by(|a, b| a.ts.total_cmp(&b.ts));
+    let pointer_events: Vec<&TelemetryEvent> =
+        events.iter().filter(|e| e.position().is_some()).collect();
+
+    let fallback = Focus { cx: 0.5, cy: 0.5 };
+    // Events are sorted, so the lookups below are binary searches rather than the
+    // linear scans of the TS version, which go quadratic on long recordings.
+    let last_pointer_before = |ts: f64| {
+        match pointer_events.partition_point(|e| e.ts <= ts) {
+            0 => fallback,
+            n => to_focus(pointer_events[n - 1], bounds, fallback),
+        }
+    };
+
+    let mut candidates = Vec::new();
+
+    for event in events.iter().filter(|e| e.kind == "mouseDown") {
+        candidates.push(ZoomRegion {
+            start_ms: event.ts - origin - CLICK_PRE_ROLL_MS,
+            end_ms: event.ts - origin + scale_tail(CLICK_TAIL_MS),
+            depth: with_depth_offset(CLICK_DEPTH),
+            focus: to_focus(event, bounds, fallback),
+        });
+    }
+
+    let key_events: Vec<&TelemetryEvent> = events
+        .iter()
+        .filter(|e| e.kind == "keyDownCategory" && is_typing_category(e.category.as_deref()))
+        .collect();
+    if let Some(first) = key_events.first() {
+        let typing_region = |start: f64, end: f64, count: usize| ZoomRegion {
+            start_ms: start - origin - TYPE_PRE_ROLL_MS,
+            end_ms: end - origin + scale_tail(TYPE_TAIL_MS),
+            depth: with_depth_offset(if count >= 14 { 2 } else { 1 }),
+            focus: last_pointer_before(start),
+        };
+        let (mut burst_start, mut burst_end, mut burst_count) = (first.ts, first.ts, 1);
+        for current in &key_events[1..] {
+            if current.ts - burst_end <= TYPE_BURST_GAP_MS {
+                burst_end = current.ts;
+                burst_count += 1;
+                continue;
+            }
+            candidates.push(typing_region(burst_start, burst_end, burst_count));
+            (burst_start, burst_end, burst_count) = (current.ts, current.ts, 1);
+        }
+        candidates.push(typing_region(burst_start, burst_end, burst_count));
+    }
+
+    let wheel_events: Vec<&TelemetryEvent> = events.iter().filter(|e| e.kind == "wheel").collect();
+    if let Some(first) = wheel_events.first() {
+        let scroll_region = |start: f64, end: f64, focus: Focus| ZoomRegion {
+            start_ms: start - origin - SCROLL_PRE_ROLL_MS,
+            end_ms: end - origin + scale_tail(SCROLL_TAIL_MS),
+            depth: with_depth_offset(SCROLL_DEPTH),
+            focus,
+        };
+        let (mut burst_start, mut burst_end) = (first.ts, first.ts);
+        let mut focus = to_focus(first, bounds, fallback);
+        for current in &wheel_events[1..] {
+            if current.ts - burst_end <= SCROLL_BURST_GAP_MS {
+                burst_end = current.ts;
+                focus = to_focus(current, bounds, focus);
+                continue;
+            }
+            candidates.push(scroll_region(burst_start, burst_end, focus));
+            (burst_start, burst_end) = (current.ts, current.ts);
+            focus = to_focus(current, bounds, fallback);
+        }
+        candidates.push(scroll_region(burst_start, burst_end, focus));
+    }
+
+    let of_kind = |kind: &str| -> Vec<&TelemetryEvent> {
+        events.iter().filter(|e| e.kind == kind).collect()
+    };
+    let mouse_ups = of_kind("mouseUp");
+    let moves = of_kind("mouseMoveSampled");
+    for down in of_kind("mouseDown") {
+        let Some(up) = mouse_ups.get(mouse_ups.partition_point(|e| e.ts <= down.ts)) else {
+            continue;
+        };
+        if up.ts - down.ts < DRAG_MIN_MS {
+            continue;
+        }
+        let first = moves.partition_point(|e| e.ts < down.ts);
+        let last = moves.partition_point(|e| e.ts <= up.ts);
+        let drag_moves = &moves[first..last.max(first)];
+        if drag_moves.len() < 2 {
+            continue;
+        }
+        let middle_ts = down.ts + (up.ts - down.ts) / 2.0;
+        // First move wins ties, as in the reduce() of the TS version.
+        let mut nearest = drag_moves[0];
+        for current in &drag_moves[1..] {
+            if (current.ts - middle_ts).abs() < (nearest.ts - middle_ts).abs() {
+                nearest = current;
+            }
+        }
+        candidates.push(ZoomRegion {
+            start_ms: down.ts - origin - DRAG_PRE_ROLL_MS,
+            end_ms: up.ts - origin + scale_tail(DRAG_TAIL_MS),
+            depth: with_depth_offset(DRAG_DEPTH),
+            focus: to_focus(nearest, bounds, fallback),
+        });
+    }
+
+    clamp_and_filter(candidates, duration_ms)
+}
+
+fn is_typing_category(category: Option<&str>) -> bool {
+    matches!(category, Some("printable" | "backspace" | "enter" | "tab"))
+}
+
+fn to_focus(event: &TelemetryEvent, bounds: Option<Bounds>, fallback: Focus) -> Focus {
+    let (Some(bounds), Some((x, y))) = (bounds, event.position()) else {
+        return fallback;
+    };
+    if bounds.width <= 0.0 || bounds.height <= 0.0 {
+        return fallback;
+    }
+    Focus {
+        cx: ((x - bounds.x) / bounds.width).clamp(0.0, 1.0),
+        cy: ((y - bounds.y) / bounds.height).clamp(0.0, 1.0),
+    }
+}
+
+fn distance(a: Focus, b: Focus) -> f64 {
+    let (dx, dy) = (a.cx - b.cx, a.cy - b.cy);
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn clamp_and_filter(regions: Vec<ZoomRegion>, duration_ms: f64) -> Vec<ZoomRegion> {
+    let mut sorted: Vec<ZoomRegion> = regions
+        .into_iter()
+        .map(|region| ZoomRegion {
+            start_ms: region.start_ms.max(0.0).min(duration_ms),
+            end_ms: region.end_ms.max(0.0).min(duration_ms),
+            ..region
+        })
+        .filter(|region| region.end_ms - region.start_ms >= MIN_DURATION_MS)
+        .collect();
+    sorted.sort_by(|a, b| a.start_ms.total_cmp(&b.start_ms));
+
+    let mut merged: Vec<ZoomRegion> = Vec::with_capacity(sorted.len());
+    for current in sorted {
+        if let Some(previous) = merged.last_mut() {
+            if current.start_ms - previous.end_ms <= MERGE_GAP_MS
+                && distance(current.focus, previous.focus) <= MERGE_FOCUS_DISTANCE
+            {
+                previous.end_ms = previous.end_ms.max(current.end_ms);
+                previous.focus = Focus {
+                    cx: (previous.focus.cx + current.focus.cx) / 2.0,
+                    cy: (previous.focus.cy + current.focus.cy) / 2.0,
+                };
+                previous.depth = previous.depth.max(current.depth);
+                continue;
+            }
+        }
+        merged.push(current);
+    }
+
+    let mut non_overlapping: Vec<ZoomRegion> = Vec::with_capacity(merged.len());
+    for region in merged {
+        let start_ms = match non_overlapping.last() {
+            Some(previous) => region.start_ms.max(previous.end_ms),
+            None => region.start_ms,
+        };
+        if non_overlapping.is_empty() || region.end_ms - start_ms >= MIN_DURATION_MS {
+            non_overlapping.push(ZoomRegion { start_ms, ..region });
+        }
+    }
+    non_overlapping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    // Shared with src/lib/autoZoom/generateAutoZoomRegions.test.ts.
+    const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../src/lib/autoZoom/fixtures");
+
+    fn expected_region(value: &Value) -> ZoomRegion {
+        let number = |value: &Value| value.as_f64().expect("number");
+        ZoomRegion {
+            start_ms: number(&value["startMs"]),
+            end_ms: number(&value["endMs"]),
+            depth: value["depth"].as_i64().expect("depth") as i32,
+            focus: Focus {
+                cx: number(&value["focus"]["cx"]),
+                cy: number(&value["focus"]["cy"]),
+            },
+        }
+    }
+
+    #[test]
+    fn matches_the_shared_fixtures() {
+        let mut paths: Vec<_> = std::fs::read_dir(FIXTURES_DIR)
+            .expect("fixtures dir")
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let fixture: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            let telemetry: TelemetryFile =
+                serde_json::from_value(fixture["telemetry"].clone()).unwrap();
+            let regions = generate_auto_zoom_regions(
+                &telemetry,
+                fixture["durationMs"].as_f64().unwrap(),
+                fixture["intensity"].as_str().unwrap(),
+            );
+            let expected: Vec<ZoomRegion> =
+                fixture["expected"].as_array().unwrap().iter().map(expected_region).collect();
+            assert_eq!(regions.len(), expected.len(), "{name}: {regions:?}");
+            for (actual, expected) in regions.iter().zip(&expected) {
+                // serde_json's default float parsing can be an ulp off the JS output.
+                let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+                assert!(
+                    close(actual.start_ms, expected.start_ms)
+                        && close(actual.end_ms, expected.end_ms)
+                        && actual.depth == expected.depth
+                        && close(actual.focus.cx, expected.focus.cx)
+                        && close(actual.focus.cy, expected.focus.cy),
+                    "{name}: {actual:?} != {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn duration_is_required() {
+        let path = std::env::temp_dir().join(format!("autozoom-test-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"startedAtMs":0,"events":[]}"#).unwrap();
+        let telemetry_path = path.to_string_lossy().into_owned();
+        let missing =
+            handle_generate_auto_zoom("1".into(), json!({ "telemetryPath": telemetry_path }));
+        let given = handle_generate_auto_zoom(
+            "2".into(),
+            json!({ "telemetryPath": telemetry_path, "durationMs": 1000 }),
+        );
+        let _ = std::fs::remove_file(&path);
+        assert!(!missing.ok);
+        assert!(matches!(missing.error.map(|err| err.code), Some(ErrorCode::InvalidPayload)));
+        assert!(given.ok);
+    }
+}
@@ -0,0 +1,1000 @@
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::adaptive::FPS_STEPS;
+use crate::embedded::{is_embedded_encoder, Av1Encoder, EMBEDDED_AV1_ENCODER};
+use crate::error::{CaptureError, ErrorCode};
+use crate::ffmpeg::{
+    encoder_codec_args, encoder_options, rate_control, rate_control_args, resolve_ffmpeg_path,
+};
+use crate::protocol::{
+    write_event, write_response, BenchmarkPayload, CancelBenchmarkPayload, Response, VideoConfig,
+};
+use crate::supervisor::{ffmpeg_command, FfmpegProcess};
+
+const DEFAULT_DURATION: Duration = Duration::from_secs(4);
+const MAX_DURATION: Duration = Duration::from_secs(30);
+// An encoder that cannot keep up is cut off after this many times the planned duration and
+// scored on the frames it managed.
+const RUN_TIMEOUT_FACTOR: u32 = 3;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+// Covers the lookahead flush after the last frame.
+const STOP_GRACE: Duration = Duration::from_secs(10);
+// Capture, cursor and overlay work share the machine with the encoder in a real recording,
+// so a recommended setup has to encode this much faster than real time.
+const REALTIME_HEADROOM: f64 = 1.25;
+const KNOWN_ENCODERS: [&str; 5] = [
+    "h264_libx264",
+    "h264_nvenc",
+    "hevc_nvenc",
+    "h264_amf",
+    EMBEDDED_AV1_ENCODER,
+];
+// Vertical scroll of the synthetic document per frame.
+const SCROLL_PX_PER_FRAME: usize = 3;
+
+// A benchmark running on its own thread. The request loop keeps serving commands meanwhile;
+// the thread writes the benchmark_encoders reply itself when done.
+pub struct BenchmarkRun {
+    id: String,
+    cancel: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl BenchmarkRun {
+    // The benchmark_encoders request id, which is also the benchmark id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn running(&self) -> bool {
+        !self.thread.is_finished()
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    // Cancels and waits for the reply to be written; the current encoder is killed, so this
+    // returns quickly.
+    pub fn cancel_and_join(self) {
+        self.cancel();
+        let _ = self.thread.join();
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EncoderBenchmark {
+    encoder: String,
+    ok: bool,
+    // Frames encoded and the wall time it took, from the first frame to the end of the flush.
+    frames: u64,
+    #[serde(rename = "elapsedMs")]
+    elapsed_ms: u64,
+    #[serde(rename = "achievedFps")]
+    achieved_fps: f64,
+    // achievedFps over the requested fps.
+    speed: f64,
+    realtime: bool,
+    // Share of all logical cores. Null for the embedded encoder, which runs in the sidecar.
+    #[serde(rename = "cpuPercent")]
+    cpu_percent: Option<f64>,
+    #[serde(rename = "outputBytes")]
+    output_bytes: u64,
+    #[serde(rename = "bytesPerMinute")]
+    bytes_per_minute: u64,
+    // Stopped at the time limit before all planned frames were encoded.
+    #[serde(rename = "timedOut")]
+    timed_out: bool,
+    error: Option<CaptureError>,
+}
+
+impl EncoderBenchmark {
+    fn failed(encoder: &str, error: CaptureError) -> Self {
+        Self {
+            encoder: encoder.to_string(),
+            ok: false,
+            frames: 0,
+            elapsed_ms: 0,
+            achieved_fps: 0.0,
+            speed: 0.0,
+            realtime: false,
+            cpu_percent: None,
+            output_bytes: 0,
+            bytes_per_minute: 0,
+            timed_out: false,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Recommendation {
+    encoder: String,
+    width: u32,
+    height: u32,
+    fps: u32,
+    // False when no encoder kept up even at the lowest frame rate step.
+    realtime: bool,
+    reason: String,
+}
+
+struct Plan {
+    id: String,
+    ffmpeg_exe: Option<String>,
+    encoders: Vec<String>,
+    width: u32,
+    height: u32,
+    fps: u32,
+    duration: Duration,
+    video: VideoConfig,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Plan {
+    fn planned_frames(&self) -> u64 {
+        (u64::from(self.fps) * self.duration.as_millis() as u64 / 1000).max(1)
+    }
+
+    fn video(&self, encoder: &str) -> VideoConfig {
+        VideoConfig {
+            encoder: encoder.to_string(),
+            ..self.video.clone()
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+}
+
+// Starts a benchmark and returns None; the reply follows once every encoder has run, with
+// `benchmark_progress` events in between. Errors in the payload are answered right away.
+pub fn handle_benchmark(
+    id: String,
+    payload: serde_json::Value,
+    running: &mut Option<BenchmarkRun>,
+    capture_running: bool,
+) -> Option<Response> {
+    let payload: BenchmarkPayload = match serde_json::from_value(payload) {
+        Ok(v) => v,
+        Err(err) => {
+            return Some(Response::err(
+                id,
+                CaptureError::invalid_payload("benchmark_encoders", err),
+            ));
+        }
+    };
+    if running.as_ref().is_some_and(|run| run.thread.is_finished()) {
+        if let Some(run) = running.take() {
+            let _ = run.thread.join();
+        }
+    }
+    if let Some(run) = running.as_ref() {
+        return Some(Response::err(
+            id,
+            CaptureError::new(ErrorCode::AlreadyRunning, "a benchmark is already running")
+                .with_details(json!({ "benchmarkId": run.id })),
+        ));
+    }
+    if capture_running {
+        return Some(Response::err(
+            id,
+            CaptureError::new(
+                ErrorCode::AlreadyRunning,
+                "cannot benchmark while a capture is running",
+            ),
+        ));
+    }
+    let plan = match plan(&id, payload) {
+        Ok(plan) => plan,
+        Err(err) => return Some(Response::err(id, err)),
+    };
+
+    log!(
+        "[native-capture][benchmark] start id={} encoders={:?} size={}x{} fps={} duration_ms={}",
+        plan.id,
+        plan.encoders,
+        plan.width,
+        plan.height,
+        plan.fps,
+        plan.duration.as_millis()
+    );
+    let cancel = Arc::clone(&plan.cancel);
+    let thread = thread::spawn(move || {
+        let response = run(plan);
+        write_response(&response);
+    });
+    *running = Some(BenchmarkRun { id, cancel, thread });
+    None
+}
+
+pub fn handle_cancel_benchmark(
+    id: String,
+    payload: serde_json::Value,
+    running: &mut Option<BenchmarkRun>,
+) -> Response {
+    let payload: CancelBenchmarkPayload = if payload.is_null() {
+        CancelBenchmarkPayload::default()
+    } else {
+        match serde_json::from_value(payload) {
+            Ok(v) => v,
+            Err(err) => {
+                return Response::err(id, CaptureError::invalid_payload("cancel_benchmark", err))
+            }
+        }
+    };
+    let run = running
+        .as_ref()
+        .filter(|run| !run.thread.is_finished())
+        .filter(|run| {
+            payload
+                .benchmark_id
+                .as_ref()
+                .is_none_or(|wanted| *wanted == run.id)
+        });
+    match run {
+        Some(run) => {
+            log!("[native-capture][benchmark] cancel id={}", run.id);
+            run.cancel();
+            Response::ok(id, json!({ "benchmarkId": run.id, "cancelled": true }))
+        }
+        None => Response::err(
+            id,
+            CaptureError::new(ErrorCode::NotRunning, "no benchmark is running")
+                .with_details(json!({ "benchmarkId": payload.benchmark_id })),
+        ),
+    }
+}
+
+fn plan(id: &str, payload: BenchmarkPayload) -> Result<Plan, CaptureError> {
+    let invalid = |message: String| CaptureError::new(ErrorCode::InvalidPayload, message);
+    if payload.width < 16
+        || payload.height < 16
+        || !payload.width.is_multiple_of(2)
+        || !payload.height.is_multiple_of(2)
+    {
+        return Err(invalid(format!(
+            "benchmark size must be even and at least 16x16, got {}x{}",
+            payload.width, payload.height
+        )));
+    }
+    if payload.fps == 0 || payload.fps > 240 {
+        return Err(invalid(format!(
+            "benchmark fps must be 1-240, got {}",
+            payload.fps
+        )));
+    }
+
+    let encoders = match &payload.encoders {
+        Some(encoders) => {
+            if let Some(unknown) = encoders
+                .iter()
+                .find(|encoder| !KNOWN_ENCODERS.contains(&encoder.as_str()))
+            {
+                return Err(invalid(format!("unknown encoder: {unknown}"))
+                    .with_details(json!({ "encoder": unknown, "known": KNOWN_ENCODERS })));
+            }
+            let mut distinct: Vec<String> = Vec::new();
+            for encoder in encoders {
+                if !distinct.contains(encoder) {
+                    distinct.push(encoder.clone());
+                }
+            }
+            distinct
+        }
+        None => encoder_options(&payload.platform, payload.ffmpeg_path.as_deref())
+            .iter()
+            .filter_map(|option| option["codec"].as_str().map(str::to_string))
+            .collect(),
+    };
+    if encoders.is_empty() {
+        return Err(invalid("no encoders to benchmark".to_string()));
+    }
+
+    let bitrate_mode = payload
+        .bitrate_mode
+        .clone()
+        .or_else(|| (payload.bitrate == 0).then(|| "auto".to_string()));
+    let video = VideoConfig {
+        width: payload.width,
+        height: payload.height,
+        fps: payload.fps,
+        bitrate: payload.bitrate,
+        bitrate_mode,
+        quality: payload.quality.clone(),
+        ..VideoConfig::default()
+    };
+    // Rejects a bad mode or tier before anything runs; per-encoder problems such as quality
+    // mode on the embedded encoder are reported in that encoder's result.
+    rate_control(&VideoConfig {
+        encoder: "h264_libx264".to_string(),
+        ..video.clone()
+    })
+    .map_err(invalid)?;
+
+    let ffmpeg_exe = resolve_ffmpeg_path(payload.ffmpeg_path.as_deref());
+    if ffmpeg_exe.is_none() && encoders.iter().any(|encoder| !is_embedded_encoder(encoder)) {
+        return Err(
+            CaptureError::new(ErrorCode::FfmpegNotFound, "ffmpeg executable not found")
+                .with_details(json!({ "ffmpegPath": payload.ffmpeg_path })),
+        );
+    }
+
+    let duration = payload
+        .duration_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_DURATION)
+        .clamp(Duration::from_millis(500), MAX_DURATION);
+    Ok(Plan {
+        id: id.to_string(),
+        ffmpeg_exe,
+        encoders,
+        width: payload.width,
+        height: payload.height,
+        fps: payload.fps,
+        duration,
+        video,
+        cancel: Arc::new(AtomicBool::new(false)),
+    })
+}
+
+fn run(plan: Plan) -> Response {
+    let started = Instant::now();
+    let mut screen = SyntheticScreen::new(plan.width as usize, plan.height as usize);
+    let mut results = Vec::new();
+    for (index, encoder) in plan.encoders.iter().enumerate() {
+        if plan.cancelled() {
+            break;
+        }
+        let mut progress = ProgressReporter::new(&plan, encoder, index);
+        progress.report(0, None);
+        let result = if is_embedded_encoder(encoder) {
+            run_embedded(&plan, encoder, &mut screen, &mut progress)
+        } else {
+            run_ffmpeg(&plan, encoder, &mut screen, &mut progress)
+        };
+        // None means the run was cancelled; a partial result would only mislead.
+        let Some(result) = result else {
+            break;
+        };
+        log!(
+            "[native-capture][benchmark] encoder={} ok={} frames={} achieved_fps={:.1} cpu_percent={:?} bytes={}{}",
+            result.encoder,
+            result.ok,
+            result.frames,
+            result.achieved_fps,
+            result.cpu_percent,
+            result.output_bytes,
+            result.error.as_ref().map(|err| format!(" error={err}")).unwrap_or_default()
+        );
+        progress.report(plan.planned_frames(), Some(&result));
+        results.push(result);
+    }
+
+    let cancelled = plan.cancelled();
+    let recommended = recommend(&results, plan.width, plan.height, plan.fps);
+    log!(
+        "[native-capture][benchmark] done id={} cancelled={} elapsed_ms={} recommended={:?}",
+        plan.id,
+        cancelled,
+        started.elapsed().as_millis(),
+        recommended.as_ref().map(|r| (&r.encoder, r.fps))
+    );
+    Response::ok(
+        &plan.id,
+        json!({
+            "benchmarkId": plan.id,
+            "cancelled": cancelled,
+            "width": plan.width,
+            "height": plan.height,
+            "fps": plan.fps,
+            "durationMs": plan.duration.as_millis() as u64,
+            "results": results,
+            "recommended": recommended,
+        }),
+    )
+}
+
+fn output_path(encoder: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "native-capture-benchmark-{}-{encoder}.mp4",
+        std::process::id()
+    ))
+}
+
+// Pipes the synthetic frames into ffmpeg as raw yuv420p, like the WGC backend does, as fast
+// as the encoder takes them.
+fn run_ffmpeg(
+    plan: &Plan,
+    encoder: &str,
+    screen: &mut SyntheticScreen,
+    progress: &mut ProgressReporter,
+) -> Option<EncoderBenchmark> {
+    let rate = match rate_control(&plan.video(encoder)) {
+        Ok(rate) => rate,
+        Err(err) => {
+            return Some(EncoderBenchmark::failed(
+                encoder,
+                CaptureError::new(ErrorCode::InvalidPayload, err),
+            ))
+        }
+    };
+    let output = output_path(encoder);
+    let (codec, encoder_args) = encoder_codec_args(encoder);
+    let mut command = ffmpeg_command(plan.ffmpeg_exe.as_deref().unwrap_or("ffmpeg"));
+    // `-benchmark` prints the CPU time used at exit, at info level.
+    command
+        .args(["-loglevel", "level+info", "-benchmark"])
+        .args(["-f", "rawvideo", "-pixel_format", "yuv420p"])
+        .arg("-video_size")
+        .arg(format!("{}x{}", plan.width, plan.height))
+        .arg("-framerate")
+        .arg(plan.fps.to_string())
+        .args(["-i", "pipe:0", "-pix_fmt", "yuv420p", "-c:v", codec])
+        .args(encoder_args)
+        .args(rate_control_args(encoder, &rate))
+        .arg("-g")
+        .arg((plan.fps * 2).to_string())
+        .arg(&output);
+
+    let mut ffmpeg = match FfmpegProcess::spawn(command, "benchmark", &plan.id) {
+        Ok(ffmpeg) => ffmpeg,
+        Err(err) => return Some(EncoderBenchmark::failed(encoder, err)),
+    };
+    let Some(mut stdin) = ffmpeg.take_stdin() else {
+        ffmpeg.abort();
+        return Some(EncoderBenchmark::failed(
+            encoder,
+            "ffmpeg stdin is not available".into(),
+        ));
+    };
+    let planned = plan.planned_frames();
+    let started = Instant::now();
+    let deadline = started + plan.duration * RUN_TIMEOUT_FACTOR;
+    let mut written = 0;
+    let mut timed_out = false;
+    while written < planned {
+        if plan.cancelled() {
+            drop(stdin);
+            ffmpeg.abort();
+            let _ = fs::remove_file(&output);
+            return None;
+        }
+        if Instant::now() >= deadline {
+            timed_out = true;
+            break;
+        }
+        // A write error means ffmpeg is gone; its exit says why.
+        if stdin.write_all(screen.render(written)).is_err() {
+            break;
+        }
+        written += 1;
+        progress.report(written, None);
+    }
+    drop(stdin);
+    let exit = ffmpeg.stop(STOP_GRACE);
+    let elapsed = started.elapsed();
+    let output_bytes = fs::metadata(&output).map(|meta| meta.len()).unwrap_or(0);
+    let _ = fs::remove_file(&output);
+
+    if let Err(err) = exit.check() {
+        return Some(EncoderBenchmark::failed(encoder, err));
+    }
+    let frames = exit
+        .progress
+        .as_ref()
+        .map(|progress| progress.frame)
+        .filter(|frame| *frame > 0)
+        .unwrap_or(written);
+    let cpu_percent = bench_cpu_seconds(&exit.stderr_excerpt).map(|(cpu, real)| {
+        let cores = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1) as f64;
+        round1(cpu / real.max(0.001) / cores * 100.0)
+    });
+    Some(score(
+        plan,
+        encoder,
+        frames,
+        elapsed,
+        cpu_percent,
+        output_bytes,
+        timed_out,
+    ))
+}
+
+// Encodes in-process. Its CPU time cannot be told apart from the sidecar's, so it is not
+// reported.
+fn run_embedded(
+    plan: &Plan,
+    encoder: &str,
+    screen: &mut SyntheticScreen,
+    progress: &mut ProgressReporter,
+) -> Option<EncoderBenchmark> {
+    let rate = match rate_control(&plan.video(encoder)) {
+        Ok(rate) => rate,
+        Err(err) => {
+            return Some(EncoderBenchmark::failed(
+                encoder,
+                CaptureError::new(ErrorCode::InvalidPayload, err),
+            ))
+        }
+    };
+    let output = output_path(encoder);
+    let output_str = output.to_string_lossy().to_string();
+    let failed = |err: String| {
+        Some(EncoderBenchmark::failed(
+            encoder,
+            CaptureError::new(ErrorCode::CaptureFailed, err),
+        ))
+    };
+    let mut av1 =
+        match Av1Encoder::create(plan.width, plan.height, plan.fps, rate.bitrate, &output_str) {
+            Ok(av1) => av1,
+            Err(err) => return failed(err),
+        };
+    let planned = plan.planned_frames();
+    let frame_duration = Duration::from_secs(1) / plan.fps;
+    let started = Instant::now();
+    let deadline = started + plan.duration * RUN_TIMEOUT_FACTOR;
+    let mut written = 0;
+    let mut timed_out = false;
+    while written < planned {
+        if plan.cancelled() {
+            drop(av1);
+            let _ = fs::remove_file(&output);
+            return None;
+        }
+        if Instant::now() >= deadline {
+            timed_out = true;
+            break;
+        }
+        if let Err(err) = av1.push(frame_duration * written as u32, screen.render(written)) {
+            let _ = fs::remove_file(&output);
+            return failed(err);
+        }
+        written += 1;
+        progress.report(written, None);
+    }
+    let finished = av1.finish();
+    let elapsed = started.elapsed();
+    let output_bytes = fs::metadata(&output).map(|meta| meta.len()).unwrap_or(0);
+    let _ = fs::remove_file(&output);
+    if let Err(err) = finished {
+        return failed(err);
+    }
+    Some(score(
+        plan,
+        encoder,
+        written,
+        elapsed,
+        None,
+        output_bytes,
+        timed_out,
+    ))
+}
+
+fn score(
+    plan: &Plan,
+    encoder: &str,
+    frames: u64,
+    elapsed: Duration,
+    cpu_percent: Option<f64>,
+    output_bytes: u64,
+    timed_out: bool,
+) -> EncoderBenchmark {
+    let achieved_fps = frames as f64 / elapsed.as_secs_f64().max(0.001);
+    let content_secs = frames as f64 / f64::from(plan.fps);
+    EncoderBenchmark {
+        encoder: encoder.to_string(),
+        ok: frames > 0,
+        frames,
+        elapsed_ms: elapsed.as_millis() as u64,
+        achieved_fps: round1(achieved_fps),
+        speed: (achieved_fps / f64::from(plan.fps) * 100.0).round() / 100.0,
+        realtime: achieved_fps >= f64::from(plan.fps),
+        cpu_percent,
+        output_bytes,
+        bytes_per_minute: if content_secs > 0.0 {
+            (output_bytes as f64 / content_secs * 60.0) as u64
+        } else {
+            0
+        },
+        timed_out,
+        error: (frames == 0)
+            .then(|| CaptureError::new(ErrorCode::FfmpegFailed, "no frames were encoded")),
+    }
+}
+
+// The least CPU-hungry encoder that keeps up with headroom, ties going to the smaller
+// output. When none does, the fastest one at the highest frame rate step it can sustain.
+fn recommend(
+    results: &[EncoderBenchmark],
+    width: u32,
+    height: u32,
+    fps: u32,
+) -> Option<Recommendation> {
+    let ok: Vec<&EncoderBenchmark> = results.iter().filter(|result| result.ok).collect();
+    let needed = f64::from(fps) * REALTIME_HEADROOM;
+    let keeping_up = ok
+        .iter()
+        .filter(|result| result.achieved_fps >= needed)
+        .min_by(|a, b| {
+            let cpu = |result: &EncoderBenchmark| result.cpu_percent.unwrap_or(100.0);
+            cpu(a)
+                .total_cmp(&cpu(b))
+                .then(a.bytes_per_minute.cmp(&b.bytes_per_minute))
+        });
+    if let Some(best) = keeping_up {
+        let cpu = best
+            .cpu_percent
+            .map(|cpu| format!(" using {cpu}% CPU"))
+            .unwrap_or_default();
+        return Some(Recommendation {
+            encoder: best.encoder.clone(),
+            width,
+            height,
+            fps,
+            realtime: true,
+            reason: format!("{} encodes {} fps{cpu}", best.encoder, best.achieved_fps),
+        });
+    }
+
+    let fastest = ok
+        .iter()
+        .max_by(|a, b| a.achieved_fps.total_cmp(&b.achieved_fps))?;
+    let step = FPS_STEPS
+        .iter()
+        .copied()
+        .find(|step| *step < fps && f64::from(*step) * REALTIME_HEADROOM <= fastest.achieved_fps);
+    let (step_fps, realtime) = match step {
+        Some(step) => (step, true),
+        None => (
+            FPS_STEPS
+                .iter()
+                .copied()
+                .filter(|step| *step < fps)
+                .min()
+                .unwrap_or(fps),
+            false,
+        ),
+    };
+    Some(Recommendation {
+        encoder: fastest.encoder.clone(),
+        width,
+        height,
+        fps: step_fps,
+        realtime,
+        reason: if realtime {
+            format!(
+                "no encoder keeps up at {fps} fps; {} encodes {} fps",
+                fastest.encoder, fastest.achieved_fps
+            )
+        } else {
+            format!(
+                "no encoder keeps up even at {step_fps} fps; {} encodes {} fps, so lower the resolution",
+                fastest.encoder, fastest.achieved_fps
+            )
+        },
+    })
+}
+
+// (user + system CPU seconds, real seconds) from the "bench: utime=1.234s stime=0.056s
+// rtime=2.000s" line `-benchmark` prints.
+fn bench_cpu_seconds(stderr: &str) -> Option<(f64, f64)> {
+    let line = stderr.split(" | ").find(|line| line.contains("utime="))?;
+    let value = |key: &str| {
+        line.split_whitespace()
+            .find_map(|token| token.strip_prefix(key))
+            .and_then(|value| value.trim_end_matches('s').parse::<f64>().ok())
+    };
+    Some((value("utime=")? + value("stime=")?, value("rtime=")?))
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+struct ProgressReporter {
+    benchmark_id: String,
+    encoder: String,
+    index: usize,
+    count: usize,
+    planned: u64,
+    started: Instant,
+    reported_at: Option<Instant>,
+}
+
+impl ProgressReporter {
+    fn new(plan: &Plan, encoder: &str, index: usize) -> Self {
+        Self {
+            benchmark_id: plan.id.clone(),
+            encoder: encoder.to_string(),
+            index,
+            count: plan.encoders.len(),
+            planned: plan.planned_frames(),
+            started: Instant::now(),
+            reported_at: None,
+        }
+    }
+
+    // Throttled while frames flow; the first report and an encoder's result always go out.
+    fn report(&mut self, frame: u64, result: Option<&EncoderBenchmark>) {
+        let now = Instant::now();
+        let due = self
+            .reported_at
+            .is_none_or(|reported_at| now.duration_since(reported_at) >= PROGRESS_INTERVAL);
+        if !due && result.is_none() {
+            return;
+        }
+        self.reported_at = Some(now);
+        let done = (self.index as f64 + frame as f64 / self.planned as f64) / self.count as f64;
+        write_event(json!({
+            "event": "benchmark_progress",
+            "benchmarkId": self.benchmark_id,
+            "encoder": self.encoder,
+            "index": self.index,
+            "count": self.count,
+            "frame": frame,
+            "frames": self.planned,
+            "elapsedMs": self.started.elapsed().as_millis() as u64,
+            "percent": (done * 100.0).round().min(100.0),
+            "result": result,
+        }));
+    }
+}
+
+// Screen-like test picture: a sidebar, a title bar and a document of text lines scrolling
+// under them. Flat areas and sharp glyph edges are what screen recordings are made of, and
+// the scroll gives the encoder real motion to search. The document is deterministic, so
+// runs are comparable across encoders and machines.
+struct SyntheticScreen {
+    width: usize,
+    height: usize,
+    content_x: usize,
+    content_y: usize,
+    // Luma of the scrolling document, `width - content_x` wide; it wraps around.
+    document: Vec<u8>,
+    document_rows: usize,
+    frame: Vec<u8>,
+}
+
+impl SyntheticScreen {
+    fn new(width: usize, height: usize) -> Self {
+        let content_x = (width / 6) & !1;
+        let content_y = (height / 20).max(2) & !1;
+        let content_w = width - content_x;
+        let content_h = height - content_y;
+        let document_rows = content_h * 2;
+        let line_height = (height / 40).max(8);
+        let glyph_height = line_height * 3 / 5;
+        let glyph_width = (line_height / 3).max(2);
+        let mut document = vec![235u8; content_w * document_rows];
+        let mut seed: u32 = 0x9e37_79b9;
+        let mut random = move |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % bound.max(1)
+        };
+        let margin = glyph_width * 4;
+        for line in 0..document_rows / line_height {
+            let top = line * line_height + (line_height - glyph_height) / 2;
+            let line_end = margin + random(content_w.saturating_sub(margin * 2).max(1));
+            let mut x = margin;
+            while x < line_end {
+                let word = 2 + random(8);
+                for _ in 0..word {
+                    let w = glyph_width / 2 + random(glyph_width);
+                    for y in top..top + glyph_height {
+                        let row = &mut document[y * content_w..(y + 1) * content_w];
+                        let end = (x + w).min(content_w);
+                        for (offset, pixel) in row[x.min(end)..end].iter_mut().enumerate() {
+                            // Stroke texture, so glyphs are not solid blocks.
+                            *pixel = if (offset + y) % 3 == 0 { 120 } else { 30 };
+                        }
+                    }
+                    x += w + 1;
+                }
+                x += glyph_width;
+            }
+        }
+
+        let y_size = width * height;
+        let mut frame = vec![235u8; y_size + y_size / 2];
+        for y in 0..height {
+            let row = &mut frame[y * width..(y + 1) * width];
+            if y < content_y {
+                row.fill(90);
+            } else {
+                row[..content_x].fill(50);
+            }
+        }
+        let (chroma_w, chroma_h) = (width / 2, height / 2);
+        let (u_plane, v_plane) = frame[y_size..].split_at_mut(chroma_w * chroma_h);
+        for y in 0..chroma_h {
+            for x in 0..chroma_w {
+                let sidebar = x < content_x / 2 && y >= content_y / 2;
+                u_plane[y * chroma_w + x] = if sidebar { 150 } else { 128 };
+                v_plane[y * chroma_w + x] = if sidebar { 110 } else { 128 };
+            }
+        }
+        Self {
+            width,
+            height,
+            content_x,
+            content_y,
+            document,
+            document_rows,
+            frame,
+        }
+    }
+
+    fn render(&mut self, index: u64) -> &[u8] {
+        let content_w = self.width - self.content_x;
+        let scroll = (index as usize).wrapping_mul(SCROLL_PX_PER_FRAME) % self.document_rows;
+        for y in self.content_y..self.height {
+            let source = (y - self.content_y + scroll) % self.document_rows;
+            let target = y * self.width + self.content_x;
+            self.frame[target..target + content_w]
+                .copy_from_slice(&self.document[source * content_w..(source + 1) * content_w]);
+        }
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(encoder: &str, achieved_fps: f64, cpu_percent: Option<f64>, bytes_per_minute: u64) -> EncoderBenchmark {
+        EncoderBenchmark {
+            achieved_fps,
+            cpu_percent,
+            bytes_per_minute,
+            ok: true,
+            frames: 100,
+            error: None,
+            ..EncoderBenchmark::failed(encoder, "unused".into())
+        }
+    }
+
+    fn recommended(results: &[EncoderBenchmark], fps: u32) -> Recommendation {
+        recommend(results, 1920, 1080, fps).unwrap()
+    }
+
+    #[test]
+    fn recommends_the_least_cpu_among_encoders_with_headroom() {
+        let results = [
+            result("h264_libx264", 80.0, Some(60.0), 30_000_000),
+            result("h264_nvenc", 200.0, Some(5.0), 40_000_000),
+            // Real time at 60 fps, but without the 1.25x headroom.
+            result("h264_amf", 70.0, Some(3.0), 20_000_000),
+        ];
+        let pick = recommended(&results, 60);
+        assert_eq!((pick.encoder.as_str(), pick.fps, pick.realtime), ("h264_nvenc", 60, true));
+        assert_eq!(pick.reason, "h264_nvenc encodes 200 fps using 5% CPU");
+    }
+
+    #[test]
+    fn breaks_cpu_ties_by_size_and_counts_unknown_cpu_as_full() {
+        let results = [
+            result("h264_libx264", 90.0, Some(20.0), 30_000_000),
+            result("hevc_nvenc", 90.0, Some(20.0), 18_000_000),
+            result(EMBEDDED_AV1_ENCODER, 300.0, None, 10_000_000),
+        ];
+        assert_eq!(recommended(&results, 60).encoder, "hevc_nvenc");
+        let embedded_only = [result(EMBEDDED_AV1_ENCODER, 300.0, None, 10_000_000)];
+        assert_eq!(recommended(&embedded_only, 60).reason, format!("{EMBEDDED_AV1_ENCODER} encodes 300 fps"));
+    }
+
+    #[test]
+    fn falls_back_to_the_fastest_at_the_highest_sustainable_fps_step() {
+        let results = [
+            result("h264_libx264", 40.0, Some(90.0), 30_000_000),
+            result("h264_amf", 25.0, Some(10.0), 20_000_000),
+        ];
+        // 48 fps would need 60 fps with headroom; 30 fps needs 37.5.
+        let pick = recommended(&results, 60);
+        assert_eq!((pick.encoder.as_str(), pick.fps, pick.realtime), ("h264_libx264", 30, true));
+        assert!(pick.reason.starts_with("no encoder keeps up at 60 fps"));
+
+        let pick = recommended(&[result("h264_libx264", 10.0, Some(90.0), 0)], 60);
+        assert_eq!((pick.fps, pick.realtime), (10, false));
+        assert!(pick.reason.ends_with("so lower the resolution"));
+    }
+
+    #[test]
+    fn ignores_failed_encoders() {
+        let failed = EncoderBenchmark::failed("h264_nvenc", "no device".into());
+        assert!(recommend(std::slice::from_ref(&failed), 1920, 1080, 30).is_none());
+        let pick = recommended(&[failed, result("h264_libx264", 50.0, Some(40.0), 0)], 30);
+        assert_eq!(pick.encoder, "h264_libx264");
+    }
+
+    #[test]
+    fn reads_cpu_time_from_the_bench_line() {
+        let stderr = "[info] Input #0, rawvideo | [info] bench: utime=1.250s stime=0.250s rtime=2.000s";
+        assert_eq!(bench_cpu_seconds(stderr), Some((1.5, 2.0)));
+        assert_eq!(bench_cpu_seconds("[info] bench: maxrss=1024KiB"), None);
+    }
+
+    // An executable sh script standing in for ffmpeg; it ignores ffmpeg's arguments.
+    #[cfg(unix)]
+    fn fake_ffmpeg(name: &str, script: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("native-capture-{name}-{}.sh", std::process::id()));
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn test_plan(ffmpeg_exe: String, duration: Duration) -> Plan {
+        Plan {
+            id: "benchmark-test".to_string(),
+            ffmpeg_exe: Some(ffmpeg_exe),
+            encoders: vec!["h264_libx264".to_string()],
+            width: 64,
+            height: 64,
+            fps: 30,
+            duration,
+            video: VideoConfig {
+                width: 64,
+                height: 64,
+                fps: 30,
+                bitrate_mode: Some("auto".to_string()),
+                ..VideoConfig::default()
+            },
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scores_a_finished_run() {
+        let exe = fake_ffmpeg(
+            "benchmark-ok",
+            "cat >/dev/null; printf 'frame=15\\nprogress=end\\n'; \
+             echo '[info] bench: utime=0.300s stime=0.100s rtime=0.500s' >&2",
+        );
+        let plan = test_plan(exe.clone(), Duration::from_millis(500));
+        let mut screen = SyntheticScreen::new(64, 64);
+        let mut progress = ProgressReporter::new(&plan, "h264_libx264", 0);
+        let result = run_ffmpeg(&plan, "h264_libx264", &mut screen, &mut progress).unwrap();
+        let _ = fs::remove_file(&exe);
+        assert!(result.ok, "{:?}", result.error);
+        assert_eq!(result.frames, 15);
+        assert!(!result.timed_out);
+        assert!(result.cpu_percent.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cancel_kills_the_running_encoder() {
+        // Takes one byte per read, so the frames only drain slowly.
+        let exe = fake_ffmpeg("benchmark-slow", "exec dd bs=1 of=/dev/null 2>/dev/null");
+        let plan = test_plan(exe.clone(), MAX_DURATION);
+        let cancel = Arc::clone(&plan.cancel);
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            cancel.store(true, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        let mut screen = SyntheticScreen::new(64, 64);
+        let mut progress = ProgressReporter::new(&plan, "h264_libx264", 0);
+        let result = run_ffmpeg(&plan, "h264_libx264", &mut screen, &mut progress);
+        canceller.join().unwrap();
+        let _ = fs::remove_file(&exe);
+        assert!(result.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!output_path("h264_libx264").exists());
+    }
+}
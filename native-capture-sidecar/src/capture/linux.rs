@@ -0,0 +1,216 @@
+use serde_json::json;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::adaptive::{with_preset, AdaptiveEncoder, AdaptiveOptions, AdaptivePolicy, EncodeStep};
+use crate::capture::{ActiveCapture, CaptureBackend, SourceBounds};
+use crate::discovery::discover_ffmpeg;
+use crate::embedded::is_embedded_encoder;
+use crate::error::{CaptureError, ErrorCode};
+use crate::ffmpeg::{
+    encoder_codec_args, ffmpeg_encoder_name, fit_filter, lossless_codec_args, rate_control,
+    rate_control_args, RECORDING_MOVFLAGS,
+};
+use crate::pipeline::{FitMode, PadColor};
+use crate::protocol::StartCapturePayload;
+use crate::supervisor::{ffmpeg_command, FfmpegProcess, Readiness};
+
+// x11grab reads the X server's framebuffer directly, so frames flow within a second.
+const X11GRAB_READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture, CaptureError> {
+    if !cfg!(target_os = "linux") {
+        return Err(CaptureError::new(
+            ErrorCode::UnsupportedPlatform,
+            "linux capture requested, but sidecar binary is not a linux build",
+        ));
+    }
+
+    if start_payload.source.source_type != "screen" {
+        return Err(CaptureError::new(
+            ErrorCode::InvalidPayload,
+            "linux native sidecar currently supports screen capture only",
+        ));
+    }
+    if is_embedded_encoder(&start_payload.video.encoder) {
+        return Err(CaptureError::new(
+            ErrorCode::EncoderUnavailable,
+            format!(
+                "encoder {} requires WGC capture, which is unavailable on linux",
+                start_payload.video.encoder
+            ),
+        )
+        .with_details(json!({ "encoder": start_payload.video.encoder })));
+    }
+    let display = x11_display().ok_or_else(|| {
+        CaptureError::new(
+            ErrorCode::UnsupportedPlatform,
+            "linux capture needs an X11 display (DISPLAY is not set)",
+        )
+    })?;
+
+    let ffmpeg_exe = discover_ffmpeg(
+        start_payload.ffmpeg_path.as_deref(),
+        ffmpeg_encoder_name(&start_payload.video).as_deref(),
+    )
+    .check("install ffmpeg on PATH or set VELOCITY_FFMPEG_PATH; no linux build is bundled")?;
+    if start_payload.video.frame_rate_mode.as_deref() == Some("vfr") {
+        log!("[native-capture][linux] frameRateMode=vfr needs a raw-frame backend; x11grab records cfr");
+    }
+    if start_payload.burn_in.is_some() {
+        log!("[native-capture][linux] burnIn needs a raw-frame backend; x11grab records without it");
+    }
+
+    let output_path = start_payload.output_path.clone();
+    let readiness = Readiness::Progress { timeout: X11GRAB_READY_TIMEOUT };
+    let mut base = EncodeStep::new(
+        start_payload.video.fps,
+        start_payload.video.width,
+        start_payload.video.height,
+    );
+    log!(
+        "[native-capture][linux] x11grab start display={} region={:?} target={}x{} fps={} encoder={}",
+        display,
+        start_payload
+            .capture_region
+            .as_ref()
+            .map(|r| format!("{}x{}@{},{}", r.width, r.height, r.x, r.y)),
+        start_payload.video.width,
+        start_payload.video.height,
+        start_payload.video.fps,
+        start_payload.video.encoder
+    );
+    let backend = match start_payload.video.adaptive_quality() {
+        Some(config) => {
+            let encoder_args = build_encoder_args(&start_payload.video.encoder);
+            let policy =
+                AdaptivePolicy::new(config, &start_payload.video.encoder, &encoder_args, &mut base);
+            let payload = start_payload.clone();
+            let exe = ffmpeg_exe.clone();
+            let options = AdaptiveOptions {
+                session_id: start_payload.session_id.clone(),
+                label: "linux",
+                ffmpeg_exe: ffmpeg_exe.clone(),
+                readiness,
+                policy,
+                base,
+                piped: false,
+                command: Box::new(move |step| build_ffmpeg_command(&exe, &payload, &display, step)),
+            };
+            let encoder = AdaptiveEncoder::start(options, &output_path)
+                .map_err(|err| err.with_hint("Try h264_libx264."))?;
+            CaptureBackend::Adaptive(encoder)
+        }
+        None => {
+            let mut command = build_ffmpeg_command(&ffmpeg_exe, &start_payload, &display, &base)?;
+            command.arg(output_path.as_str());
+            let mut ffmpeg = FfmpegProcess::spawn(command, "linux", &start_payload.session_id)?;
+            ffmpeg
+                .wait_ready(readiness)
+                .map_err(|err| err.with_hint("Confirm the X display is reachable and not Wayland-only."))?;
+            CaptureBackend::Ffmpeg(ffmpeg)
+        }
+    };
+
+    // Input hooks report root-window coordinates, which are what the region is given in.
+    let source_bounds = start_payload.capture_region.as_ref().map(|region| SourceBounds {
+        x: region.x,
+        y: region.y,
+        width: region.width,
+        height: region.height,
+    });
+    Ok(ActiveCapture {
+        session_id: start_payload.session_id,
+        output_path,
+        width: start_payload.video.width,
+        height: start_payload.video.height,
+        fps: start_payload.video.fps,
+        started_at: Instant::now(),
+        platform: "linux".to_string(),
+        restore_cursor_on_stop: false,
+        vfr: false,
+        source_bounds,
+        input_telemetry: None,
+        cursor_tracker: None,
+        journal: None,
+        recording_path: None,
+        ffmpeg_exe: Some(ffmpeg_exe),
+        overwrite: false,
+        overlay_feed: None,
+        backend,
+    })
+}
+
+// XWayland sessions set DISPLAY as well, but only see X11 clients' windows.
+fn x11_display() -> Option<String> {
+    std::env::var("DISPLAY").ok().filter(|display| !display.is_empty())
+}
+
+// `step` sets the frame rate, output size and encoder preset.
+fn build_ffmpeg_command(
+    ffmpeg_exe: &str,
+    payload: &StartCapturePayload,
+    display: &str,
+    step: &EncodeStep,
+) -> Result<Command, CaptureError> {
+    let draw_mouse = if payload.cursor.mode == "hide" { "0" } else { "1" };
+
+    let mut command = ffmpeg_command(ffmpeg_exe);
+    command
+        .arg("-f")
+        .arg("x11grab")
+        .arg("-thread_queue_size")
+        .arg("2048")
+        .arg("-framerate")
+        .arg(format!("{}", step.fps))
+        .arg("-draw_mouse")
+        .arg(draw_mouse);
+    // Without a region x11grab grabs the whole root window.
+    let input = match &payload.capture_region {
+        Some(region) => {
+            command
+                .arg("-video_size")
+                .arg(format!("{}x{}", region.width.max(1), region.height.max(1)));
+            format!("{display}+{},{}", region.x.max(0), region.y.max(0))
+        }
+        None => display.to_string(),
+    };
+    command.arg("-i").arg(input);
+
+    // The screen size is only known to the X server, so the fit always runs, as for
+    // avfoundation.
+    let invalid = |err: String| CaptureError::new(ErrorCode::InvalidPayload, err);
+    let fit = FitMode::parse(payload.video.fit.as_deref()).map_err(invalid)?;
+    let pad = PadColor::parse(payload.video.pad_color.as_deref()).map_err(invalid)?;
+    command
+        .arg("-vf")
+        .arg(fit_filter(step.width, step.height, fit, pad));
+
+    command.arg("-r").arg(format!("{}", step.fps));
+
+    if let Some(codec) = payload.video.lossless_codec() {
+        command.args(lossless_codec_args(codec)?);
+        return Ok(command);
+    }
+    command.arg("-pix_fmt").arg("yuv420p");
+
+    let (video_codec, encoder_args) = encoder_codec_args(&payload.video.encoder);
+    let rate = rate_control(&payload.video)
+        .map_err(|err| CaptureError::new(ErrorCode::InvalidPayload, err))?;
+    command
+        .arg("-g")
+        .arg(format!("{}", (step.fps.max(1)) * 2))
+        .arg("-movflags")
+        .arg(RECORDING_MOVFLAGS)
+        .arg("-c:v")
+        .arg(video_codec)
+        .args(with_preset(&payload.video.encoder, encoder_args, step.preset.as_deref()))
+        .args(rate_control_args(&payload.video.encoder, &rate));
+
+    Ok(command)
+}
+
+fn build_encoder_args(encoder: &str) -> Vec<String> {
+    let (_, args) = encoder_codec_args(encoder);
+    args.iter().map(|arg| arg.to_string()).collect()
+}
@@ -1,69 +1,102 @@
-use std::process::{Command, Stdio};
-use std::thread;
+use serde_json::json;
+use std::process::Command;
 use std::time::{Duration, Instant};
 
+use crate::adaptive::{AdaptiveEncoder, AdaptiveOptions, AdaptivePolicy, EncodeStep};
 use crate::capture::{ActiveCapture, CaptureBackend};
-use crate::ffmpeg::resolve_ffmpeg_path;
+use crate::discovery::discover_ffmpeg;
+use crate::error::{CaptureError, ErrorCode};
+use crate::ffmpeg::{
+    ffmpeg_encoder_name, fit_filter, lossless_codec_args, rate_control, rate_control_args,
+    RECORDING_MOVFLAGS,
+};
+use crate::pipeline::{FitMode, PadColor};
 use crate::protocol::StartCapturePayload;
+use crate::supervisor::{ffmpeg_command, FfmpegProcess, Readiness};
 
-pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture, String> {
+// Opening the avfoundation device can take a second or two; a pending screen recording
+// permission prompt keeps it from delivering anything at all.
+const AVFOUNDATION_READY_TIMEOUT: Duration = Duration::from_secs(8);
+
+pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture, CaptureError> {
     if !cfg!(target_os = "macos") {
-        return Err("darwin capture requested, but sidecar binary is not a macOS build".to_string());
+        return Err(CaptureError::new(
+            ErrorCode::UnsupportedPlatform,
+            "darwin capture requested, but sidecar binary is not a macOS build",
+        ));
     }
 
     if start_payload.source.source_type != "screen" {
-        return Err("macOS native sidecar currently supports screen capture only".to_string());
+        return Err(CaptureError::new(
+            ErrorCode::InvalidPayload,
+            "macOS native sidecar currently supports screen capture only",
+        ));
     }
 
     if start_payload.video.encoder != "h264_libx264" {
-        return Err("macOS native sidecar v1 supports h264_libx264 only".to_string());
+        return Err(CaptureError::new(
+            ErrorCode::EncoderUnavailable,
+            "macOS native sidecar v1 supports h264_libx264 only",
+        )
+        .with_details(json!({ "encoder": start_payload.video.encoder })));
     }
 
-    let ffmpeg_exe = resolve_ffmpeg_path(start_payload.ffmpeg_path.as_deref());
-    if ffmpeg_exe.is_none() {
-        return Err(
-            "ffmpeg executable not found (bundle native-capture-sidecar/bin/darwin/ffmpeg or install ffmpeg on PATH)".to_string(),
-        );
-    }
-    let ffmpeg_exe = ffmpeg_exe.unwrap_or_else(|| "ffmpeg".to_string());
+    let ffmpeg_exe = discover_ffmpeg(
+        start_payload.ffmpeg_path.as_deref(),
+        ffmpeg_encoder_name(&start_payload.video).as_deref(),
+    )
+    .check("bundle native-capture-sidecar/bin/darwin/ffmpeg, set VELOCITY_FFMPEG_PATH or install ffmpeg on PATH")?;
     let should_hide_native_cursor = start_payload.cursor.mode == "hide";
-
-    let output_path = start_payload.output_path.clone();
-    let mut command = build_ffmpeg_command(&ffmpeg_exe, &start_payload)?;
-
-    command
-        .arg(output_path.as_str())
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
-
-    let child = command
-        .spawn()
-        .map_err(|err| format!("failed to spawn ffmpeg: {err}"))?;
-    let mut child = child;
-
-    if should_hide_native_cursor {
-        hide_cursor_globally();
+    if start_payload.video.frame_rate_mode.as_deref() == Some("vfr") {
+        log!("[native-capture][mac] frameRateMode=vfr needs a raw-frame backend; avfoundation records cfr");
     }
 
-    thread::sleep(Duration::from_millis(350));
-    match child.try_wait() {
-        Ok(Some(status)) => {
+    let output_path = start_payload.output_path.clone();
+    let readiness = Readiness::Progress { timeout: AVFOUNDATION_READY_TIMEOUT };
+    let mut base = EncodeStep::new(
+        start_payload.video.fps,
+        start_payload.video.width,
+        start_payload.video.height,
+    );
+    let started = match start_payload.video.adaptive_quality() {
+        Some(config) => {
+            let policy = AdaptivePolicy::new(config, "h264_libx264", &["-preset", "ultrafast"], &mut base);
+            let payload = start_payload.clone();
+            let exe = ffmpeg_exe.clone();
+            let options = AdaptiveOptions {
+                session_id: start_payload.session_id.clone(),
+                label: "mac",
+                ffmpeg_exe: ffmpeg_exe.clone(),
+                readiness,
+                policy,
+                base,
+                piped: false,
+                command: Box::new(move |step| build_ffmpeg_command(&exe, &payload, step)),
+            };
             if should_hide_native_cursor {
-                show_cursor_globally();
+                hide_cursor_globally();
             }
-            return Err(format!(
-                "ffmpeg exited immediately during startup (status={status}). Confirm screen capture permissions and avfoundation input."
-            ));
+            AdaptiveEncoder::start(options, &output_path).map(CaptureBackend::Adaptive)
         }
-        Ok(None) => {}
+        None => {
+            let mut command = build_ffmpeg_command(&ffmpeg_exe, &start_payload, &base)?;
+            command.arg(output_path.as_str());
+            let mut ffmpeg = FfmpegProcess::spawn(command, "mac", &start_payload.session_id)?;
+            if should_hide_native_cursor {
+                hide_cursor_globally();
+            }
+            ffmpeg.wait_ready(readiness).map(|()| CaptureBackend::Ffmpeg(ffmpeg))
+        }
+    };
+    let backend = match started {
+        Ok(backend) => backend,
         Err(err) => {
             if should_hide_native_cursor {
                 show_cursor_globally();
             }
-            return Err(format!("failed to verify ffmpeg startup: {err}"));
+            return Err(err.with_hint("Confirm screen capture permissions and avfoundation input."));
         }
-    }
+    };
 
     Ok(ActiveCapture {
         session_id: start_payload.session_id,
@@ -74,15 +107,27 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
         started_at: Instant::now(),
         platform: "darwin".to_string(),
         restore_cursor_on_stop: should_hide_native_cursor,
+        vfr: false,
         source_bounds: None,
-        backend: CaptureBackend::Ffmpeg(child),
+        input_telemetry: None,
+        cursor_tracker: None,
+        journal: None,
+        recording_path: None,
+        ffmpeg_exe: Some(ffmpeg_exe),
+        overwrite: false,
+        overlay_feed: None,
+        backend,
     })
 }
 
-fn build_ffmpeg_command(ffmpeg_exe: &str, payload: &StartCapturePayload) -> Result<Command, String> {
+// `step` sets the output frame rate and size; the device keeps the requested frame rate.
+fn build_ffmpeg_command(
+    ffmpeg_exe: &str,
+    payload: &StartCapturePayload,
+    step: &EncodeStep,
+) -> Result<Command, CaptureError> {
     let capture_cursor = if payload.cursor.mode == "hide" { "0" } else { "1" };
     let capture_mouse_clicks = if payload.cursor.mode == "hide" { "0" } else { "1" };
-    let bitrate = format!("{}", payload.video.bitrate.max(1_000_000));
 
     let screen_index = payload
         .source
@@ -96,9 +141,8 @@ fn build_ffmpeg_command(ffmpeg_exe: &str, payload: &StartCapturePayload) -> Resu
         })
         .unwrap_or(0);
 
-    let mut command = Command::new(ffmpeg_exe);
+    let mut command = ffmpeg_command(ffmpeg_exe);
     command
-        .arg("-y")
         .arg("-f")
         .arg("avfoundation")
         .arg("-thread_queue_size")
@@ -108,31 +152,44 @@ fn build_ffmpeg_command(ffmpeg_exe: &str, payload: &StartCapturePayload) -> Resu
         .arg("-capture_cursor")
         .arg(capture_cursor)
         .arg("-capture_mouse_clicks")
-        .arg(capture_mouse_clicks)
-        .arg("-i")
-        .arg(format!("{screen_index}:none"));
+        .arg(capture_mouse_clicks);
+    if payload.video.lossless_codec().is_some() {
+        // The default uyvy422 has already halved the chroma.
+        command.arg("-pixel_format").arg("bgr0");
+    }
+    command.arg("-i").arg(format!("{screen_index}:none"));
 
+    // avfoundation records at the display's native size, which is not known up front, so
+    // the fit always runs (the default stretch included), as it does for gdigrab and WGC.
+    let invalid = |err: String| CaptureError::new(ErrorCode::InvalidPayload, err);
+    let fit = FitMode::parse(payload.video.fit.as_deref()).map_err(invalid)?;
+    let pad = PadColor::parse(payload.video.pad_color.as_deref()).map_err(invalid)?;
+    command
+        .arg("-vf")
+        .arg(fit_filter(step.width, step.height, fit, pad));
+
+    command.arg("-r").arg(format!("{}", step.fps));
+
+    if let Some(codec) = payload.video.lossless_codec() {
+        command.args(lossless_codec_args(codec)?);
+        return Ok(command);
+    }
+    command.arg("-pix_fmt").arg("yuv420p");
+
+    let rate = rate_control(&payload.video)
+        .map_err(|err| CaptureError::new(ErrorCode::InvalidPayload, err))?;
     command
-        .arg("-r")
-        .arg(format!("{}", payload.video.fps))
-        .arg("-pix_fmt")
-        .arg("yuv420p")
-        .arg("-b:v")
-        .arg(bitrate.as_str())
-        .arg("-maxrate")
-        .arg(bitrate.as_str())
-        .arg("-bufsize")
-        .arg(format!("{}", payload.video.bitrate.saturating_mul(3)))
         .arg("-g")
-        .arg(format!("{}", (payload.video.fps.max(1)) * 2))
+        .arg(format!("{}", (step.fps.max(1)) * 2))
         .arg("-movflags")
-        .arg("+faststart")
+        .arg(RECORDING_MOVFLAGS)
         .arg("-c:v")
         .arg("libx264")
         .arg("-preset")
         .arg("ultrafast")
         .arg("-tune")
-        .arg("zerolatency");
+        .arg("zerolatency")
+        .args(rate_control_args("h264_libx264", &rate));
 
     Ok(command)
 }
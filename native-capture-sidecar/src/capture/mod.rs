@@ -1,13 +1,46 @@
 use serde_json::json;
-use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::protocol::{Response, StartCapturePayload, StopCapturePayload};
+use crate::adaptive::{Adaptation, AdaptiveEncoder};
+use crate::embedded::is_embedded_encoder;
+use crate::error::{CaptureError, ErrorCode};
+use crate::ffmpeg::{lossless_codec_args, rate_control};
+use crate::input::{
+    cursor_images_dir, cursor_track_path, telemetry_path, CursorTracker, InputSessionInfo,
+    InputTelemetry,
+};
+use crate::journal::{SessionJournal, SessionState};
+use crate::output::{
+    check_output_target, commit_output, faststart_remux, is_fragmented_mp4, is_mp4_family,
+    lossless_output_path, lossless_preflight, recording_path,
+};
+use crate::pipeline::{origin_epoch_ms, timestamps_path, OverlayEvent, OverlayFeed};
+use crate::protocol::{
+    InjectInputPayload, InjectedInputEvent, Response, StartCapturePayload, StopCapturePayload,
+};
+use crate::supervisor::{FfmpegProcess, Progress};
+use crate::system::kill_process;
+use crate::warnings::{fatal_warning, CaptureWarning};
 
+mod linux;
 mod macos;
+// gdigrab and WGC; the WGC half lives in crate::wgc, which only builds on Windows.
+#[cfg(target_os = "windows")]
 mod windows;
 
+// How long ffmpeg may take to flush after "q" on stop_capture before it is killed.
+const FFMPEG_STOP_GRACE: Duration = Duration::from_secs(8);
+// Part of the shutdown budget kept back for the side files once ffmpeg has exited.
+const SHUTDOWN_SIDE_FILE_MARGIN: Duration = Duration::from_millis(500);
+// The faststart remux at stop is a stream copy at disk speed.
+const REMUX_TIMEOUT: Duration = Duration::from_secs(90);
+// get_status reports a recording as degraded below this encode speed.
+const DEGRADED_SPEED: f64 = 0.9;
+
 pub struct SourceBounds {
     pub x: i32,
     pub y: i32,
@@ -16,7 +49,9 @@ pub struct SourceBounds {
 }
 
 pub enum CaptureBackend {
-    Ffmpeg(std::process::Child),
+    Ffmpeg(FfmpegProcess),
+    // Device capture that restarts ffmpeg with cheaper settings when encoding falls behind.
+    Adaptive(AdaptiveEncoder),
     #[cfg(target_os = "windows")]
     Wgc(crate::wgc::WgcCapture),
 }
@@ -30,23 +65,140 @@ pub struct ActiveCapture {
     pub started_at: Instant,
     pub platform: String,
     pub restore_cursor_on_stop: bool,
+    pub vfr: bool,
     pub source_bounds: Option<SourceBounds>,
+    pub input_telemetry: Option<InputTelemetry>,
+    pub cursor_tracker: Option<CursorTracker>,
+    pub journal: Option<SessionJournal>,
+    // Temp file the backend writes, renamed to output_path once the stop has finalized it.
+    // None when the backend writes output_path directly.
+    pub recording_path: Option<String>,
+    // Remuxes a fragmented MP4 recording into a faststart file at stop.
+    pub ffmpeg_exe: Option<String>,
+    // Whether that rename may replace a file that appeared at output_path meanwhile.
+    pub overwrite: bool,
+    // Set when the backend composites the burn-in overlay.
+    pub overlay_feed: Option<OverlayFeed>,
     pub backend: CaptureBackend,
 }
 
+impl ActiveCapture {
+    pub fn ffmpeg_pid(&self) -> Option<u32> {
+        match &self.backend {
+            CaptureBackend::Ffmpeg(ffmpeg) => Some(ffmpeg.id()),
+            CaptureBackend::Adaptive(encoder) => Some(encoder.id()),
+            #[cfg(target_os = "windows")]
+            CaptureBackend::Wgc(wgc_capture) => wgc_capture.ffmpeg_pid,
+        }
+    }
+
+    // The pid of whichever ffmpeg is working for the capture when it is read (0 for none);
+    // adaptive recordings move to a new one per segment and for the join at stop, and any
+    // recording to the faststart remux.
+    pub fn ffmpeg_pid_watch(&self) -> Arc<AtomicU32> {
+        match &self.backend {
+            CaptureBackend::Adaptive(encoder) => encoder.pid_watch(),
+            _ => Arc::new(AtomicU32::new(self.ffmpeg_pid().unwrap_or(0))),
+        }
+    }
+}
+
+// A running session as get_status reports it. `bytesWritten` is the size of the files the
+// backend is writing, so it lags behind what the encoder has buffered.
+pub fn capture_status(capture: &mut ActiveCapture) -> serde_json::Value {
+    let device = match capture.platform.as_str() {
+        "darwin" => "avfoundation",
+        "linux" => "x11grab",
+        _ => "gdigrab",
+    };
+    let recording = vec![capture
+        .recording_path
+        .clone()
+        .unwrap_or_else(|| capture.output_path.clone())];
+    let (backend, running, progress, warnings, files) = match &mut capture.backend {
+        CaptureBackend::Ffmpeg(ffmpeg) => {
+            (device, ffmpeg.running(), ffmpeg.progress(), ffmpeg.warnings(), recording)
+        }
+        CaptureBackend::Adaptive(encoder) => {
+            let status = encoder.status();
+            (device, encoder.running(), status.progress, status.warnings, status.segments)
+        }
+        #[cfg(target_os = "windows")]
+        CaptureBackend::Wgc(wgc_capture) => {
+            ("wgc", crate::wgc::running(wgc_capture), None, Vec::new(), recording)
+        }
+    };
+    let bytes_written: u64 = files
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    json!({
+        "sessionId": capture.session_id,
+        "state": "recording",
+        "platform": capture.platform,
+        "backend": backend,
+        "adaptive": matches!(capture.backend, CaptureBackend::Adaptive(_)),
+        "outputPath": capture.output_path,
+        "elapsedMs": capture.started_at.elapsed().as_millis() as u64,
+        "bytesWritten": bytes_written,
+        "health": health(running, progress.as_ref(), &warnings),
+    })
+}
+
+// `healthy`, `degraded` (behind real time, or a warning-level condition was seen),
+// `failing` (a condition that ends the recording was seen) or `exited` (the encoder is
+// gone; stop_capture reports why). WGC has no progress counters, so only exits show.
+fn health(running: bool, progress: Option<&Progress>, warnings: &[CaptureWarning]) -> serde_json::Value {
+    let speed = progress.and_then(|progress| progress.speed);
+    let state = if !running {
+        "exited"
+    } else if fatal_warning(warnings).is_some() {
+        "failing"
+    } else if speed.is_some_and(|speed| speed < DEGRADED_SPEED)
+        || warnings.iter().any(|warning| warning.severity == "warning")
+    {
+        "degraded"
+    } else {
+        "healthy"
+    };
+    json!({
+        "state": state,
+        "frames": progress.map(|progress| progress.frame),
+        "encodeFps": progress.map(|progress| progress.fps),
+        "speed": speed,
+        "droppedFrames": progress.map(|progress| progress.drop_frames),
+        "duplicatedFrames": progress.map(|progress| progress.dup_frames),
+        "warnings": warnings
+            .iter()
+            .map(|warning| json!({ "kind": warning.kind, "severity": warning.severity, "count": warning.count }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+// `cancelled` is set by `cancel`; it is checked between encoder attempts and once the
+// backend is up, so a cancelled start does not go on to the next encoder or the hooks.
 pub fn handle_start(
     id: String,
     payload: serde_json::Value,
     active_capture: &mut Option<ActiveCapture>,
+    cancelled: &AtomicBool,
 ) -> Response {
-    if active_capture.is_some() {
-        return Response::err(id, "capture already running");
+    let requested_at = Instant::now();
+    if let Some(capture) = active_capture.as_ref() {
+        return Response::err(
+            id,
+            CaptureError::new(ErrorCode::AlreadyRunning, "capture already running")
+                .with_details(json!({ "sessionId": capture.session_id })),
+        );
     }
 
-    let start_payload: StartCapturePayload = match serde_json::from_value(payload) {
+    // Journaled as sent, so recovery reports the settings the app asked for.
+    let settings = payload.get("video").cloned().unwrap_or_default();
+    let mut start_payload: StartCapturePayload = match serde_json::from_value(payload) {
         Ok(v) => v,
         Err(err) => {
-            return Response::err(id, format!("invalid start_capture payload: {err}"));
+            return Response::err(id, CaptureError::invalid_payload("start_capture", err));
         }
     };
 
@@ -54,56 +206,374 @@ pub fn handle_start(
         || start_payload.video.height == 0
         || start_payload.video.fps == 0
     {
-        return Response::err(id, "invalid video dimensions/fps");
+        return Response::err(
+            id,
+            CaptureError::new(ErrorCode::InvalidPayload, "invalid video dimensions/fps"),
+        );
+    }
+
+    let mut lossless = None;
+    if let Some(codec) = start_payload.video.lossless_codec() {
+        if let Err(message) = lossless_codec_args(codec) {
+            return Response::err(id, CaptureError::new(ErrorCode::InvalidPayload, message));
+        }
+        if is_embedded_encoder(&start_payload.video.encoder) {
+            return Response::err(
+                id,
+                CaptureError::new(
+                    ErrorCode::InvalidPayload,
+                    format!("{} does not support lossless quality", start_payload.video.encoder),
+                ),
+            );
+        }
+        lossless = Some(codec.to_string());
+        start_payload.output_path = lossless_output_path(&start_payload.output_path);
+    } else if let Err(message) = rate_control(&start_payload.video) {
+        return Response::err(id, CaptureError::new(ErrorCode::InvalidPayload, message));
+    }
+
+    if let Err(err) = check_output_target(&start_payload.output_path, start_payload.overwrite) {
+        return Response::err(id, err);
     }
+    // Probed while the backend starts, so the disk test does not delay the recording.
+    let preflight = lossless.map(|codec| {
+        let output_path = start_payload.output_path.clone();
+        let video = &start_payload.video;
+        let (width, height, fps) = (video.width, video.height, video.fps);
+        std::thread::spawn(move || lossless_preflight(&output_path, width, height, fps, &codec))
+    });
+    // Backends record into the temp file; the output path only ever holds a finished file.
+    let temp_path = recording_path(&start_payload.output_path, &start_payload.session_id);
+    let output_path = std::mem::replace(&mut start_payload.output_path, temp_path);
 
-    let start_result = match start_payload.platform.as_str() {
-        "win32" => windows::start_capture(start_payload),
-        "darwin" => macos::start_capture(start_payload),
-        platform => Err(format!("unsupported platform for native capture: {platform}")),
+    let write_input_telemetry = start_payload.input_telemetry;
+    let overlay_feed = start_payload.burn_in.as_ref().map(|_| OverlayFeed::new(requested_at));
+    let input_session = InputSessionInfo {
+        session_id: start_payload.session_id.clone(),
+        source_id: start_payload.source.id.clone(),
+        source_display_id: start_payload.source.display_id.clone(),
+        source_bounds: start_payload
+            .capture_region
+            .as_ref()
+            .map(|region| (region.x, region.y, region.width, region.height)),
     };
 
+    let track_cursor = start_payload.cursor.track_enabled();
+    let overwrite = start_payload.overwrite;
+    let ffmpeg_path = start_payload.ffmpeg_path.clone();
+    let mut video = start_payload.video.clone();
+
+    let (start_result, encoder, encoder_fallbacks) =
+        start_with_fallback(start_payload, requested_at, overlay_feed, cancelled);
+
     match start_result {
-        Ok(capture) => {
+        Ok(mut capture) => {
+            capture.recording_path = Some(std::mem::replace(&mut capture.output_path, output_path));
+            capture.overwrite = overwrite;
+            if cancelled.load(Ordering::SeqCst) {
+                abort_capture(capture);
+                return Response::err(id, start_cancelled());
+            }
+            // The hooks also run for burn-in alone, to drive the overlay.
+            let overlay_feed = capture.overlay_feed.clone();
+            let mut input_telemetry_error: Option<String> = None;
+            if write_input_telemetry || overlay_feed.is_some() {
+                let mut info = input_session;
+                if info.source_bounds.is_none() {
+                    info.source_bounds = capture
+                        .source_bounds
+                        .as_ref()
+                        .map(|bounds| (bounds.x, bounds.y, bounds.width, bounds.height));
+                }
+                // Telemetry is best effort: a missing hook never fails the recording.
+                capture.input_telemetry = match InputTelemetry::start(
+                    info,
+                    requested_at,
+                    write_input_telemetry,
+                    overlay_feed,
+                ) {
+                    Ok(telemetry) => Some(telemetry),
+                    Err(err) => {
+                        log!("[native-capture][input] telemetry unavailable: {err}");
+                        input_telemetry_error = write_input_telemetry.then_some(err);
+                        None
+                    }
+                };
+            }
+            if track_cursor {
+                capture.cursor_tracker = match CursorTracker::start(requested_at, capture.fps) {
+                    Ok(tracker) => Some(tracker),
+                    Err(err) => {
+                        log!("[native-capture][cursor] track unavailable: {err}");
+                        None
+                    }
+                };
+            }
+            let mut journal = SessionJournal::new(
+                &capture.session_id,
+                capture.ffmpeg_pid(),
+                ffmpeg_path,
+                &capture.output_path,
+                &capture.platform,
+                &encoder,
+                settings,
+                origin_epoch_ms(requested_at).round() as u64,
+            );
+            journal.recording_path = capture.recording_path.clone();
+            journal.write();
+            capture.journal = Some(journal);
             let output_path = capture.output_path.clone();
+            let frame_rate_mode = if capture.vfr { "vfr" } else { "cfr" };
+            let input_telemetry_active = write_input_telemetry && capture.input_telemetry.is_some();
+            let burn_in_active = capture.overlay_feed.is_some();
+            let cursor_track_active = capture.cursor_tracker.is_some();
+            // Resolved again for the encoder that actually started.
+            video.encoder = encoder.clone();
+            let rate = video
+                .lossless_codec()
+                .is_none()
+                .then(|| rate_control(&video).ok())
+                .flatten();
             *active_capture = Some(capture);
+            let preflight = preflight.and_then(|probe| probe.join().ok());
             Response::ok(
                 id,
                 json!({
                     "status": "recording",
                     "outputPath": output_path,
+                    "frameRateMode": frame_rate_mode,
+                    "preflight": preflight,
+                    "encoderUsed": encoder,
+                    "encoderFallbacks": encoder_fallbacks,
+                    "rateControl": rate,
+                    "inputTelemetry": input_telemetry_active,
+                    "inputTelemetryUnavailable": input_telemetry_error,
+                    "cursorTrack": cursor_track_active,
+                    "burnIn": burn_in_active,
                 }),
             )
         }
-        Err(message) => Response::err(id, message),
+        Err(err) => Response::err(id, err),
     }
 }
 
+// Starts the backend with `video.encoder`, then with each of `video.fallbackEncoders` for
+// as long as it is the encoder that fails while ffmpeg starts up. Returns the encoder of
+// the last attempt and why each earlier one was given up.
+#[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+fn start_with_fallback(
+    start_payload: StartCapturePayload,
+    requested_at: Instant,
+    overlay_feed: Option<OverlayFeed>,
+    cancelled: &AtomicBool,
+) -> (Result<ActiveCapture, CaptureError>, String, Vec<serde_json::Value>) {
+    let chain = start_payload.video.encoder_chain();
+    let mut fallbacks = Vec::new();
+    let mut index = 0;
+    loop {
+        let encoder = &chain[index];
+        if index > 0 && cancelled.load(Ordering::SeqCst) {
+            return (Err(start_cancelled()), encoder.clone(), fallbacks);
+        }
+        let mut payload = start_payload.clone();
+        payload.video.encoder = encoder.clone();
+        let result = match payload.platform.as_str() {
+            #[cfg(target_os = "windows")]
+            "win32" => windows::start_capture(payload, requested_at, overlay_feed.clone()),
+            "darwin" => macos::start_capture(payload),
+            "linux" => linux::start_capture(payload),
+            platform => Err(CaptureError::new(
+                ErrorCode::UnsupportedPlatform,
+                format!("unsupported platform for native capture: {platform}"),
+            )),
+        };
+        let err = match result {
+            Err(err) if index + 1 < chain.len() && encoder_failed_to_start(&err) => err,
+            Err(mut err) if !fallbacks.is_empty() => {
+                let details = err.details.get_or_insert_with(|| json!({}));
+                if let Some(details) = details.as_object_mut() {
+                    details.insert("encoderFallbacks".to_string(), json!(fallbacks));
+                }
+                return (Err(err), encoder.clone(), fallbacks);
+            }
+            result => return (result, encoder.clone(), fallbacks),
+        };
+        log!(
+            "[native-capture][start] encoder={} failed to start, falling back to {} code={:?} err={}",
+            encoder,
+            chain[index + 1],
+            err.code,
+            err.message
+        );
+        fallbacks.push(json!({ "encoder": encoder, "code": err.code, "reason": err.message }));
+        index += 1;
+    }
+}
+
+fn start_cancelled() -> CaptureError {
+    CaptureError::new(ErrorCode::Cancelled, "start_capture was cancelled")
+}
+
+// Failures another encoder might not hit: the encoder could not be opened, or ffmpeg
+// exited (or never produced a frame) for a reason nothing more specific explains.
+fn encoder_failed_to_start(err: &CaptureError) -> bool {
+    matches!(err.code, ErrorCode::EncoderUnavailable | ErrorCode::FfmpegFailed)
+}
+
+// `ffmpeg_pid` is the capture's pid watch when a shutdown may have to kill the stop.
 pub fn handle_stop(
     id: String,
     payload: serde_json::Value,
     active_capture: &mut Option<ActiveCapture>,
+    ffmpeg_pid: Option<Arc<AtomicU32>>,
 ) -> Response {
     let stop_payload: StopCapturePayload = match serde_json::from_value(payload) {
         Ok(v) => v,
         Err(err) => {
-            return Response::err(id, format!("invalid stop_capture payload: {err}"));
+            return Response::err(id, CaptureError::invalid_payload("stop_capture", err));
         }
     };
 
     let capture = match active_capture.take() {
         Some(v) => v,
         None => {
-            return Response::err(id, "capture is not running");
+            return Response::err(id, CaptureError::not_running());
         }
     };
 
     if capture.session_id != stop_payload.session_id {
+        let err = CaptureError::session_mismatch(&capture.session_id, &stop_payload.session_id);
         *active_capture = Some(capture);
-        return Response::err(id, "sessionId mismatch");
+        return Response::err(id, err);
+    }
+
+    if stop_payload.finalize == Some(false) {
+        return Response::ok(id, abort_capture(capture));
+    }
+    match finalize_capture(capture, FFMPEG_STOP_GRACE, None, ffmpeg_pid) {
+        Ok(result) => Response::ok(id, result),
+        Err(err) => Response::err(id, err),
+    }
+}
+
+// stop_capture with `finalize: false`: the encoder is killed without flushing, nothing is
+// padded or written, and the partial output and any side files are deleted.
+pub fn abort_capture(capture: ActiveCapture) -> serde_json::Value {
+    let ActiveCapture {
+        session_id,
+        output_path,
+        started_at,
+        platform,
+        restore_cursor_on_stop,
+        input_telemetry,
+        cursor_tracker,
+        journal,
+        recording_path,
+        backend,
+        ..
+    } = capture;
+    log!(
+        "[native-capture][stop] aborting session={} output={}",
+        session_id,
+        output_path
+    );
+
+    let mut ffmpeg_exit: Option<serde_json::Value> = None;
+    let mut deleted_segments = Vec::new();
+    match backend {
+        CaptureBackend::Ffmpeg(ffmpeg) => ffmpeg_exit = Some(ffmpeg.abort().to_json()),
+        CaptureBackend::Adaptive(encoder) => deleted_segments = encoder.abort(),
+        #[cfg(target_os = "windows")]
+        CaptureBackend::Wgc(wgc_capture) => crate::wgc::abort(wgc_capture),
+    }
+    let duration_ms = started_at.elapsed().as_millis() as u64;
+
+    if let Some(telemetry) = input_telemetry {
+        telemetry.discard();
+    }
+    if let Some(tracker) = cursor_tracker {
+        tracker.discard();
+    }
+    if platform == "darwin" && restore_cursor_on_stop {
+        macos::restore_cursor_visibility();
+    }
+
+    // Side files are only written at finalize, so a temp recording is all there is.
+    let mut deleted_files = match &recording_path {
+        Some(recording_path) => delete_files(&[PathBuf::from(recording_path)]),
+        None => delete_capture_files(&output_path),
+    };
+    deleted_files.extend(deleted_segments);
+    if let Some(journal) = &journal {
+        journal.remove();
     }
+    log!(
+        "[native-capture][stop] aborted session={} deleted={}",
+        session_id,
+        deleted_files.len()
+    );
 
+    json!({
+        "status": "aborted",
+        "sessionId": session_id,
+        "outputPath": output_path,
+        "durationMs": duration_ms,
+        "deletedFiles": deleted_files,
+        "ffmpegExit": ffmpeg_exit,
+    })
+}
+
+// Removes the output and every side file derived from its path; returns what was deleted.
+fn delete_capture_files(output_path: &str) -> Vec<String> {
+    let mut deleted = delete_files(&[
+        PathBuf::from(output_path),
+        timestamps_path(output_path),
+        telemetry_path(output_path),
+        cursor_track_path(output_path),
+    ]);
+    let cursors = cursor_images_dir(output_path);
+    if cursors.is_dir() {
+        match std::fs::remove_dir_all(&cursors) {
+            Ok(()) => deleted.push(cursors.to_string_lossy().into_owned()),
+            Err(err) => log!(
+                "[native-capture][stop] failed to delete path={} err={}",
+                cursors.display(),
+                err
+            ),
+        }
+    }
+    deleted
+}
+
+fn delete_files(files: &[PathBuf]) -> Vec<String> {
+    let mut deleted = Vec::new();
+    for path in files {
+        match std::fs::remove_file(path) {
+            Ok(()) => deleted.push(path.to_string_lossy().into_owned()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => log!(
+                "[native-capture][stop] failed to delete path={} err={}",
+                path.display(),
+                err
+            ),
+        }
+    }
+    deleted
+}
+
+// Stops the backend and writes the side files of a capture; shared by stop_capture and
+// shutdown. ffmpeg gets `ffmpeg_grace` to finish after "q" before the supervisor escalates.
+// The input hooks are stopped first, so a failed stop cannot leave their threads running.
+// A shutdown passes its `deadline` and the pid watch it kills on timeout, which the
+// faststart remux has to fit into and report to.
+fn finalize_capture(
+    capture: ActiveCapture,
+    ffmpeg_grace: Duration,
+    deadline: Option<Instant>,
+    ffmpeg_pid: Option<Arc<AtomicU32>>,
+) -> Result<serde_json::Value, CaptureError> {
     let ActiveCapture {
+        session_id,
         output_path,
         width,
         height,
@@ -111,50 +581,157 @@ pub fn handle_stop(
         started_at,
         platform,
         restore_cursor_on_stop,
+        vfr,
         source_bounds,
+        input_telemetry,
+        cursor_tracker,
+        mut journal,
+        recording_path,
+        ffmpeg_exe,
+        overwrite,
         backend,
         ..
     } = capture;
+    if let Some(journal) = journal.as_mut() {
+        journal.set_state(SessionState::Stopping, None);
+    }
 
-    let mut duration_ms = started_at.elapsed().as_millis() as u64;
-    let mut bytes = std::fs::metadata(&output_path)
-        .map(|m| m.len())
-        .unwrap_or(0);
+    let recorded_path = recording_path.as_deref().unwrap_or(&output_path);
 
-    match backend {
-        CaptureBackend::Ffmpeg(mut child) => {
-            if let Some(stdin) = child.stdin.as_mut() {
-                let _ = stdin.write_all(b"q\n");
-                let _ = stdin.flush();
+    let mut input_telemetry_path: Option<String> = None;
+    let mut input_telemetry_stats: Option<serde_json::Value> = None;
+    if let Some(telemetry) = input_telemetry {
+        let path = telemetry_path(&output_path);
+        match telemetry.finish(&path) {
+            Ok(None) => {}
+            Ok(Some(stats)) => {
+                input_telemetry_path = Some(path.to_string_lossy().into_owned());
+                input_telemetry_stats = Some(stats);
             }
+            Err(err) => log!("[native-capture][input] failed to write telemetry: {err}"),
+        }
+    }
 
-            let wait_deadline = Instant::now() + Duration::from_secs(8);
-            loop {
-                match child.try_wait() {
-                    Ok(Some(_status)) => break,
-                    Ok(None) => {
-                        if Instant::now() >= wait_deadline {
-                            let _ = child.kill();
-                            let _ = child.wait();
-                            break;
-                        }
-                        thread::sleep(Duration::from_millis(60));
-                    }
-                    Err(_) => break,
-                }
+    let mut cursor_track_path: Option<String> = None;
+    let mut cursor_shapes: Option<u64> = None;
+    if let Some(tracker) = cursor_tracker {
+        let bounds = source_bounds
+            .as_ref()
+            .map(|bounds| (bounds.x, bounds.y, bounds.width, bounds.height));
+        match tracker.finish(&output_path, bounds) {
+            Ok(summary) => {
+                cursor_track_path = summary["path"].as_str().map(str::to_string);
+                cursor_shapes = summary["shapes"].as_u64();
+            }
+            Err(err) => log!("[native-capture][cursor] failed to write track: {err}"),
+        }
+    }
+
+    let duration_ms = started_at.elapsed().as_millis() as u64;
+    let mut stopped = match backend {
+        CaptureBackend::Ffmpeg(ffmpeg) => {
+            let exit = ffmpeg.stop(ffmpeg_grace);
+            if let Err(err) = exit.check() {
+                return Err(stop_failed(&mut journal, recorded_path, err));
+            }
+            BackendStop {
+                bytes: file_len(recorded_path),
+                ffmpeg_exit: Some(exit.to_json()),
+                warnings: exit.warnings,
+                ..BackendStop::default()
+            }
+        }
+        CaptureBackend::Adaptive(encoder) => {
+            let mut result = encoder.stop(ffmpeg_grace);
+            let exit = result.exit.take();
+            let checked = exit.as_ref().map_or(Ok(()), |exit| exit.check());
+            if let Err(err) = checked.and_then(|()| result.check()) {
+                return Err(stop_failed(&mut journal, recorded_path, err));
+            }
+            BackendStop {
+                bytes: file_len(recorded_path),
+                ffmpeg_exit: exit.as_ref().map(|exit| exit.to_json()),
+                warnings: result.warnings,
+                adaptations: result.adaptations,
+                ..BackendStop::default()
             }
         }
         #[cfg(target_os = "windows")]
         CaptureBackend::Wgc(wgc_capture) => {
-            let result = match crate::wgc::stop(wgc_capture) {
+            let result = match crate::wgc::stop(wgc_capture, &output_path) {
                 Ok(result) => result,
-                Err(err) => return Response::err(id, err),
+                Err(err) => return Err(stop_failed(&mut journal, recorded_path, err)),
             };
-            duration_ms = result.duration_ms;
-            bytes = result.bytes;
+            let timing = result.frame_timing.as_ref();
+            BackendStop {
+                bytes: result.bytes,
+                duration_ms: Some(result.duration_ms),
+                fps_actual: timing.and_then(|timing| timing.effective_fps()),
+                frames_encoded: Some(result.frames_encoded),
+                frames_duplicated: Some(result.frames_duplicated),
+                frame_timing: timing.map(|timing| {
+                    json!({
+                        "frames": timing.frames,
+                        "spanMs": timing.span().as_secs_f64() * 1000.0,
+                        "meanIntervalMs": timing.mean_interval_ms(),
+                        "minIntervalMs": timing.min_interval.map(|d| d.as_secs_f64() * 1000.0),
+                        "maxIntervalMs": timing.max_interval.map(|d| d.as_secs_f64() * 1000.0),
+                    })
+                }),
+                timestamps_path: result.timestamps_path,
+                frame0_epoch_ms: result.frame0_epoch_ms,
+                first_frame_latency_ms: result.first_frame_latency_ms,
+                static_segments: result.static_segments.map(|segments| json!(segments)),
+                ffmpeg_exit: None,
+                warnings: result.warnings,
+                adaptations: result.adaptations,
+            }
+        }
+    };
+
+    // Recorded fragmented so a crash still leaves a playable file; delivered as faststart.
+    // A recording the remux fails on is delivered as it is.
+    let mut faststart: Option<bool> = None;
+    if let (Some(recording_path), Some(ffmpeg_exe)) = (&recording_path, &ffmpeg_exe) {
+        let timeout = deadline.map_or(REMUX_TIMEOUT, |deadline| {
+            deadline
+                .saturating_duration_since(Instant::now())
+                .saturating_sub(SHUTDOWN_SIDE_FILE_MARGIN)
+                .min(REMUX_TIMEOUT)
+        });
+        if is_mp4_family(recording_path)
+            && !timeout.is_zero()
+            && is_fragmented_mp4(recording_path).unwrap_or(false)
+        {
+            let remuxed = faststart_remux(
+                ffmpeg_exe,
+                recording_path,
+                &session_id,
+                timeout,
+                ffmpeg_pid.as_deref(),
+            );
+            match &remuxed {
+                Ok(()) => stopped.bytes = file_len(recording_path),
+                Err(err) => log!(
+                    "[native-capture][stop] keeping the fragmented recording: {}",
+                    err.message
+                ),
+            }
+            faststart = Some(remuxed.is_ok());
+        }
+    }
+
+    if let Some(recording_path) = &recording_path {
+        if let Err(err) = commit_output(recording_path, &output_path, overwrite) {
+            return Err(stop_failed(&mut journal, recording_path, err));
         }
     }
 
+    // The output is final; only the side files are left.
+    if let Some(journal) = &journal {
+        journal.remove();
+    }
+
     if platform == "darwin" && restore_cursor_on_stop {
         macos::restore_cursor_visibility();
     }
@@ -168,16 +745,197 @@ pub fn handle_stop(
         })
     });
 
-    Response::ok(
-        id,
-        json!({
-            "outputPath": output_path,
-            "durationMs": duration_ms,
-            "width": width,
-            "height": height,
-            "fpsActual": fps,
-            "bytes": bytes,
-            "sourceBounds": source_bounds,
-        }),
-    )
+    Ok(json!({
+        "status": "finalized",
+        "outputPath": output_path,
+        "durationMs": stopped.duration_ms.unwrap_or(duration_ms),
+        "width": width,
+        "height": height,
+        "fpsActual": stopped.fps_actual.unwrap_or(fps as f64),
+        "bytes": stopped.bytes,
+        "sourceBounds": source_bounds,
+        "frameRateMode": if vfr { "vfr" } else { "cfr" },
+        "faststart": faststart,
+        "framesEncoded": stopped.frames_encoded,
+        "framesDuplicated": stopped.frames_duplicated,
+        "frameTiming": stopped.frame_timing,
+        "timestampsPath": stopped.timestamps_path,
+        "frame0EpochMs": stopped.frame0_epoch_ms,
+        "firstFrameLatencyMs": stopped.first_frame_latency_ms,
+        "staticSegments": stopped.static_segments,
+        "inputTelemetryPath": input_telemetry_path,
+        "inputTelemetryStats": input_telemetry_stats,
+        "cursorTrackPath": cursor_track_path,
+        "cursorShapes": cursor_shapes,
+        "ffmpegExit": stopped.ffmpeg_exit,
+        "warnings": stopped.warnings,
+        "adaptations": stopped.adaptations,
+    }))
+}
+
+// What a backend reports once stopped. The frame figures only come from WGC, which sees
+// every frame; for ffmpeg-captured inputs the stop falls back to the wall clock.
+#[derive(Default)]
+struct BackendStop {
+    bytes: u64,
+    duration_ms: Option<u64>,
+    fps_actual: Option<f64>,
+    frames_encoded: Option<u64>,
+    frames_duplicated: Option<u64>,
+    frame_timing: Option<serde_json::Value>,
+    timestamps_path: Option<String>,
+    frame0_epoch_ms: Option<u64>,
+    first_frame_latency_ms: Option<f64>,
+    static_segments: Option<serde_json::Value>,
+    ffmpeg_exit: Option<serde_json::Value>,
+    warnings: Vec<CaptureWarning>,
+    adaptations: Vec<Adaptation>,
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// Marks the journal failed and says in the error where the recording was left, so the app
+// can point the user at it or call recover_session.
+fn stop_failed(
+    journal: &mut Option<SessionJournal>,
+    recorded_path: &str,
+    mut err: CaptureError,
+) -> CaptureError {
+    if let Some(journal) = journal.as_mut() {
+        journal.set_state(SessionState::Failed, Some(err.message.clone()));
+    }
+    match err.details.as_mut().and_then(serde_json::Value::as_object_mut) {
+        Some(details) => {
+            details.insert("recordingPath".to_string(), json!(recorded_path));
+        }
+        None => err.details = Some(json!({ "recordingPath": recorded_path })),
+    }
+    err
+}
+
+// Finalizes every capture that is still running when the sidecar has to exit (stdin
+// closed, a termination signal or the shutdown command). The stop runs on its own thread
+// so a wedged backend cannot hold the process past `deadline`; a capture that misses it
+// is reported as "timedOut".
+pub fn shutdown_captures(
+    active_capture: &mut Option<ActiveCapture>,
+    deadline: Instant,
+) -> Vec<serde_json::Value> {
+    let Some(capture) = active_capture.take() else {
+        return Vec::new();
+    };
+    let session_id = capture.session_id.clone();
+    let output_path = capture.output_path.clone();
+    let ffmpeg_grace = deadline
+        .saturating_duration_since(Instant::now())
+        .saturating_sub(SHUTDOWN_SIDE_FILE_MARGIN)
+        .min(FFMPEG_STOP_GRACE);
+    log!(
+        "[native-capture][shutdown] finalizing session={} output={} ffmpeg_grace_ms={}",
+        session_id,
+        output_path,
+        ffmpeg_grace.as_millis()
+    );
+
+    let ffmpeg_pid = capture.ffmpeg_pid_watch();
+    let (tx, rx) = mpsc::channel();
+    let pid_watch = Arc::clone(&ffmpeg_pid);
+    thread::spawn(move || {
+        let _ = tx.send(finalize_capture(capture, ffmpeg_grace, Some(deadline), Some(pid_watch)));
+    });
+    let (status, result, error) = match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(Ok(result)) => ("finalized", Some(result), None),
+        Ok(Err(err)) => ("failed", None, Some(err)),
+        Err(_) => {
+            let err = CaptureError::from("capture did not finalize before the shutdown deadline");
+            abandon_capture(&session_id, Some(&ffmpeg_pid), &err.message);
+            ("timedOut", None, Some(err))
+        }
+    };
+    log!(
+        "[native-capture][shutdown] session={} status={} error={}",
+        session_id,
+        status,
+        error.as_ref().map_or("none", |err| err.message.as_str())
+    );
+    vec![json!({
+        "sessionId": session_id,
+        "outputPath": output_path,
+        "status": status,
+        "result": result,
+        "error": error,
+    })]
+}
+
+// Last resort for a capture whose stop overran the shutdown: the sidecar is about to exit
+// under it, so its ffmpeg is killed rather than orphaned and the journal is marked failed
+// for recover_session to salvage the recording.
+pub fn abandon_capture(session_id: &str, ffmpeg_pid: Option<&AtomicU32>, reason: &str) {
+    let pid = ffmpeg_pid
+        .map(|pid| pid.load(Ordering::SeqCst))
+        .filter(|pid| *pid != 0);
+    let killed = pid.is_some_and(kill_process);
+    if let Ok(mut journal) = SessionJournal::load(session_id) {
+        journal.set_state(SessionState::Failed, Some(reason.to_string()));
+    }
+    log!(
+        "[native-capture][shutdown] abandoned session={} ffmpeg_pid={:?} killed={}",
+        session_id,
+        pid,
+        killed
+    );
+}
+
+// Feeds clicks and shortcuts from the app's own input hooks into the burn-in overlay,
+// for platforms where the sidecar has no hook of its own (or for scripted tests).
+pub fn handle_inject_input(
+    id: String,
+    payload: serde_json::Value,
+    active_capture: &mut Option<ActiveCapture>,
+) -> Response {
+    let inject_payload: InjectInputPayload = match serde_json::from_value(payload) {
+        Ok(v) => v,
+        Err(err) => {
+            return Response::err(id, CaptureError::invalid_payload("inject_input_events", err));
+        }
+    };
+    let Some(capture) = active_capture.as_ref() else {
+        return Response::err(id, CaptureError::not_running());
+    };
+    if capture.session_id != inject_payload.session_id {
+        return Response::err(
+            id,
+            CaptureError::session_mismatch(&capture.session_id, &inject_payload.session_id),
+        );
+    }
+    let Some(feed) = capture.overlay_feed.as_ref() else {
+        return Response::err(
+            id,
+            CaptureError::new(ErrorCode::NotRunning, "burn-in overlay is not active for this capture"),
+        );
+    };
+
+    let now = Instant::now();
+    let mut accepted = 0;
+    for event in inject_payload.events {
+        let (ts, event) = match event {
+            InjectedInputEvent::MouseDown { ts, x, y } => (ts, OverlayEvent::Click { x, y }),
+            InjectedInputEvent::KeyDownCategory { ts, category, label }
+                if category == "shortcut" =>
+            {
+                let label = label
+                    .filter(|label| !label.trim().is_empty())
+                    .unwrap_or_else(|| "SHORTCUT".to_string());
+                (ts, OverlayEvent::Shortcut { label })
+            }
+            _ => continue,
+        };
+        // Without `ts` the event is drawn as of its arrival.
+        let at = ts.map_or(now, |ts| feed.instant_at(ts, now));
+        feed.push(at, event);
+        accepted += 1;
+    }
+    Response::ok(id, json!({ "accepted": accepted }))
 }
@@ -1,25 +1,95 @@
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use serde_json::json;
+use std::process::Command;
 use std::time::{Duration, Instant};
 
+use crate::adaptive::{with_preset, AdaptiveEncoder, AdaptiveOptions, AdaptivePolicy, EncodeStep};
 use crate::capture::{ActiveCapture, CaptureBackend, SourceBounds};
-use crate::ffmpeg::resolve_ffmpeg_path;
+use crate::discovery::discover_ffmpeg;
+use crate::embedded::{embedded_encoder_available, is_embedded_encoder};
+use crate::error::{CaptureError, ErrorCode};
+use crate::ffmpeg::{
+    encoder_codec_args, ffmpeg_encoder_name, fit_filter, lossless_codec_args, rate_control,
+    rate_control_args, RECORDING_MOVFLAGS,
+};
+use crate::pipeline::{
+    BurnInSettings, FitMode, OverlayFeed, PadColor, PipelineOptions, ResampleFilter,
+    StaticDetectionSettings,
+};
 use crate::protocol::StartCapturePayload;
+use crate::supervisor::{ffmpeg_command, FfmpegProcess, Readiness};
 
-pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture, String> {
-    if !cfg!(target_os = "windows") {
-        return Err("win32 capture requested, but sidecar binary is not a windows build".to_string());
-    }
+// gdigrab delivers frames at once, so no progress by then means the grab is stuck.
+const GDIGRAB_READY_TIMEOUT: Duration = Duration::from_secs(5);
 
-    let ffmpeg_exe = resolve_ffmpeg_path(start_payload.ffmpeg_path.as_deref());
-    if ffmpeg_exe.is_none() {
-        return Err(
-            "ffmpeg executable not found (bundle native-capture-sidecar/bin/win32/ffmpeg.exe or install ffmpeg on PATH)".to_string(),
-        );
+pub fn start_capture(
+    start_payload: StartCapturePayload,
+    requested_at: Instant,
+    overlay_feed: Option<OverlayFeed>,
+) -> Result<ActiveCapture, CaptureError> {
+    // The built-in encoder runs in-process on the WGC path, so ffmpeg is optional for it.
+    let embedded = is_embedded_encoder(&start_payload.video.encoder);
+    if embedded && !embedded_encoder_available() {
+        return Err(CaptureError::new(
+            ErrorCode::EncoderUnavailable,
+            format!(
+                "encoder {} is not available in this build (embedded-encoder feature disabled)",
+                start_payload.video.encoder
+            ),
+        )
+        .with_details(json!({ "encoder": start_payload.video.encoder })));
     }
-    let ffmpeg_exe = ffmpeg_exe.unwrap_or_else(|| "ffmpeg.exe".to_string());
+    let discovery = discover_ffmpeg(
+        start_payload.ffmpeg_path.as_deref(),
+        ffmpeg_encoder_name(&start_payload.video).as_deref(),
+    );
+    let ffmpeg_exe = if embedded {
+        discovery.path().unwrap_or_else(|| "ffmpeg.exe".to_string())
+    } else {
+        discovery.check(
+            "bundle native-capture-sidecar/bin/win32/ffmpeg.exe, set VELOCITY_FFMPEG_PATH or install ffmpeg on PATH",
+        )?
+    };
+    let vfr = start_payload.video.frame_rate_mode.as_deref() == Some("vfr");
+    let static_detection = start_payload
+        .static_detection
+        .as_ref()
+        .map(|config| StaticDetectionSettings {
+            threshold: config.threshold.unwrap_or(0.002).clamp(0.0, 1.0),
+            min_duration: Duration::from_millis(config.min_duration_ms.unwrap_or(2_000)),
+        });
+    let invalid = |err: String| CaptureError::new(ErrorCode::InvalidPayload, err);
+    let rate_control = match start_payload.video.lossless_codec() {
+        Some(_) => None,
+        None => Some(rate_control(&start_payload.video).map_err(invalid)?),
+    };
+    let bitrate = rate_control
+        .as_ref()
+        .map_or(start_payload.video.bitrate, |rate| rate.bitrate);
+    let scale_filter =
+        ResampleFilter::parse(start_payload.video.scale_filter.as_deref()).map_err(invalid)?;
+    let mut pipeline = PipelineOptions {
+        session_id: start_payload.session_id.clone(),
+        vfr,
+        requested_at: Some(requested_at),
+        static_detection,
+        lossless: start_payload.video.lossless_codec().map(str::to_string),
+        scale_filter,
+        fit: FitMode::parse(start_payload.video.fit.as_deref()).map_err(invalid)?,
+        pad_color: PadColor::parse(start_payload.video.pad_color.as_deref()).map_err(invalid)?,
+        burn_in: None,
+        adaptive_quality: start_payload.video.adaptive_quality().cloned(),
+        rate_control,
+    };
+    let burn_in = |screen_origin: (i32, i32)| {
+        let feed = overlay_feed.clone()?;
+        let config = start_payload.burn_in.as_ref()?;
+        Some(BurnInSettings {
+            feed,
+            clicks: config.clicks.unwrap_or(true),
+            keys: config.keys.unwrap_or(true),
+            screen_origin,
+        })
+    };
 
     if start_payload.source.source_type == "screen" {
         let region = start_payload.capture_region.as_ref();
@@ -39,7 +109,9 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
         let out_h = out_h.max(2) & !1;
         let hide_cursor = start_payload.cursor.mode == "hide";
         let encoder_args = build_encoder_args(&start_payload.video.encoder);
-        eprintln!(
+        pipeline.burn_in = burn_in((mon_x + crop_x as i32, mon_y + crop_y as i32));
+        let burn_in_feed = pipeline.burn_in.as_ref().map(|settings| settings.feed.clone());
+        log!(
             "[native-capture][win][wgc] screen start hmonitor=0x{:x} monitor={}x{}@{},{} crop={}x{}+{},{} out={}x{} target={}x{} fps={} encoder={}",
             hmonitor as usize, mon_w, mon_h, mon_x, mon_y,
             out_w, out_h, crop_x, crop_y,
@@ -57,13 +129,14 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
             start_payload.video.height,
             start_payload.video.fps,
             &start_payload.video.encoder,
-            start_payload.video.bitrate,
+            bitrate,
             &ffmpeg_exe,
             &start_payload.output_path,
             hide_cursor,
             encoder_args,
+            pipeline,
         )?;
-        eprintln!(
+        log!(
             "[native-capture][win][wgc] screen start succeeded output_path={} size={}x{}",
             start_payload.output_path, wgc_capture.width, wgc_capture.height
         );
@@ -76,7 +149,15 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
             started_at: Instant::now(),
             platform: "win32".to_string(),
             restore_cursor_on_stop: false,
+            vfr,
             source_bounds: None,
+            input_telemetry: None,
+            cursor_tracker: None,
+            journal: None,
+            recording_path: None,
+            ffmpeg_exe: Some(ffmpeg_exe.clone()),
+            overwrite: false,
+            overlay_feed: burn_in_feed,
             backend: CaptureBackend::Wgc(wgc_capture),
         });
     }
@@ -87,14 +168,16 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
             .id
             .as_deref()
             .and_then(crate::wgc::hwnd_from_source_id)
-            .ok_or_else(|| "window capture requires source.id in the form window:<hwnd>:...".to_string())?;
+            .ok_or_else(|| {
+                invalid("window capture requires source.id in the form window:<hwnd>:...".to_string())
+            })?;
         let source_bounds = crate::wgc::source_bounds_from_hwnd(hwnd).map(|bounds| SourceBounds {
             x: bounds.x,
             y: bounds.y,
             width: bounds.width,
             height: bounds.height,
         });
-        eprintln!(
+        log!(
             "[native-capture][win][wgc] start requested source_id={:?} source_name={:?} hwnd=0x{:x} bounds={:?} fps={} encoder={} bitrate={} cursor_mode={}",
             start_payload.source.id,
             start_payload.source.name,
@@ -102,22 +185,26 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
             source_bounds.as_ref().map(|b| format!("{}x{}@{},{}", b.width, b.height, b.x, b.y)),
             start_payload.video.fps,
             start_payload.video.encoder,
-            start_payload.video.bitrate,
+            bitrate,
             start_payload.cursor.mode
         );
         let encoder_args = build_encoder_args(&start_payload.video.encoder);
         let hide_cursor = start_payload.cursor.mode == "hide";
+        pipeline.burn_in =
+            burn_in(source_bounds.as_ref().map_or((0, 0), |bounds| (bounds.x, bounds.y)));
+        let burn_in_feed = pipeline.burn_in.as_ref().map(|settings| settings.feed.clone());
         let wgc_capture = crate::wgc::start(
             hwnd,
             start_payload.video.fps,
             &start_payload.video.encoder,
-            start_payload.video.bitrate,
+            bitrate,
             &ffmpeg_exe,
             &start_payload.output_path,
             hide_cursor,
             encoder_args,
+            pipeline,
         )?;
-        eprintln!(
+        log!(
             "[native-capture][win][wgc] start succeeded output_path={} capture_size={}x{}",
             start_payload.output_path,
             wgc_capture.width,
@@ -133,79 +220,74 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
             started_at: Instant::now(),
             platform: "win32".to_string(),
             restore_cursor_on_stop: false,
+            vfr,
             source_bounds,
+            input_telemetry: None,
+            cursor_tracker: None,
+            journal: None,
+            recording_path: None,
+            ffmpeg_exe: Some(ffmpeg_exe.clone()),
+            overwrite: false,
+            overlay_feed: burn_in_feed,
             backend: CaptureBackend::Wgc(wgc_capture),
         });
     }
 
-    let output_path = start_payload.output_path.clone();
-    let mut command = build_ffmpeg_command(&ffmpeg_exe, &start_payload)?;
-
-    command
-        .arg(output_path.as_str())
-        .stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::piped());
-
-    let child = command
-        .spawn()
-        .map_err(|err| format!("failed to spawn ffmpeg: {err}"))?;
-    let mut child = child;
-    let stderr_lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
-    if let Some(stderr) = child.stderr.take() {
-        let stderr_lines_for_thread = Arc::clone(&stderr_lines);
-        thread::spawn(move || {
-            let reader = BufReader::new(stderr);
-            for line in reader.lines().map_while(Result::ok) {
-                let trimmed = line.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
-                eprintln!("[native-capture][win][ffmpeg][stderr] {}", trimmed);
-                if let Ok(mut guard) = stderr_lines_for_thread.lock() {
-                    if guard.len() >= 30 {
-                        let _ = guard.remove(0);
-                    }
-                    guard.push(trimmed.to_string());
-                }
-            }
-        });
+    if embedded {
+        return Err(CaptureError::new(
+            ErrorCode::EncoderUnavailable,
+            format!(
+                "encoder {} requires WGC capture, which is unavailable for this source",
+                start_payload.video.encoder
+            ),
+        )
+        .with_details(json!({ "encoder": start_payload.video.encoder })));
+    }
+    if vfr {
+        log!("[native-capture][win] frameRateMode=vfr needs a raw-frame backend; gdigrab records cfr");
+    }
+    if start_payload.burn_in.is_some() {
+        log!("[native-capture][win] burnIn needs a raw-frame backend; gdigrab records without it");
     }
 
-    thread::sleep(Duration::from_millis(350));
-    match child.try_wait() {
-        Ok(Some(status)) => {
-            let stderr_excerpt = stderr_lines
-                .lock()
-                .ok()
-                .map(|lines| lines.join(" | "))
-                .unwrap_or_default();
-            return Err(format!(
-                "ffmpeg exited immediately during startup (status={status}). Try h264_libx264.{}",
-                if stderr_excerpt.is_empty() {
-                    String::new()
-                } else {
-                    format!(" stderr={stderr_excerpt}")
-                }
-            ));
+    let output_path = start_payload.output_path.clone();
+    let readiness = Readiness::Progress { timeout: GDIGRAB_READY_TIMEOUT };
+    let mut base = EncodeStep::new(
+        start_payload.video.fps,
+        start_payload.video.width,
+        start_payload.video.height,
+    );
+    let backend = match start_payload.video.adaptive_quality() {
+        Some(config) => {
+            let encoder_args = build_encoder_args(&start_payload.video.encoder);
+            let policy =
+                AdaptivePolicy::new(config, &start_payload.video.encoder, &encoder_args, &mut base);
+            let payload = start_payload.clone();
+            let exe = ffmpeg_exe.clone();
+            let options = AdaptiveOptions {
+                session_id: start_payload.session_id.clone(),
+                label: "win",
+                ffmpeg_exe: ffmpeg_exe.clone(),
+                readiness,
+                policy,
+                base,
+                piped: false,
+                command: Box::new(move |step| build_ffmpeg_command(&exe, &payload, step)),
+            };
+            let encoder = AdaptiveEncoder::start(options, &output_path)
+                .map_err(|err| err.with_hint("Try h264_libx264."))?;
+            CaptureBackend::Adaptive(encoder)
         }
-        Ok(None) => {}
-        Err(err) => {
-            let stderr_excerpt = stderr_lines
-                .lock()
-                .ok()
-                .map(|lines| lines.join(" | "))
-                .unwrap_or_default();
-            return Err(format!(
-                "failed to verify ffmpeg startup: {err}.{}",
-                if stderr_excerpt.is_empty() {
-                    String::new()
-                } else {
-                    format!(" stderr={stderr_excerpt}")
-                }
-            ));
+        None => {
+            let mut command = build_ffmpeg_command(&ffmpeg_exe, &start_payload, &base)?;
+            command.arg(output_path.as_str());
+            let mut ffmpeg = FfmpegProcess::spawn(command, "win", &start_payload.session_id)?;
+            ffmpeg
+                .wait_ready(readiness)
+                .map_err(|err| err.with_hint("Try h264_libx264."))?;
+            CaptureBackend::Ffmpeg(ffmpeg)
         }
-    }
+    };
 
     Ok(ActiveCapture {
         session_id: start_payload.session_id,
@@ -216,14 +298,26 @@ pub fn start_capture(start_payload: StartCapturePayload) -> Result<ActiveCapture
         started_at: Instant::now(),
         platform: "win32".to_string(),
         restore_cursor_on_stop: false,
+        vfr: false,
         source_bounds: None,
-        backend: CaptureBackend::Ffmpeg(child),
+        input_telemetry: None,
+        cursor_tracker: None,
+        journal: None,
+        recording_path: None,
+        ffmpeg_exe: Some(ffmpeg_exe),
+        overwrite: false,
+        overlay_feed: None,
+        backend,
     })
 }
 
-fn build_ffmpeg_command(ffmpeg_exe: &str, payload: &StartCapturePayload) -> Result<Command, String> {
+// `step` sets the frame rate, output size and encoder preset.
+fn build_ffmpeg_command(
+    ffmpeg_exe: &str,
+    payload: &StartCapturePayload,
+    step: &EncodeStep,
+) -> Result<Command, CaptureError> {
     let draw_mouse = if payload.cursor.mode == "hide" { "0" } else { "1" };
-    let bitrate = format!("{}", payload.video.bitrate.max(1_000_000));
     let source_dimensions = if payload.source.source_type == "screen" {
         payload
             .capture_region
@@ -233,22 +327,22 @@ fn build_ffmpeg_command(ffmpeg_exe: &str, payload: &StartCapturePayload) -> Resu
         None
     };
     let needs_scale = match source_dimensions {
-        Some((src_w, src_h)) => src_w != payload.video.width || src_h != payload.video.height,
-        None => payload.source.source_type == "window",
+        Some((src_w, src_h)) => src_w != step.width || src_h != step.height,
+        None => {
+            payload.source.source_type == "window"
+                || (step.width, step.height) != (payload.video.width, payload.video.height)
+        }
     };
 
-    let mut command = Command::new(ffmpeg_exe);
+    let mut command = ffmpeg_command(ffmpeg_exe);
 
     command
-        .arg("-y")
-        .arg("-loglevel")
-        .arg("warning")
         .arg("-f")
         .arg("gdigrab")
         .arg("-thread_queue_size")
         .arg("2048")
         .arg("-framerate")
-        .arg(format!("{}", payload.video.fps))
+        .arg(format!("{}", step.fps))
         .arg("-draw_mouse")
         .arg(draw_mouse);
 
@@ -264,70 +358,49 @@ fn build_ffmpeg_command(ffmpeg_exe: &str, payload: &StartCapturePayload) -> Resu
         }
     }
 
-    let (video_codec, encoder_args): (&str, Vec<&str>) = match payload.video.encoder.as_str() {
-        "h264_nvenc" => ("h264_nvenc", vec!["-preset", "p4", "-tune", "ll", "-rc", "vbr", "-cq", "20"]),
-        "hevc_nvenc" => ("hevc_nvenc", vec!["-preset", "p4", "-tune", "ll", "-rc", "vbr", "-cq", "22"]),
-        "h264_amf" => ("h264_amf", vec!["-quality", "quality"]),
-        _ => ("libx264", vec!["-preset", "medium", "-tune", "zerolatency"]),
-    };
+    let (video_codec, encoder_args) = encoder_codec_args(&payload.video.encoder);
 
     if payload.source.source_type == "screen" {
         command.arg("-i").arg("desktop");
     } else if payload.source.source_type == "window" {
-        return Err("window capture is handled by WGC path before FFmpeg command build".to_string());
+        return Err("window capture is handled by WGC path before FFmpeg command build".into());
     } else {
-        return Err("unsupported source type".to_string());
+        return Err(CaptureError::new(ErrorCode::InvalidPayload, "unsupported source type"));
     }
 
     if needs_scale {
+        let invalid = |err: String| CaptureError::new(ErrorCode::InvalidPayload, err);
+        let fit = FitMode::parse(payload.video.fit.as_deref()).map_err(invalid)?;
+        let pad = PadColor::parse(payload.video.pad_color.as_deref()).map_err(invalid)?;
         command
             .arg("-vf")
-            .arg(format!("scale={}x{}", payload.video.width, payload.video.height));
+            .arg(fit_filter(step.width, step.height, fit, pad));
     }
 
+    command.arg("-r").arg(format!("{}", step.fps));
+
+    if let Some(codec) = payload.video.lossless_codec() {
+        command.args(lossless_codec_args(codec)?);
+        return Ok(command);
+    }
+    command.arg("-pix_fmt").arg("yuv420p");
+
+    let rate = rate_control(&payload.video)
+        .map_err(|err| CaptureError::new(ErrorCode::InvalidPayload, err))?;
     command
-        .arg("-r")
-        .arg(format!("{}", payload.video.fps))
-        .arg("-pix_fmt")
-        .arg("yuv420p")
-        .arg("-b:v")
-        .arg(bitrate.as_str())
-        .arg("-maxrate")
-        .arg(bitrate.as_str())
-        .arg("-bufsize")
-        .arg(format!("{}", payload.video.bitrate.saturating_mul(3)))
         .arg("-g")
-        .arg(format!("{}", (payload.video.fps.max(1)) * 2))
+        .arg(format!("{}", (step.fps.max(1)) * 2))
         .arg("-movflags")
-        .arg("+faststart")
+        .arg(RECORDING_MOVFLAGS)
         .arg("-c:v")
-        .arg(video_codec);
-
-    for arg in encoder_args {
-        command.arg(arg);
-    }
+        .arg(video_codec)
+        .args(with_preset(&payload.video.encoder, encoder_args, step.preset.as_deref()))
+        .args(rate_control_args(&payload.video.encoder, &rate));
 
     Ok(command)
 }
 
 fn build_encoder_args(encoder: &str) -> Vec<String> {
-    match encoder {
-        "h264_nvenc" => vec![
-            "-preset".to_string(), "p4".to_string(),
-            "-tune".to_string(), "ll".to_string(),
-            "-rc".to_string(), "vbr".to_string(),
-            "-cq".to_string(), "20".to_string(),
-        ],
-        "hevc_nvenc" => vec![
-            "-preset".to_string(), "p4".to_string(),
-            "-tune".to_string(), "ll".to_string(),
-            "-rc".to_string(), "vbr".to_string(),
-            "-cq".to_string(), "22".to_string(),
-        ],
-        "h264_amf" => vec!["-quality".to_string(), "quality".to_string()],
-        _ => vec![
-            "-preset".to_string(), "medium".to_string(),
-            "-tune".to_string(), "zerolatency".to_string(),
-        ],
-    }
+    let (_, args) = encoder_codec_args(encoder);
+    args.iter().map(|arg| arg.to_string()).collect()
 }
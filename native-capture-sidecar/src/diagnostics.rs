@@ -0,0 +1,627 @@
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::discovery::discover_ffmpeg;
+use crate::embedded::embedded_encoder_available;
+use crate::error::CaptureError;
+use crate::journal::{journal_dir, SessionJournal};
+use crate::protocol::{DiagnosticsPayload, Response, SIDECAR_VERSION};
+use crate::system::{detect_gpu_vendors_windows, gpu_adapter_names_windows};
+
+const LOG_RING_LINES: usize = 500;
+const DEFAULT_JOURNALS: usize = 5;
+// system_profiler and a cold PowerShell can take a few seconds; anything slower is stuck.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+static RECENT_LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static BOOT: OnceLock<Instant> = OnceLock::new();
+
+// Keeps the latest log lines for diagnostics bundles. Called by the crate's log!.
+pub fn record_log_line(line: String) {
+    let elapsed_ms = BOOT.get_or_init(Instant::now).elapsed().as_millis();
+    if let Ok(mut ring) = RECENT_LOG.lock() {
+        if ring.len() >= LOG_RING_LINES {
+            ring.pop_front();
+        }
+        ring.push_back(format!("[+{elapsed_ms}ms] {line}"));
+    }
+}
+
+// A bundle finished after its request was cancelled is not wanted.
+pub fn discard_bundle(response: Response) {
+    if let Some(path) = response.payload.as_ref().and_then(|payload| payload["path"].as_str()) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Gathers what support needs to debug a capture problem into one JSON file, with the user's
+// home folder and other absolute paths redacted, and returns where it was written.
+pub fn handle_collect_diagnostics(
+    id: String,
+    payload: Value,
+    capture_session: Option<&str>,
+) -> Response {
+    let payload: DiagnosticsPayload = if payload.is_null() {
+        DiagnosticsPayload::default()
+    } else {
+        match serde_json::from_value(payload) {
+            Ok(v) => v,
+            Err(err) => {
+                return Response::err(
+                    id,
+                    CaptureError::invalid_payload("collect_diagnostics", err),
+                )
+            }
+        }
+    };
+    let started = Instant::now();
+
+    let mut bundle = json!({
+        "generatedAtEpochMs": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        "sidecar": {
+            "version": SIDECAR_VERSION,
+            "pid": std::process::id(),
+            "platform": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "embeddedEncoder": embedded_encoder_available(),
+            "captureSessionId": capture_session,
+        },
+        "os": os_info(),
+        "ffmpeg": ffmpeg_info(payload.ffmpeg_path.as_deref()),
+        "gpu": gpu_info(),
+        "displays": displays(),
+        "windows": windows(),
+        "journals": journals(payload.journals.unwrap_or(DEFAULT_JOURNALS)),
+        "log": RECENT_LOG.lock().map(|ring| ring.iter().cloned().collect::<Vec<_>>()).unwrap_or_default(),
+    });
+    Redactor::new().redact(&mut bundle);
+
+    let dir = payload
+        .output_dir
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!(
+        "native-capture-diagnostics-{}.json",
+        bundle["generatedAtEpochMs"].as_u64().unwrap_or(0)
+    ));
+    let text = serde_json::to_vec_pretty(&bundle).unwrap_or_default();
+    if let Err(err) = fs::write(&path, &text) {
+        return Response::err(
+            id,
+            CaptureError::io(format!("failed to write {}", path.display()), &err),
+        );
+    }
+    log!(
+        "[native-capture][diagnostics] wrote path={} bytes={} elapsed_ms={}",
+        path.display(),
+        text.len(),
+        started.elapsed().as_millis()
+    );
+    Response::ok(
+        id,
+        json!({
+            "path": path.to_string_lossy(),
+            "format": "json",
+            "bytes": text.len(),
+            "elapsedMs": started.elapsed().as_millis() as u64,
+        }),
+    )
+}
+
+// stdout and stderr of a probe command. Runs on its own thread so a hung tool cannot hold
+// up the request loop past PROBE_TIMEOUT.
+fn probe(program: &str, args: &[&str]) -> Result<String, String> {
+    probe_with_timeout(program, args, PROBE_TIMEOUT)
+}
+
+pub fn probe_with_timeout(program: &str, args: &[&str], timeout: Duration) -> Result<String, String> {
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(command.output());
+    });
+    let output = match rx.recv_timeout(timeout) {
+        Ok(Ok(output)) => output,
+        Ok(Err(err)) => return Err(format!("{program}: {err}")),
+        Err(_) => return Err(format!("{program}: no answer within {} s", timeout.as_secs())),
+    };
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() && text.trim().is_empty() {
+        return Err(format!("{program}: {}", output.status));
+    }
+    Ok(text)
+}
+
+fn probe_json(program: &str, args: &[&str]) -> Value {
+    match probe(program, args)
+        .and_then(|text| serde_json::from_str::<Value>(&text).map_err(|e| e.to_string()))
+    {
+        // PowerShell's ConvertTo-Json turns a single item into an object.
+        Ok(value @ Value::Object(_)) if program == "powershell" => Value::Array(vec![value]),
+        Ok(value) => value,
+        Err(err) => json!({ "error": err }),
+    }
+}
+
+fn first_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+fn os_info() -> Value {
+    let env = |name: &str| std::env::var(name).ok();
+    if cfg!(target_os = "windows") {
+        json!({
+            "version": probe("cmd", &["/C", "ver"]).ok().as_deref().and_then(first_line),
+            // "Console" locally, "RDP-Tcp#N" over Remote Desktop, where capture behaves differently.
+            "sessionName": env("SESSIONNAME"),
+        })
+    } else if cfg!(target_os = "macos") {
+        json!({
+            "version": probe("sw_vers", &["-productVersion"]).ok().as_deref().and_then(first_line),
+            "build": probe("sw_vers", &["-buildVersion"]).ok().as_deref().and_then(first_line),
+        })
+    } else {
+        let release = fs::read_to_string("/etc/os-release").ok().and_then(|text| {
+            text.lines()
+                .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+                .map(|name| name.trim_matches('"').to_string())
+        });
+        json!({
+            "version": release,
+            "kernel": probe("uname", &["-r"]).ok().as_deref().and_then(first_line),
+            // Screen and input capture only work on X11; under Wayland the X calls reach
+            // XWayland and see no other application.
+            "sessionType": env("XDG_SESSION_TYPE"),
+            "waylandDisplay": env("WAYLAND_DISPLAY"),
+            "x11Display": env("DISPLAY"),
+            "desktop": env("XDG_CURRENT_DESKTOP"),
+        })
+    }
+}
+
+fn ffmpeg_info(preferred: Option<&str>) -> Value {
+    let discovery = discover_ffmpeg(preferred, None);
+    let Some(exe) = discovery.path() else {
+        return json!({
+            "path": null,
+            "requestedPath": preferred,
+            "discovery": discovery.report(),
+            "error": "ffmpeg executable not found",
+        });
+    };
+    let version = probe(&exe, &["-hide_banner", "-version"]);
+    // Encoder lines follow the " ------" separator, e.g. " V....D libx264  libx264 H.264 ...".
+    let encoders = probe(&exe, &["-hide_banner", "-encoders"]).map(|text| {
+        text.lines()
+            .skip_while(|line| !line.trim_start().starts_with("---"))
+            .skip(1)
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    });
+    json!({
+        "path": exe,
+        "requestedPath": preferred,
+        "version": version.as_ref().ok().and_then(|text| first_line(text)),
+        "buildConfiguration": version
+            .as_ref()
+            .ok()
+            .and_then(|text| text.lines().find_map(|line| line.strip_prefix("configuration:")))
+            .map(str::trim),
+        "encoders": encoders.as_ref().ok(),
+        "discovery": discovery.report(),
+        "error": version.err().or(encoders.err()),
+    })
+}
+
+fn gpu_info() -> Value {
+    if cfg!(target_os = "windows") {
+        let vendors = detect_gpu_vendors_windows();
+        json!({
+            "adapters": gpu_adapter_names_windows()
+                .map(|text| text.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect::<Vec<_>>()),
+            "nvidia": vendors.map(|(nvidia, _)| nvidia),
+            "amd": vendors.map(|(_, amd)| amd),
+        })
+    } else if cfg!(target_os = "macos") {
+        // Also lists the displays attached to each GPU.
+        probe_json("system_profiler", &["SPDisplaysDataType", "-json"])
+    } else {
+        match probe("lspci", &[]) {
+            Ok(text) => json!({
+                "adapters": text
+                    .lines()
+                    .filter(|line| line.contains("VGA") || line.contains("3D controller") || line.contains("Display controller"))
+                    .collect::<Vec<_>>(),
+            }),
+            Err(err) => json!({ "error": err }),
+        }
+    }
+}
+
+fn displays() -> Value {
+    if cfg!(target_os = "windows") {
+        probe_json(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Add-Type -AssemblyName System.Windows.Forms; [System.Windows.Forms.Screen]::AllScreens | ForEach-Object { [pscustomobject]@{ name = $_.DeviceName; primary = $_.Primary; x = $_.Bounds.X; y = $_.Bounds.Y; width = $_.Bounds.Width; height = $_.Bounds.Height } } | ConvertTo-Json -Compress",
+            ],
+        )
+    } else if cfg!(target_os = "macos") {
+        json!({ "note": "see gpu, which lists the displays of each GPU" })
+    } else {
+        x11_displays()
+    }
+}
+
+// Top-level windows with their owning process; titles are left out, they often name
+// documents.
+fn windows() -> Value {
+    if cfg!(target_os = "windows") {
+        probe_json(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-Process | Where-Object { $_.MainWindowHandle -ne 0 } | ForEach-Object { [pscustomobject]@{ pid = $_.Id; process = $_.ProcessName; hwnd = [int64]$_.MainWindowHandle } } | ConvertTo-Json -Compress",
+            ],
+        )
+    } else if cfg!(target_os = "macos") {
+        json!({ "note": "window enumeration is not collected on macOS" })
+    } else {
+        x11_windows()
+    }
+}
+
+fn journals(limit: usize) -> Value {
+    let sessions: Vec<SessionJournal> = SessionJournal::load_all();
+    let skip = sessions.len().saturating_sub(limit);
+    json!({
+        "dir": journal_dir().map(|dir| dir.to_string_lossy().to_string()),
+        "total": sessions.len(),
+        "sessions": sessions.into_iter().skip(skip).rev().collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn x11_displays() -> Value {
+    let (conn, _) = match x11rb::connect(None) {
+        Ok(v) => v,
+        Err(err) => return json!({ "error": format!("cannot connect to X display: {err}") }),
+    };
+    use x11rb::connection::Connection;
+    let screens: Vec<Value> = conn
+        .setup()
+        .roots
+        .iter()
+        .map(|screen| {
+            json!({
+                "width": screen.width_in_pixels,
+                "height": screen.height_in_pixels,
+                "widthMm": screen.width_in_millimeters,
+                "heightMm": screen.height_in_millimeters,
+                "depth": screen.root_depth,
+            })
+        })
+        .collect();
+    json!(screens)
+}
+
+#[cfg(target_os = "linux")]
+fn x11_windows() -> Value {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _};
+
+    let (conn, screen_num) = match x11rb::connect(None) {
+        Ok(v) => v,
+        Err(err) => return json!({ "error": format!("cannot connect to X display: {err}") }),
+    };
+    let root = conn.setup().roots[screen_num].root;
+    let atom = |name: &str| {
+        conn.intern_atom(false, name.as_bytes())
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .map(|reply| reply.atom)
+    };
+    let (Some(client_list), Some(wm_pid)) = (atom("_NET_CLIENT_LIST"), atom("_NET_WM_PID")) else {
+        return json!({ "error": "cannot intern window manager atoms" });
+    };
+    let ids: Vec<u32> = conn
+        .get_property(false, root, client_list, AtomEnum::WINDOW, 0, 4096)
+        .ok()
+        .and_then(|cookie| cookie.reply().ok())
+        .and_then(|reply| reply.value32().map(|ids| ids.collect()))
+        .unwrap_or_default();
+    let windows: Vec<Value> = ids
+        .into_iter()
+        .map(|window| {
+            let class = conn
+                .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)
+                .ok()
+                .and_then(|cookie| cookie.reply().ok())
+                .map(|reply| {
+                    String::from_utf8_lossy(&reply.value)
+                        .split('\0')
+                        .rfind(|part| !part.is_empty())
+                        .unwrap_or_default()
+                        .to_string()
+                });
+            let pid = conn
+                .get_property(false, window, wm_pid, AtomEnum::CARDINAL, 0, 1)
+                .ok()
+                .and_then(|cookie| cookie.reply().ok())
+                .and_then(|reply| reply.value32().and_then(|mut values| values.next()));
+            let geometry = conn
+                .get_geometry(window)
+                .ok()
+                .and_then(|cookie| cookie.reply().ok());
+            json!({
+                "id": window,
+                "class": class,
+                "pid": pid,
+                "width": geometry.as_ref().map(|g| g.width),
+                "height": geometry.as_ref().map(|g| g.height),
+            })
+        })
+        .collect();
+    json!(windows)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn x11_displays() -> Value {
+    Value::Null
+}
+
+#[cfg(not(target_os = "linux"))]
+fn x11_windows() -> Value {
+    Value::Null
+}
+
+// Replaces the user's home folder with "~" and every absolute path outside the system
+// folders with its root and "<path>", in every string of the bundle. Recording paths name
+// clients and projects as often as users.
+struct Redactor {
+    homes: Vec<String>,
+}
+
+impl Redactor {
+    // Lowercase, matched case-insensitively.
+    const PROFILE_MARKERS: [&'static str; 3] = ["\\users\\", "/users/", "/home/"];
+    // Folders that name no user or client, kept so support can see which ffmpeg ran.
+    // Lowercase, with `/` separators and without the drive letter.
+    const SYSTEM_DIRS: [&'static str; 15] = [
+        "/usr/", "/bin/", "/sbin/", "/lib/", "/lib64/", "/opt/homebrew/", "/nix/store/", "/snap/",
+        "/system/", "/library/", "/applications/", "/dev/", "/proc/", "/windows/", "/program files",
+    ];
+
+    fn new() -> Self {
+        let mut homes = Vec::new();
+        for name in ["USERPROFILE", "HOME"] {
+            if let Some(home) = std::env::var(name).ok().filter(|home| home.len() > 3) {
+                let home = home.trim_end_matches(['/', '\\']).to_string();
+                for variant in [home.replace('\\', "/"), home.replace('/', "\\"), home] {
+                    if !homes.contains(&variant) {
+                        homes.push(variant);
+                    }
+                }
+            }
+        }
+        // Longest first, so a home inside another is not half replaced.
+        homes.sort_by_key(|home| std::cmp::Reverse(home.len()));
+        Self { homes }
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.redact_text(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact(item)),
+            Value::Object(map) => map.values_mut().for_each(|item| self.redact(item)),
+            _ => {}
+        }
+    }
+
+    fn redact_text(&self, text: &str) -> String {
+        let mut out = text.to_string();
+        for home in &self.homes {
+            out = out.replace(home.as_str(), "~");
+        }
+        out = Self::redact_paths(&out);
+        // Catches profile paths that do not start a word, as in `file:///Users/...`.
+        for marker in Self::PROFILE_MARKERS {
+            let mut from = 0;
+            while let Some(at) = out[from..].to_ascii_lowercase().find(marker).map(|at| from + at) {
+                let start = at + marker.len();
+                let end = out[start..]
+                    .find(|c: char| matches!(c, '/' | '\\' | '"' | '\'') || c.is_whitespace())
+                    .map_or(out.len(), |end| start + end);
+                let name = out[start..end].to_ascii_lowercase();
+                if !name.is_empty() && name != "<user>" && name != "public" && name != "shared" {
+                    out.replace_range(start..end, "<user>");
+                }
+                from = start;
+            }
+        }
+        out
+    }
+
+    // `D:\Clients\Acme\take1.mp4` becomes `D:\<path>.mp4`: the root and the file extension
+    // stay, so support can still tell a drive or a container apart.
+    fn redact_paths(text: &str) -> String {
+        let bytes = text.as_bytes();
+        let mut out = String::with_capacity(text.len());
+        let (mut copied, mut at) = (0, 0);
+        while at < bytes.len() {
+            let Some(root) = Self::path_root(bytes, at) else {
+                at += 1;
+                continue;
+            };
+            let end = Self::path_end(bytes, at + root);
+            let path = &text[at..end];
+            // A lone `/x` is more likely a ratio or a unit than a path.
+            let nested = root > 1 || path[1..].contains(['/', '\\']);
+            if end > at + root && nested && !Self::in_system_dir(path) {
+                out.push_str(&text[copied..at]);
+                out.push_str(&path[..root]);
+                out.push_str("<path>");
+                let name = path.rsplit(['/', '\\']).next().unwrap_or_default();
+                if let Some((stem, ext)) = name.rsplit_once('.') {
+                    if !stem.is_empty() && ext.len() <= 8 && ext.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                        out.push('.');
+                        out.push_str(ext);
+                    }
+                }
+                copied = end;
+            }
+            at = end.max(at + 1);
+        }
+        out.push_str(&text[copied..]);
+        out
+    }
+
+    // Length of the root when an absolute path starts at `at`: `C:\`, `\\server`, `~/` or `/`.
+    fn path_root(bytes: &[u8], at: usize) -> Option<usize> {
+        let starts_word = at == 0
+            || matches!(bytes[at - 1], b' ' | b'"' | b'\'' | b'=' | b'(' | b'[' | b',' | b':')
+            || bytes[at - 1].is_ascii_control();
+        if !starts_word {
+            return None;
+        }
+        let next = |offset: usize| bytes.get(at + offset).copied().unwrap_or(0);
+        let separator = |byte: u8| byte == b'/' || byte == b'\\';
+        match *bytes.get(at)? {
+            letter if letter.is_ascii_alphabetic() && next(1) == b':' && separator(next(2)) => Some(3),
+            b'\\' if next(1) == b'\\' && next(2).is_ascii_alphanumeric() => Some(2),
+            b'~' if separator(next(1)) => Some(2),
+            b'/' if next(1).is_ascii_alphanumeric() || matches!(next(1), b'.' | b'_') => Some(1),
+            _ => None,
+        }
+    }
+
+    // Where the path starting before `from` ends. It runs on over spaces up to the last word
+    // that holds more folders, as in `C:\Program Files (x86)\ffmpeg.exe`, but not into a
+    // `key=value` or the next path.
+    fn path_end(bytes: &[u8], from: usize) -> usize {
+        let stops = |byte: u8| matches!(byte, b'"' | b'\'' | b'`' | b'|' | b',' | b';') || byte.is_ascii_control();
+        let word_end = |start: usize| {
+            (start..bytes.len())
+                .find(|&at| stops(bytes[at]) || bytes[at] == b' ')
+                .unwrap_or(bytes.len())
+        };
+        let mut end = word_end(from);
+        let mut scan = end;
+        while bytes.get(scan) == Some(&b' ') {
+            let next = word_end(scan + 1);
+            let word = &bytes[scan + 1..next];
+            if word.contains(&b'=') || Self::path_root(bytes, scan + 1).is_some() {
+                break;
+            }
+            if word.iter().any(|&b| b == b'/' || b == b'\\') {
+                end = next;
+            }
+            scan = next;
+        }
+        // Punctuation of the sentence around the path.
+        while end > from && matches!(bytes[end - 1], b'.' | b':' | b')' | b']') {
+            end -= 1;
+        }
+        end
+    }
+
+    fn in_system_dir(path: &str) -> bool {
+        let path = path.replace('\\', "/").to_ascii_lowercase();
+        let path = match path.as_bytes().get(1) {
+            Some(b':') => &path[2..],
+            _ => &path[..],
+        };
+        Self::SYSTEM_DIRS.iter().any(|dir| path.starts_with(dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor {
+            homes: vec!["C:\\Users\\Bob".to_string(), "C:/Users/Bob".to_string(), "/home/bob".to_string()],
+        }
+    }
+
+    #[test]
+    fn redacts_windows_paths() {
+        let redactor = redactor();
+        assert_eq!(redactor.redact_text("C:\\Users\\Bob\\Videos\\demo.mp4"), "~\\<path>.mp4");
+        assert_eq!(
+            redactor.redact_text("[native-capture][win] output=D:\\Clients\\Acme Corp\\take1.mp4 size=10"),
+            "[native-capture][win] output=D:\\<path>.mp4 size=10"
+        );
+        assert_eq!(redactor.redact_text("\\\\nas\\clients\\acme\\rec.mkv"), "\\\\<path>.mkv");
+        let system = "C:\\Program Files (x86)\\ffmpeg\\bin\\ffmpeg.exe";
+        assert_eq!(redactor.redact_text(system), system);
+    }
+
+    #[test]
+    fn redacts_unix_paths() {
+        let redactor = redactor();
+        assert_eq!(redactor.redact_text("/home/bob/Videos/demo.mp4"), "~/<path>.mp4");
+        assert_eq!(
+            redactor.redact_text("failed to open /Volumes/Work/Acme/take1.mov: No such file or directory"),
+            "failed to open /<path>.mov: No such file or directory"
+        );
+        assert_eq!(
+            redactor.redact_text("moving /tmp/acme/a.mp4 to /srv/acme/b.mp4"),
+            "moving /<path>.mp4 to /<path>.mp4"
+        );
+        for kept in ["/usr/bin/ffmpeg", "--prefix=/opt/homebrew/Cellar/ffmpeg", "30/1 fps, 6000 kbits/s", "https://ffmpeg.org/download.html"] {
+            assert_eq!(redactor.redact_text(kept), kept);
+        }
+    }
+
+    #[test]
+    fn redacts_profile_names_in_either_case() {
+        let redactor = redactor();
+        for path in ["C:\\Users\\alice\\rec.mp4", "C:\\users\\alice\\rec.mp4", "C:\\USERS\\ALICE\\rec.mp4"] {
+            assert_eq!(redactor.redact_text(path), "C:\\<path>.mp4");
+        }
+        assert_eq!(redactor.redact_text("file:///users/alice/rec.mp4"), "file:///users/<user>/rec.mp4");
+        assert_eq!(redactor.redact_text("file:///USERS/ALICE/rec.mp4"), "file:///USERS/<user>/rec.mp4");
+        assert_eq!(redactor.redact_text("file:///Users/Shared/rec.mp4"), "file:///Users/Shared/rec.mp4");
+    }
+
+    #[test]
+    fn redacts_journal_paths_in_the_bundle() {
+        let mut bundle = json!({
+            "journals": [{
+                "outputPath": "D:\\Clients\\Acme\\final.mp4",
+                "recordingPath": "D:\\Clients\\Acme\\final.rec-1.partial.mp4",
+                "state": "recording",
+            }],
+        });
+        redactor().redact(&mut bundle);
+        assert_eq!(bundle["journals"][0]["outputPath"], "D:\\<path>.mp4");
+        assert_eq!(bundle["journals"][0]["recordingPath"], "D:\\<path>.mp4");
+        assert_eq!(bundle["journals"][0]["state"], "recording");
+    }
+}
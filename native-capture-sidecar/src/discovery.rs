@@ -0,0 +1,516 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use crate::diagnostics::probe_with_timeout;
+use crate::error::{CaptureError, ErrorCode};
+use crate::protocol::{init_response, InitPayload, Response};
+
+// Overrides the bundled ffmpeg when the request does not name one.
+pub const FFMPEG_PATH_ENV: &str = "VELOCITY_FFMPEG_PATH";
+// `-fps_mode` (VFR recording) first shipped in 5.1.
+const MIN_VERSION: (u32, u32) = (5, 1);
+// Per query; a healthy ffmpeg answers in well under a second.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const EXE_NAME: &str = if cfg!(target_os = "windows") {
+    "ffmpeg.exe"
+} else {
+    "ffmpeg"
+};
+
+// Probe results by path, dropped when the file changes.
+type ProbeCache = Mutex<HashMap<PathBuf, (Option<SystemTime>, Arc<Probe>)>>;
+static PROBES: OnceLock<ProbeCache> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FfmpegSource {
+    // `ffmpegPath` from the request.
+    Payload,
+    // VELOCITY_FFMPEG_PATH.
+    Env,
+    // Shipped next to the sidecar, or in bin/<platform> of a development checkout.
+    Bundled,
+    Path,
+}
+
+impl FfmpegSource {
+    fn describe(self) -> &'static str {
+        match self {
+            FfmpegSource::Payload => "the requested ffmpegPath",
+            FfmpegSource::Env => FFMPEG_PATH_ENV,
+            FfmpegSource::Bundled => "the bundled ffmpeg",
+            FfmpegSource::Path => "ffmpeg on PATH",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegCandidate {
+    pub source: FfmpegSource,
+    pub path: String,
+    pub version: Option<String>,
+    // Meets the version, capture device and demuxer requirements.
+    pub usable: bool,
+    // Why it is not usable: missing file, failed probe, old version or missing features.
+    pub problems: Vec<String>,
+    // The requested encoder, when this build does not have it.
+    #[serde(rename = "missingEncoder")]
+    pub missing_encoder: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FfmpegDiscovery {
+    #[serde(skip)]
+    chosen: Option<usize>,
+    pub reason: String,
+    pub candidates: Vec<FfmpegCandidate>,
+}
+
+impl FfmpegDiscovery {
+    pub fn chosen(&self) -> Option<&FfmpegCandidate> {
+        self.chosen.map(|index| &self.candidates[index])
+    }
+
+    pub fn path(&self) -> Option<String> {
+        self.chosen().map(|candidate| candidate.path.clone())
+    }
+
+    // `{ path, source, version, reason, candidates }`, as reported by `init`.
+    pub fn report(&self) -> Value {
+        let chosen = self.chosen();
+        json!({
+            "path": chosen.map(|c| c.path.as_str()),
+            "source": chosen.map(|c| c.source),
+            "version": chosen.and_then(|c| c.version.as_deref()),
+            "reason": self.reason,
+            "candidates": self.candidates,
+        })
+    }
+
+    // FFMPEG_NOT_FOUND when nothing qualified, ENCODER_UNAVAILABLE when the chosen build
+    // lacks the requested encoder.
+    pub fn check(&self, hint: &str) -> Result<String, CaptureError> {
+        let Some(chosen) = self.chosen() else {
+            return Err(CaptureError::new(
+                ErrorCode::FfmpegNotFound,
+                format!("no usable ffmpeg found ({hint})"),
+            )
+            .with_details(json!({ "ffmpeg": self.report() })));
+        };
+        if let Some(encoder) = &chosen.missing_encoder {
+            return Err(CaptureError::new(
+                ErrorCode::EncoderUnavailable,
+                format!("ffmpeg at {} has no {encoder} encoder", chosen.path),
+            )
+            .with_details(json!({ "encoder": encoder, "ffmpeg": self.report() })));
+        }
+        Ok(chosen.path.clone())
+    }
+}
+
+// What an ffmpeg build reports about itself.
+#[derive(Debug, Default)]
+struct Probe {
+    version: Option<String>,
+    devices: Vec<String>,
+    demuxers: Vec<String>,
+    encoders: Vec<String>,
+    error: Option<String>,
+}
+
+// Checks the ffmpegPath from the request, VELOCITY_FFMPEG_PATH, the bundled binary and
+// PATH, in that order, and picks the first that can capture on this platform and has
+// `encoder`. When none has the encoder the first otherwise usable one is chosen, so the
+// caller can report the encoder rather than ffmpeg as missing. A requested ffmpegPath that
+// cannot capture is an error of its own; the others are not tried in its place.
+pub fn discover_ffmpeg(preferred: Option<&str>, encoder: Option<&str>) -> FfmpegDiscovery {
+    let mut candidates = Vec::new();
+    for (source, path) in candidate_paths(preferred) {
+        if candidates
+            .iter()
+            .any(|c: &FfmpegCandidate| Path::new(&c.path) == path)
+        {
+            continue;
+        }
+        let candidate = validate(source, &path, encoder);
+        let rejected = source == FfmpegSource::Payload && !candidate.usable;
+        candidates.push(candidate);
+        if rejected {
+            break;
+        }
+    }
+
+    let chosen = candidates
+        .iter()
+        .position(|c| c.usable && c.missing_encoder.is_none())
+        .or_else(|| candidates.iter().position(|c| c.usable));
+    let reason = match chosen {
+        Some(index) => {
+            let candidate = &candidates[index];
+            let mut reason = format!(
+                "{} {} ({})",
+                candidate.source.describe(),
+                candidate.version.as_deref().unwrap_or("unknown version"),
+                candidate.path
+            );
+            let skipped = rejection_summary(&candidates[..index]);
+            if !skipped.is_empty() {
+                reason.push_str(&format!("; skipped {skipped}"));
+            }
+            reason
+        }
+        None if candidates.is_empty() => "no ffmpeg binary found".to_string(),
+        None => format!("no usable ffmpeg: {}", rejection_summary(&candidates)),
+    };
+    log!(
+        "[native-capture][ffmpeg] discovery encoder={:?} chosen={:?} reason={}",
+        encoder,
+        chosen.map(|index| &candidates[index].path),
+        reason
+    );
+    FfmpegDiscovery {
+        chosen,
+        reason,
+        candidates,
+    }
+}
+
+fn rejection_summary(candidates: &[FfmpegCandidate]) -> String {
+    candidates
+        .iter()
+        .map(|c| {
+            let mut problems = c.problems.clone();
+            problems.extend(c.missing_encoder.iter().map(|e| format!("no {e} encoder")));
+            format!(
+                "{} {} ({})",
+                c.source.describe(),
+                c.path,
+                problems.join(", ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn candidate_paths(preferred: Option<&str>) -> Vec<(FfmpegSource, PathBuf)> {
+    let mut paths = Vec::new();
+    if let Some(path) = preferred.filter(|p| !p.trim().is_empty()) {
+        paths.push((FfmpegSource::Payload, PathBuf::from(path)));
+    }
+    if let Some(path) = env::var_os(FFMPEG_PATH_ENV).filter(|p| !p.is_empty()) {
+        paths.push((FfmpegSource::Env, PathBuf::from(path)));
+    }
+    if let Some(dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        // Packaged: resources/native-capture/<platform>/ holds both. Development: the
+        // sidecar runs from target/<profile>/ and ffmpeg sits in ../../bin/<platform>/.
+        let platform = if cfg!(target_os = "windows") {
+            "win32"
+        } else if cfg!(target_os = "macos") {
+            "darwin"
+        } else {
+            "linux"
+        };
+        let dev = dir
+            .join("..")
+            .join("..")
+            .join("bin")
+            .join(platform)
+            .join(EXE_NAME);
+        for path in [dir.join(EXE_NAME), dev] {
+            if path.is_file() {
+                paths.push((FfmpegSource::Bundled, path));
+            }
+        }
+    }
+    if let Some(path) = env::var_os("PATH")
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(EXE_NAME))
+        .find(|path| path.is_file())
+    {
+        paths.push((FfmpegSource::Path, path));
+    }
+    paths
+}
+
+fn validate(source: FfmpegSource, path: &Path, encoder: Option<&str>) -> FfmpegCandidate {
+    let mut candidate = FfmpegCandidate {
+        source,
+        path: path.display().to_string(),
+        version: None,
+        usable: false,
+        problems: Vec::new(),
+        missing_encoder: None,
+    };
+    let Ok(metadata) = fs::metadata(path) else {
+        candidate.problems.push("file not found".to_string());
+        return candidate;
+    };
+    let probe = cached_probe(path, metadata.modified().ok());
+    if let Some(error) = &probe.error {
+        candidate.problems.push(error.clone());
+        return candidate;
+    }
+    candidate.version = probe.version.clone();
+    match probe.version.as_deref().and_then(parse_version) {
+        Some(version) if version < MIN_VERSION => candidate.problems.push(format!(
+            "version {}.{} is older than {}.{}",
+            version.0, version.1, MIN_VERSION.0, MIN_VERSION.1
+        )),
+        // Git snapshots ("N-113245-g...") and unparseable versions are given the benefit
+        // of the doubt; the feature checks below still apply.
+        _ => {}
+    }
+    let device = if cfg!(target_os = "windows") {
+        Some("gdigrab")
+    } else if cfg!(target_os = "macos") {
+        Some("avfoundation")
+    } else if cfg!(target_os = "linux") {
+        Some("x11grab")
+    } else {
+        None
+    };
+    if let Some(device) = device.filter(|d| !probe.devices.iter().any(|name| name == d)) {
+        candidate.problems.push(format!("no {device} input device"));
+    }
+    // Raw frames are piped in by the WGC and embedded encoder paths and the benchmark.
+    if !probe.demuxers.iter().any(|name| name == "rawvideo") {
+        candidate.problems.push("no rawvideo demuxer".to_string());
+    }
+    if let Some(encoder) = encoder.filter(|e| !probe.encoders.iter().any(|name| name == e)) {
+        candidate.missing_encoder = Some(encoder.to_string());
+    }
+    candidate.usable = candidate.problems.is_empty();
+    candidate
+}
+
+fn cached_probe(path: &Path, modified: Option<SystemTime>) -> Arc<Probe> {
+    let cache = PROBES.get_or_init(Default::default);
+    if let Some((stamp, probe)) = cache.lock().unwrap().get(path) {
+        if *stamp == modified {
+            return probe.clone();
+        }
+    }
+    let probe = Arc::new(run_probe(path));
+    cache
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (modified, probe.clone()));
+    probe
+}
+
+fn run_probe(path: &Path) -> Probe {
+    let exe = path.to_string_lossy();
+    let probe = |flag: &str| probe_with_timeout(&exe, &["-hide_banner", flag], PROBE_TIMEOUT);
+    let version = match probe("-version") {
+        Ok(text) => text,
+        Err(err) => {
+            return Probe {
+                error: Some(err),
+                ..Probe::default()
+            }
+        }
+    };
+    let Some(version) = version_name(&version) else {
+        return Probe {
+            error: Some("not an ffmpeg binary (no version line)".to_string()),
+            ..Probe::default()
+        };
+    };
+    let list = |flag: &str| probe(flag).map(|text| listed_names(&text));
+    match (list("-devices"), list("-demuxers"), list("-encoders")) {
+        (Ok(devices), Ok(demuxers), Ok(encoders)) => Probe {
+            version: Some(version.to_string()),
+            devices,
+            demuxers,
+            encoders,
+            error: None,
+        },
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => Probe {
+            version: Some(version.to_string()),
+            error: Some(err),
+            ..Probe::default()
+        },
+    }
+}
+
+// The version from the first line of `-version`, e.g. "ffmpeg version n7.0 Copyright ...".
+fn version_name(text: &str) -> Option<&str> {
+    text.lines()
+        .find_map(|line| line.trim().strip_prefix("ffmpeg version "))
+        .and_then(|rest| rest.split_whitespace().next())
+}
+
+// Names from `-devices`, `-demuxers` or `-encoders`: the second column of each line after
+// the dashed separator, e.g. " D  gdigrab  GDI API Windows frame grabber" or
+// " DE matroska,webm  Matroska / WebM".
+fn listed_names(text: &str) -> Vec<String> {
+    text.lines()
+        .skip_while(|line| !line.trim_start().starts_with("--"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .flat_map(|names| names.split(','))
+        .map(str::to_string)
+        .collect()
+}
+
+// "6.1.1", "n7.0", "5.1.2-full_build-www.gyan.dev" -> (major, minor). Git snapshot
+// versions carry no release number and give None.
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let version = version.strip_prefix('n').unwrap_or(version);
+    let mut parts = version
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or(0);
+    // Date-stamped builds ("2024-03-10-git-...") are snapshots too.
+    (major < 1000).then_some((major, minor))
+}
+
+pub fn handle_init(id: String, payload: Value) -> Response {
+    let init_payload: InitPayload = if payload.is_null() {
+        InitPayload::default()
+    } else {
+        match serde_json::from_value(payload) {
+            Ok(v) => v,
+            Err(err) => return Response::err(id, CaptureError::invalid_payload("init", err)),
+        }
+    };
+    let discovery = discover_ffmpeg(init_payload.ffmpeg_path.as_deref(), None);
+    init_response(id, discovery.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICES: &str = "Devices:
+ D. = Demuxing supported
+ .E = Muxing supported
+ ---
+ D  avfoundation    AVFoundation input device
+ D  dshow           DirectShow capture
+ D  gdigrab         GDI API Windows frame grabber
+ D  lavfi           Libavfilter virtual input device
+ E  sdl,sdl2        SDL2 output device
+ D  x11grab         X11 screen capture, using XCB
+";
+
+    const DEMUXERS: &str = "File formats:
+ D. = Demuxing supported
+ .E = Muxing supported
+ ---
+ D  matroska,webm   Matroska / WebM
+ D  rawvideo        raw video
+";
+
+    const ENCODERS: &str = "Encoders:
+ V..... = Video
+ A..... = Audio
+ S..... = Subtitle
+ .F.... = Frame-level multithreading
+ ..S... = Slice-level multithreading
+ ...X.. = Codec is experimental
+ ....B. = Supports draw_horiz_band
+ .....D = Supports direct rendering method 1
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D h264_amf             AMD AMF H.264 Encoder (codec h264)
+ V....D h264_nvenc           NVIDIA NVENC H.264 encoder (codec h264)
+ A....D aac                  AAC (Advanced Audio Coding)
+";
+
+    #[test]
+    fn lists_names_after_the_separator() {
+        assert_eq!(
+            listed_names(DEVICES),
+            ["avfoundation", "dshow", "gdigrab", "lavfi", "sdl", "sdl2", "x11grab"]
+        );
+        assert_eq!(listed_names(DEMUXERS), ["matroska", "webm", "rawvideo"]);
+        assert_eq!(listed_names(ENCODERS), ["libx264", "h264_amf", "h264_nvenc", "aac"]);
+        assert!(listed_names("Encoders:\n V..... = Video\n").is_empty());
+    }
+
+    #[test]
+    fn reads_release_and_snapshot_versions() {
+        let version = |text: &str| version_name(text).and_then(parse_version);
+        let gyan = "ffmpeg version 7.0.1-full_build-www.gyan.dev Copyright (c) 2000-2024 the FFmpeg developers
+built with gcc 13.2.0 (Rev5, Built by MSYS2 project)
+configuration: --enable-gpl --enable-version3 --enable-static";
+        assert_eq!(version_name(gyan), Some("7.0.1-full_build-www.gyan.dev"));
+        assert_eq!(version(gyan), Some((7, 0)));
+        assert_eq!(version("ffmpeg version n7.0 Copyright (c) 2000-2024 the FFmpeg developers"), Some((7, 0)));
+        assert_eq!(version("ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg developers"), Some((6, 1)));
+        assert_eq!(
+            version("ffmpeg version 4.4.2-0ubuntu0.22.04.1 Copyright (c) 2000-2021 the FFmpeg developers"),
+            Some((4, 4))
+        );
+        let snapshot = "ffmpeg version N-113245-g1f4c2a0e01-20240110 Copyright (c) 2000-2024 the FFmpeg developers";
+        assert_eq!(version_name(snapshot), Some("N-113245-g1f4c2a0e01-20240110"));
+        assert_eq!(version(snapshot), None);
+        assert_eq!(
+            version("ffmpeg version 2024-03-10-git-3d5c6a4f5e-full_build-www.gyan.dev Copyright (c) 2000-2024"),
+            None
+        );
+        assert_eq!(version_name("ffprobe version 7.0 Copyright (c) 2007-2024"), None);
+    }
+
+    // An executable sh script answering the discovery probes with `version` and the lists above.
+    #[cfg(unix)]
+    fn fake_ffmpeg(name: &str, version: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("native-capture-{name}-{}.sh", std::process::id()));
+        let script = format!(
+            "#!/bin/sh\ncase \"$2\" in\n\
+             -version) printf '%s\\n' 'ffmpeg version {version} Copyright (c) 2000-2024 the FFmpeg developers' ;;\n\
+             -devices) cat <<'EOF'\n{DEVICES}EOF\n;;\n\
+             -demuxers) cat <<'EOF'\n{DEMUXERS}EOF\n;;\n\
+             -encoders) cat <<'EOF'\n{ENCODERS}EOF\n;;\n\
+             esac\n"
+        );
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn validates_a_requested_ffmpeg() {
+        let exe = fake_ffmpeg("discovery-ok", "N-113245-g1f4c2a0e01");
+        let discovery = discover_ffmpeg(Some(&exe), Some("h264_nvenc"));
+        let chosen = discovery.chosen().unwrap();
+        assert_eq!((chosen.source, chosen.usable), (FfmpegSource::Payload, true));
+        assert_eq!(chosen.version.as_deref(), Some("N-113245-g1f4c2a0e01"));
+        assert_eq!(discovery.check("test").unwrap(), exe);
+        let _ = fs::remove_file(&exe);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_rejected_ffmpeg_path_does_not_fall_through() {
+        let exe = fake_ffmpeg("discovery-old", "4.4.2-0ubuntu0.22.04.1");
+        let discovery = discover_ffmpeg(Some(&exe), None);
+        let _ = fs::remove_file(&exe);
+        assert!(discovery.chosen().is_none());
+        assert_eq!(discovery.candidates.len(), 1);
+        assert_eq!(discovery.candidates[0].problems, ["version 4.4 is older than 5.1"]);
+        assert_eq!(discovery.check("test").unwrap_err().code, ErrorCode::FfmpegNotFound);
+
+        let missing = std::env::temp_dir().join(format!("native-capture-no-ffmpeg-{}", std::process::id()));
+        let discovery = discover_ffmpeg(missing.to_str(), None);
+        assert_eq!(discovery.candidates.len(), 1);
+        assert_eq!(discovery.candidates[0].source, FfmpegSource::Payload);
+        assert_eq!(discovery.candidates[0].problems, ["file not found"]);
+        assert!(discovery.reason.starts_with("no usable ffmpeg: the requested ffmpegPath"));
+    }
+}
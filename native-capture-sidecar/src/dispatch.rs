@@ -0,0 +1,645 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::benchmark::BenchmarkRun;
+use crate::capture::{
+    abandon_capture, abort_capture, capture_status, handle_start, handle_stop, ActiveCapture,
+};
+use crate::error::{CaptureError, ErrorCode};
+use crate::protocol::{write_response, CancelPayload, Response, SIDECAR_VERSION};
+
+// How often shutdown checks whether a start or stop running on a worker has finished.
+const SETTLE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+// How far past the shutdown deadline a stop may run: ffmpeg's flush and kill escalation
+// plus a short join.
+const SETTLE_OVERRUN_LIMIT: Duration = Duration::from_secs(15);
+
+// The capture, and the start or stop working on it off the request loop.
+#[derive(Default)]
+pub struct CaptureSlot {
+    pub active: Option<ActiveCapture>,
+    transition: Option<Transition>,
+}
+
+struct Transition {
+    // "starting" or "stopping".
+    state: &'static str,
+    request_id: String,
+    since: Instant,
+    // get_status entry for the session; a stopping one keeps its last recording status.
+    status: Value,
+    // The stopping capture's ffmpeg, for a shutdown that gives up on the stop.
+    ffmpeg_pid: Option<Arc<AtomicU32>>,
+}
+
+struct InFlight {
+    cmd: String,
+    started: Instant,
+    cancellable: bool,
+    // Already answered with CANCELLED; the worker's own response is dropped.
+    cancelled: bool,
+    // Set on cancel for work that checks it to give up early (start_capture).
+    cancel_flag: Option<Arc<AtomicBool>>,
+}
+
+// Where replies go: stdout, or a collector in tests.
+type ReplySink = Arc<dyn Fn(&Response) + Send + Sync>;
+
+// Runs slow commands on worker threads so the request loop keeps answering. Responses
+// carry the request `id` and arrive in completion order, not request order.
+#[derive(Clone)]
+pub struct Dispatcher {
+    requests: Arc<Mutex<HashMap<String, InFlight>>>,
+    capture: Arc<Mutex<CaptureSlot>>,
+    booted: Instant,
+    reply: ReplySink,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self::with_reply_sink(Arc::new(write_response))
+    }
+
+    fn with_reply_sink(reply: ReplySink) -> Self {
+        Self {
+            requests: Arc::default(),
+            capture: Arc::default(),
+            booted: Instant::now(),
+            reply,
+        }
+    }
+
+    pub fn capture(&self) -> MutexGuard<'_, CaptureSlot> {
+        self.capture
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn requests(&self) -> MutexGuard<'_, HashMap<String, InFlight>> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn capture_busy(&self) -> bool {
+        let slot = self.capture();
+        slot.active.is_some() || slot.transition.is_some()
+    }
+
+    // Runs `work` on a worker thread. Returns the response right away when the request
+    // cannot be taken on. `on_cancelled` gets the result of work that finished after its
+    // request was cancelled, to clean up what it left behind.
+    pub fn spawn<F>(
+        &self,
+        id: String,
+        cmd: &str,
+        cancellable: bool,
+        work: F,
+        on_cancelled: fn(Response),
+    ) -> Option<Response>
+    where
+        F: FnOnce(String) -> Response + Send + 'static,
+    {
+        if let Err(err) = self.register(&id, cmd, cancellable, None) {
+            return Some(Response::err(id, err));
+        }
+        let dispatcher = self.clone();
+        thread::spawn(move || {
+            let response = work(id.clone());
+            if let Some(response) = dispatcher.finish(&id, response) {
+                on_cancelled(response);
+            }
+        });
+        None
+    }
+
+    fn register(
+        &self,
+        id: &str,
+        cmd: &str,
+        cancellable: bool,
+        cancel_flag: Option<Arc<AtomicBool>>,
+    ) -> Result<(), CaptureError> {
+        let mut requests = self.requests();
+        if let Some(existing) = requests.get(id) {
+            return Err(CaptureError::new(
+                ErrorCode::InvalidPayload,
+                format!("request id {id} is already in flight"),
+            )
+            .with_details(json!({ "cmd": existing.cmd })));
+        }
+        requests.insert(
+            id.to_string(),
+            InFlight {
+                cmd: cmd.to_string(),
+                started: Instant::now(),
+                cancellable,
+                cancelled: false,
+                cancel_flag,
+            },
+        );
+        Ok(())
+    }
+
+    // Writes the worker's response, unless the request was cancelled meanwhile; then the
+    // response is handed back. Done under the request lock so exactly one reply goes out.
+    fn finish(&self, id: &str, response: Response) -> Option<Response> {
+        let mut requests = self.requests();
+        let entry = requests.remove(id);
+        let elapsed_ms = entry
+            .as_ref()
+            .map_or(0, |entry| entry.started.elapsed().as_millis());
+        let cmd = entry.as_ref().map_or("", |entry| entry.cmd.as_str());
+        if entry.as_ref().is_some_and(|entry| entry.cancelled) {
+            log!(
+                "[native-capture][sidecar] cmd={} id={} finished after cancel ok={} elapsed_ms={}",
+                cmd, id, response.ok, elapsed_ms
+            );
+            return Some(response);
+        }
+        log!(
+            "[native-capture][sidecar] cmd={} id={} ok={} elapsed_ms={}",
+            cmd, id, response.ok, elapsed_ms
+        );
+        (self.reply)(&response);
+        None
+    }
+
+    // start_capture runs on a worker; the slot is marked as starting until it is done so a
+    // second start or a stop is refused meanwhile. A running benchmark is stopped first.
+    pub fn start(
+        &self,
+        id: String,
+        payload: Value,
+        benchmark: &mut Option<BenchmarkRun>,
+    ) -> Option<Response> {
+        let mut slot = self.capture();
+        let busy = transition_state(&slot).or(slot.active.as_ref().map(|_| "running"));
+        if let Some(busy) = busy {
+            return Some(Response::err(
+                id,
+                CaptureError::new(ErrorCode::AlreadyRunning, format!("capture already {busy}"))
+                    .with_details(json!({ "sessionId": slot_session_id(&slot) })),
+            ));
+        }
+        let cancelled = Arc::new(AtomicBool::new(false));
+        if let Err(err) = self.register(&id, "start_capture", true, Some(Arc::clone(&cancelled))) {
+            return Some(Response::err(id, err));
+        }
+        slot.transition = Some(Transition {
+            state: "starting",
+            request_id: id.clone(),
+            since: Instant::now(),
+            status: json!({ "sessionId": payload.get("sessionId"), "state": "starting" }),
+            ffmpeg_pid: None,
+        });
+        drop(slot);
+
+        let benchmark = benchmark.take();
+        let dispatcher = self.clone();
+        thread::spawn(move || {
+            // Recording takes priority over a benchmark competing for the same encoder.
+            if let Some(run) = benchmark {
+                log!("[native-capture][sidecar] cancelling benchmark for start_capture");
+                run.cancel_and_join();
+            }
+            let mut started = None;
+            let response = handle_start(id.clone(), payload, &mut started, &cancelled);
+            let mut slot = dispatcher.capture();
+            slot.transition = None;
+            // Still under the slot lock, so get_status never sees a cancelled capture.
+            match dispatcher.finish(&id, response) {
+                None => slot.active = started,
+                Some(_) => {
+                    drop(slot);
+                    if let Some(capture) = started {
+                        log!(
+                            "[native-capture][sidecar] start_capture id={} was cancelled, discarding session={}",
+                            id, capture.session_id
+                        );
+                        abort_capture(capture);
+                    }
+                }
+            }
+        });
+        None
+    }
+
+    // The capture leaves the slot while it finalizes on a worker; get_status keeps
+    // reporting it as stopping. Stops cannot be cancelled.
+    pub fn stop(&self, id: String, payload: Value) -> Option<Response> {
+        let mut slot = self.capture();
+        if let Some(busy) = transition_state(&slot) {
+            return Some(Response::err(
+                id,
+                CaptureError::new(ErrorCode::NotRunning, format!("capture is {busy}"))
+                    .with_details(json!({ "sessionId": slot_session_id(&slot) })),
+            ));
+        }
+        let Some(mut capture) = slot.active.take() else {
+            return Some(handle_stop(id, payload, &mut None, None));
+        };
+        if let Err(err) = self.register(&id, "stop_capture", false, None) {
+            slot.active = Some(capture);
+            return Some(Response::err(id, err));
+        }
+        let mut status = capture_status(&mut capture);
+        status["state"] = json!("stopping");
+        let ffmpeg_pid = capture.ffmpeg_pid_watch();
+        slot.transition = Some(Transition {
+            state: "stopping",
+            request_id: id.clone(),
+            since: Instant::now(),
+            status,
+            ffmpeg_pid: Some(Arc::clone(&ffmpeg_pid)),
+        });
+        drop(slot);
+
+        let dispatcher = self.clone();
+        thread::spawn(move || {
+            let mut active = Some(capture);
+            let response = handle_stop(id.clone(), payload, &mut active, Some(ffmpeg_pid));
+            let mut slot = dispatcher.capture();
+            slot.transition = None;
+            // Put back when the stop was refused (bad payload, other session).
+            slot.active = active;
+            dispatcher.finish(&id, response);
+        });
+        None
+    }
+
+    // Answers the cancelled request with CANCELLED at once. What it was doing may still
+    // run to completion on its worker; its result is then discarded, and a capture that
+    // a cancelled start_capture brought up is aborted. `pending` in the reply says the
+    // capture slot stays busy until that start has wound down.
+    pub fn cancel(&self, id: String, payload: Value, benchmark: Option<&BenchmarkRun>) -> Response {
+        let payload: CancelPayload = match serde_json::from_value(payload) {
+            Ok(v) => v,
+            Err(err) => return Response::err(id, CaptureError::invalid_payload("cancel", err)),
+        };
+        let target = payload.request_id;
+        // A benchmark stops at the next frame and replies itself with what it measured.
+        if let Some(run) = benchmark.filter(|run| run.id() == target && run.running()) {
+            log!(
+                "[native-capture][sidecar] cancel id={} cmd=benchmark_encoders",
+                target
+            );
+            run.cancel();
+            return Response::ok(
+                id,
+                json!({ "requestId": target, "cmd": "benchmark_encoders", "cancelled": true }),
+            );
+        }
+
+        let mut requests = self.requests();
+        let Some(entry) = requests.get_mut(&target).filter(|entry| !entry.cancelled) else {
+            return Response::err(
+                id,
+                CaptureError::new(
+                    ErrorCode::NotRunning,
+                    format!("no request {target} is in flight"),
+                )
+                .with_details(json!({ "requestId": target })),
+            );
+        };
+        if !entry.cancellable {
+            return Response::err(
+                id,
+                CaptureError::new(
+                    ErrorCode::InvalidPayload,
+                    format!("{} cannot be cancelled", entry.cmd),
+                )
+                .with_details(json!({ "requestId": target, "cmd": entry.cmd })),
+            );
+        }
+        entry.cancelled = true;
+        if let Some(flag) = &entry.cancel_flag {
+            flag.store(true, Ordering::SeqCst);
+        }
+        let elapsed_ms = entry.started.elapsed().as_millis() as u64;
+        log!(
+            "[native-capture][sidecar] cancel id={} cmd={} elapsed_ms={}",
+            target, entry.cmd, elapsed_ms
+        );
+        (self.reply)(&Response::err(
+            &target,
+            CaptureError::new(ErrorCode::Cancelled, format!("{} was cancelled", entry.cmd))
+                .with_details(json!({ "cancelRequestId": id })),
+        ));
+        Response::ok(
+            id,
+            json!({
+                "requestId": target,
+                "cmd": entry.cmd,
+                "cancelled": true,
+                "pending": entry.cmd == "start_capture",
+                "elapsedMs": elapsed_ms,
+            }),
+        )
+    }
+
+    pub fn status(&self, id: String, benchmark: Option<&BenchmarkRun>) -> Response {
+        let mut slot = self.capture();
+        let mut sessions = Vec::new();
+        if let Some(transition) = &slot.transition {
+            let mut status = transition.status.clone();
+            status["requestId"] = json!(transition.request_id);
+            status["stateMs"] = json!(transition.since.elapsed().as_millis() as u64);
+            sessions.push(status);
+        }
+        if let Some(capture) = slot.active.as_mut() {
+            sessions.push(capture_status(capture));
+        }
+        drop(slot);
+
+        let requests: Vec<Value> = self
+            .requests()
+            .iter()
+            .filter(|(_, entry)| !entry.cancelled)
+            .map(|(request_id, entry)| {
+                json!({
+                    "id": request_id,
+                    "cmd": entry.cmd,
+                    "elapsedMs": entry.started.elapsed().as_millis() as u64,
+                    "cancellable": entry.cancellable,
+                })
+            })
+            .collect();
+        Response::ok(
+            id,
+            json!({
+                "version": SIDECAR_VERSION,
+                "pid": std::process::id(),
+                "uptimeMs": self.booted.elapsed().as_millis() as u64,
+                "sessions": sessions,
+                "requests": requests,
+                "benchmarkId": benchmark.filter(|run| run.running()).map(|run| run.id()),
+            }),
+        )
+    }
+
+    // Waits for a start or stop on a worker to finish, so shutdown sees the capture it
+    // produced. A start is given up on at `deadline`. A stop is waited for past it, since
+    // exiting would cut ffmpeg off mid-flush, but only by SETTLE_OVERRUN_LIMIT; then its
+    // ffmpeg is killed and the session left to recover_session. Returns the shutdown entry
+    // of a stop given up on that way.
+    pub fn settle(&self, deadline: Instant) -> Vec<Value> {
+        let mut overran = false;
+        loop {
+            let slot = self.capture();
+            let now = Instant::now();
+            match slot.transition.as_ref() {
+                None => return Vec::new(),
+                Some(transition) if transition.state == "stopping" => {
+                    if now >= deadline + SETTLE_OVERRUN_LIMIT {
+                        let session_id = transition.status["sessionId"].as_str().unwrap_or_default().to_string();
+                        let output_path = transition.status["outputPath"].clone();
+                        let ffmpeg_pid = transition.ffmpeg_pid.clone();
+                        drop(slot);
+                        let err = CaptureError::from("stop_capture did not finalize before the shutdown deadline");
+                        abandon_capture(&session_id, ffmpeg_pid.as_deref(), &err.message);
+                        return vec![json!({
+                            "sessionId": session_id,
+                            "outputPath": output_path,
+                            "status": "timedOut",
+                            "result": null,
+                            "error": err,
+                        })];
+                    }
+                    if now >= deadline && !overran {
+                        overran = true;
+                        log!("[native-capture][shutdown] waiting past the deadline for stop_capture to finalize");
+                    }
+                }
+                Some(_) if now >= deadline => return Vec::new(),
+                Some(_) => {}
+            }
+            drop(slot);
+            thread::sleep(SETTLE_POLL_INTERVAL);
+        }
+    }
+
+    // Answers every request still in flight with CANCELLED; their workers' results are
+    // discarded, as with `cancel`.
+    pub fn cancel_all(&self, reason: &str) {
+        for (request_id, entry) in self
+            .requests()
+            .iter_mut()
+            .filter(|(_, entry)| !entry.cancelled)
+        {
+            entry.cancelled = true;
+            if let Some(flag) = &entry.cancel_flag {
+                flag.store(true, Ordering::SeqCst);
+            }
+            (self.reply)(&Response::err(
+                request_id,
+                CaptureError::new(
+                    ErrorCode::Cancelled,
+                    format!("{} was cancelled: {reason}", entry.cmd),
+                ),
+            ));
+        }
+    }
+}
+
+fn transition_state(slot: &CaptureSlot) -> Option<&'static str> {
+    slot.transition.as_ref().map(|transition| transition.state)
+}
+
+fn slot_session_id(slot: &CaptureSlot) -> Value {
+    match (&slot.transition, &slot.active) {
+        (Some(transition), _) => transition.status["sessionId"].clone(),
+        (None, Some(capture)) => json!(capture.session_id),
+        (None, None) => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    // Replies as they would be written to stdout.
+    fn collecting() -> (Dispatcher, Arc<Mutex<Vec<Value>>>) {
+        let replies = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&replies);
+        let dispatcher = Dispatcher::with_reply_sink(Arc::new(move |response: &Response| {
+            sink.lock().unwrap().push(serde_json::to_value(response).unwrap());
+        }));
+        (dispatcher, replies)
+    }
+
+    fn wait_for(replies: &Mutex<Vec<Value>>, count: usize) -> Vec<Value> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while replies.lock().unwrap().len() < count && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        replies.lock().unwrap().clone()
+    }
+
+    // Work that runs until the test sends it the go-ahead.
+    fn gated() -> (Sender<()>, impl FnOnce(String) -> Response + Send + 'static) {
+        let (tx, rx): (Sender<()>, Receiver<()>) = mpsc::channel();
+        (tx, move |id: String| {
+            let _ = rx.recv();
+            Response::ok(id, json!({ "done": true }))
+        })
+    }
+
+    fn ignore(_: Response) {}
+
+    #[test]
+    fn concurrent_replies_keep_their_ids() {
+        let (dispatcher, replies) = collecting();
+        for n in 0..16u64 {
+            let queued = dispatcher.spawn(
+                format!("req-{n}"),
+                "test",
+                true,
+                // Later requests finish first.
+                move |id| {
+                    thread::sleep(Duration::from_millis(2 * (16 - n)));
+                    Response::ok(id, json!({ "n": n }))
+                },
+                ignore,
+            );
+            assert!(queued.is_none());
+        }
+        let replies = wait_for(&replies, 16);
+        assert_eq!(replies.len(), 16);
+        let mut seen: Vec<u64> = replies
+            .iter()
+            .map(|reply| {
+                let n = reply["payload"]["n"].as_u64().unwrap();
+                assert_eq!(reply["id"], format!("req-{n}"));
+                n
+            })
+            .collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..16).collect::<Vec<_>>());
+        assert!(dispatcher.requests().is_empty());
+    }
+
+    #[test]
+    fn a_duplicate_id_is_refused_while_in_flight() {
+        let (dispatcher, replies) = collecting();
+        let (go, work) = gated();
+        assert!(dispatcher.spawn("a".into(), "test", true, work, ignore).is_none());
+
+        let (_go, again) = gated();
+        let refused = dispatcher.spawn("a".into(), "other", true, again, ignore).unwrap();
+        let err = refused.error.unwrap();
+        assert_eq!(err.code, ErrorCode::InvalidPayload);
+        assert_eq!(err.details.unwrap()["cmd"], "test");
+
+        go.send(()).unwrap();
+        assert_eq!(wait_for(&replies, 1).len(), 1);
+        // Once answered, the id is free again.
+        let (go, work) = gated();
+        assert!(dispatcher.spawn("a".into(), "test", true, work, ignore).is_none());
+        go.send(()).unwrap();
+        assert_eq!(wait_for(&replies, 2).len(), 2);
+    }
+
+    static DISCARDED: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_discarded(response: Response) {
+        assert!(response.ok);
+        DISCARDED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn a_cancelled_request_gets_exactly_one_reply() {
+        let (dispatcher, replies) = collecting();
+        let (go, work) = gated();
+        assert!(dispatcher.spawn("a".into(), "test", true, work, count_discarded).is_none());
+
+        let ack = dispatcher.cancel("c".into(), json!({ "requestId": "a" }), None);
+        assert!(ack.ok);
+        assert_eq!(ack.payload.unwrap()["pending"], false);
+        // A second cancel finds nothing left to cancel.
+        let again = dispatcher.cancel("c2".into(), json!({ "requestId": "a" }), None);
+        assert_eq!(again.error.unwrap().code, ErrorCode::NotRunning);
+
+        go.send(()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while DISCARDED.load(Ordering::SeqCst) == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(DISCARDED.load(Ordering::SeqCst), 1);
+        let replies = replies.lock().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["id"], "a");
+        assert_eq!(replies[0]["error"]["code"], "CANCELLED");
+    }
+
+    #[test]
+    fn uncancellable_requests_are_left_running() {
+        let (dispatcher, replies) = collecting();
+        let (go, work) = gated();
+        assert!(dispatcher.spawn("s".into(), "stop_capture", false, work, ignore).is_none());
+        let refused = dispatcher.cancel("c".into(), json!({ "requestId": "s" }), None);
+        assert_eq!(refused.error.unwrap().code, ErrorCode::InvalidPayload);
+
+        go.send(()).unwrap();
+        let replies = wait_for(&replies, 1);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0]["ok"], true);
+    }
+
+    #[test]
+    fn cancel_all_answers_once_and_raises_the_cancel_flag() {
+        let (dispatcher, replies) = collecting();
+        let flag = Arc::new(AtomicBool::new(false));
+        dispatcher.register("start", "start_capture", true, Some(Arc::clone(&flag))).unwrap();
+        dispatcher.register("diag", "collect_diagnostics", true, None).unwrap();
+
+        dispatcher.cancel_all("shutting down");
+        dispatcher.cancel_all("shutting down");
+        assert!(flag.load(Ordering::SeqCst));
+        assert_eq!(replies.lock().unwrap().len(), 2);
+
+        // The workers finish afterwards; their results are handed back, not written.
+        assert!(dispatcher.finish("start", Response::ok("start", json!({}))).is_some());
+        assert!(dispatcher.finish("diag", Response::ok("diag", json!({}))).is_some());
+        assert_eq!(replies.lock().unwrap().len(), 2);
+    }
+
+    fn transition(state: &'static str, ffmpeg_pid: Option<u32>) -> Transition {
+        Transition {
+            state,
+            request_id: "req".to_string(),
+            since: Instant::now(),
+            status: json!({ "sessionId": "settle-test", "outputPath": "/tmp/settle-test.mp4" }),
+            ffmpeg_pid: ffmpeg_pid.map(|pid| Arc::new(AtomicU32::new(pid))),
+        }
+    }
+
+    #[test]
+    fn settle_gives_up_on_a_start_at_the_deadline() {
+        let (dispatcher, _) = collecting();
+        dispatcher.capture().transition = Some(transition("starting", None));
+        assert!(dispatcher.settle(Instant::now()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn settle_kills_a_stop_that_overruns_the_limit() {
+        let (dispatcher, _) = collecting();
+        let mut child = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        dispatcher.capture().transition = Some(transition("stopping", Some(child.id())));
+
+        let deadline = Instant::now().checked_sub(SETTLE_OVERRUN_LIMIT).unwrap();
+        let captures = dispatcher.settle(deadline);
+        assert_eq!(captures.len(), 1);
+        assert_eq!(captures[0]["sessionId"], "settle-test");
+        assert_eq!(captures[0]["status"], "timedOut");
+        // Killed rather than left to outlive the sidecar.
+        assert_eq!(child.wait().unwrap().code(), None);
+    }
+}
@@ -0,0 +1,263 @@
+use rav1e::prelude::*;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::mp4::{av01_sample_entry, Mp4Writer};
+use crate::warnings::{CaptureWarning, WarningKind, WarningLog};
+
+// rav1e's fastest preset; anything slower cannot keep up with screen capture on a CPU.
+const SPEED_PRESET: u8 = 10;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+// Frames captured longer ago than this are dropped instead of encoded, so a CPU that
+// cannot keep up loses frames rather than queueing them without bound.
+const MAX_ENCODE_LAG: Duration = Duration::from_secs(1);
+
+pub struct Av1Encoder {
+    ctx: Context<u8>,
+    muxer: Mp4Writer,
+    width: usize,
+    height: usize,
+    // pts of frames sent to rav1e but not yet returned as packets, oldest first.
+    pending_pts: VecDeque<Duration>,
+    last_pts: Duration,
+    // When the first frame was pushed, and its pts.
+    clock: Option<(Instant, Duration)>,
+    // Set for live capture, where frames too far behind are dropped.
+    realtime: Option<WarningLog>,
+}
+
+impl Av1Encoder {
+    pub fn create(
+        width: u32,
+        height: u32,
+        fps: u32,
+        bitrate: u32,
+        output_path: &str,
+    ) -> Result<Self, String> {
+        let mut enc = EncoderConfig::with_speed_preset(SPEED_PRESET);
+        enc.width = width as usize;
+        enc.height = height as usize;
+        enc.bit_depth = 8;
+        enc.chroma_sampling = ChromaSampling::Cs420;
+        enc.time_base = Rational::new(1, fps.max(1) as u64);
+        enc.bitrate = bitrate.max(1_000_000).min(i32::MAX as u32) as i32;
+        enc.min_key_frame_interval = 0;
+        enc.max_key_frame_interval = (fps.max(1) * 2) as u64;
+        enc.low_latency = true;
+
+        let ctx: Context<u8> = Config::new()
+            .with_encoder_config(enc)
+            .with_threads(0)
+            .new_context()
+            .map_err(|e| format!("rav1e config rejected: {e}"))?;
+        let sample_entry = av01_sample_entry(width, height, &ctx.container_sequence_header());
+        let muxer = Mp4Writer::create(output_path, width, height, sample_entry)
+            .map_err(|e| format!("failed to create output {output_path}: {e}"))?;
+
+        Ok(Self {
+            ctx,
+            muxer,
+            width: width as usize,
+            height: height as usize,
+            pending_pts: VecDeque::new(),
+            last_pts: Duration::ZERO,
+            clock: None,
+            realtime: None,
+        })
+    }
+
+    // Drops frames captured more than MAX_ENCODE_LAG before they reach the encoder and
+    // reports them as dropped-frame warnings of the session.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub fn realtime(mut self, session_id: &str) -> Self {
+        self.realtime = Some(WarningLog::new(session_id, "wgc"));
+        self
+    }
+
+    // `frame` is planar yuv420p at the size given to `create`; odd sizes round the chroma
+    // planes up.
+    pub fn push(&mut self, pts: Duration, frame: &[u8]) -> Result<(), String> {
+        let y_size = self.width * self.height;
+        let uv_w = self.width.div_ceil(2);
+        let uv_size = uv_w * self.height.div_ceil(2);
+        if frame.len() < y_size + uv_size * 2 {
+            return Err("frame buffer smaller than yuv420p size".to_string());
+        }
+
+        let now = Instant::now();
+        let (first_at, first_pts) = *self.clock.get_or_insert((now, pts));
+        let behind = now
+            .duration_since(first_at)
+            .saturating_sub(pts.saturating_sub(first_pts));
+        if let Some(warnings) = self.realtime.as_mut().filter(|_| behind > MAX_ENCODE_LAG) {
+            warnings.record(
+                WarningKind::DroppedFrames,
+                1,
+                Some(&format!(
+                    "av1 encoder is {} ms behind capture",
+                    behind.as_millis()
+                )),
+            );
+            return Ok(());
+        }
+
+        let mut input = self.ctx.new_frame();
+        input.planes[0].copy_from_raw_u8(&frame[..y_size], self.width, 1);
+        input.planes[1].copy_from_raw_u8(&frame[y_size..y_size + uv_size], uv_w, 1);
+        input.planes[2].copy_from_raw_u8(&frame[y_size + uv_size..y_size + uv_size * 2], uv_w, 1);
+        // rav1e reads past the visible area, so the edges must be replicated into the padding.
+        for plane in input.planes.iter_mut() {
+            plane.pad(self.width, self.height);
+        }
+
+        let input = Arc::new(input);
+        self.pending_pts.push_back(pts);
+        self.last_pts = pts;
+        loop {
+            match self.ctx.send_frame(Arc::clone(&input)) {
+                Ok(()) => break,
+                Err(EncoderStatus::EnoughData) => self.drain()?,
+                Err(e) => return Err(format!("rav1e send_frame: {e}")),
+            }
+        }
+        self.drain()
+    }
+
+    // Returns the frame drops of the real-time guard, as the ffmpeg paths report theirs.
+    pub fn finish(mut self) -> Result<Vec<CaptureWarning>, String> {
+        self.ctx.flush();
+        self.drain()?;
+        // The last sample reuses the previous frame duration.
+        self.muxer
+            .finish(self.last_pts)
+            .map_err(|e| format!("failed to finalize mp4: {e}"))?;
+        Ok(self.realtime.map(|log| log.warnings()).unwrap_or_default())
+    }
+
+    fn drain(&mut self) -> Result<(), String> {
+        loop {
+            match self.ctx.receive_packet() {
+                Ok(packet) => {
+                    let pts = self.pending_pts.pop_front().unwrap_or(self.last_pts);
+                    let keyframe = packet.frame_type == FrameType::KEY;
+                    self.muxer
+                        .write_sample(pts, &strip_temporal_delimiters(&packet.data), keyframe)
+                        .map_err(|e| format!("failed to write sample: {e}"))?;
+                }
+                Err(EncoderStatus::Encoded) => continue,
+                Err(EncoderStatus::NeedMoreData) | Err(EncoderStatus::LimitReached) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(format!("rav1e receive_packet: {e}")),
+            }
+        }
+    }
+}
+
+// ISOBMFF AV1 samples must not carry temporal delimiter OBUs; rav1e emits one per packet.
+fn strip_temporal_delimiters(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data[pos];
+        let obu_type = (header >> 3) & 0x0F;
+        let has_extension = header & 0x04 != 0;
+        let has_size = header & 0x02 != 0;
+        let mut cursor = pos + 1 + usize::from(has_extension);
+        if !has_size {
+            // Without a size field the OBU runs to the end of the packet.
+            out.extend_from_slice(&data[pos..]);
+            break;
+        }
+        let mut size: usize = 0;
+        for i in 0..8 {
+            let Some(byte) = data.get(cursor) else {
+                break;
+            };
+            size |= ((byte & 0x7F) as usize) << (7 * i);
+            cursor += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let end = (cursor + size).min(data.len());
+        if obu_type != OBU_TEMPORAL_DELIMITER {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: usize, height: usize, shade: u8) -> Vec<u8> {
+        let chroma = width.div_ceil(2) * height.div_ceil(2);
+        let mut data = vec![shade; width * height];
+        data.resize(width * height + chroma * 2, 128);
+        data
+    }
+
+    #[test]
+    fn encodes_odd_sizes() {
+        let path = std::env::temp_dir().join(format!("av1-test-{}.mp4", std::process::id()));
+        let (width, height) = (35, 19);
+        let mut encoder = Av1Encoder::create(width, height, 30, 1_000_000, path.to_str().unwrap())
+            .unwrap()
+            .realtime("test");
+        for i in 0..6u8 {
+            let pts = Duration::from_millis(i as u64 * 33);
+            encoder
+                .push(pts, &frame(width as usize, height as usize, 16 + i * 30))
+                .unwrap();
+        }
+        let warnings = encoder.finish().unwrap();
+        let file = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(warnings.is_empty());
+        assert_eq!(&file[4..8], b"ftyp");
+    }
+
+    #[test]
+    fn rejects_frames_without_rounded_up_chroma() {
+        let path = std::env::temp_dir().join(format!("av1-short-{}.mp4", std::process::id()));
+        let mut encoder =
+            Av1Encoder::create(35, 19, 30, 1_000_000, path.to_str().unwrap()).unwrap();
+        // Chroma planes sized with truncating division are one row and column short.
+        let short = vec![0u8; 35 * 19 + (17 * 9) * 2];
+        assert!(encoder.push(Duration::ZERO, &short).is_err());
+        drop(encoder);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn drops_frames_that_fall_behind_capture() {
+        let path = std::env::temp_dir().join(format!("av1-lag-{}.mp4", std::process::id()));
+        let mut encoder = Av1Encoder::create(16, 16, 30, 1_000_000, path.to_str().unwrap())
+            .unwrap()
+            .realtime("test");
+        encoder.push(Duration::ZERO, &frame(16, 16, 40)).unwrap();
+        // Pretend the first frame was pushed two seconds ago; a frame captured 100 ms
+        // after it is now far behind.
+        let (first_at, first_pts) = encoder.clock.unwrap();
+        encoder.clock = Some((first_at - Duration::from_secs(2), first_pts));
+        encoder
+            .push(Duration::from_millis(100), &frame(16, 16, 80))
+            .unwrap();
+        let warnings = encoder.finish().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::DroppedFrames);
+        assert_eq!(warnings[0].count, 1);
+    }
+
+    #[test]
+    fn strips_temporal_delimiters() {
+        // Temporal delimiter (size 0), then a sequence header OBU with two payload bytes.
+        let packet = [0x12, 0x00, 0x0A, 0x02, 0xAA, 0xBB];
+        assert_eq!(strip_temporal_delimiters(&packet), [0x0A, 0x02, 0xAA, 0xBB]);
+    }
+}
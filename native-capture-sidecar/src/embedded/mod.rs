@@ -0,0 +1,48 @@
+#[cfg(feature = "embedded-encoder")]
+mod av1;
+#[cfg(feature = "embedded-encoder")]
+mod mp4;
+
+// In-process encoding for raw-frame backends: no ffmpeg binary, subprocess or pipe.
+pub const EMBEDDED_AV1_ENCODER: &str = "av1_rav1e";
+
+pub fn is_embedded_encoder(encoder: &str) -> bool {
+    encoder == EMBEDDED_AV1_ENCODER
+}
+
+pub fn embedded_encoder_available() -> bool {
+    cfg!(feature = "embedded-encoder")
+}
+
+#[cfg(feature = "embedded-encoder")]
+pub use av1::Av1Encoder;
+
+#[cfg(not(feature = "embedded-encoder"))]
+pub struct Av1Encoder;
+
+#[cfg(not(feature = "embedded-encoder"))]
+impl Av1Encoder {
+    pub fn create(
+        _width: u32,
+        _height: u32,
+        _fps: u32,
+        _bitrate: u32,
+        _output_path: &str,
+    ) -> Result<Self, String> {
+        Err(format!(
+            "{EMBEDDED_AV1_ENCODER} requested, but the sidecar was built without the embedded-encoder feature"
+        ))
+    }
+
+    pub fn realtime(self, _session_id: &str) -> Self {
+        self
+    }
+
+    pub fn push(&mut self, _pts: std::time::Duration, _frame: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Vec<crate::warnings::CaptureWarning>, String> {
+        Ok(Vec::new())
+    }
+}
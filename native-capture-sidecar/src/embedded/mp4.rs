@@ -0,0 +1,405 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Single-track MP4 writer for the in-process encoder. Samples are streamed into one
+// `mdat` (64-bit size, patched on finish), so nothing but the tables is held in memory.
+// Finish copies the file once to put `moov` in front, like ffmpeg's faststart.
+
+const MEDIA_TIMESCALE: u32 = 90_000;
+const MOVIE_TIMESCALE: u32 = 1_000;
+
+pub struct Mp4Writer {
+    path: PathBuf,
+    out: BufWriter<File>,
+    width: u32,
+    height: u32,
+    sample_entry: Vec<u8>,
+    mdat_start: u64,
+    offset: u64,
+    offsets: Vec<u64>,
+    sizes: Vec<u32>,
+    pts: Vec<u64>,
+    sync_samples: Vec<u32>,
+}
+
+impl Mp4Writer {
+    // `sample_entry` is the complete visual sample entry box (e.g. `av01` with `av1C`).
+    pub fn create(path: &str, width: u32, height: u32, sample_entry: Vec<u8>) -> io::Result<Self> {
+        // Read back by the faststart copy on finish.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut out = BufWriter::new(file);
+
+        let mut ftyp = Vec::new();
+        ftyp.extend_from_slice(b"isom");
+        ftyp.extend_from_slice(&0x200u32.to_be_bytes());
+        for brand in [b"isom", b"iso6", b"av01", b"mp41"] {
+            ftyp.extend_from_slice(brand);
+        }
+        out.write_all(&boxed(b"ftyp", &ftyp))?;
+
+        let mdat_start = out.stream_position()?;
+        out.write_all(&1u32.to_be_bytes())?;
+        out.write_all(b"mdat")?;
+        out.write_all(&0u64.to_be_bytes())?;
+        let offset = out.stream_position()?;
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            out,
+            width,
+            height,
+            sample_entry,
+            mdat_start,
+            offset,
+            offsets: Vec::new(),
+            sizes: Vec::new(),
+            pts: Vec::new(),
+            sync_samples: Vec::new(),
+        })
+    }
+
+    pub fn write_sample(&mut self, pts: Duration, data: &[u8], keyframe: bool) -> io::Result<()> {
+        self.out.write_all(data)?;
+        self.offsets.push(self.offset);
+        self.sizes.push(data.len() as u32);
+        self.pts.push(to_media_time(pts));
+        if keyframe {
+            self.sync_samples.push(self.sizes.len() as u32);
+        }
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    // `end` is when the last sample stops being displayed. If the faststart copy cannot be
+    // written the tables are appended after `mdat` instead, which players also accept.
+    pub fn finish(mut self, end: Duration) -> io::Result<()> {
+        let mdat_size = self.offset - self.mdat_start;
+        let durations = self.sample_durations(to_media_time(end));
+        let media_duration: u64 = durations.iter().map(|d| *d as u64).sum();
+        let movie_duration = media_duration * MOVIE_TIMESCALE as u64 / MEDIA_TIMESCALE as u64;
+
+        self.out.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.out.write_all(&mdat_size.to_be_bytes())?;
+        self.out.flush()?;
+
+        // In front of `mdat`, `moov` moves every sample by its own size, which the offsets
+        // in it do not change.
+        let moov_len = self.moov(&durations, media_duration, movie_duration, 0).len() as u64;
+        let moov = self.moov(&durations, media_duration, movie_duration, moov_len);
+        let faststart = self.path.with_extension("faststart.tmp");
+        match self.copy_with_moov_first(&faststart, &moov) {
+            Ok(()) => {
+                let Self { out, path, .. } = self;
+                drop(out);
+                fs::rename(&faststart, &path)
+            }
+            Err(err) => {
+                log!(
+                    "[native-capture][av1] faststart copy failed, appending moov path={} err={}",
+                    self.path.display(),
+                    err
+                );
+                let _ = fs::remove_file(&faststart);
+                let moov = self.moov(&durations, media_duration, movie_duration, 0);
+                self.out.seek(SeekFrom::End(0))?;
+                self.out.write_all(&moov)?;
+                self.out.flush()
+            }
+        }
+    }
+
+    fn copy_with_moov_first(&mut self, path: &Path, moov: &[u8]) -> io::Result<()> {
+        let recorded = self.out.get_mut();
+        recorded.seek(SeekFrom::Start(0))?;
+        let mut out = BufWriter::new(File::create(path)?);
+        io::copy(&mut Read::take(&mut *recorded, self.mdat_start), &mut out)?;
+        out.write_all(moov)?;
+        io::copy(recorded, &mut out)?;
+        out.flush()
+    }
+
+    fn sample_durations(&self, end: u64) -> Vec<u32> {
+        let mut durations: Vec<u32> = self
+            .pts
+            .windows(2)
+            .map(|pair| pair[1].saturating_sub(pair[0]).max(1) as u32)
+            .collect();
+        if let Some(last) = self.pts.last() {
+            let fallback = durations.last().copied().unwrap_or(MEDIA_TIMESCALE / 30);
+            let tail = end.saturating_sub(*last);
+            durations.push(if tail > 0 { tail as u32 } else { fallback });
+        }
+        durations
+    }
+
+    // `shift` is added to every sample offset.
+    fn moov(&self, durations: &[u32], media_duration: u64, movie_duration: u64, shift: u64) -> Vec<u8> {
+        let mut mvhd = full_header(0, 0);
+        mvhd.extend_from_slice(&[0; 8]); // creation/modification time
+        mvhd.extend_from_slice(&MOVIE_TIMESCALE.to_be_bytes());
+        mvhd.extend_from_slice(&(movie_duration as u32).to_be_bytes());
+        mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate 1.0
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume 1.0
+        mvhd.extend_from_slice(&[0; 10]);
+        mvhd.extend_from_slice(&unity_matrix());
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&2u32.to_be_bytes()); // next track id
+
+        let mut tkhd = full_header(0, 0x3); // enabled | in movie
+        tkhd.extend_from_slice(&[0; 8]);
+        tkhd.extend_from_slice(&1u32.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 4]);
+        tkhd.extend_from_slice(&(movie_duration as u32).to_be_bytes());
+        tkhd.extend_from_slice(&[0; 8]);
+        tkhd.extend_from_slice(&[0; 4]); // layer, alternate group
+        tkhd.extend_from_slice(&[0; 4]); // volume, reserved
+        tkhd.extend_from_slice(&unity_matrix());
+        tkhd.extend_from_slice(&(self.width << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(self.height << 16).to_be_bytes());
+
+        let mut mdhd = full_header(0, 0);
+        mdhd.extend_from_slice(&[0; 8]);
+        mdhd.extend_from_slice(&MEDIA_TIMESCALE.to_be_bytes());
+        mdhd.extend_from_slice(&(media_duration as u32).to_be_bytes());
+        mdhd.extend_from_slice(&0x55C4u16.to_be_bytes()); // "und"
+        mdhd.extend_from_slice(&[0; 2]);
+
+        let mut hdlr = full_header(0, 0);
+        hdlr.extend_from_slice(&[0; 4]);
+        hdlr.extend_from_slice(b"vide");
+        hdlr.extend_from_slice(&[0; 12]);
+        hdlr.extend_from_slice(b"VideoHandler\0");
+
+        let mut vmhd = full_header(0, 1);
+        vmhd.extend_from_slice(&[0; 8]);
+
+        let mut dref = full_header(0, 0);
+        dref.extend_from_slice(&1u32.to_be_bytes());
+        dref.extend_from_slice(&boxed(b"url ", &full_header(0, 1)));
+
+        let mut stsd = full_header(0, 0);
+        stsd.extend_from_slice(&1u32.to_be_bytes());
+        stsd.extend_from_slice(&self.sample_entry);
+
+        let mut stts = full_header(0, 0);
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for duration in durations {
+            match runs.last_mut() {
+                Some((count, delta)) if delta == duration => *count += 1,
+                _ => runs.push((1, *duration)),
+            }
+        }
+        stts.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+        for (count, delta) in runs {
+            stts.extend_from_slice(&count.to_be_bytes());
+            stts.extend_from_slice(&delta.to_be_bytes());
+        }
+
+        let mut stss = full_header(0, 0);
+        stss.extend_from_slice(&(self.sync_samples.len() as u32).to_be_bytes());
+        for sample in &self.sync_samples {
+            stss.extend_from_slice(&sample.to_be_bytes());
+        }
+
+        // One sample per chunk keeps the chunk tables trivial.
+        let mut stsc = full_header(0, 0);
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+        stsc.extend_from_slice(&1u32.to_be_bytes());
+
+        let mut stsz = full_header(0, 0);
+        stsz.extend_from_slice(&0u32.to_be_bytes());
+        stsz.extend_from_slice(&(self.sizes.len() as u32).to_be_bytes());
+        for size in &self.sizes {
+            stsz.extend_from_slice(&size.to_be_bytes());
+        }
+
+        let mut co64 = full_header(0, 0);
+        co64.extend_from_slice(&(self.offsets.len() as u32).to_be_bytes());
+        for offset in &self.offsets {
+            co64.extend_from_slice(&(offset + shift).to_be_bytes());
+        }
+
+        let stbl = [
+            boxed(b"stsd", &stsd),
+            boxed(b"stts", &stts),
+            boxed(b"stss", &stss),
+            boxed(b"stsc", &stsc),
+            boxed(b"stsz", &stsz),
+            boxed(b"co64", &co64),
+        ]
+        .concat();
+        let minf = [
+            boxed(b"vmhd", &vmhd),
+            boxed(b"dinf", &boxed(b"dref", &dref)),
+            boxed(b"stbl", &stbl),
+        ]
+        .concat();
+        let mdia = [
+            boxed(b"mdhd", &mdhd),
+            boxed(b"hdlr", &hdlr),
+            boxed(b"minf", &minf),
+        ]
+        .concat();
+        let trak = [boxed(b"tkhd", &tkhd), boxed(b"mdia", &mdia)].concat();
+        boxed(
+            b"moov",
+            &[boxed(b"mvhd", &mvhd), boxed(b"trak", &trak)].concat(),
+        )
+    }
+}
+
+pub fn av01_sample_entry(width: u32, height: u32, av1c: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&[0; 6]);
+    entry.extend_from_slice(&1u16.to_be_bytes()); // data reference index
+    entry.extend_from_slice(&[0; 16]);
+    entry.extend_from_slice(&(width as u16).to_be_bytes());
+    entry.extend_from_slice(&(height as u16).to_be_bytes());
+    entry.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // 72 dpi
+    entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+    entry.extend_from_slice(&[0; 4]);
+    entry.extend_from_slice(&1u16.to_be_bytes()); // frame count
+    entry.extend_from_slice(&[0; 32]); // compressor name
+    entry.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+    entry.extend_from_slice(&0xFFFFu16.to_be_bytes());
+    entry.extend_from_slice(&boxed(b"av1C", av1c));
+    boxed(b"av01", &entry)
+}
+
+fn to_media_time(pts: Duration) -> u64 {
+    (pts.as_nanos() * MEDIA_TIMESCALE as u128 / 1_000_000_000) as u64
+}
+
+fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 8);
+    out.extend_from_slice(&((body.len() + 8) as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+fn full_header(version: u8, flags: u32) -> Vec<u8> {
+    let mut out = flags.to_be_bytes().to_vec();
+    out[0] = version;
+    out
+}
+
+fn unity_matrix() -> [u8; 36] {
+    let mut matrix = [0u8; 36];
+    matrix[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    matrix[16..20].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    matrix[32..36].copy_from_slice(&0x4000_0000u32.to_be_bytes());
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (type, body) of each box in `buf`.
+    fn boxes(buf: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let mut size = u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap()) as u64;
+            let kind: [u8; 4] = buf[pos + 4..pos + 8].try_into().unwrap();
+            let mut header = 8;
+            if size == 1 {
+                size = u64::from_be_bytes(buf[pos + 8..pos + 16].try_into().unwrap());
+                header = 16;
+            }
+            let end = pos + size as usize;
+            out.push((kind, &buf[pos + header..end]));
+            pos = end;
+        }
+        assert_eq!(pos, buf.len(), "boxes overrun their parent");
+        out
+    }
+
+    fn child<'a>(buf: &'a [u8], path: &[&[u8; 4]]) -> &'a [u8] {
+        path.iter().fold(buf, |body, kind| {
+            boxes(body)
+                .into_iter()
+                .find(|(k, _)| k == *kind)
+                .unwrap_or_else(|| panic!("no {} box", String::from_utf8_lossy(*kind)))
+                .1
+        })
+    }
+
+    fn be32(buf: &[u8], at: usize) -> u32 {
+        u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn muxes_samples_into_mdat_behind_moov() {
+        let path = std::env::temp_dir().join(format!("mp4-test-{}.mp4", std::process::id()));
+        let entry = av01_sample_entry(64, 48, &[0x81, 0x00, 0x0C, 0x00]);
+        let mut writer = Mp4Writer::create(path.to_str().unwrap(), 64, 48, entry).unwrap();
+        let samples: [(u64, &[u8], bool); 4] = [
+            (0, b"key-0", true),
+            (33, b"delta-1", false),
+            (66, b"delta-two", false),
+            (100, b"key-3", true),
+        ];
+        for (ms, data, keyframe) in samples {
+            writer
+                .write_sample(Duration::from_millis(ms), data, keyframe)
+                .unwrap();
+        }
+        writer.finish(Duration::from_millis(133)).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let top = boxes(&file);
+        let kinds: Vec<&[u8; 4]> = top.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"ftyp", b"moov", b"mdat"]);
+        assert_eq!(&top[0].1[..4], b"isom");
+        assert_eq!(top[2].1, b"key-0delta-1delta-twokey-3");
+
+        let stbl = child(top[1].1, &[b"trak", b"mdia", b"minf", b"stbl"]);
+        let stsd = child(stbl, &[b"stsd"]);
+        assert_eq!(be32(stsd, 4), 1);
+        assert_eq!(&stsd[12..16], b"av01");
+
+        let stsz = child(stbl, &[b"stsz"]);
+        assert_eq!(be32(stsz, 4), 0);
+        assert_eq!(be32(stsz, 8), samples.len() as u32);
+        let sizes: Vec<u32> = (0..samples.len()).map(|i| be32(stsz, 12 + i * 4)).collect();
+        assert_eq!(sizes, [5, 7, 9, 5]);
+
+        // Chunk offsets point at each sample inside mdat.
+        let co64 = child(stbl, &[b"co64"]);
+        assert_eq!(be32(co64, 4), 4);
+        let mut expected = b"key-0delta-1delta-twokey-3".as_slice();
+        for (i, size) in sizes.iter().enumerate() {
+            let at = 8 + i * 8;
+            let offset = u64::from_be_bytes(co64[at..at + 8].try_into().unwrap()) as usize;
+            let (sample, rest) = expected.split_at(*size as usize);
+            assert_eq!(&file[offset..offset + *size as usize], sample);
+            expected = rest;
+        }
+
+        let stss = child(stbl, &[b"stss"]);
+        assert_eq!(be32(stss, 4), 2);
+        assert_eq!((be32(stss, 8), be32(stss, 12)), (1, 4));
+
+        // 33 ms steps at 90 kHz, then the tail up to `end`.
+        let stts = child(stbl, &[b"stts"]);
+        let runs: Vec<(u32, u32)> = (0..be32(stts, 4) as usize)
+            .map(|i| (be32(stts, 8 + i * 8), be32(stts, 12 + i * 8)))
+            .collect();
+        assert_eq!(runs, [(2, 2970), (1, 3060), (1, 2970)]);
+        let mdhd = child(top[1].1, &[b"trak", b"mdia", b"mdhd"]);
+        assert_eq!(be32(mdhd, 12), MEDIA_TIMESCALE);
+        assert_eq!(be32(mdhd, 16), 133 * 90);
+    }
+}
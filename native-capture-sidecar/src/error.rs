@@ -0,0 +1,111 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::warnings::WarningKind;
+
+// Stable identifiers the app can branch on and localize; `message` stays English and is
+// meant for logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    FfmpegNotFound,
+    EncoderUnavailable,
+    InvalidPayload,
+    UnknownCommand,
+    UnsupportedPlatform,
+    AlreadyRunning,
+    NotRunning,
+    SessionMismatch,
+    SessionNotFound,
+    OutputExists,
+    OutputDirNotFound,
+    PermissionDenied,
+    // The captured window or monitor went away; only WGC notices.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    SourceGone,
+    DiskFull,
+    // ffmpeg failed for a reason none of the codes above describe.
+    FfmpegFailed,
+    CaptureFailed,
+    // The request was withdrawn with `cancel`, or the sidecar shut down before it finished.
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<Value>,
+}
+
+impl CaptureError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), details: None }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    // Appends advice to the message without touching the code.
+    pub fn with_hint(mut self, hint: &str) -> Self {
+        self.message = format!("{} {hint}", self.message);
+        self
+    }
+
+    pub fn invalid_payload(cmd: &str, err: impl fmt::Display) -> Self {
+        Self::new(ErrorCode::InvalidPayload, format!("invalid {cmd} payload: {err}"))
+            .with_details(json!({ "command": cmd }))
+    }
+
+    pub fn not_running() -> Self {
+        Self::new(ErrorCode::NotRunning, "capture is not running")
+    }
+
+    pub fn session_mismatch(active: &str, requested: &str) -> Self {
+        Self::new(ErrorCode::SessionMismatch, "sessionId mismatch")
+            .with_details(json!({ "activeSessionId": active, "sessionId": requested }))
+    }
+
+    // File system failures keep their cause when it is one the app can act on.
+    pub fn io(context: impl fmt::Display, err: &std::io::Error) -> Self {
+        let code = match err.kind() {
+            std::io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+            std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => ErrorCode::DiskFull,
+            _ => ErrorCode::CaptureFailed,
+        };
+        Self::new(code, format!("{context}: {err}"))
+    }
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// Untyped backend failures (Win32/WinRT calls, frame plumbing) end up as CAPTURE_FAILED.
+impl From<String> for CaptureError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorCode::CaptureFailed, message)
+    }
+}
+
+impl From<&str> for CaptureError {
+    fn from(message: &str) -> Self {
+        Self::new(ErrorCode::CaptureFailed, message)
+    }
+}
+
+impl From<WarningKind> for ErrorCode {
+    fn from(kind: WarningKind) -> Self {
+        match kind {
+            WarningKind::EncoderInitFailed => ErrorCode::EncoderUnavailable,
+            WarningKind::PermissionDenied => ErrorCode::PermissionDenied,
+            WarningKind::DiskFull => ErrorCode::DiskFull,
+            _ => ErrorCode::FfmpegFailed,
+        }
+    }
+}
@@ -1,34 +1,17 @@
+use serde::Serialize;
 use serde_json::json;
-use std::path::Path;
 use std::process::{Command, Stdio};
 
-use crate::protocol::{EncoderOptionsPayload, Response};
+use crate::discovery::discover_ffmpeg;
+use crate::embedded::{embedded_encoder_available, is_embedded_encoder, EMBEDDED_AV1_ENCODER};
+use crate::error::CaptureError;
+use crate::pipeline::{FitMode, PadColor};
+use crate::protocol::{EncoderOptionsPayload, Response, VideoConfig};
 use crate::system::detect_gpu_vendors_windows;
 
+// The ffmpeg discovery picks, checked for capture support but not for any encoder.
 pub fn resolve_ffmpeg_path(preferred: Option<&str>) -> Option<String> {
-    if let Some(path) = preferred {
-        if Path::new(path).exists() {
-            return Some(path.to_string());
-        }
-    }
-
-    let candidate = if cfg!(target_os = "windows") {
-        "ffmpeg.exe"
-    } else {
-        "ffmpeg"
-    };
-
-    let probe = Command::new(candidate)
-        .arg("-version")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
-
-    if probe.is_ok() {
-        Some(candidate.to_string())
-    } else {
-        None
-    }
+    discover_ffmpeg(preferred, None).path()
 }
 
 pub fn ffmpeg_has_encoder(ffmpeg_exe: &str, encoder_name: &str) -> bool {
@@ -51,39 +34,280 @@ pub fn ffmpeg_has_encoder(ffmpeg_exe: &str, encoder_name: &str) -> bool {
     text.contains(encoder_name)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BitrateMode {
+    // `video.bitrate` as sent.
+    Manual,
+    // A bitrate derived from the size, frame rate, codec and quality tier.
+    Auto,
+    // Constant quality (CRF/CQ/QP) under a bitrate ceiling.
+    Quality,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityTier {
+    Draft,
+    Standard,
+    High,
+}
+
+impl QualityTier {
+    // H.264 bits per pixel and frame that keep text sharp in screen content.
+    fn bits_per_pixel(self) -> f64 {
+        match self {
+            QualityTier::Draft => 0.05,
+            QualityTier::Standard => 0.08,
+            QualityTier::High => 0.12,
+        }
+    }
+}
+
+// Rate control of a lossy recording, resolved from `bitrateMode`, `bitrate` and `quality`.
+// Returned by start_capture so the app can show the expected file size.
+#[derive(Debug, Clone, Serialize)]
+pub struct RateControl {
+    pub mode: BitrateMode,
+    pub tier: QualityTier,
+    // Target in bits/s; the expected average in quality mode.
+    pub bitrate: u32,
+    #[serde(rename = "maxBitrate")]
+    pub max_bitrate: u32,
+    #[serde(rename = "bufferSize")]
+    pub buffer_size: u32,
+    // "crf" (libx264), "cq" (NVENC) or "qp" (AMF) and its value, in quality mode.
+    #[serde(rename = "qualityParam")]
+    pub quality_param: Option<&'static str>,
+    #[serde(rename = "qualityValue")]
+    pub quality_value: Option<u32>,
+    #[serde(rename = "estimatedBytesPerMinute")]
+    pub estimated_bytes_per_minute: u64,
+}
+
+impl RateControl {
+    pub fn manual(bitrate: u32) -> Self {
+        let target = bitrate.max(1_000_000);
+        Self {
+            mode: BitrateMode::Manual,
+            tier: QualityTier::Standard,
+            bitrate: target,
+            max_bitrate: target,
+            buffer_size: bitrate.saturating_mul(3),
+            quality_param: None,
+            quality_value: None,
+            estimated_bytes_per_minute: u64::from(target) * 60 / 8,
+        }
+    }
+}
+
+pub fn rate_control(video: &VideoConfig) -> Result<RateControl, String> {
+    let tier = match video.quality.as_deref() {
+        None | Some("standard") => QualityTier::Standard,
+        Some("draft") => QualityTier::Draft,
+        Some("high") => QualityTier::High,
+        Some(other) => {
+            return Err(format!(
+                "unsupported quality: {other} (expected draft, standard, high or lossless)"
+            ))
+        }
+    };
+    let mode = match video.bitrate_mode.as_deref() {
+        None | Some("manual") => BitrateMode::Manual,
+        Some("auto") => BitrateMode::Auto,
+        Some("quality") => BitrateMode::Quality,
+        Some(other) => {
+            return Err(format!(
+                "unsupported bitrateMode: {other} (expected manual, auto or quality)"
+            ))
+        }
+    };
+    let target = auto_bitrate(video.width, video.height, video.fps, &video.encoder, tier);
+    let rate = match mode {
+        BitrateMode::Manual if video.bitrate == 0 => {
+            return Err("video.bitrate is required unless bitrateMode is auto or quality".to_string())
+        }
+        BitrateMode::Manual => RateControl::manual(video.bitrate),
+        BitrateMode::Auto => RateControl {
+            mode,
+            tier,
+            ..RateControl::manual(target)
+        },
+        BitrateMode::Quality => {
+            if video.encoder == EMBEDDED_AV1_ENCODER {
+                return Err(format!("{} does not support bitrateMode quality", video.encoder));
+            }
+            let (param, value) = quality_value(&video.encoder, tier);
+            // Busy scenes may spend up to twice the expected average.
+            let ceiling = target.saturating_mul(2);
+            RateControl {
+                mode,
+                tier,
+                bitrate: target,
+                max_bitrate: ceiling,
+                buffer_size: ceiling.saturating_mul(2),
+                quality_param: Some(param),
+                quality_value: Some(value),
+                estimated_bytes_per_minute: u64::from(target) * 60 / 8,
+            }
+        }
+    };
+    Ok(rate)
+}
+
+// Target bitrate for `bitrateMode: "auto"`, rounded to 100 kb/s.
+fn auto_bitrate(width: u32, height: u32, fps: u32, encoder: &str, tier: QualityTier) -> u32 {
+    // Bitrate relative to H.264 for the same picture.
+    let efficiency = match encoder {
+        "hevc_nvenc" => 0.65,
+        EMBEDDED_AV1_ENCODER => 0.55,
+        _ => 1.0,
+    };
+    // Frames above 30 fps differ less from their neighbours and cost about half as much.
+    let effective_fps = f64::from(fps.min(30)) + f64::from(fps.saturating_sub(30)) / 2.0;
+    let bits = f64::from(width) * f64::from(height) * effective_fps * tier.bits_per_pixel() * efficiency;
+    ((bits / 100_000.0).round() * 100_000.0).clamp(1_000_000.0, 80_000_000.0) as u32
+}
+
+// The encoder's constant-quality parameter for a tier; lower is better in all three.
+fn quality_value(encoder: &str, tier: QualityTier) -> (&'static str, u32) {
+    let (param, [draft, standard, high]) = match encoder {
+        "h264_nvenc" => ("cq", [30, 24, 19]),
+        "hevc_nvenc" => ("cq", [32, 26, 21]),
+        "h264_amf" => ("qp", [30, 24, 20]),
+        _ => ("crf", [28, 23, 18]),
+    };
+    let value = match tier {
+        QualityTier::Draft => draft,
+        QualityTier::Standard => standard,
+        QualityTier::High => high,
+    };
+    (param, value)
+}
+
+// ffmpeg codec and default arguments for a recording encoder; names it does not know record
+// with libx264.
+pub fn encoder_codec_args(encoder: &str) -> (&'static str, &'static [&'static str]) {
+    match encoder {
+        "h264_nvenc" => ("h264_nvenc", &["-preset", "p4", "-tune", "ll", "-rc", "vbr", "-cq", "20"]),
+        "hevc_nvenc" => ("hevc_nvenc", &["-preset", "p4", "-tune", "ll", "-rc", "vbr", "-cq", "22"]),
+        "h264_amf" => ("h264_amf", &["-quality", "quality"]),
+        // medium preset produces dramatically sharper text/UI than ultrafast at the
+        // cost of slightly more CPU; still real-time at 1080p60 on any modern CPU.
+        _ => ("libx264", &["-preset", "medium", "-tune", "zerolatency"]),
+    }
+}
+
+// The ffmpeg encoder a recording with these settings runs; None for the in-process encoder.
+pub fn ffmpeg_encoder_name(video: &VideoConfig) -> Option<String> {
+    if let Some(codec) = video.lossless_codec() {
+        return lossless_codec_args(codec).ok().map(|args| args[1].clone());
+    }
+    (!is_embedded_encoder(&video.encoder)).then(|| encoder_codec_args(&video.encoder).0.to_string())
+}
+
+// Rate control arguments shared by the lossy command builders. They go after the encoder
+// arguments, so a quality value replaces the CQ those set by default.
+pub fn rate_control_args(encoder: &str, rate: &RateControl) -> Vec<String> {
+    let (bitrate, max_bitrate, buffer_size) = (
+        rate.bitrate.to_string(),
+        rate.max_bitrate.to_string(),
+        rate.buffer_size.to_string(),
+    );
+    let value = rate.quality_value.map(|value| value.to_string());
+    let args: Vec<&str> = match &value {
+        None => vec!["-b:v", &bitrate, "-maxrate", &max_bitrate, "-bufsize", &buffer_size],
+        Some(value) => match encoder {
+            "h264_nvenc" | "hevc_nvenc" => vec![
+                "-rc", "vbr", "-cq", &value, "-b:v", "0", "-maxrate", &max_bitrate, "-bufsize", &buffer_size,
+            ],
+            // AMF's constant QP mode has no ceiling.
+            "h264_amf" => vec!["-rc", "cqp", "-qp_i", &value, "-qp_p", &value],
+            _ => vec!["-crf", &value, "-maxrate", &max_bitrate, "-bufsize", &buffer_size],
+        },
+    };
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+// `-movflags` of MP4 recordings. Each keyframe opens a fragment, so a recording cut off by
+// a crash still parses up to its last fragment and `recover_session` can remux it. Stop
+// remuxes the finished recording to faststart before it is delivered.
+pub const RECORDING_MOVFLAGS: &str = "+frag_keyframe+empty_moov+default_base_moof";
+
+// Codec arguments for `video.quality: "lossless"`. These replace the encoder, bitrate, GOP
+// and pixel format arguments entirely; the output is always Matroska. Every codec encodes
+// RGB, since going through 4:2:0 chroma would lose detail before the codec sees it.
+pub fn lossless_codec_args(codec: &str) -> Result<Vec<String>, String> {
+    let args: &[&str] = match codec {
+        "ffv1" => &[
+            "-c:v", "ffv1", "-level", "3", "-g", "1", "-slices", "16", "-slicecrc", "1",
+            "-pix_fmt", "bgr0",
+        ],
+        "utvideo" => &["-c:v", "utvideo", "-pred", "median", "-pix_fmt", "gbrp"],
+        "x264" => &["-c:v", "libx264rgb", "-preset", "ultrafast", "-qp", "0", "-pix_fmt", "bgr0"],
+        other => {
+            return Err(format!(
+                "unsupported lossless codec: {other} (expected ffv1, utvideo or x264)"
+            ))
+        }
+    };
+    Ok(args.iter().map(|arg| arg.to_string()).collect())
+}
+
+// `-vf` chain bringing an ffmpeg-captured input to width x height under the given fit.
+// Pad and crop offsets are rounded down to even values so yuv420p chroma stays aligned.
+pub fn fit_filter(width: u32, height: u32, fit: FitMode, pad: PadColor) -> String {
+    match fit {
+        FitMode::Stretch => format!("scale={width}x{height}"),
+        FitMode::Contain => format!(
+            "scale={width}:{height}:force_original_aspect_ratio=decrease:force_divisible_by=2,pad={width}:{height}:trunc((ow-iw)/4)*2:trunc((oh-ih)/4)*2:color={}",
+            pad.to_ffmpeg()
+        ),
+        FitMode::Cover => format!(
+            "scale={width}:{height}:force_original_aspect_ratio=increase,crop={width}:{height}:trunc((iw-{width})/4)*2:trunc((ih-{height})/4)*2"
+        ),
+    }
+}
+
 pub fn handle_get_encoder_options(id: String, payload: serde_json::Value) -> Response {
     let payload: EncoderOptionsPayload = match serde_json::from_value(payload) {
         Ok(v) => v,
         Err(err) => {
-            return Response::err(id, format!("invalid get_encoder_options payload: {err}"));
+            return Response::err(id, CaptureError::invalid_payload("get_encoder_options", err));
         }
     };
+    let options = encoder_options(&payload.platform, payload.ffmpeg_path.as_deref());
+    Response::ok(id, json!({ "options": options }))
+}
 
+// Encoders this machine can record with, as `{ codec, label, hardware }`. libx264 is always
+// listed; the GPU encoders need both ffmpeg support and a matching GPU.
+pub fn encoder_options(platform: &str, ffmpeg_path: Option<&str>) -> Vec<serde_json::Value> {
     let mut options = vec![json!({
         "codec": "h264_libx264",
         "label": "x264 CPU",
         "hardware": "cpu",
     })];
 
-    if payload.platform != "win32" {
-        eprintln!(
+    if platform != "win32" {
+        log!(
             "[encoder-options][sidecar] Non-win32 platform={}, returning CPU-only option",
-            payload.platform
+            platform
         );
-        return Response::ok(id, json!({ "options": options }));
+        return options;
     }
 
-    let ffmpeg_exe = resolve_ffmpeg_path(payload.ffmpeg_path.as_deref());
-    eprintln!(
+    let ffmpeg_exe = resolve_ffmpeg_path(ffmpeg_path);
+    log!(
         "[encoder-options][sidecar] ffmpeg_path_input={:?} resolved_ffmpeg={:?}",
-        payload.ffmpeg_path, ffmpeg_exe
+        ffmpeg_path, ffmpeg_exe
     );
     if let Some(ffmpeg_exe) = ffmpeg_exe {
         let gpu_vendors = detect_gpu_vendors_windows();
-        eprintln!("[encoder-options][sidecar] gpu_vendor_detection={:?}", gpu_vendors);
+        log!("[encoder-options][sidecar] gpu_vendor_detection={:?}", gpu_vendors);
         let has_nvenc = ffmpeg_has_encoder(&ffmpeg_exe, "h264_nvenc");
         let has_amf = ffmpeg_has_encoder(&ffmpeg_exe, "h264_amf");
-        eprintln!(
+        log!(
             "[encoder-options][sidecar] encoder_detection ffmpeg={} h264_nvenc={} h264_amf={}",
             ffmpeg_exe, has_nvenc, has_amf
         );
@@ -95,7 +319,7 @@ pub fn handle_get_encoder_options(id: String, payload: serde_json::Value) -> Res
             Some((_, has_amd_gpu)) => has_amd_gpu,
             None => true,
         };
-        eprintln!(
+        log!(
             "[encoder-options][sidecar] encoder_gating allow_nvenc={} allow_amf={}",
             allow_nvenc, allow_amf
         );
@@ -114,7 +338,110 @@ pub fn handle_get_encoder_options(id: String, payload: serde_json::Value) -> Res
             }));
         }
     }
-    eprintln!("[encoder-options][sidecar] returning options={}", json!({ "options": options }));
+    if embedded_encoder_available() {
+        options.push(json!({
+            "codec": EMBEDDED_AV1_ENCODER,
+            "label": "AV1 (built-in, no FFmpeg)",
+            "hardware": "cpu",
+        }));
+    }
+    log!("[encoder-options][sidecar] returning options={}", json!({ "options": options }));
 
-    Response::ok(id, json!({ "options": options }))
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(encoder: &str, width: u32, height: u32, fps: u32) -> VideoConfig {
+        VideoConfig {
+            width,
+            height,
+            fps,
+            encoder: encoder.to_string(),
+            ..VideoConfig::default()
+        }
+    }
+
+    fn with_mode(mut video: VideoConfig, mode: &str, quality: Option<&str>) -> VideoConfig {
+        video.bitrate_mode = Some(mode.to_string());
+        video.quality = quality.map(str::to_string);
+        video
+    }
+
+    #[test]
+    fn manual_mode_takes_the_bitrate_with_a_floor() {
+        let mut config = video("libx264", 1920, 1080, 30);
+        assert!(rate_control(&config).is_err());
+
+        config.bitrate = 6_000_000;
+        let rate = rate_control(&config).unwrap();
+        assert_eq!(rate.mode, BitrateMode::Manual);
+        assert_eq!((rate.bitrate, rate.max_bitrate, rate.buffer_size), (6_000_000, 6_000_000, 18_000_000));
+        assert_eq!(rate.quality_param, None);
+        assert_eq!(rate.estimated_bytes_per_minute, 45_000_000);
+
+        config.bitrate = 500_000;
+        let rate = rate_control(&with_mode(config, "manual", None)).unwrap();
+        assert_eq!((rate.bitrate, rate.max_bitrate), (1_000_000, 1_000_000));
+    }
+
+    #[test]
+    fn auto_mode_scales_with_size_fps_codec_and_tier() {
+        let auto = |encoder: &str, width, height, fps, quality| {
+            let rate = rate_control(&with_mode(video(encoder, width, height, fps), "auto", quality)).unwrap();
+            assert_eq!(rate.mode, BitrateMode::Auto);
+            assert_eq!(rate.max_bitrate, rate.bitrate);
+            rate.bitrate
+        };
+        assert_eq!(auto("libx264", 1920, 1080, 30, None), 5_000_000);
+        assert_eq!(auto("libx264", 1920, 1080, 30, Some("draft")), 3_100_000);
+        // Frames past 30 fps count half.
+        assert_eq!(auto("libx264", 1920, 1080, 60, Some("high")), 11_200_000);
+        assert_eq!(auto("hevc_nvenc", 1920, 1080, 30, Some("standard")), 3_200_000);
+        assert_eq!(auto(EMBEDDED_AV1_ENCODER, 1920, 1080, 30, None), 2_700_000);
+    }
+
+    #[test]
+    fn auto_bitrate_is_clamped_to_1m_80m() {
+        assert_eq!(auto_bitrate(320, 240, 10, "libx264", QualityTier::Draft), 1_000_000);
+        assert_eq!(auto_bitrate(7680, 4320, 120, "libx264", QualityTier::High), 80_000_000);
+    }
+
+    #[test]
+    fn quality_mode_maps_the_tier_to_the_encoders_parameter() {
+        let quality = |encoder: &str, tier| {
+            let rate = rate_control(&with_mode(video(encoder, 1920, 1080, 30), "quality", tier)).unwrap();
+            assert_eq!(rate.mode, BitrateMode::Quality);
+            assert_eq!(rate.max_bitrate, rate.bitrate * 2);
+            assert_eq!(rate.buffer_size, rate.max_bitrate * 2);
+            (rate.quality_param.unwrap(), rate.quality_value.unwrap())
+        };
+        assert_eq!(quality("libx264", Some("draft")), ("crf", 28));
+        assert_eq!(quality("libx264", None), ("crf", 23));
+        assert_eq!(quality("libx264", Some("high")), ("crf", 18));
+        assert_eq!(quality("h264_nvenc", Some("draft")), ("cq", 30));
+        assert_eq!(quality("hevc_nvenc", Some("standard")), ("cq", 26));
+        assert_eq!(quality("h264_amf", Some("high")), ("qp", 20));
+        let config = with_mode(video(EMBEDDED_AV1_ENCODER, 1920, 1080, 30), "quality", None);
+        assert!(rate_control(&config).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_modes_and_tiers() {
+        let config = video("libx264", 1920, 1080, 30);
+        assert!(rate_control(&with_mode(config.clone(), "cbr", None)).is_err());
+        assert!(rate_control(&with_mode(config, "auto", Some("ultra"))).is_err());
+    }
+
+    #[test]
+    fn quality_args_replace_the_bitrate_target() {
+        let rate = rate_control(&with_mode(video("h264_nvenc", 1920, 1080, 30), "quality", None)).unwrap();
+        let args = rate_control_args("h264_nvenc", &rate);
+        assert_eq!(args[..4], ["-rc", "vbr", "-cq", "24"]);
+        assert!(args.windows(2).any(|pair| pair == ["-b:v", "0"]));
+        let rate = rate_control(&with_mode(video("h264_amf", 1920, 1080, 30), "quality", None)).unwrap();
+        assert_eq!(rate_control_args("h264_amf", &rate), ["-rc", "cqp", "-qp_i", "24", "-qp_p", "24"]);
+    }
 }
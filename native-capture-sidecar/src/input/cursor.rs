@@ -0,0 +1,364 @@
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::pipeline::origin_epoch_ms;
+
+pub fn cursor_track_path(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("cursor.json")
+}
+
+pub fn cursor_images_dir(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("cursors")
+}
+
+// Editor CursorVisualType for a platform cursor name (X cursor-font and freedesktop
+// cursor-spec names, plus the CSS names most themes alias).
+pub fn cursor_visual_type(name: &str) -> &'static str {
+    match name {
+        "hand" | "hand1" | "hand2" | "pointer" | "pointing_hand" => "pointer",
+        "xterm" | "text" | "ibeam" | "vertical-text" => "text",
+        "crosshair" | "cross" | "tcross" | "cross_reverse" | "diamond_cross" => "crosshair",
+        "fleur" | "move" | "all-scroll" | "size_all" => "move",
+        "grab" | "openhand" | "fd_grab" => "grab",
+        "grabbing" | "closedhand" | "dnd-move" | "dnd-none" => "grabbing",
+        _ => "default",
+    }
+}
+
+// Straight (non-premultiplied) RGBA cursor bitmap with its hotspot.
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+    pub hotspot_x: u32,
+    pub hotspot_y: u32,
+    pub rgba: Vec<u8>,
+    pub name: String,
+}
+
+impl CursorImage {
+    // A fully transparent cursor is how applications hide the pointer.
+    pub fn is_visible(&self) -> bool {
+        self.rgba.chunks_exact(4).any(|px| px[3] != 0)
+    }
+}
+
+struct Shape {
+    image: CursorImage,
+    visual_type: &'static str,
+}
+
+// Cursor position, visibility and shape sampled at the capture fps. Distinct bitmaps
+// are stored once and samples refer to them by index (-1 when unknown).
+pub struct CursorTrack {
+    origin: Instant,
+    fps: u32,
+    shapes: Vec<Shape>,
+    shape_ids: HashMap<u64, usize>,
+    mono_us: Vec<u64>,
+    x: Vec<i32>,
+    y: Vec<i32>,
+    visible: Vec<u8>,
+    shape: Vec<i32>,
+}
+
+impl CursorTrack {
+    pub fn new(origin: Instant, fps: u32) -> Self {
+        Self {
+            origin,
+            fps,
+            shapes: Vec::new(),
+            shape_ids: HashMap::new(),
+            mono_us: Vec::new(),
+            x: Vec::new(),
+            y: Vec::new(),
+            visible: Vec::new(),
+            shape: Vec::new(),
+        }
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn intern_shape(&mut self, image: CursorImage) -> i32 {
+        let mut hasher = DefaultHasher::new();
+        (image.width, image.height, image.hotspot_x, image.hotspot_y).hash(&mut hasher);
+        image.rgba.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(id) = self.shape_ids.get(&key) {
+            return *id as i32;
+        }
+        let id = self.shapes.len();
+        let visual_type = cursor_visual_type(&image.name);
+        self.shapes.push(Shape { image, visual_type });
+        self.shape_ids.insert(key, id);
+        id as i32
+    }
+
+    pub fn shape_visible(&self, id: i32) -> bool {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| self.shapes.get(id))
+            .is_some_and(|shape| shape.image.is_visible())
+    }
+
+    pub fn push(&mut self, at: Instant, x: i32, y: i32, visible: bool, shape: i32) {
+        self.mono_us
+            .push(at.saturating_duration_since(self.origin).as_micros() as u64);
+        self.x.push(x);
+        self.y.push(y);
+        self.visible.push(u8::from(visible));
+        self.shape.push(shape);
+    }
+
+    // Writes the PNGs and the track file; returns a summary for the stop response.
+    pub fn write(
+        &self,
+        output_path: &str,
+        source_bounds: Option<(i32, i32, u32, u32)>,
+    ) -> Result<Value, String> {
+        let images_dir = cursor_images_dir(output_path);
+        if !self.shapes.is_empty() {
+            fs::create_dir_all(&images_dir)
+                .map_err(|e| format!("failed to create {}: {e}", images_dir.display()))?;
+        }
+        let dir_name = images_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut shapes = Vec::with_capacity(self.shapes.len());
+        for (id, shape) in self.shapes.iter().enumerate() {
+            let file_name = format!("cursor-{id}.png");
+            write_png(&images_dir.join(&file_name), &shape.image)?;
+            shapes.push(json!({
+                "id": id,
+                // Relative to the track file so the recording folder can be moved.
+                "file": format!("{dir_name}/{file_name}"),
+                "width": shape.image.width,
+                "height": shape.image.height,
+                "hotspotX": shape.image.hotspot_x,
+                "hotspotY": shape.image.hotspot_y,
+                "cursorType": shape.visual_type,
+                "name": shape.image.name,
+            }));
+        }
+
+        let body = json!({
+            "version": 1,
+            "fps": self.fps,
+            "originEpochMs": origin_epoch_ms(self.origin).round() as u64,
+            "sourceBounds": source_bounds.map(|(x, y, width, height)| json!({
+                "x": x,
+                "y": y,
+                "width": width,
+                "height": height,
+            })),
+            "shapes": shapes,
+            "samples": {
+                "count": self.mono_us.len(),
+                "monoUs": self.mono_us,
+                "x": self.x,
+                "y": self.y,
+                "visible": self.visible,
+                "shape": self.shape,
+            },
+        });
+        let path = cursor_track_path(output_path);
+        let text = serde_json::to_string(&body).map_err(|e| e.to_string())?;
+        fs::write(&path, text).map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+
+        Ok(json!({
+            "path": path.to_string_lossy(),
+            "samples": self.mono_us.len(),
+            "shapes": self.shapes.len(),
+        }))
+    }
+}
+
+fn write_png(path: &Path, image: &CursorImage) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("failed to create {}: {e}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&image.rgba))
+        .map_err(|e| format!("failed to encode {}: {e}", path.display()))
+}
+
+pub struct CursorTracker {
+    stop_flag: Arc<AtomicBool>,
+    thread: JoinHandle<CursorTrack>,
+}
+
+impl CursorTracker {
+    pub fn start(origin: Instant, fps: u32) -> Result<Self, String> {
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread = spawn_backend(CursorTrack::new(origin, fps), Arc::clone(&stop_flag))?;
+        Ok(Self { stop_flag, thread })
+    }
+
+    // Stops sampling without writing the track, for discarded recordings.
+    pub fn discard(self) {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+
+    // Stops sampling and writes `<output>.cursor.json` plus the shape PNGs.
+    pub fn finish(
+        self,
+        output_path: &str,
+        source_bounds: Option<(i32, i32, u32, u32)>,
+    ) -> Result<Value, String> {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        let track = self
+            .thread
+            .join()
+            .map_err(|_| "cursor tracker thread panicked".to_string())?;
+        track.write(output_path, source_bounds)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn spawn_backend(
+    track: CursorTrack,
+    stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<CursorTrack>, String> {
+    super::linux::spawn_cursor(track, stop_flag)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn spawn_backend(
+    _track: CursorTrack,
+    _stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<CursorTrack>, String> {
+    Err(format!(
+        "cursor tracking has no {} backend yet",
+        std::env::consts::OS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn arrow(hotspot: (u32, u32), name: &str) -> CursorImage {
+        let mut rgba = vec![0u8; 4 * 4 * 4];
+        for px in rgba.chunks_exact_mut(4).take(6) {
+            px.copy_from_slice(&[255, 255, 255, 255]);
+        }
+        CursorImage {
+            width: 4,
+            height: 4,
+            hotspot_x: hotspot.0,
+            hotspot_y: hotspot.1,
+            rgba,
+            name: name.to_string(),
+        }
+    }
+
+    fn output_in(dir: &Path) -> String {
+        dir.join("take.mp4").to_string_lossy().into_owned()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cursor-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn identical_bitmaps_share_one_png() {
+        let dir = temp_dir("dedup");
+        let mut track = CursorTrack::new(Instant::now(), 30);
+        let first = track.intern_shape(arrow((0, 0), "left_ptr"));
+        // The same bitmap again, as after a round trip through another cursor.
+        let again = track.intern_shape(arrow((0, 0), "left_ptr"));
+        assert_eq!(first, again);
+
+        let summary = track.write(&output_in(&dir), None).unwrap();
+        assert_eq!(summary["shapes"], 1);
+        let pngs = fs::read_dir(cursor_images_dir(&output_in(&dir))).unwrap().count();
+        assert_eq!(pngs, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_moved_hotspot_is_a_new_shape() {
+        let mut track = CursorTrack::new(Instant::now(), 30);
+        let arrow_id = track.intern_shape(arrow((0, 0), "left_ptr"));
+        let shifted = track.intern_shape(arrow((2, 1), "left_ptr"));
+        assert_ne!(arrow_id, shifted);
+        let mut hidden = arrow((0, 0), "none");
+        hidden.rgba.fill(0);
+        let hidden = track.intern_shape(hidden);
+        assert!(track.shape_visible(arrow_id));
+        assert!(!track.shape_visible(hidden));
+        assert!(!track.shape_visible(-1));
+    }
+
+    #[test]
+    fn writes_columnar_samples_and_a_shape_table() {
+        let dir = temp_dir("format");
+        let origin = Instant::now();
+        let mut track = CursorTrack::new(origin, 60);
+        let arrow_id = track.intern_shape(arrow((1, 1), "left_ptr"));
+        let hand = track.intern_shape(arrow((2, 0), "hand2"));
+        track.push(origin + Duration::from_millis(10), 5, 6, true, arrow_id);
+        track.push(origin + Duration::from_millis(27), 7, 8, false, hand);
+        let output = output_in(&dir);
+        track.write(&output, Some((100, 200, 640, 480))).unwrap();
+
+        let body: Value =
+            serde_json::from_str(&fs::read_to_string(cursor_track_path(&output)).unwrap()).unwrap();
+        assert_eq!(body["version"], 1);
+        assert_eq!(body["fps"], 60);
+        assert_eq!(body["originEpochMs"], origin_epoch_ms(origin).round() as u64);
+        assert_eq!(body["sourceBounds"], json!({ "x": 100, "y": 200, "width": 640, "height": 480 }));
+        assert_eq!(
+            body["samples"],
+            json!({
+                "count": 2,
+                "monoUs": [10_000, 27_000],
+                "x": [5, 7],
+                "y": [6, 8],
+                "visible": [1, 0],
+                "shape": [0, 1],
+            })
+        );
+        let shapes = body["shapes"].as_array().unwrap();
+        assert_eq!(shapes[1]["file"], "take.cursors/cursor-1.png");
+        assert_eq!(shapes[1]["cursorType"], "pointer");
+        assert_eq!(shapes[1]["hotspotX"], 2);
+        assert_eq!(shapes[1]["hotspotY"], 0);
+        assert_eq!(shapes[0]["cursorType"], "default");
+
+        // PNGs decode back to the straight RGBA bitmap.
+        let decoder = png::Decoder::new(File::open(dir.join("take.cursors/cursor-0.png")).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, arrow((1, 1), "left_ptr").rgba);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cursor_names_map_to_visual_types() {
+        assert_eq!(cursor_visual_type("xterm"), "text");
+        assert_eq!(cursor_visual_type("hand2"), "pointer");
+        assert_eq!(cursor_visual_type("fleur"), "move");
+        assert_eq!(cursor_visual_type("left_ptr"), "default");
+    }
+}
@@ -0,0 +1,537 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use x11rb::connection::Connection;
+use x11rb::protocol::xfixes::{self, ConnectionExt as _};
+use x11rb::protocol::xinput::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use super::cursor::{CursorImage, CursorTrack};
+use super::{EventRecorder, InputEvent, RawInput};
+
+// XIAllMasterDevices: raw events from every master pointer and keyboard.
+const ALL_MASTER_DEVICES: u16 = 1;
+const IDLE_POLL: Duration = Duration::from_millis(4);
+// Raw motion arrives at the mouse's report rate (up to 1 kHz); each position lookup is
+// a server round trip, so positions are sampled at most this often while moving.
+const POINTER_QUERY_INTERVAL: Duration = Duration::from_millis(8);
+
+const XK_BACKSPACE: u32 = 0xff08;
+const XK_TAB: u32 = 0xff09;
+const XK_ISO_LEFT_TAB: u32 = 0xfe20;
+const XK_RETURN: u32 = 0xff0d;
+const XK_KP_ENTER: u32 = 0xff8d;
+const XK_ISO_LEVEL3_SHIFT: u32 = 0xfe03;
+// Shift_L .. Hyper_R.
+const XK_MODIFIERS: std::ops::RangeInclusive<u32> = 0xffe1..=0xffee;
+// Control, Meta, Alt, Super and Hyper make the next key a shortcut; Shift and locks do not.
+const XK_SHORTCUT_MODIFIERS: [u32; 10] = [
+    0xffe3, 0xffe4, 0xffe7, 0xffe8, 0xffe9, 0xffea, 0xffeb, 0xffec, 0xffed, 0xffee,
+];
+
+// Records pointer and keyboard input through XInput2 raw events on the root window.
+// Raw events are delivered regardless of which client has focus or grabs, and work
+// under Xvfb, so the backend can be driven end to end with xdotool.
+pub fn spawn(
+    recorder: EventRecorder,
+    stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<Vec<InputEvent>>, String> {
+    spawn_on(None, recorder, stop_flag)
+}
+
+// `display` overrides $DISPLAY.
+fn spawn_on(
+    display: Option<String>,
+    mut recorder: EventRecorder,
+    stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<Vec<InputEvent>>, String> {
+    let (setup_tx, setup_rx) = mpsc::sync_channel::<Result<(), String>>(1);
+    let stop_flag_thread = Arc::clone(&stop_flag);
+    let thread = thread::spawn(move || {
+        let (conn, root, keymap) = match connect(display.as_deref()) {
+            Ok(v) => {
+                let _ = setup_tx.send(Ok(()));
+                v
+            }
+            Err(err) => {
+                let _ = setup_tx.send(Err(err));
+                return Vec::new();
+            }
+        };
+
+        let mut shortcut_modifiers: HashSet<u32> = HashSet::new();
+        let mut last_pointer_query: Option<Instant> = None;
+        let mut motion_pending = false;
+        while !stop_flag_thread.load(Ordering::SeqCst) {
+            let event = match conn.poll_for_event() {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    // Resolve the final position of a movement cut short by the throttle.
+                    if motion_pending {
+                        motion_pending = false;
+                        if let Some((x, y)) = query_pointer(&conn, root) {
+                            recorder.record(Instant::now(), RawInput::Move { x, y });
+                        }
+                        last_pointer_query = Some(Instant::now());
+                    }
+                    thread::sleep(IDLE_POLL);
+                    continue;
+                }
+                Err(err) => {
+                    log!("[native-capture][input][linux] X connection lost: {err}");
+                    break;
+                }
+            };
+
+            let now = Instant::now();
+            let input = match event {
+                Event::XinputRawButtonPress(e) => {
+                    query_pointer(&conn, root).and_then(|(x, y)| button_input(e.detail, x, y, true))
+                }
+                Event::XinputRawButtonRelease(e) => {
+                    query_pointer(&conn, root).and_then(|(x, y)| button_input(e.detail, x, y, false))
+                }
+                Event::XinputRawMotion(_) => {
+                    let due = last_pointer_query
+                        .is_none_or(|at| now.duration_since(at) >= POINTER_QUERY_INTERVAL);
+                    if due {
+                        motion_pending = false;
+                        last_pointer_query = Some(now);
+                        query_pointer(&conn, root).map(|(x, y)| RawInput::Move { x, y })
+                    } else {
+                        motion_pending = true;
+                        None
+                    }
+                }
+                Event::XinputRawKeyPress(e) => keymap.keysym(e.detail).map(|keysym| {
+                    let category = categorize_key(keysym, !shortcut_modifiers.is_empty());
+                    let label =
+                        (category == "shortcut").then(|| shortcut_label(&shortcut_modifiers, keysym));
+                    if XK_SHORTCUT_MODIFIERS.contains(&keysym) {
+                        shortcut_modifiers.insert(keysym);
+                    }
+                    RawInput::Key { category, label }
+                }),
+                Event::XinputRawKeyRelease(e) => {
+                    if let Some(keysym) = keymap.keysym(e.detail) {
+                        shortcut_modifiers.remove(&keysym);
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let Some(input) = input {
+                recorder.record(now, input);
+            }
+        }
+        recorder.into_events()
+    });
+
+    match setup_rx.recv_timeout(Duration::from_secs(5)) {
+        Ok(Ok(())) => Ok(thread),
+        Ok(Err(err)) => {
+            let _ = thread.join();
+            Err(err)
+        }
+        Err(_) => {
+            stop_flag.store(true, Ordering::SeqCst);
+            Err("input telemetry setup timed out".to_string())
+        }
+    }
+}
+
+struct Keymap {
+    min_keycode: u32,
+    keysyms_per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+impl Keymap {
+    // Unshifted keysym of a keycode; enough to categorise the key.
+    fn keysym(&self, keycode: u32) -> Option<u32> {
+        let index = (keycode.checked_sub(self.min_keycode)? as usize) * self.keysyms_per_keycode;
+        self.keysyms.get(index).copied().filter(|keysym| *keysym != 0)
+    }
+}
+
+fn connect(display: Option<&str>) -> Result<(RustConnection, xproto::Window, Keymap), String> {
+    let (conn, screen_num) =
+        x11rb::connect(display).map_err(|e| format!("cannot connect to X display: {e}"))?;
+    let root = conn.setup().roots[screen_num].root;
+
+    // Raw events on the root window need XI 2.1.
+    let version = conn
+        .xinput_xi_query_version(2, 2)
+        .map_err(|e| format!("XInput2 query failed: {e}"))?
+        .reply()
+        .map_err(|e| format!("XInput2 is not available: {e}"))?;
+    if (version.major_version, version.minor_version) < (2, 1) {
+        return Err(format!(
+            "XInput {}.{} is too old for raw events (need 2.1)",
+            version.major_version, version.minor_version
+        ));
+    }
+
+    let min_keycode = conn.setup().min_keycode;
+    let max_keycode = conn.setup().max_keycode;
+    let mapping = conn
+        .get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)
+        .map_err(|e| format!("GetKeyboardMapping failed: {e}"))?
+        .reply()
+        .map_err(|e| format!("GetKeyboardMapping failed: {e}"))?;
+    let keymap = Keymap {
+        min_keycode: min_keycode as u32,
+        keysyms_per_keycode: mapping.keysyms_per_keycode.max(1) as usize,
+        keysyms: mapping.keysyms,
+    };
+
+    let mask = xinput::XIEventMask::RAW_BUTTON_PRESS
+        | xinput::XIEventMask::RAW_BUTTON_RELEASE
+        | xinput::XIEventMask::RAW_MOTION
+        | xinput::XIEventMask::RAW_KEY_PRESS
+        | xinput::XIEventMask::RAW_KEY_RELEASE;
+    conn.xinput_xi_select_events(
+        root,
+        &[xinput::EventMask {
+            deviceid: ALL_MASTER_DEVICES,
+            mask: vec![mask],
+        }],
+    )
+    .map_err(|e| format!("XISelectEvents failed: {e}"))?
+    .check()
+    .map_err(|e| format!("XISelectEvents failed: {e}"))?;
+
+    log!(
+        "[native-capture][input][linux] XInput {}.{} raw events on root=0x{:x}",
+        version.major_version, version.minor_version, root
+    );
+    Ok((conn, root, keymap))
+}
+
+// Samples the cursor once per frame interval. XFixes DisplayCursorNotify fires on every
+// shape change, so the image is only fetched when the shape actually changed.
+pub fn spawn_cursor(
+    mut track: CursorTrack,
+    stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<CursorTrack>, String> {
+    let interval = Duration::from_secs_f64(1.0 / track.fps().max(1) as f64);
+    let (setup_tx, setup_rx) = mpsc::sync_channel::<Result<(), String>>(1);
+    let stop_flag_thread = Arc::clone(&stop_flag);
+    let thread = thread::spawn(move || {
+        let (conn, root) = match connect_xfixes() {
+            Ok(v) => {
+                let _ = setup_tx.send(Ok(()));
+                v
+            }
+            Err(err) => {
+                let _ = setup_tx.send(Err(err));
+                return track;
+            }
+        };
+
+        let mut shape = fetch_cursor_image(&conn).map_or(-1, |image| track.intern_shape(image));
+        let mut next_tick = Instant::now();
+        while !stop_flag_thread.load(Ordering::SeqCst) {
+            let mut shape_changed = false;
+            loop {
+                match conn.poll_for_event() {
+                    Ok(Some(Event::XfixesCursorNotify(_))) => shape_changed = true,
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(err) => {
+                        log!("[native-capture][cursor][linux] X connection lost: {err}");
+                        return track;
+                    }
+                }
+            }
+            if shape_changed {
+                if let Some(image) = fetch_cursor_image(&conn) {
+                    shape = track.intern_shape(image);
+                }
+            }
+
+            let now = Instant::now();
+            match conn.query_pointer(root).ok().and_then(|cookie| cookie.reply().ok()) {
+                Some(pointer) => {
+                    // Off this screen (multi-screen setups) counts as hidden.
+                    let visible = pointer.same_screen && track.shape_visible(shape);
+                    track.push(now, pointer.root_x as i32, pointer.root_y as i32, visible, shape);
+                }
+                None => track.push(now, 0, 0, false, shape),
+            }
+
+            next_tick += interval;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else {
+                // Fell behind (suspended or overloaded); resume on the grid from here.
+                next_tick = now;
+            }
+        }
+        track
+    });
+
+    match setup_rx.recv_timeout(Duration::from_secs(5)) {
+        Ok(Ok(())) => Ok(thread),
+        Ok(Err(err)) => {
+            let _ = thread.join();
+            Err(err)
+        }
+        Err(_) => {
+            stop_flag.store(true, Ordering::SeqCst);
+            Err("cursor tracker setup timed out".to_string())
+        }
+    }
+}
+
+fn connect_xfixes() -> Result<(RustConnection, xproto::Window), String> {
+    let (conn, screen_num) =
+        x11rb::connect(None).map_err(|e| format!("cannot connect to X display: {e}"))?;
+    let root = conn.setup().roots[screen_num].root;
+
+    // XFixes requires the version handshake before any other request; cursor names
+    // arrived in 2.0.
+    let version = conn
+        .xfixes_query_version(5, 0)
+        .map_err(|e| format!("XFixes query failed: {e}"))?
+        .reply()
+        .map_err(|e| format!("XFixes is not available: {e}"))?;
+    if version.major_version < 2 {
+        return Err(format!(
+            "XFixes {}.{} is too old for cursor names (need 2.0)",
+            version.major_version, version.minor_version
+        ));
+    }
+    conn.xfixes_select_cursor_input(root, xfixes::CursorNotifyMask::DISPLAY_CURSOR)
+        .map_err(|e| format!("XFixesSelectCursorInput failed: {e}"))?
+        .check()
+        .map_err(|e| format!("XFixesSelectCursorInput failed: {e}"))?;
+
+    log!(
+        "[native-capture][cursor][linux] XFixes {}.{} cursor notifications on root=0x{:x}",
+        version.major_version, version.minor_version, root
+    );
+    Ok((conn, root))
+}
+
+fn fetch_cursor_image(conn: &RustConnection) -> Option<CursorImage> {
+    let reply = conn.xfixes_get_cursor_image_and_name().ok()?.reply().ok()?;
+    // Pixels are premultiplied ARGB in native u32s; PNG wants straight RGBA.
+    let mut rgba = Vec::with_capacity(reply.cursor_image.len() * 4);
+    for pixel in &reply.cursor_image {
+        let [b, g, r, a] = pixel.to_le_bytes();
+        let unpremultiply = |c: u8| {
+            if a == 0 {
+                0
+            } else {
+                ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+            }
+        };
+        rgba.extend_from_slice(&[unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+    }
+    Some(CursorImage {
+        width: reply.width as u32,
+        height: reply.height as u32,
+        hotspot_x: reply.xhot as u32,
+        hotspot_y: reply.yhot as u32,
+        rgba,
+        name: String::from_utf8_lossy(&reply.name).into_owned(),
+    })
+}
+
+fn query_pointer(conn: &RustConnection, root: xproto::Window) -> Option<(i32, i32)> {
+    let reply = conn.query_pointer(root).ok()?.reply().ok()?;
+    Some((reply.root_x as i32, reply.root_y as i32))
+}
+
+// X11 buttons: 1 left, 2 middle, 3 right, 4/5 wheel up/down, 6/7 wheel left/right,
+// 8/9 back/forward. Wheel "buttons" only produce an event on press.
+fn button_input(detail: u32, x: i32, y: i32, pressed: bool) -> Option<RawInput> {
+    let wheel = match detail {
+        4 => Some((0, -1)),
+        5 => Some((0, 1)),
+        6 => Some((-1, 0)),
+        7 => Some((1, 0)),
+        _ => None,
+    };
+    if let Some((delta_x, delta_y)) = wheel {
+        return pressed.then_some(RawInput::Wheel { x, y, delta_x, delta_y });
+    }
+    let button = match detail {
+        1 => 1,
+        2 => 3,
+        3 => 2,
+        8 => 4,
+        9 => 5,
+        _ => return None,
+    };
+    Some(if pressed {
+        RawInput::ButtonDown { x, y, button }
+    } else {
+        RawInput::ButtonUp { x, y, button }
+    })
+}
+
+fn categorize_key(keysym: u32, shortcut_modifier_held: bool) -> &'static str {
+    if XK_MODIFIERS.contains(&keysym) || keysym == XK_ISO_LEVEL3_SHIFT {
+        return "modifier";
+    }
+    if shortcut_modifier_held {
+        return "shortcut";
+    }
+    match keysym {
+        XK_BACKSPACE => "backspace",
+        XK_TAB | XK_ISO_LEFT_TAB => "tab",
+        XK_RETURN | XK_KP_ENTER => "enter",
+        // Latin-1, keypad digits and Unicode keysyms.
+        0x20..=0x7e | 0xa0..=0xff | 0xffb0..=0xffb9 | 0x0100_0000..=0x0110_ffff => "printable",
+        _ => "other",
+    }
+}
+
+// "CTRL+ALT+T" style label for the burn-in badge. Shift is not tracked as a shortcut
+// modifier, so shifted shortcuts show without it.
+fn shortcut_label(modifiers: &HashSet<u32>, keysym: u32) -> String {
+    let held = |keysyms: &[u32]| keysyms.iter().any(|k| modifiers.contains(k));
+    let mut parts: Vec<String> = Vec::new();
+    if held(&[0xffe3, 0xffe4]) {
+        parts.push("CTRL".to_string());
+    }
+    if held(&[0xffe7, 0xffe8, 0xffe9, 0xffea]) {
+        parts.push("ALT".to_string());
+    }
+    if held(&[0xffeb, 0xffec, 0xffed, 0xffee]) {
+        parts.push("SUPER".to_string());
+    }
+    let key = match keysym {
+        0x20 => "SPACE".to_string(),
+        0x21..=0x7e => (keysym as u8 as char).to_ascii_uppercase().to_string(),
+        0xffbe..=0xffc9 => format!("F{}", keysym - 0xffbe + 1),
+        XK_RETURN | XK_KP_ENTER => "ENTER".to_string(),
+        XK_TAB | XK_ISO_LEFT_TAB => "TAB".to_string(),
+        XK_BACKSPACE => "BKSP".to_string(),
+        0xff1b => "ESC".to_string(),
+        0xffff => "DEL".to_string(),
+        0xff50 => "HOME".to_string(),
+        0xff51 => "LEFT".to_string(),
+        0xff52 => "UP".to_string(),
+        0xff53 => "RIGHT".to_string(),
+        0xff54 => "DOWN".to_string(),
+        0xff55 => "PGUP".to_string(),
+        0xff56 => "PGDN".to_string(),
+        0xff57 => "END".to_string(),
+        _ => "KEY".to_string(),
+    };
+    parts.push(key);
+    parts.join("+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+
+    #[test]
+    fn x11_buttons_map_to_editor_numbering_and_wheel_deltas() {
+        assert!(matches!(button_input(1, 5, 6, true), Some(RawInput::ButtonDown { x: 5, y: 6, button: 1 })));
+        assert!(matches!(button_input(3, 0, 0, false), Some(RawInput::ButtonUp { button: 2, .. })));
+        assert!(matches!(button_input(2, 0, 0, true), Some(RawInput::ButtonDown { button: 3, .. })));
+        assert!(matches!(
+            button_input(5, 1, 2, true),
+            Some(RawInput::Wheel { x: 1, y: 2, delta_x: 0, delta_y: 1 })
+        ));
+        assert!(matches!(button_input(6, 0, 0, true), Some(RawInput::Wheel { delta_x: -1, .. })));
+        // Wheel releases and unknown buttons produce nothing.
+        assert!(button_input(4, 0, 0, false).is_none());
+        assert!(button_input(12, 0, 0, true).is_none());
+    }
+
+    #[test]
+    fn keys_are_categorised_by_keysym() {
+        assert_eq!(categorize_key(0x61, false), "printable");
+        assert_eq!(categorize_key(0x61, true), "shortcut");
+        assert_eq!(categorize_key(0xffe3, true), "modifier");
+        assert_eq!(categorize_key(XK_RETURN, false), "enter");
+        assert_eq!(categorize_key(XK_ISO_LEFT_TAB, false), "tab");
+        assert_eq!(categorize_key(XK_BACKSPACE, false), "backspace");
+        assert_eq!(categorize_key(0xff51, false), "other");
+        let modifiers = HashSet::from([0xffe3, 0xffe9]);
+        assert_eq!(shortcut_label(&modifiers, 0x73), "CTRL+ALT+S");
+        assert_eq!(shortcut_label(&HashSet::from([0xffeb]), 0xffbf), "SUPER+F2");
+    }
+
+    struct Xvfb(Child);
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn xdotool(display: &str, args: &[&str]) {
+        let status = Command::new("xdotool")
+            .env("DISPLAY", display)
+            .args(args)
+            .status()
+            .expect("xdotool");
+        assert!(status.success(), "xdotool {args:?} failed");
+    }
+
+    #[test]
+    #[ignore = "needs Xvfb and xdotool on PATH"]
+    fn records_xdotool_input_under_xvfb() {
+        let display = format!(":{}", 90 + std::process::id() % 100);
+        let _xvfb = Xvfb(
+            Command::new("Xvfb")
+                .args([display.as_str(), "-screen", "0", "640x480x24", "-nolisten", "tcp"])
+                .spawn()
+                .expect("Xvfb"),
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while x11rb::connect(Some(&display)).is_err() {
+            assert!(Instant::now() < deadline, "Xvfb did not come up on {display}");
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let origin = Instant::now();
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread = spawn_on(Some(display.clone()), EventRecorder::new(origin), Arc::clone(&stop_flag))
+            .expect("XInput2 backend");
+        xdotool(&display, &["mousemove", "100", "120"]);
+        xdotool(&display, &["click", "1"]);
+        xdotool(&display, &["click", "5"]);
+        xdotool(&display, &["key", "a", "ctrl+s", "Return"]);
+        thread::sleep(Duration::from_millis(200));
+        stop_flag.store(true, Ordering::SeqCst);
+        let events = thread.join().unwrap();
+
+        assert!(events.iter().any(|e| matches!(e, InputEvent::MouseMoveSampled { x: 100, y: 120, .. })));
+        assert!(events.iter().any(|e| matches!(e, InputEvent::MouseDown { x: 100, y: 120, button: 1, .. })));
+        assert!(events.iter().any(|e| matches!(e, InputEvent::MouseUp { x: 100, y: 120, button: 1, .. })));
+        assert!(events.iter().any(|e| matches!(e, InputEvent::Wheel { delta_y: 1, .. })));
+        let keys: Vec<&str> = events
+            .iter()
+            .filter_map(|e| match e {
+                InputEvent::KeyDownCategory { category, .. } => Some(*category),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, ["printable", "modifier", "shortcut", "enter"]);
+        // Stamped on the capture clock: epoch ms no earlier than the origin.
+        let origin_ms = crate::pipeline::origin_epoch_ms(origin).round() as u64;
+        assert!(events.iter().all(|e| match e {
+            InputEvent::MouseDown { ts, .. }
+            | InputEvent::MouseUp { ts, .. }
+            | InputEvent::MouseMoveSampled { ts, .. }
+            | InputEvent::Wheel { ts, .. }
+            | InputEvent::KeyDownCategory { ts, .. } => *ts >= origin_ms,
+        }));
+    }
+}
@@ -0,0 +1,245 @@
+mod cursor;
+#[cfg(target_os = "linux")]
+mod linux;
+
+pub use cursor::{cursor_images_dir, cursor_track_path, CursorTracker};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::pipeline::{origin_epoch_ms, OverlayEvent, OverlayFeed};
+
+// Same throttle as the Electron hook: a move is kept after 33 ms or 4 px.
+const MOVE_MIN_INTERVAL_MS: u64 = 33;
+const MOVE_MIN_DELTA_PX: i32 = 4;
+
+pub fn telemetry_path(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("input.json")
+}
+
+// Events of the editor's InputTelemetryFileV1 (src/types/inputTelemetry.ts).
+// `cursorType` is optional there and left out: the hooks do not see the cursor shape.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+pub enum InputEvent {
+    #[serde(rename = "mouseDown")]
+    MouseDown { ts: u64, x: i32, y: i32, button: u8 },
+    #[serde(rename = "mouseUp")]
+    MouseUp { ts: u64, x: i32, y: i32, button: u8 },
+    #[serde(rename = "mouseMoveSampled")]
+    MouseMoveSampled { ts: u64, x: i32, y: i32 },
+    #[serde(rename = "wheel")]
+    Wheel {
+        ts: u64,
+        x: i32,
+        y: i32,
+        #[serde(rename = "deltaX")]
+        delta_x: i32,
+        #[serde(rename = "deltaY")]
+        delta_y: i32,
+    },
+    #[serde(rename = "keyDownCategory")]
+    KeyDownCategory { ts: u64, category: &'static str },
+}
+
+// Backend-neutral input as reported by a platform hook. Buttons use the uiohook
+// numbering the editor expects: 1 left, 2 right, 3 middle. Key labels ("CTRL+S") are
+// only filled in for shortcuts and only reach the burn-in overlay, never the file.
+#[derive(Debug, Clone)]
+pub enum RawInput {
+    ButtonDown { x: i32, y: i32, button: u8 },
+    ButtonUp { x: i32, y: i32, button: u8 },
+    Move { x: i32, y: i32 },
+    Wheel { x: i32, y: i32, delta_x: i32, delta_y: i32 },
+    Key { category: &'static str, label: Option<String> },
+}
+
+// Turns raw input into v1 events, stamped in epoch ms on the clock of the frame
+// timestamp file (both derive from the start_capture request instant).
+pub struct EventRecorder {
+    origin: Instant,
+    origin_epoch_ms: f64,
+    events: Vec<InputEvent>,
+    last_move: Option<(u64, i32, i32)>,
+    overlay: Option<OverlayFeed>,
+}
+
+impl EventRecorder {
+    pub fn new(origin: Instant) -> Self {
+        Self {
+            origin,
+            origin_epoch_ms: origin_epoch_ms(origin),
+            events: Vec::new(),
+            last_move: None,
+            overlay: None,
+        }
+    }
+
+    // Also forwards clicks and shortcuts to the burn-in overlay of the capture.
+    pub fn with_overlay(mut self, overlay: Option<OverlayFeed>) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    pub fn record(&mut self, at: Instant, input: RawInput) {
+        if let Some(overlay) = &self.overlay {
+            match &input {
+                RawInput::ButtonDown { x, y, .. } => {
+                    overlay.push(at, OverlayEvent::Click { x: *x, y: *y });
+                }
+                RawInput::Key { category: "shortcut", label } => overlay.push(
+                    at,
+                    OverlayEvent::Shortcut {
+                        label: label.clone().unwrap_or_else(|| "SHORTCUT".to_string()),
+                    },
+                ),
+                _ => {}
+            }
+        }
+        let ts = (self.origin_epoch_ms
+            + at.saturating_duration_since(self.origin).as_secs_f64() * 1000.0)
+            .round() as u64;
+        let event = match input {
+            RawInput::ButtonDown { x, y, button } => InputEvent::MouseDown { ts, x, y, button },
+            RawInput::ButtonUp { x, y, button } => InputEvent::MouseUp { ts, x, y, button },
+            RawInput::Move { x, y } => {
+                if let Some((last_ts, last_x, last_y)) = self.last_move {
+                    let (dx, dy) = (x - last_x, y - last_y);
+                    if ts.saturating_sub(last_ts) < MOVE_MIN_INTERVAL_MS
+                        && dx * dx + dy * dy < MOVE_MIN_DELTA_PX * MOVE_MIN_DELTA_PX
+                    {
+                        return;
+                    }
+                }
+                self.last_move = Some((ts, x, y));
+                InputEvent::MouseMoveSampled { ts, x, y }
+            }
+            RawInput::Wheel { x, y, delta_x, delta_y } => {
+                InputEvent::Wheel { ts, x, y, delta_x, delta_y }
+            }
+            RawInput::Key { category, .. } => InputEvent::KeyDownCategory { ts, category },
+        };
+        self.events.push(event);
+    }
+
+    pub fn into_events(self) -> Vec<InputEvent> {
+        self.events
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InputSessionInfo {
+    pub session_id: String,
+    pub source_id: Option<String>,
+    pub source_display_id: Option<String>,
+    // x, y, width, height in screen pixels.
+    pub source_bounds: Option<(i32, i32, u32, u32)>,
+}
+
+pub struct InputTelemetry {
+    info: InputSessionInfo,
+    // False when the hooks only drive the burn-in overlay.
+    write_file: bool,
+    started_at_ms: u64,
+    stop_flag: Arc<AtomicBool>,
+    thread: JoinHandle<Vec<InputEvent>>,
+}
+
+impl InputTelemetry {
+    pub fn start(
+        info: InputSessionInfo,
+        origin: Instant,
+        write_file: bool,
+        overlay: Option<OverlayFeed>,
+    ) -> Result<Self, String> {
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let recorder = EventRecorder::new(origin).with_overlay(overlay);
+        let thread = spawn_backend(recorder, Arc::clone(&stop_flag))?;
+        Ok(Self {
+            info,
+            write_file,
+            started_at_ms: origin_epoch_ms(origin).round() as u64,
+            stop_flag,
+            thread,
+        })
+    }
+
+    // Stops the hook without writing anything, for discarded recordings.
+    pub fn discard(self) {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        let _ = self.thread.join();
+    }
+
+    // Stops the hook and writes the v1 file; returns the stats block, or None when no
+    // file was requested.
+    pub fn finish(self, path: &Path) -> Result<Option<Value>, String> {
+        self.stop_flag.store(true, Ordering::SeqCst);
+        let events = self
+            .thread
+            .join()
+            .map_err(|_| "input telemetry thread panicked".to_string())?;
+        if !self.write_file {
+            return Ok(None);
+        }
+
+        let count = |f: fn(&InputEvent) -> bool| events.iter().filter(|e| f(e)).count();
+        let stats = json!({
+            "totalEvents": events.len(),
+            "mouseDownCount": count(|e| matches!(e, InputEvent::MouseDown { .. })),
+            "mouseUpCount": count(|e| matches!(e, InputEvent::MouseUp { .. })),
+            "mouseMoveCount": count(|e| matches!(e, InputEvent::MouseMoveSampled { .. })),
+            "wheelCount": count(|e| matches!(e, InputEvent::Wheel { .. })),
+            "keyDownCount": count(|e| matches!(e, InputEvent::KeyDownCategory { .. })),
+        });
+        let source_id = self.info.source_id.as_deref();
+        let source_kind = match source_id {
+            Some(id) if id.starts_with("screen:") => "screen",
+            Some(id) if id.starts_with("window:") => "window",
+            _ => "unknown",
+        };
+        let body = json!({
+            "version": 1,
+            "sessionId": self.info.session_id,
+            "startedAtMs": self.started_at_ms,
+            "sourceKind": source_kind,
+            "sourceId": source_id,
+            "sourceDisplayId": self.info.source_display_id,
+            "sourceBounds": self.info.source_bounds.map(|(x, y, width, height)| json!({
+                "x": x,
+                "y": y,
+                "width": width,
+                "height": height,
+            })),
+            "events": events,
+            "stats": stats,
+        });
+        let text = serde_json::to_string(&body).map_err(|e| e.to_string())?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+        Ok(Some(stats))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn spawn_backend(
+    recorder: EventRecorder,
+    stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<Vec<InputEvent>>, String> {
+    linux::spawn(recorder, stop_flag)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn spawn_backend(
+    _recorder: EventRecorder,
+    _stop_flag: Arc<AtomicBool>,
+) -> Result<JoinHandle<Vec<InputEvent>>, String> {
+    Err(format!(
+        "input telemetry has no {} backend yet",
+        std::env::consts::OS
+    ))
+}
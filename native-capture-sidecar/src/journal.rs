@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{CaptureError, ErrorCode};
+use crate::system::process_image_name;
+
+// Overrides where session journals are kept (tests, portable installs).
+pub const JOURNAL_DIR_ENV: &str = "VELOCITY_CAPTURE_JOURNAL_DIR";
+const JOURNAL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionState {
+    Recording,
+    Stopping,
+    Failed,
+}
+
+// One file per in-flight session, written on start and removed once stop has finalized
+// the output. Whatever is left behind after a crash is what recover_session works from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionJournal {
+    pub version: u32,
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+    pub state: SessionState,
+    #[serde(rename = "sidecarPid")]
+    pub sidecar_pid: u32,
+    #[serde(rename = "ffmpegPid")]
+    pub ffmpeg_pid: Option<u32>,
+    #[serde(rename = "ffmpegPath")]
+    pub ffmpeg_path: Option<String>,
+    #[serde(rename = "outputPath")]
+    pub output_path: String,
+    // Temp file being recorded into; it only becomes outputPath once stop finalizes it.
+    #[serde(rename = "recordingPath")]
+    pub recording_path: Option<String>,
+    pub platform: String,
+    pub encoder: String,
+    // The `video` block of the start payload as the app sent it.
+    pub settings: Value,
+    #[serde(rename = "startedAtEpochMs")]
+    pub started_at_epoch_ms: u64,
+    #[serde(rename = "updatedAtEpochMs")]
+    pub updated_at_epoch_ms: u64,
+    pub error: Option<String>,
+}
+
+// Per-user state directory, so journals survive a reboot after a crash.
+pub fn journal_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(JOURNAL_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    let home = || std::env::var_os("HOME").map(PathBuf::from);
+    let base = if cfg!(target_os = "windows") {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home().map(|home| home.join("Library").join("Application Support"))
+    } else {
+        std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home().map(|home| home.join(".local").join("state")))
+    }?;
+    Some(base.join("velocity").join("native-capture").join("sessions"))
+}
+
+fn journal_file(dir: &Path, session_id: &str) -> PathBuf {
+    let name: String = session_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    dir.join(format!("{name}.json"))
+}
+
+fn now_epoch_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl SessionJournal {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        session_id: &str,
+        ffmpeg_pid: Option<u32>,
+        ffmpeg_path: Option<String>,
+        output_path: &str,
+        platform: &str,
+        encoder: &str,
+        settings: Value,
+        started_at_epoch_ms: u64,
+    ) -> Self {
+        Self {
+            version: JOURNAL_VERSION,
+            session_id: session_id.to_string(),
+            state: SessionState::Recording,
+            sidecar_pid: std::process::id(),
+            ffmpeg_pid,
+            ffmpeg_path,
+            output_path: output_path.to_string(),
+            recording_path: None,
+            platform: platform.to_string(),
+            encoder: encoder.to_string(),
+            settings,
+            started_at_epoch_ms,
+            updated_at_epoch_ms: started_at_epoch_ms,
+            error: None,
+        }
+    }
+
+    pub fn load(session_id: &str) -> Result<Self, CaptureError> {
+        let dir = journal_dir().ok_or("no journal directory on this system")?;
+        let path = journal_file(&dir, session_id);
+        let text = fs::read_to_string(&path).map_err(|_| {
+            CaptureError::new(
+                ErrorCode::SessionNotFound,
+                format!("no journal for session {session_id}"),
+            )
+        })?;
+        serde_json::from_str(&text)
+            .map_err(|e| format!("invalid journal {}: {e}", path.display()).into())
+    }
+
+    pub fn load_all() -> Vec<Self> {
+        let Some(entries) = journal_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        let mut journals: Vec<Self> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let text = fs::read_to_string(&path).ok()?;
+                match serde_json::from_str(&text) {
+                    Ok(journal) => Some(journal),
+                    Err(err) => {
+                        log!(
+                            "[native-capture][journal] skipping unreadable journal path={} err={}",
+                            path.display(),
+                            err
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+        journals.sort_by_key(|journal: &Self| journal.started_at_epoch_ms);
+        journals
+    }
+
+    // Best effort: a journal that cannot be written never fails the recording.
+    pub fn write(&mut self) {
+        let Some(dir) = journal_dir() else {
+            return;
+        };
+        self.updated_at_epoch_ms = now_epoch_ms();
+        let path = journal_file(&dir, &self.session_id);
+        let tmp = path.with_extension("json.tmp");
+        let result = fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&tmp, serde_json::to_vec_pretty(self).unwrap_or_default()))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(err) = result {
+            log!(
+                "[native-capture][journal] failed to write path={} err={}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    pub fn set_state(&mut self, state: SessionState, error: Option<String>) {
+        self.state = state;
+        self.error = error;
+        self.write();
+    }
+
+    pub fn remove(&self) {
+        if let Some(dir) = journal_dir() {
+            let _ = fs::remove_file(journal_file(&dir, &self.session_id));
+        }
+    }
+
+    // The file the session was writing when it stopped.
+    pub fn recorded_path(&self) -> &str {
+        self.recording_path.as_deref().unwrap_or(&self.output_path)
+    }
+
+    // The sidecar that wrote the journal is gone (or gave up on the session), so nothing
+    // else will finalize the output.
+    pub fn is_recoverable(&self) -> bool {
+        if self.state == SessionState::Failed {
+            return true;
+        }
+        if self.sidecar_pid == std::process::id() {
+            return false;
+        }
+        !process_image_name(self.sidecar_pid).is_some_and(|name| name.contains("native-capture"))
+    }
+
+    pub fn ffmpeg_running(&self) -> bool {
+        self.ffmpeg_pid
+            .and_then(process_image_name)
+            .is_some_and(|name| name.to_lowercase().contains("ffmpeg"))
+    }
+}
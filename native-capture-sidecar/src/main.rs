@@ -1,32 +1,111 @@
 use serde_json::json;
-use std::io::{self, BufRead, Write};
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
+// Writes a log line to stderr and to the ring that diagnostics bundles are built from.
+// Defined before the modules so all of them can use it.
+macro_rules! log {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{line}");
+        $crate::diagnostics::record_log_line(line);
+    }};
+}
+
+mod adaptive;
+mod autozoom;
+mod benchmark;
 mod capture;
+mod diagnostics;
+mod discovery;
+mod dispatch;
+mod embedded;
+mod error;
 mod ffmpeg;
+mod input;
+mod journal;
+mod output;
+mod pipeline;
 mod protocol;
+mod recovery;
+mod supervisor;
 mod system;
+mod warnings;
 #[cfg(target_os = "windows")]
 mod wgc;
 
-use capture::{handle_start, handle_stop};
+use autozoom::handle_generate_auto_zoom;
+use benchmark::{handle_benchmark, handle_cancel_benchmark, BenchmarkRun};
+use capture::{handle_inject_input, shutdown_captures};
+use diagnostics::{discard_bundle, handle_collect_diagnostics};
+use discovery::handle_init;
+use dispatch::Dispatcher;
+use error::{CaptureError, ErrorCode};
 use ffmpeg::handle_get_encoder_options;
-use protocol::{init_response, Request, Response};
+use protocol::{write_event, write_response, Request, Response, ShutdownPayload};
+use recovery::{handle_list_recoverable_sessions, handle_recover_session};
+
+// Budget for finalizing captures on exit when the shutdown command gives none, and the
+// most it may ask for.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+const SHUTDOWN_TIMEOUT_MAX: Duration = Duration::from_secs(60);
+// How often the request loop checks for a termination signal while stdin is quiet.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Incoming {
+    Line(String),
+    Closed,
+}
 
 fn main() {
-    eprintln!(
+    log!(
         "[native-capture][sidecar] boot pid={} platform={} ready_for_stdio_protocol=true",
         std::process::id(),
         std::env::consts::OS
     );
 
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
-    let mut active_capture = None;
+    // The first SIGINT/SIGTERM asks for a graceful shutdown; a second one while that is
+    // still running exits immediately.
+    let terminate = Arc::new(AtomicBool::new(false));
+    for &signal in signal_hook::consts::TERM_SIGNALS {
+        let registered = signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&terminate))
+            .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&terminate)));
+        if let Err(err) = registered {
+            log!("[native-capture][sidecar] failed to register signal={signal} err={err}");
+        }
+    }
 
-    for line in stdin.lock().lines() {
-        let line = match line {
-            Ok(v) => v,
-            Err(_) => continue,
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if tx.send(Incoming::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Incoming::Closed);
+    });
+
+    let dispatcher = Dispatcher::new();
+    let mut benchmark: Option<BenchmarkRun> = None;
+
+    let (reason, shutdown_id, timeout) = loop {
+        if terminate.load(Ordering::SeqCst) {
+            break ("signal", None, SHUTDOWN_TIMEOUT);
+        }
+        let line = match rx.recv_timeout(SIGNAL_POLL_INTERVAL) {
+            Ok(Incoming::Line(line)) => line,
+            Ok(Incoming::Closed) | Err(RecvTimeoutError::Disconnected) => {
+                break ("stdinClosed", None, SHUTDOWN_TIMEOUT);
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
         };
         if line.trim().is_empty() {
             continue;
@@ -35,44 +114,142 @@ fn main() {
         let request: Request = match serde_json::from_str(&line) {
             Ok(v) => v,
             Err(err) => {
-                let _ = writeln!(
-                    stdout,
-                    "{}",
-                    json!({
-                        "ok": false,
-                        "error": format!("invalid request json: {err}")
-                    })
-                );
-                let _ = stdout.flush();
+                write_event(json!({
+                    "ok": false,
+                    "error": CaptureError::new(
+                        ErrorCode::InvalidPayload,
+                        format!("invalid request json: {err}"),
+                    ),
+                }));
                 continue;
             }
         };
 
+        if request.cmd == "shutdown" {
+            let shutdown_payload: ShutdownPayload = match serde_json::from_value(request.payload) {
+                Ok(v) => v,
+                Err(err) => {
+                    write_response(&Response::err(
+                        &request.id,
+                        CaptureError::invalid_payload("shutdown", err),
+                    ));
+                    continue;
+                }
+            };
+            let timeout = shutdown_payload
+                .timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(SHUTDOWN_TIMEOUT)
+                .min(SHUTDOWN_TIMEOUT_MAX);
+            break ("command", Some(request.id), timeout);
+        }
+
+        let id = request.id.clone();
+        let payload = request.payload;
+        // Slow commands run on worker threads and reply from there, so the loop stays free
+        // for get_status, cancel and inject_input_events.
         let response = match request.cmd.as_str() {
-            "init" => init_response(&request.id),
-            "get_encoder_options" => handle_get_encoder_options(request.id.clone(), request.payload),
-            "start_capture" => handle_start(request.id.clone(), request.payload, &mut active_capture),
-            "stop_capture" => handle_stop(request.id.clone(), request.payload, &mut active_capture),
-            _ => Response::err(&request.id, format!("unknown command: {}", request.cmd)),
+            "init" => dispatcher.spawn(id, "init", true, move |id| handle_init(id, payload), drop),
+            "get_encoder_options" => dispatcher.spawn(
+                id,
+                "get_encoder_options",
+                true,
+                move |id| handle_get_encoder_options(id, payload),
+                drop,
+            ),
+            "start_capture" => dispatcher.start(id, payload, &mut benchmark),
+            "stop_capture" => dispatcher.stop(id, payload),
+            "inject_input_events" => Some(handle_inject_input(id, payload, &mut dispatcher.capture().active)),
+            "get_status" => Some(dispatcher.status(id, benchmark.as_ref())),
+            "cancel" => Some(dispatcher.cancel(id, payload, benchmark.as_ref())),
+            // Runs on its own thread, which replies when it is done.
+            "benchmark_encoders" => handle_benchmark(id, payload, &mut benchmark, dispatcher.capture_busy()),
+            "cancel_benchmark" => Some(handle_cancel_benchmark(id, payload, &mut benchmark)),
+            "collect_diagnostics" => {
+                let session_id = dispatcher
+                    .capture()
+                    .active
+                    .as_ref()
+                    .map(|capture| capture.session_id.clone());
+                dispatcher.spawn(
+                    id,
+                    "collect_diagnostics",
+                    true,
+                    move |id| handle_collect_diagnostics(id, payload, session_id.as_deref()),
+                    discard_bundle,
+                )
+            }
+            "generate_auto_zoom" => dispatcher.spawn(
+                id,
+                "generate_auto_zoom",
+                true,
+                move |id| handle_generate_auto_zoom(id, payload),
+                drop,
+            ),
+            "list_recoverable_sessions" => dispatcher.spawn(
+                id,
+                "list_recoverable_sessions",
+                true,
+                handle_list_recoverable_sessions,
+                drop,
+            ),
+            // Moves and deletes files, so it always runs to the end.
+            "recover_session" => dispatcher.spawn(
+                id,
+                "recover_session",
+                false,
+                move |id| handle_recover_session(id, payload),
+                drop,
+            ),
+            _ => Some(Response::err(
+                &request.id,
+                CaptureError::new(
+                    ErrorCode::UnknownCommand,
+                    format!("unknown command: {}", request.cmd),
+                ),
+            )),
         };
 
-        if request.cmd == "init" || request.cmd == "get_encoder_options" {
-            eprintln!(
-                "[native-capture][sidecar] cmd={} id={} ok={}",
-                request.cmd, request.id, response.ok
-            );
+        if let Some(response) = response {
+            write_response(&response);
         }
+    };
 
-        if writeln!(
-            stdout,
-            "{}",
-            serde_json::to_string(&response)
-                .unwrap_or_else(|_| "{\"ok\":false,\"error\":\"serialize failed\"}".to_string())
-        )
-        .is_err()
-        {
-            continue;
-        }
-        let _ = stdout.flush();
+    let started = Instant::now();
+    log!(
+        "[native-capture][sidecar] shutdown reason={} timeout_ms={} capture_running={}",
+        reason,
+        timeout.as_millis(),
+        dispatcher.capture_busy()
+    );
+    if let Some(run) = benchmark.take() {
+        run.cancel_and_join();
     }
+    // A start still on its worker is let finish until the deadline, so its capture is
+    // finalized too; a stop gets a little longer before it is abandoned.
+    let mut captures = dispatcher.settle(started + timeout);
+    captures.extend(shutdown_captures(&mut dispatcher.capture().active, started + timeout));
+    dispatcher.cancel_all("the sidecar is shutting down");
+    let clean = captures.iter().all(|capture| capture["status"] == "finalized");
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    if let Some(id) = shutdown_id {
+        write_response(&Response::ok(
+            id,
+            json!({ "reason": reason, "captures": captures, "elapsedMs": elapsed_ms }),
+        ));
+    }
+    // Final status line, written whether or not anyone asked; Electron may already be gone.
+    write_event(json!({
+        "event": "shutdown",
+        "reason": reason,
+        "captures": captures,
+        "elapsedMs": elapsed_ms,
+    }));
+    log!(
+        "[native-capture][sidecar] exit reason={} clean={} elapsed_ms={}",
+        reason,
+        clean,
+        elapsed_ms
+    );
+    std::process::exit(if clean { 0 } else { 1 });
 }
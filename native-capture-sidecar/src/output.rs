@@ -0,0 +1,374 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::error::{CaptureError, ErrorCode};
+use crate::supervisor::{ffmpeg_command, FfmpegProcess, StopStep};
+
+const PROBE_BYTES: usize = 8 * 1024 * 1024;
+const PROBE_CHUNK: usize = 1024 * 1024;
+
+pub fn output_dir(output_path: &str) -> PathBuf {
+    match Path::new(output_path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+// The file a capture records into until finalize renames it to the output path. It sits
+// in the same directory so the rename never crosses volumes, and keeps the extension
+// because ffmpeg picks the muxer from it.
+pub fn recording_path(output_path: &str, session_id: &str) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let session: String = session_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.{session}.partial.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{session}.partial"),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// Runs before anything is spawned: the directory must exist and take writes, and an
+// existing output is only replaced when the caller asked for it.
+pub fn check_output_target(output_path: &str, overwrite: bool) -> Result<(), CaptureError> {
+    let dir = output_dir(output_path);
+    if !dir.is_dir() {
+        return Err(CaptureError::new(
+            ErrorCode::OutputDirNotFound,
+            format!("output directory does not exist: {}", dir.display()),
+        )
+        .with_details(json!({ "outputDir": dir })));
+    }
+    let target = Path::new(output_path);
+    if target.is_dir() {
+        return Err(CaptureError::new(
+            ErrorCode::InvalidPayload,
+            format!("outputPath is a directory: {output_path}"),
+        ));
+    }
+    if target.exists() && !overwrite {
+        return Err(CaptureError::new(
+            ErrorCode::OutputExists,
+            format!("output already exists: {output_path}"),
+        )
+        .with_details(json!({ "outputPath": output_path })));
+    }
+    let probe_path = dir.join(format!(".native-capture-writable-{}", std::process::id()));
+    let probe = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe_path);
+    let _ = fs::remove_file(&probe_path);
+    probe.map(drop).map_err(|err| {
+        CaptureError::io(format!("output directory is not writable: {}", dir.display()), &err)
+            .with_details(json!({ "outputDir": dir }))
+    })
+}
+
+// Moves a finished recording to its final name. The rename is atomic on one volume, so
+// the output path never holds a half-written file. A rename replaces an existing target,
+// so without `overwrite` the recording is hard-linked instead, which fails on a file that
+// appeared at the output path during the recording. The recording is then left in place.
+pub fn commit_output(recording_path: &str, output_path: &str, overwrite: bool) -> Result<(), CaptureError> {
+    let moved = if overwrite {
+        fs::rename(recording_path, output_path)
+    } else {
+        move_no_clobber(Path::new(recording_path), Path::new(output_path))
+    };
+    moved.map_err(|err| {
+        let details = json!({
+            "recordingPath": recording_path,
+            "outputPath": output_path,
+        });
+        if err.kind() == io::ErrorKind::AlreadyExists {
+            return CaptureError::new(
+                ErrorCode::OutputExists,
+                format!("output appeared while recording: {output_path}"),
+            )
+            .with_details(details);
+        }
+        CaptureError::io("failed to move the recording to outputPath", &err).with_details(details)
+    })
+}
+
+// Filesystems without hard links (FAT, some network shares) fall back to a rename after a
+// last check, which leaves only a short window for a racing writer.
+fn move_no_clobber(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => {
+            if let Err(err) = fs::remove_file(from) {
+                log!(
+                    "[native-capture][stop] failed to delete path={} err={}",
+                    from.display(),
+                    err
+                );
+            }
+            Ok(())
+        }
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+        Err(_) if to.exists() => Err(io::ErrorKind::AlreadyExists.into()),
+        Err(_) => fs::rename(from, to),
+    }
+}
+
+// Lossless recordings are always Matroska, whatever extension the caller asked for.
+pub fn is_mp4_family(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ext == "mp4" || ext == "mov" || ext == "m4v")
+}
+
+// Whether an MP4 still has the fragmented layout it was recorded in (`mvex` in `moov`).
+// Only box headers are read, so a long recording costs a seek per fragment.
+pub fn is_fragmented_mp4(path: &str) -> io::Result<bool> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut at = 0;
+    while at + 8 <= len {
+        let (kind, header, size) = read_box_header(&mut file, at, len)?;
+        if &kind == b"moov" {
+            let end = at + size;
+            let mut child = at + header;
+            while child + 8 <= end {
+                let (kind, _, size) = read_box_header(&mut file, child, end)?;
+                if &kind == b"mvex" {
+                    return Ok(true);
+                }
+                child += size;
+            }
+            return Ok(false);
+        }
+        at += size;
+    }
+    Ok(false)
+}
+
+// (type, header length, box length) of the box at `at`; a zero size runs to `end`.
+fn read_box_header(file: &mut File, at: u64, end: u64) -> io::Result<([u8; 4], u64, u64)> {
+    let mut head = [0u8; 8];
+    file.seek(SeekFrom::Start(at))?;
+    file.read_exact(&mut head)?;
+    let kind = [head[4], head[5], head[6], head[7]];
+    let (header, size) = match u32::from_be_bytes([head[0], head[1], head[2], head[3]]) {
+        0 => (8, end - at),
+        1 => {
+            let mut large = [0u8; 8];
+            file.read_exact(&mut large)?;
+            (16, u64::from_be_bytes(large))
+        }
+        size => (8, u64::from(size)),
+    };
+    if size < header {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid MP4 box size"));
+    }
+    Ok((kind, header, size))
+}
+
+// MP4 recordings are fragmented while they run, so a crash still leaves a playable file.
+// At stop they are stream-copied into a faststart file (`moov` first) in place. `pid`
+// follows the remux for a shutdown that has to kill it. On failure the fragmented file is
+// left as it was.
+pub fn faststart_remux(
+    ffmpeg_exe: &str,
+    recording_path: &str,
+    session_id: &str,
+    timeout: Duration,
+    pid: Option<&AtomicU32>,
+) -> Result<(), CaptureError> {
+    let path = Path::new(recording_path);
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let ext = path.extension().map(|e| e.to_string_lossy()).unwrap_or_default();
+    let remuxed = path.with_file_name(format!("{stem}.faststart.{ext}"));
+
+    let mut command = ffmpeg_command(ffmpeg_exe);
+    command
+        .args(["-i", recording_path, "-map", "0", "-c", "copy", "-movflags", "+faststart"])
+        .arg(&remuxed)
+        .stdin(Stdio::null());
+    let process = FfmpegProcess::spawn(command, "stop", session_id)?;
+    if let Some(pid) = pid {
+        pid.store(process.id(), Ordering::SeqCst);
+    }
+    let started = Instant::now();
+    let exit = process.stop(timeout);
+    // Anything but exiting on its own means the remux was cut short.
+    let checked = if exit.step == StopStep::Exited {
+        exit.check()
+    } else {
+        Err(exit.error(&format!("remux did not finish within {} s", timeout.as_secs())))
+    };
+    let result = checked.and_then(|()| {
+        fs::rename(&remuxed, path)
+            .map_err(|err| CaptureError::io("failed to replace the recording with its faststart remux", &err))
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&remuxed);
+    }
+    log!(
+        "[native-capture][stop] faststart remux path={} ok={} elapsed_ms={}",
+        recording_path,
+        result.is_ok(),
+        started.elapsed().as_millis()
+    );
+    result
+}
+
+pub fn lossless_output_path(output_path: &str) -> String {
+    Path::new(output_path)
+        .with_extension("mkv")
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Typical compression of each lossless codec on screen content. Only used for the
+// preflight estimate, so erring low (a higher data rate) is the safe direction.
+fn lossless_ratio(codec: &str) -> f64 {
+    match codec {
+        "ffv1" => 3.0,
+        "utvideo" => 2.0,
+        _ => 4.0,
+    }
+}
+
+// Compares the projected lossless data rate against a short write probe in the output
+// directory. Never fails the start; the result is reported so the UI can warn.
+pub fn lossless_preflight(output_path: &str, width: u32, height: u32, fps: u32, codec: &str) -> Value {
+    // Lossless codecs are fed RGB.
+    let raw_bytes_per_sec = width as f64 * height as f64 * 3.0 * fps as f64;
+    let estimated = raw_bytes_per_sec / lossless_ratio(codec);
+    let disk = match measure_write_speed(&output_dir(output_path)) {
+        Ok(speed) => Some(speed),
+        Err(err) => {
+            log!("[native-capture][preflight] disk write probe failed: {err}");
+            None
+        }
+    };
+    let warning = disk.filter(|speed| estimated > *speed).map(|speed| {
+        format!(
+            "lossless {codec} at {width}x{height}@{fps} needs about {:.0} MB/s but the output disk wrote {:.0} MB/s; frames may be dropped",
+            estimated / 1_000_000.0,
+            speed / 1_000_000.0
+        )
+    });
+    log!(
+        "[native-capture][preflight] codec={} estimated_bytes_per_sec={:.0} disk_bytes_per_sec={:?} warning={}",
+        codec,
+        estimated,
+        disk.map(|speed| speed.round()),
+        warning.is_some()
+    );
+
+    json!({
+        "estimatedBytesPerSec": estimated.round() as u64,
+        "diskWriteBytesPerSec": disk.map(|speed| speed.round() as u64),
+        "warning": warning,
+    })
+}
+
+fn measure_write_speed(dir: &Path) -> io::Result<f64> {
+    let probe_path = dir.join(format!(".native-capture-speed-{}", std::process::id()));
+    let chunk = vec![0x5a_u8; PROBE_CHUNK];
+    let started = Instant::now();
+    let result = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&probe_path)
+        .and_then(|mut file| {
+            for _ in 0..PROBE_BYTES / PROBE_CHUNK {
+                file.write_all(&chunk)?;
+            }
+            // Without the sync the probe measures the page cache, not the disk.
+            file.sync_all()
+        });
+    let elapsed = started.elapsed();
+    let _ = fs::remove_file(&probe_path);
+    result?;
+    Ok(PROBE_BYTES as f64 / elapsed.as_secs_f64().max(1e-6))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("output-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn commit_keeps_a_file_that_appeared_during_the_recording() {
+        let dir = temp_dir("noclobber");
+        let output = dir.join("take.mp4");
+        let recording = recording_path(&output.to_string_lossy(), "s1");
+        fs::write(&recording, b"recording").unwrap();
+        fs::write(&output, b"someone else").unwrap();
+
+        let err = commit_output(&recording, &output.to_string_lossy(), false).unwrap_err();
+        assert_eq!(err.code, ErrorCode::OutputExists);
+        assert_eq!(fs::read(&output).unwrap(), b"someone else");
+        assert_eq!(fs::read(&recording).unwrap(), b"recording");
+
+        commit_output(&recording, &output.to_string_lossy(), true).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"recording");
+        assert!(!Path::new(&recording).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn commit_moves_the_recording_to_a_free_output() {
+        let dir = temp_dir("free");
+        let output = dir.join("take.mp4");
+        let recording = recording_path(&output.to_string_lossy(), "s1");
+        fs::write(&recording, b"recording").unwrap();
+
+        commit_output(&recording, &output.to_string_lossy(), false).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"recording");
+        assert!(!Path::new(&recording).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn tells_fragmented_recordings_from_finished_files() {
+        let dir = temp_dir("layout");
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0");
+        let fragmented_moov = [mp4_box(b"mvhd", &[0; 100]), mp4_box(b"mvex", &mp4_box(b"trex", &[0; 24]))].concat();
+        let finished_moov = [mp4_box(b"mvhd", &[0; 100]), mp4_box(b"trak", &[0; 40])].concat();
+        // A 64-bit mdat, as long recordings have.
+        let mut large_mdat = 1u32.to_be_bytes().to_vec();
+        large_mdat.extend_from_slice(b"mdat");
+        large_mdat.extend_from_slice(&21u64.to_be_bytes());
+        large_mdat.extend_from_slice(b"frame");
+        let layouts: [(&str, Vec<u8>, bool); 3] = [
+            ("fragmented", [ftyp.clone(), mp4_box(b"moov", &fragmented_moov), mp4_box(b"moof", &[0; 16]), large_mdat.clone()].concat(), true),
+            ("faststart", [ftyp.clone(), mp4_box(b"moov", &finished_moov), large_mdat.clone()].concat(), false),
+            ("trailing", [ftyp.clone(), large_mdat.clone(), mp4_box(b"moov", &finished_moov)].concat(), false),
+        ];
+        for (name, bytes, fragmented) in layouts {
+            let path = dir.join(format!("{name}.mp4"));
+            fs::write(&path, bytes).unwrap();
+            assert_eq!(is_fragmented_mp4(&path.to_string_lossy()).unwrap(), fragmented, "{name}");
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,130 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    #[default]
+    Stretch,
+    // Scale to fit inside the output and pad the rest.
+    Contain,
+    // Scale to fill the output and crop the overflow.
+    Cover,
+}
+
+impl FitMode {
+    pub fn parse(name: Option<&str>) -> Result<Self, String> {
+        match name {
+            None | Some("stretch") => Ok(Self::Stretch),
+            Some("contain") => Ok(Self::Contain),
+            Some("cover") => Ok(Self::Cover),
+            Some(other) => Err(format!(
+                "unsupported fit: {other} (expected stretch, contain or cover)"
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stretch => "stretch",
+            Self::Contain => "contain",
+            Self::Cover => "cover",
+        }
+    }
+}
+
+// Defaults to black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PadColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl PadColor {
+    // Accepts "#rrggbb" or "rrggbb".
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        let Some(value) = value else {
+            return Ok(Self::default());
+        };
+        let hex = value.trim().trim_start_matches('#');
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Self { r, g, b }),
+            _ => Err(format!("invalid padColor: {value} (expected #rrggbb)")),
+        }
+    }
+
+    pub fn to_ffmpeg(self) -> String {
+        format!("0x{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+// Where a source of one size lands in an output of another: the source window that is
+// used (cover crops it) and the rectangle it is scaled into (contain pads around it).
+// Scaled sizes and offsets are even so yuv420p chroma stays aligned with the picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FitPlan {
+    pub src_x: u32,
+    pub src_y: u32,
+    pub src_w: u32,
+    pub src_h: u32,
+    pub dst_x: u32,
+    pub dst_y: u32,
+    pub dst_w: u32,
+    pub dst_h: u32,
+    pub out_w: u32,
+    pub out_h: u32,
+}
+
+impl FitPlan {
+    pub fn new(src: (u32, u32), out: (u32, u32), mode: FitMode) -> Self {
+        let (sw, sh) = (src.0.max(1), src.1.max(1));
+        let (ow, oh) = (out.0.max(1), out.1.max(1));
+        let mut plan = Self {
+            src_x: 0,
+            src_y: 0,
+            src_w: sw,
+            src_h: sh,
+            dst_x: 0,
+            dst_y: 0,
+            dst_w: ow,
+            dst_h: oh,
+            out_w: ow,
+            out_h: oh,
+        };
+        // Compare aspect ratios without floats: source wider than output?
+        let src_wider = sw as u64 * oh as u64 > ow as u64 * sh as u64;
+        let src_taller = (sw as u64 * oh as u64) < ow as u64 * sh as u64;
+        match mode {
+            FitMode::Stretch => {}
+            FitMode::Contain if src_wider => {
+                plan.dst_h = even(scale(sh, ow, sw)).min(oh);
+                plan.dst_y = even((oh - plan.dst_h) / 2);
+            }
+            FitMode::Contain if src_taller => {
+                plan.dst_w = even(scale(sw, oh, sh)).min(ow);
+                plan.dst_x = even((ow - plan.dst_w) / 2);
+            }
+            FitMode::Cover if src_wider => {
+                plan.src_w = scale(sh, ow, oh).clamp(1, sw);
+                plan.src_x = (sw - plan.src_w) / 2;
+            }
+            FitMode::Cover if src_taller => {
+                plan.src_h = scale(sw, oh, ow).clamp(1, sh);
+                plan.src_y = (sh - plan.src_h) / 2;
+            }
+            FitMode::Contain | FitMode::Cover => {}
+        }
+        plan
+    }
+
+    pub fn is_padded(&self) -> bool {
+        self.dst_w != self.out_w || self.dst_h != self.out_h
+    }
+}
+
+// value * num / den, rounded.
+fn scale(value: u32, num: u32, den: u32) -> u32 {
+    ((value as u64 * num as u64 + den as u64 / 2) / den as u64) as u32
+}
+
+fn even(value: u32) -> u32 {
+    (value & !1).max(2)
+}
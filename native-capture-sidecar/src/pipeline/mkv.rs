@@ -0,0 +1,302 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use super::RawFormat;
+
+// Minimal streaming Matroska writer for raw frames. Plain rawvideo on a pipe has
+// no timestamps, so VFR capture wraps every frame in its own cluster carrying the real
+// capture time and lets ffmpeg's matroska demuxer hand those pts to the encoder.
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const COLOUR_SPACE: u32 = 0x2E_B524;
+const CLUSTER: u32 = 0x1F43_B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+// Timestamps are written in microseconds.
+const TIMESTAMP_SCALE_NS: u64 = 1_000;
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+pub struct MkvRawWriter<W: Write> {
+    out: W,
+    width: u32,
+    height: u32,
+    format: RawFormat,
+    header_written: bool,
+}
+
+impl<W: Write> MkvRawWriter<W> {
+    pub fn new(out: W, width: u32, height: u32, format: RawFormat) -> Self {
+        Self {
+            out,
+            width,
+            height,
+            format,
+            header_written: false,
+        }
+    }
+
+    pub fn write_frame(&mut self, pts: Duration, frame: &[u8]) -> io::Result<()> {
+        if !self.header_written {
+            self.write_header()?;
+            self.header_written = true;
+        }
+
+        let mut timestamp = Vec::with_capacity(12);
+        put_uint(&mut timestamp, CLUSTER_TIMESTAMP, pts.as_nanos() as u64 / TIMESTAMP_SCALE_NS);
+
+        // Track 1, relative timestamp 0, keyframe flag.
+        let block_header = [0x81, 0x00, 0x00, 0x80];
+        let block_len = (block_header.len() + frame.len()) as u64;
+        let mut block_prefix = Vec::with_capacity(16);
+        put_id(&mut block_prefix, SIMPLE_BLOCK);
+        put_size(&mut block_prefix, block_len);
+        block_prefix.extend_from_slice(&block_header);
+
+        let mut cluster = Vec::with_capacity(32);
+        put_id(&mut cluster, CLUSTER);
+        put_size(
+            &mut cluster,
+            (timestamp.len() + block_prefix.len() + frame.len()) as u64,
+        );
+        cluster.extend_from_slice(&timestamp);
+        cluster.extend_from_slice(&block_prefix);
+
+        self.out.write_all(&cluster)?;
+        self.out.write_all(frame)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut ebml_body = Vec::new();
+        put_uint(&mut ebml_body, EBML_VERSION, 1);
+        put_uint(&mut ebml_body, EBML_READ_VERSION, 1);
+        put_uint(&mut ebml_body, EBML_MAX_ID_LENGTH, 4);
+        put_uint(&mut ebml_body, EBML_MAX_SIZE_LENGTH, 8);
+        put_bytes(&mut ebml_body, DOC_TYPE, b"matroska");
+        put_uint(&mut ebml_body, DOC_TYPE_VERSION, 4);
+        put_uint(&mut ebml_body, DOC_TYPE_READ_VERSION, 2);
+
+        let mut info_body = Vec::new();
+        put_uint(&mut info_body, TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS);
+        put_bytes(&mut info_body, MUXING_APP, b"native-capture-sidecar");
+        put_bytes(&mut info_body, WRITING_APP, b"native-capture-sidecar");
+
+        let mut video_body = Vec::new();
+        put_uint(&mut video_body, PIXEL_WIDTH, self.width as u64);
+        put_uint(&mut video_body, PIXEL_HEIGHT, self.height as u64);
+        put_bytes(&mut video_body, COLOUR_SPACE, self.format.fourcc());
+
+        let mut track_body = Vec::new();
+        put_uint(&mut track_body, TRACK_NUMBER, 1);
+        put_uint(&mut track_body, TRACK_UID, 1);
+        put_uint(&mut track_body, TRACK_TYPE, 1);
+        put_uint(&mut track_body, FLAG_LACING, 0);
+        put_bytes(&mut track_body, CODEC_ID, b"V_UNCOMPRESSED");
+        put_bytes(&mut track_body, VIDEO, &video_body);
+
+        let mut tracks_body = Vec::new();
+        put_bytes(&mut tracks_body, TRACK_ENTRY, &track_body);
+
+        let mut header = Vec::new();
+        put_bytes(&mut header, EBML, &ebml_body);
+        put_id(&mut header, SEGMENT);
+        header.extend_from_slice(&UNKNOWN_SIZE);
+        put_bytes(&mut header, INFO, &info_body);
+        put_bytes(&mut header, TRACKS, &tracks_body);
+
+        self.out.write_all(&header)
+    }
+}
+
+fn put_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+fn put_size(buf: &mut Vec<u8>, size: u64) {
+    // Smallest EBML vint that can hold `size`; all-ones values are reserved for "unknown".
+    let mut len = 1;
+    while len < 8 && size >= (1u64 << (7 * len)) - 1 {
+        len += 1;
+    }
+    let marked = size | (1u64 << (7 * len));
+    buf.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn put_uint(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    put_bytes(buf, id, &bytes[skip..]);
+}
+
+fn put_bytes(buf: &mut Vec<u8>, id: u32, data: &[u8]) {
+    put_id(buf, id);
+    put_size(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads one element header at `pos`: (id, size or None when unknown, header length).
+    fn element(buf: &[u8], pos: usize) -> (u32, Option<u64>, usize) {
+        let id_len = buf[pos].leading_zeros() as usize + 1;
+        let id = buf[pos..pos + id_len]
+            .iter()
+            .fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        let size_pos = pos + id_len;
+        let size_len = buf[size_pos].leading_zeros() as usize + 1;
+        let mut size = (buf[size_pos] as u64) & ((1 << (8 - size_len)) - 1);
+        for b in &buf[size_pos + 1..size_pos + size_len] {
+            size = (size << 8) | *b as u64;
+        }
+        let unknown = size == (1u64 << (7 * size_len)) - 1;
+        (id, (!unknown).then_some(size), id_len + size_len)
+    }
+
+    fn children(buf: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let (id, size, header) = element(buf, pos);
+            let size = size.expect("child with unknown size") as usize;
+            out.push((id, &buf[pos + header..pos + header + size]));
+            pos += header + size;
+        }
+        assert_eq!(pos, buf.len(), "children overrun their parent");
+        out
+    }
+
+    fn uint(data: &[u8]) -> u64 {
+        data.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+    }
+
+    fn write(frames: &[(u64, u8)], width: u32, height: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut writer = MkvRawWriter::new(&mut out, width, height, RawFormat::Yuv420p);
+        let frame_len = RawFormat::Yuv420p.frame_len(width, height);
+        for (pts_us, fill) in frames {
+            writer
+                .write_frame(Duration::from_micros(*pts_us), &vec![*fill; frame_len])
+                .unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn writes_ebml_header_and_track() {
+        let out = write(&[(0, 1)], 4, 2);
+        let (id, size, header) = element(&out, 0);
+        assert_eq!(id, EBML);
+        let ebml_end = header + size.unwrap() as usize;
+        let ebml = children(&out[header..ebml_end]);
+        let doc_type = ebml.iter().find(|(id, _)| *id == DOC_TYPE).unwrap().1;
+        assert_eq!(doc_type, b"matroska");
+
+        let (id, size, header) = element(&out, ebml_end);
+        assert_eq!(id, SEGMENT);
+        assert_eq!(size, None);
+        let segment = children(&out[ebml_end + header..]);
+        let ids: Vec<u32> = segment.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [INFO, TRACKS, CLUSTER]);
+
+        let info = children(segment[0].1);
+        let scale = info.iter().find(|(id, _)| *id == TIMESTAMP_SCALE).unwrap().1;
+        assert_eq!(uint(scale), TIMESTAMP_SCALE_NS);
+
+        let entry = children(segment[1].1);
+        assert_eq!(entry[0].0, TRACK_ENTRY);
+        let track = children(entry[0].1);
+        let codec = track.iter().find(|(id, _)| *id == CODEC_ID).unwrap().1;
+        assert_eq!(codec, b"V_UNCOMPRESSED");
+        let video = children(track.iter().find(|(id, _)| *id == VIDEO).unwrap().1);
+        assert_eq!(uint(video[0].1), 4);
+        assert_eq!(uint(video[1].1), 2);
+        assert_eq!(video[2].1, b"I420");
+    }
+
+    #[test]
+    fn clusters_carry_monotonic_capture_timestamps() {
+        let frames = [(0, 1), (16_667, 2), (50_000, 3), (1_200_000, 4)];
+        let out = write(&frames, 4, 2);
+        let (_, ebml_size, ebml_header) = element(&out, 0);
+        let segment_pos = ebml_header + ebml_size.unwrap() as usize;
+        let (_, _, segment_header) = element(&out, segment_pos);
+        let segment = children(&out[segment_pos + segment_header..]);
+
+        let clusters: Vec<_> = segment.iter().filter(|(id, _)| *id == CLUSTER).collect();
+        assert_eq!(clusters.len(), frames.len());
+        let mut last = None;
+        for ((_, cluster), (pts_us, fill)) in clusters.iter().zip(&frames) {
+            let body = children(cluster);
+            assert_eq!(body[0].0, CLUSTER_TIMESTAMP);
+            let timestamp = uint(body[0].1);
+            assert_eq!(timestamp, *pts_us);
+            assert!(last.is_none_or(|last| timestamp > last));
+            last = Some(timestamp);
+
+            assert_eq!(body[1].0, SIMPLE_BLOCK);
+            let block = body[1].1;
+            // Track 1, zero relative timestamp, keyframe.
+            assert_eq!(&block[..4], &[0x81, 0x00, 0x00, 0x80]);
+            assert_eq!(block.len() - 4, 12);
+            assert!(block[4..].iter().all(|b| b == fill));
+        }
+    }
+
+    #[test]
+    fn sizes_use_the_shortest_vint() {
+        let mut buf = Vec::new();
+        put_size(&mut buf, 126);
+        put_size(&mut buf, 127);
+        put_size(&mut buf, 16_382);
+        put_size(&mut buf, 16_383);
+        // 127 and 16383 are the all-ones "unknown" values at one and two bytes.
+        assert_eq!(buf, [0xFE, 0x40, 0x7F, 0x7F, 0xFE, 0x20, 0x3F, 0xFF]);
+    }
+
+    #[test]
+    #[ignore = "needs ffprobe on PATH"]
+    fn ffprobe_reads_vfr_timestamps() {
+        let frames = [(0, 16), (33_333, 80), (250_000, 160), (260_000, 235)];
+        let path = std::env::temp_dir().join(format!("mkv-test-{}.mkv", std::process::id()));
+        std::fs::write(&path, write(&frames, 16, 16)).unwrap();
+        let output = std::process::Command::new("ffprobe")
+            .args(["-v", "error", "-select_streams", "v:0", "-show_entries"])
+            .args(["frame=pts_time", "-of", "csv=p=0"])
+            .arg(&path)
+            .output()
+            .expect("ffprobe");
+        let _ = std::fs::remove_file(&path);
+        assert!(output.status.success());
+        let pts: Vec<f64> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().parse().unwrap())
+            .collect();
+        assert_eq!(pts, [0.0, 0.033333, 0.25, 0.26]);
+    }
+}
@@ -0,0 +1,91 @@
+// Frames only pass through Rust in the WGC backend; elsewhere the pipeline is built for the
+// overlay feed, the fit filter and its tests.
+#![cfg_attr(not(target_os = "windows"), allow(dead_code))]
+
+mod fit;
+mod mkv;
+mod overlay;
+mod resample;
+mod static_detect;
+mod timestamps;
+mod timing;
+
+use std::time::Instant;
+
+use crate::ffmpeg::RateControl;
+use crate::protocol::AdaptiveQualityConfig;
+
+pub use fit::{FitMode, FitPlan, PadColor};
+pub use overlay::{BurnInSettings, OverlayEvent, OverlayFeed};
+pub use resample::ResampleFilter;
+pub use static_detect::{StaticDetectionSettings, StaticSegment};
+pub use timestamps::{origin_epoch_ms, timestamps_path};
+#[cfg(target_os = "windows")]
+pub use {
+    mkv::MkvRawWriter, overlay::BurnInOverlay, resample::Resampler, static_detect::StaticDetector,
+    timestamps::FrameTimestampLog, timing::FrameTimingStats,
+};
+
+// Layout of the raw frames handed to the encoder. Lossless recordings keep the captured
+// BGRA; everything else is converted to yuv420p on the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    Yuv420p,
+    Bgra,
+}
+
+impl RawFormat {
+    pub fn for_options(options: &PipelineOptions) -> Self {
+        if options.lossless.is_some() {
+            RawFormat::Bgra
+        } else {
+            RawFormat::Yuv420p
+        }
+    }
+
+    // `-pixel_format` of the rawvideo input.
+    pub fn ffmpeg_name(self) -> &'static str {
+        match self {
+            RawFormat::Yuv420p => "yuv420p",
+            RawFormat::Bgra => "bgra",
+        }
+    }
+
+    // Matroska ColourSpace FourCC.
+    pub fn fourcc(self) -> &'static [u8; 4] {
+        match self {
+            RawFormat::Yuv420p => b"I420",
+            RawFormat::Bgra => b"BGRA",
+        }
+    }
+
+    pub fn frame_len(self, width: u32, height: u32) -> usize {
+        let (w, h) = (width as usize, height as usize);
+        match self {
+            RawFormat::Yuv420p => w * h + w.div_ceil(2) * h.div_ceil(2) * 2,
+            RawFormat::Bgra => w * h * 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PipelineOptions {
+    // Tags the capture_warning events of the encoder.
+    pub session_id: String,
+    pub vfr: bool,
+    // When start_capture was received; origin of the per-frame timestamp file.
+    pub requested_at: Option<Instant>,
+    pub static_detection: Option<StaticDetectionSettings>,
+    // Lossless codec (ffv1/utvideo/x264) replacing the encoder and bitrate arguments.
+    pub lossless: Option<String>,
+    // Filter for the CPU downscaler; None picks one from the scale ratio.
+    pub scale_filter: Option<ResampleFilter>,
+    pub fit: FitMode,
+    pub pad_color: PadColor,
+    // Click ripples and shortcut badges composited into frames (WGC only).
+    pub burn_in: Option<BurnInSettings>,
+    // Bounds for stepping the ffmpeg encoder down when it falls behind (CFR pipe only).
+    pub adaptive_quality: Option<AdaptiveQualityConfig>,
+    // Resolved from the payload; None for lossless recordings.
+    pub rate_control: Option<RateControl>,
+}
@@ -0,0 +1,461 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{origin_epoch_ms, FitPlan};
+
+const RIPPLE_LIFETIME: Duration = Duration::from_millis(600);
+const BADGE_LIFETIME: Duration = Duration::from_millis(1500);
+const BADGE_FADE: Duration = Duration::from_millis(250);
+const RIPPLE_COLOR: [u8; 3] = [255, 212, 0];
+const BADGE_BACKGROUND: [u8; 3] = [24, 24, 27];
+const BADGE_TEXT: [u8; 3] = [255, 255, 255];
+// Longest badge label; longer shortcuts are truncated.
+const BADGE_MAX_CHARS: usize = 24;
+
+#[derive(Debug, Clone)]
+pub enum OverlayEvent {
+    // Screen coordinates, as reported by the input hooks.
+    Click { x: i32, y: i32 },
+    Shortcut { label: String },
+}
+
+// Events handed from the input hooks (or inject_input_events) to the capture loop.
+#[derive(Debug, Clone)]
+pub struct OverlayFeed {
+    events: Arc<Mutex<Vec<(Instant, OverlayEvent)>>>,
+    // The capture timeline (the start_capture request) and its epoch ms.
+    origin: Instant,
+    origin_epoch_ms: f64,
+}
+
+impl OverlayFeed {
+    pub fn new(origin: Instant) -> Self {
+        Self {
+            events: Arc::default(),
+            origin,
+            origin_epoch_ms: origin_epoch_ms(origin),
+        }
+    }
+
+    pub fn push(&self, at: Instant, event: OverlayEvent) {
+        if let Ok(mut guard) = self.events.lock() {
+            guard.push((at, event));
+        }
+    }
+
+    // Instant of an epoch-ms `ts` on the capture timeline, the clock of the input
+    // telemetry and frame timestamp files. Kept within the recording so far.
+    pub fn instant_at(&self, epoch_ms: f64, now: Instant) -> Instant {
+        let offset_ms = (epoch_ms - self.origin_epoch_ms).max(0.0);
+        self.origin
+            .checked_add(Duration::from_secs_f64(offset_ms / 1000.0))
+            .map_or(now, |at| at.min(now))
+    }
+
+    fn drain(&self) -> Vec<(Instant, OverlayEvent)> {
+        self.events
+            .lock()
+            .map(|mut guard| std::mem::take(&mut *guard))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BurnInSettings {
+    pub feed: OverlayFeed,
+    pub clicks: bool,
+    pub keys: bool,
+    // Screen position of the first captured pixel (monitor or window origin plus crop).
+    pub screen_origin: (i32, i32),
+}
+
+struct Ripple {
+    at: Instant,
+    x: f32,
+    y: f32,
+}
+
+// Click ripples and a shortcut badge drawn into BGRA frames ahead of colour conversion.
+pub struct BurnInOverlay {
+    settings: BurnInSettings,
+    plan: FitPlan,
+    ripples: Vec<Ripple>,
+    badge: Option<(Instant, String)>,
+    drew_last: bool,
+}
+
+impl BurnInOverlay {
+    // `plan` maps the captured area into the output canvas; pass an identity plan when
+    // frames are not scaled.
+    pub fn new(settings: BurnInSettings, plan: FitPlan) -> Self {
+        Self {
+            settings,
+            plan,
+            ripples: Vec::new(),
+            badge: None,
+            drew_last: false,
+        }
+    }
+
+    // True while an effect is on screen or the last frame still shows one, so the
+    // capture loop keeps redrawing a static screen until the effect has faded out.
+    pub fn needs_redraw(&mut self, now: Instant) -> bool {
+        self.take_events();
+        self.drew_last || self.has_effects(now)
+    }
+
+    // Copies `clean` into `out` and draws the live effects on top. Both buffers are
+    // tightly packed BGRA of the plan's output size.
+    pub fn render(&mut self, now: Instant, clean: &[u8], out: &mut [u8]) {
+        self.take_events();
+        out.copy_from_slice(clean);
+        self.ripples.retain(|ripple| now.saturating_duration_since(ripple.at) < RIPPLE_LIFETIME);
+        if self
+            .badge
+            .as_ref()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) >= BADGE_LIFETIME)
+        {
+            self.badge = None;
+        }
+
+        let (width, height) = (self.plan.out_w as usize, self.plan.out_h as usize);
+        let mut canvas = Canvas { data: out, width, height };
+        let max_radius = (height as f32 / 18.0).max(12.0);
+        for ripple in &self.ripples {
+            // Events can arrive slightly after the frame they belong to; treat as t=0.
+            let t = now.saturating_duration_since(ripple.at).as_secs_f32()
+                / RIPPLE_LIFETIME.as_secs_f32();
+            let ease = 1.0 - (1.0 - t) * (1.0 - t);
+            let radius = max_radius * (0.25 + 0.75 * ease);
+            let alpha = 1.0 - t;
+            canvas.disc(ripple.x, ripple.y, radius, RIPPLE_COLOR, 0.18 * alpha);
+            canvas.ring(ripple.x, ripple.y, radius, (max_radius / 10.0).max(2.0), RIPPLE_COLOR, 0.9 * alpha);
+        }
+        if let Some((at, label)) = &self.badge {
+            let age = now.saturating_duration_since(*at);
+            let remaining = BADGE_LIFETIME.saturating_sub(age);
+            let alpha = (remaining.as_secs_f32() / BADGE_FADE.as_secs_f32()).min(1.0);
+            canvas.badge(label, alpha);
+        }
+        self.drew_last = !self.ripples.is_empty() || self.badge.is_some();
+    }
+
+    fn has_effects(&self, now: Instant) -> bool {
+        self.ripples
+            .iter()
+            .any(|ripple| now.saturating_duration_since(ripple.at) < RIPPLE_LIFETIME)
+            || self
+                .badge
+                .as_ref()
+                .is_some_and(|(at, _)| now.saturating_duration_since(*at) < BADGE_LIFETIME)
+    }
+
+    fn take_events(&mut self) {
+        for (at, event) in self.settings.feed.drain() {
+            match event {
+                OverlayEvent::Click { x, y } if self.settings.clicks => {
+                    let (x, y) = self.map_point(x, y);
+                    self.ripples.push(Ripple { at, x, y });
+                }
+                OverlayEvent::Shortcut { label } if self.settings.keys && !label.trim().is_empty() => {
+                    let label: String = label.to_uppercase().chars().take(BADGE_MAX_CHARS).collect();
+                    self.badge = Some((at, label));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Screen point to canvas point through the crop offset and the fit plan.
+    fn map_point(&self, x: i32, y: i32) -> (f32, f32) {
+        let plan = &self.plan;
+        let local_x = (x - self.settings.screen_origin.0) as f32 - plan.src_x as f32;
+        let local_y = (y - self.settings.screen_origin.1) as f32 - plan.src_y as f32;
+        (
+            plan.dst_x as f32 + local_x * plan.dst_w as f32 / plan.src_w as f32,
+            plan.dst_y as f32 + local_y * plan.dst_h as f32 / plan.src_h as f32,
+        )
+    }
+}
+
+struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Canvas<'_> {
+    fn blend(&mut self, x: usize, y: usize, color: [u8; 3], alpha: f32) {
+        if alpha <= 0.0 {
+            return;
+        }
+        let alpha = alpha.min(1.0);
+        let i = (y * self.width + x) * 4;
+        let px = &mut self.data[i..i + 3];
+        // BGRA in memory.
+        for (channel, value) in px.iter_mut().zip([color[2], color[1], color[0]]) {
+            *channel = (*channel as f32 + (value as f32 - *channel as f32) * alpha).round() as u8;
+        }
+    }
+
+    // Calls `f(x, y, distance from centre)` for every pixel of the circle's bounding box.
+    fn for_each_near(&mut self, cx: f32, cy: f32, reach: f32, mut f: impl FnMut(&mut Self, usize, usize, f32)) {
+        let x0 = (cx - reach).floor().max(0.0) as usize;
+        let y0 = (cy - reach).floor().max(0.0) as usize;
+        let x1 = ((cx + reach).ceil().max(0.0) as usize).min(self.width);
+        let y1 = ((cy + reach).ceil().max(0.0) as usize).min(self.height);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                f(self, x, y, (dx * dx + dy * dy).sqrt());
+            }
+        }
+    }
+
+    fn disc(&mut self, cx: f32, cy: f32, radius: f32, color: [u8; 3], alpha: f32) {
+        self.for_each_near(cx, cy, radius + 1.0, |canvas, x, y, d| {
+            let coverage = (radius - d + 0.5).clamp(0.0, 1.0);
+            canvas.blend(x, y, color, alpha * coverage);
+        });
+    }
+
+    fn ring(&mut self, cx: f32, cy: f32, radius: f32, thickness: f32, color: [u8; 3], alpha: f32) {
+        self.for_each_near(cx, cy, radius + thickness, |canvas, x, y, d| {
+            let coverage = (thickness / 2.0 - (d - radius).abs() + 0.5).clamp(0.0, 1.0);
+            canvas.blend(x, y, color, alpha * coverage);
+        });
+    }
+
+    // Rounded pill with the label, centred near the bottom edge.
+    fn badge(&mut self, label: &str, alpha: f32) {
+        let scale = (self.height / 270).max(2);
+        let glyph_w = (GLYPH_WIDTH + 1) * scale;
+        if label.is_empty() {
+            return;
+        }
+        let text_w = (label.chars().count() * glyph_w).saturating_sub(scale);
+        let text_h = GLYPH_HEIGHT * scale;
+        let pad = 3 * scale;
+        let box_h = text_h + pad * 2;
+        // At least a circle, so the rounded ends never overlap.
+        let box_w = (text_w + pad * 2).max(box_h);
+        if box_w >= self.width || box_h >= self.height {
+            return;
+        }
+        let box_x = (self.width - box_w) / 2;
+        let box_y = self.height.saturating_sub(box_h + self.height / 12);
+
+        let radius = box_h as f32 / 2.0;
+        for y in box_y..box_y + box_h {
+            for x in box_x..box_x + box_w {
+                // Distance outside the rounded rectangle, for an anti-aliased edge.
+                let (px, py) = (x as f32 + 0.5 - box_x as f32, y as f32 + 0.5 - box_y as f32);
+                let qx = (px - px.clamp(radius, box_w as f32 - radius)).abs();
+                let qy = (py - py.clamp(radius, box_h as f32 - radius)).abs();
+                let outside = (qx * qx + qy * qy).sqrt() - radius;
+                let coverage = (0.5 - outside).clamp(0.0, 1.0);
+                self.blend(x, y, BADGE_BACKGROUND, 0.78 * alpha * coverage);
+            }
+        }
+        let text_x = box_x + (box_w - text_w) / 2;
+        for (i, ch) in label.chars().enumerate() {
+            let rows = glyph(ch);
+            let gx = text_x + i * glyph_w;
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                        continue;
+                    }
+                    for sy in 0..scale {
+                        for sx in 0..scale {
+                            let x = gx + col * scale + sx;
+                            let y = box_y + pad + row * scale + sy;
+                            self.blend(x, y, BADGE_TEXT, alpha);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// 5x7 bitmaps, one byte per row with the leftmost pixel in bit 4.
+fn glyph(ch: char) -> [u8; GLYPH_HEIGHT] {
+    match ch {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        ' ' => [0x00; GLYPH_HEIGHT],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::FitMode;
+    use super::*;
+
+    const CLEAN: [u8; 4] = [10, 20, 30, 255];
+
+    fn overlay(feed: &OverlayFeed, plan: FitPlan) -> BurnInOverlay {
+        let settings = BurnInSettings {
+            feed: feed.clone(),
+            clicks: true,
+            keys: true,
+            screen_origin: (100, 50),
+        };
+        BurnInOverlay::new(settings, plan)
+    }
+
+    fn clean_frame(plan: &FitPlan) -> Vec<u8> {
+        CLEAN.repeat((plan.out_w * plan.out_h) as usize)
+    }
+
+    fn pixel(frame: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * width + x) * 4) as usize;
+        frame[i..i + 4].try_into().unwrap()
+    }
+
+    // Bounding box (x0, y0, x1, y1) of the pixels that differ from the clean frame.
+    fn changed_area(frame: &[u8], width: u32) -> Option<(u32, u32, u32, u32)> {
+        let mut area: Option<(u32, u32, u32, u32)> = None;
+        for (i, px) in frame.chunks_exact(4).enumerate() {
+            if px != CLEAN {
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                let (x0, y0, x1, y1) = area.unwrap_or((x, y, x, y));
+                area = Some((x0.min(x), y0.min(y), x1.max(x), y1.max(y)));
+            }
+        }
+        area
+    }
+
+    #[test]
+    fn clicks_ripple_at_the_mapped_canvas_point() {
+        // A square source letterboxed into a 2:1 canvas: 200 px wide, 100 px from the left.
+        let plan = FitPlan::new((400, 400), (400, 200), FitMode::Contain);
+        let feed = OverlayFeed::new(Instant::now());
+        let mut overlay = overlay(&feed, plan);
+        let clean = clean_frame(&plan);
+        let mut out = vec![0; clean.len()];
+
+        let now = Instant::now();
+        // Screen (200, 150) is source (100, 100): canvas (100 + 50, 50).
+        feed.push(now, OverlayEvent::Click { x: 200, y: 150 });
+        assert!(overlay.needs_redraw(now));
+        overlay.render(now, &clean, &mut out);
+
+        let centre = pixel(&out, plan.out_w, 150, 50);
+        assert_ne!(centre, CLEAN);
+        // Blended towards the ripple colour: red up, blue down; alpha untouched.
+        assert!(centre[2] > CLEAN[2] && centre[0] < CLEAN[0] && centre[3] == 255);
+        let (x0, y0, x1, y1) = changed_area(&out, plan.out_w).unwrap();
+        assert!(x0 < 150 && 150 < x1 && y0 < 50 && 50 < y1);
+        assert!(x1 - x0 < 40 && y1 - y0 < 40, "ripple spread to {x0},{y0}..{x1},{y1}");
+
+        // Gone once the ripple has run its course, with one more clean redraw.
+        let later = now + RIPPLE_LIFETIME;
+        assert!(overlay.needs_redraw(later));
+        overlay.render(later, &clean, &mut out);
+        assert_eq!(out, clean);
+        assert!(!overlay.needs_redraw(later));
+    }
+
+    #[test]
+    fn shortcut_badge_is_drawn_near_the_bottom_centre() {
+        let plan = FitPlan::new((640, 360), (640, 360), FitMode::Stretch);
+        let feed = OverlayFeed::new(Instant::now());
+        let mut overlay = overlay(&feed, plan);
+        let clean = clean_frame(&plan);
+        let mut out = vec![0; clean.len()];
+
+        let now = Instant::now();
+        feed.push(now, OverlayEvent::Shortcut { label: "ctrl+s".to_string() });
+        overlay.render(now, &clean, &mut out);
+
+        let (x0, y0, x1, y1) = changed_area(&out, plan.out_w).unwrap();
+        assert!(y0 > 360 / 2 && y1 < 360 - 360 / 12, "badge rows {y0}..{y1}");
+        assert_eq!(x0 + x1, 640 - 1, "badge not centred: {x0}..{x1}");
+        // Text pixels are white; the pill above them is blended towards its background.
+        assert!(out.chunks_exact(4).any(|px| px[..3] == [255, 255, 255]));
+        let pill = pixel(&out, plan.out_w, (x0 + x1) / 2, y0 + 1);
+        assert_ne!(pill, CLEAN);
+        let background = [BADGE_BACKGROUND[2], BADGE_BACKGROUND[1], BADGE_BACKGROUND[0]];
+        for ((value, clean), target) in pill.iter().zip(CLEAN).zip(background) {
+            assert!(clean.min(target) <= *value && *value <= clean.max(target), "{pill:?}");
+        }
+    }
+
+    #[test]
+    fn empty_labels_draw_nothing() {
+        let plan = FitPlan::new((64, 64), (64, 64), FitMode::Stretch);
+        let feed = OverlayFeed::new(Instant::now());
+        let mut overlay = overlay(&feed, plan);
+        let clean = clean_frame(&plan);
+        let mut out = vec![0; clean.len()];
+        let now = Instant::now();
+        feed.push(now, OverlayEvent::Shortcut { label: String::new() });
+        feed.push(now, OverlayEvent::Shortcut { label: "  ".to_string() });
+        overlay.render(now, &clean, &mut out);
+        assert_eq!(out, clean);
+
+        // The canvas itself copes with an empty label, and a single key fits a circle.
+        let mut data = clean.clone();
+        Canvas { data: &mut data, width: 64, height: 64 }.badge("", 1.0);
+        assert_eq!(data, clean);
+        Canvas { data: &mut data, width: 64, height: 64 }.badge("A", 1.0);
+        assert_ne!(data, clean);
+    }
+
+    #[test]
+    fn epoch_timestamps_map_onto_the_capture_timeline() {
+        let origin = Instant::now();
+        let feed = OverlayFeed::new(origin);
+        let now = origin + Duration::from_secs(2);
+        let at = feed.instant_at(feed.origin_epoch_ms + 250.0, now);
+        let offset = at.duration_since(origin).as_secs_f64();
+        assert!((offset - 0.25).abs() < 1e-6, "{offset}");
+        // Before the recording started, or not yet happened.
+        assert_eq!(feed.instant_at(feed.origin_epoch_ms - 1_000.0, now), origin);
+        assert_eq!(feed.instant_at(feed.origin_epoch_ms + 5_000.0, now), now);
+    }
+}
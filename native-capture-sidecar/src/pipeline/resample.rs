@@ -0,0 +1,561 @@
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+// Filter weights are 1.14 fixed point; 255 * 2^14 * taps stays well inside i32.
+const PRECISION_BITS: u32 = 14;
+const MAX_THREADS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleFilter {
+    // Exact pixel-coverage average when shrinking (bilinear when enlarging).
+    Area,
+    Lanczos,
+}
+
+impl ResampleFilter {
+    // `None` (or "auto") leaves the choice to `auto`.
+    pub fn parse(name: Option<&str>) -> Result<Option<Self>, String> {
+        match name {
+            None | Some("auto") => Ok(None),
+            Some("lanczos") => Ok(Some(Self::Lanczos)),
+            Some("area") => Ok(Some(Self::Area)),
+            Some(other) => Err(format!(
+                "unsupported scale filter: {other} (expected auto, area or lanczos)"
+            )),
+        }
+    }
+
+    // Lanczos needs 6x the reduction factor in taps per axis, which is too slow for
+    // large reductions (5K to 1080p) in the capture loop; area is alias-free there anyway.
+    pub fn auto(src: (u32, u32), dst: (u32, u32)) -> Self {
+        if src.0 >= dst.0.saturating_mul(2) || src.1 >= dst.1.saturating_mul(2) {
+            Self::Area
+        } else {
+            Self::Lanczos
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Area => "area",
+            Self::Lanczos => "lanczos",
+        }
+    }
+}
+
+// Contributing source range and weights for every output pixel along one axis.
+struct Axis {
+    starts: Vec<usize>,
+    counts: Vec<usize>,
+    stride: usize,
+    weights: Vec<i32>,
+}
+
+impl Axis {
+    fn new(src_len: usize, dst_len: usize, filter: ResampleFilter) -> Self {
+        let scale = src_len as f64 / dst_len as f64;
+        let filter_scale = scale.max(1.0);
+        let radius = match filter {
+            ResampleFilter::Area if scale >= 1.0 => scale / 2.0,
+            ResampleFilter::Area => 1.0,
+            ResampleFilter::Lanczos => 3.0 * filter_scale,
+        };
+        let stride = radius.ceil() as usize * 2 + 1;
+
+        let mut starts = Vec::with_capacity(dst_len);
+        let mut counts = Vec::with_capacity(dst_len);
+        let mut weights = vec![0_i32; dst_len * stride];
+        let mut row = vec![0.0_f64; stride];
+        for i in 0..dst_len {
+            let center = (i as f64 + 0.5) * scale;
+            let lo = (center - radius).floor().max(0.0) as usize;
+            let hi = ((center + radius).ceil() as usize).min(src_len).max(lo + 1);
+            let count = (hi - lo).min(stride);
+
+            let mut sum = 0.0;
+            for (k, weight) in row.iter_mut().enumerate().take(count) {
+                let x = (lo + k) as f64;
+                *weight = match filter {
+                    ResampleFilter::Area if scale >= 1.0 => {
+                        // Overlap of source pixel [x, x+1) with the output pixel footprint.
+                        ((x + 1.0).min(center + radius) - x.max(center - radius)).max(0.0)
+                    }
+                    ResampleFilter::Area => triangle(x + 0.5 - center),
+                    ResampleFilter::Lanczos => lanczos3((x + 0.5 - center) / filter_scale),
+                };
+                sum += *weight;
+            }
+            let fixed: Vec<i32> = row[..count]
+                .iter()
+                .map(|weight| {
+                    let normalized = if sum != 0.0 { weight / sum } else { 0.0 };
+                    (normalized * (1 << PRECISION_BITS) as f64).round() as i32
+                })
+                .collect();
+            // Taps that round to zero at the kernel edges are pure cost.
+            let first = fixed.iter().position(|&w| w != 0).unwrap_or(0);
+            let last = fixed.iter().rposition(|&w| w != 0).map_or(first, |p| p + 1);
+            weights[i * stride..i * stride + (last - first)].copy_from_slice(&fixed[first..last]);
+            starts.push(lo + first);
+            counts.push(last - first);
+        }
+
+        Self {
+            starts,
+            counts,
+            stride,
+            weights,
+        }
+    }
+
+    fn taps(&self, i: usize) -> (usize, &[i32]) {
+        let offset = i * self.stride;
+        (
+            self.starts[i],
+            &self.weights[offset..offset + self.counts[i]],
+        )
+    }
+}
+
+fn triangle(x: f64) -> f64 {
+    (1.0 - x.abs()).max(0.0)
+}
+
+fn lanczos3(x: f64) -> f64 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= 3.0 {
+        return 0.0;
+    }
+    let px = std::f64::consts::PI * x;
+    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+}
+
+fn clamp_fixed(acc: i32) -> u8 {
+    (acc >> PRECISION_BITS).clamp(0, 255) as u8
+}
+
+// Both axes of one capture size; shared by the workers.
+struct Kernel {
+    src_w: usize,
+    dst_w: usize,
+    horizontal: Axis,
+    vertical: Axis,
+}
+
+// Source and destination of one `resize_bgra` call.
+#[derive(Clone, Copy)]
+struct Frame<'a> {
+    src: &'a [u8],
+    src_stride: usize,
+    src_x: usize,
+    src_y: usize,
+    dst_stride: usize,
+}
+
+impl Kernel {
+    // Output rows `rows` in one pass: the source rows they draw from are resampled
+    // horizontally into `scratch`, then combined vertically into `dst`, which starts at
+    // output row `rows.start`.
+    fn run_band(&self, frame: Frame, rows: Range<usize>, dst: &mut [u8], scratch: &mut Vec<u8>) {
+        if rows.is_empty() {
+            return;
+        }
+        let mid_row = self.dst_w * 4;
+        let (mut first_src, mut end_src) = (usize::MAX, 0);
+        for y in rows.clone() {
+            let (start, weights) = self.vertical.taps(y);
+            first_src = first_src.min(start);
+            end_src = end_src.max(start + weights.len());
+        }
+        scratch.resize((end_src - first_src) * mid_row, 0);
+
+        for (r, out_row) in scratch.chunks_exact_mut(mid_row).enumerate() {
+            let row_start = (frame.src_y + first_src + r) * frame.src_stride + frame.src_x * 4;
+            let in_row = &frame.src[row_start..row_start + self.src_w * 4];
+            for (x, out_px) in out_row.chunks_exact_mut(4).enumerate() {
+                let (start, weights) = self.horizontal.taps(x);
+                let pixels = &in_row[start * 4..(start + weights.len()) * 4];
+                let mut acc = [1_i32 << (PRECISION_BITS - 1); 3];
+                for (px, &w) in pixels.chunks_exact(4).zip(weights) {
+                    acc[0] += px[0] as i32 * w;
+                    acc[1] += px[1] as i32 * w;
+                    acc[2] += px[2] as i32 * w;
+                }
+                out_px[0] = clamp_fixed(acc[0]);
+                out_px[1] = clamp_fixed(acc[1]);
+                out_px[2] = clamp_fixed(acc[2]);
+                // Alpha is ignored by colour conversion.
+                out_px[3] = 255;
+            }
+        }
+
+        let mut acc = vec![0_i32; mid_row];
+        for (y, out_row) in rows.zip(dst.chunks_mut(frame.dst_stride)) {
+            let out_row = &mut out_row[..mid_row];
+            let (start, weights) = self.vertical.taps(y);
+            acc.fill(1 << (PRECISION_BITS - 1));
+            for (k, &w) in weights.iter().enumerate() {
+                let row = start + k - first_src;
+                let in_row = &scratch[row * mid_row..(row + 1) * mid_row];
+                for (a, &v) in acc.iter_mut().zip(in_row) {
+                    *a += v as i32 * w;
+                }
+            }
+            for (dst_c, &a) in out_row.iter_mut().zip(&acc) {
+                *dst_c = clamp_fixed(a);
+            }
+        }
+    }
+}
+
+// A band of one frame handed to a worker. The pointers come from the borrows of a
+// `resize_bgra` call, which waits for every worker to report back before returning, and
+// the `dst` bands of one frame never overlap.
+struct Job {
+    src: *const u8,
+    src_len: usize,
+    src_stride: usize,
+    src_x: usize,
+    src_y: usize,
+    dst: *mut u8,
+    dst_len: usize,
+    dst_stride: usize,
+    rows: Range<usize>,
+}
+
+unsafe impl Send for Job {}
+
+struct Worker {
+    jobs: Option<Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn(kernel: Arc<Kernel>, done: Sender<bool>) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let handle = thread::Builder::new()
+            .name("resample".to_string())
+            .spawn(move || {
+                let mut scratch = Vec::new();
+                while let Ok(job) = queue.recv() {
+                    // SAFETY: see `Job`; the caller keeps both buffers borrowed until `done`.
+                    let (src, dst) = unsafe {
+                        (
+                            std::slice::from_raw_parts(job.src, job.src_len),
+                            std::slice::from_raw_parts_mut(job.dst, job.dst_len),
+                        )
+                    };
+                    let frame = Frame {
+                        src,
+                        src_stride: job.src_stride,
+                        src_x: job.src_x,
+                        src_y: job.src_y,
+                        dst_stride: job.dst_stride,
+                    };
+                    let ok = panic::catch_unwind(AssertUnwindSafe(|| {
+                        kernel.run_band(frame, job.rows, dst, &mut scratch)
+                    }))
+                    .is_ok();
+                    if done.send(ok).is_err() {
+                        break;
+                    }
+                }
+            })
+            .ok();
+        Self {
+            jobs: Some(jobs),
+            handle,
+        }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Separable BGRA resampler run ahead of colour conversion, so a 5K capture recorded at
+// 1080p is converted and piped at 1080p. Weights are computed once per capture size, and
+// frames are split into bands of output rows across workers that live as long as the
+// resampler, so the capture loop never spawns threads.
+pub struct Resampler {
+    src_h: usize,
+    dst_w: usize,
+    dst_h: usize,
+    kernel: Arc<Kernel>,
+    workers: Vec<Worker>,
+    done: Receiver<bool>,
+    // Horizontally resampled rows when running without workers.
+    scratch: Vec<u8>,
+}
+
+impl Resampler {
+    pub fn new(src: (u32, u32), dst: (u32, u32), filter: ResampleFilter) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .clamp(1, MAX_THREADS);
+        Self::with_threads(src, dst, filter, threads)
+    }
+
+    fn with_threads(
+        src: (u32, u32),
+        dst: (u32, u32),
+        filter: ResampleFilter,
+        threads: usize,
+    ) -> Self {
+        let (src_w, src_h) = (src.0.max(1) as usize, src.1.max(1) as usize);
+        let (dst_w, dst_h) = (dst.0.max(1) as usize, dst.1.max(1) as usize);
+        let kernel = Arc::new(Kernel {
+            src_w,
+            dst_w,
+            horizontal: Axis::new(src_w, dst_w, filter),
+            vertical: Axis::new(src_h, dst_h, filter),
+        });
+        let (done_tx, done) = mpsc::channel();
+        let workers = if threads > 1 {
+            (0..threads.min(dst_h))
+                .map(|_| Worker::spawn(Arc::clone(&kernel), done_tx.clone()))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            src_h,
+            dst_w,
+            dst_h,
+            kernel,
+            workers,
+            done,
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn dst_size(&self) -> (u32, u32) {
+        (self.dst_w as u32, self.dst_h as u32)
+    }
+
+    // `src` is BGRA with `src_stride` bytes per row; the source window starts at
+    // (`src_x`, `src_y`). `dst` receives dst_w x dst_h BGRA rows `dst_stride` bytes apart,
+    // so the output can land inside a larger (padded) canvas.
+    pub fn resize_bgra(
+        &mut self,
+        src: &[u8],
+        src_stride: usize,
+        src_x: usize,
+        src_y: usize,
+        dst: &mut [u8],
+        dst_stride: usize,
+    ) {
+        let mid_row = self.dst_w * 4;
+        let needed_src = (src_y + self.src_h - 1) * src_stride + (src_x + self.kernel.src_w) * 4;
+        assert!(
+            src.len() >= needed_src,
+            "source smaller than the resample window"
+        );
+        let dst = &mut dst[..(self.dst_h - 1) * dst_stride + mid_row];
+        let frame = Frame {
+            src,
+            src_stride,
+            src_x,
+            src_y,
+            dst_stride,
+        };
+        if self.workers.is_empty() {
+            self.kernel
+                .run_band(frame, 0..self.dst_h, dst, &mut self.scratch);
+            return;
+        }
+
+        let rows_per_band = self.dst_h.div_ceil(self.workers.len());
+        // Bands are carved from one base pointer so they never alias each other.
+        let base = dst.as_mut_ptr();
+        let mut sent = 0;
+        let mut ok = true;
+        for (band, worker) in self.workers.iter().enumerate() {
+            let rows = band * rows_per_band..((band + 1) * rows_per_band).min(self.dst_h);
+            if rows.is_empty() {
+                break;
+            }
+            let len = (rows.len() - 1) * dst_stride + mid_row;
+            // SAFETY: the band lies inside `dst`, which was cut to end at the last row.
+            let band_ptr = unsafe { base.add(rows.start * dst_stride) };
+            let job = Job {
+                src: src.as_ptr(),
+                src_len: src.len(),
+                src_stride,
+                src_x,
+                src_y,
+                dst: band_ptr,
+                dst_len: len,
+                dst_stride,
+                rows: rows.clone(),
+            };
+            match worker.jobs.as_ref().map(|jobs| jobs.send(job)) {
+                Some(Ok(())) => sent += 1,
+                // A worker that failed to start: do its band here.
+                _ => {
+                    // SAFETY: as above; no worker was given this band.
+                    let band_dst = unsafe { std::slice::from_raw_parts_mut(band_ptr, len) };
+                    ok &= panic::catch_unwind(AssertUnwindSafe(|| {
+                        self.kernel
+                            .run_band(frame, rows, band_dst, &mut self.scratch)
+                    }))
+                    .is_ok();
+                }
+            }
+        }
+        // Every band must be finished before the borrows of `src` and `dst` end.
+        for _ in 0..sent {
+            ok &= self.done.recv().unwrap_or(false);
+        }
+        assert!(ok, "resample worker panicked");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ResampleFilter; 2] = [ResampleFilter::Area, ResampleFilter::Lanczos];
+
+    fn image(width: usize, height: usize, pixel: impl Fn(usize, usize) -> [u8; 4]) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(x, y))
+            .collect()
+    }
+
+    fn resize(
+        src: &[u8],
+        src_size: (usize, usize),
+        dst_size: (usize, usize),
+        filter: ResampleFilter,
+        threads: usize,
+    ) -> Vec<u8> {
+        let mut resampler = Resampler::with_threads(
+            (src_size.0 as u32, src_size.1 as u32),
+            (dst_size.0 as u32, dst_size.1 as u32),
+            filter,
+            threads,
+        );
+        let mut dst = vec![0; dst_size.0 * dst_size.1 * 4];
+        resampler.resize_bgra(src, src_size.0 * 4, 0, 0, &mut dst, dst_size.0 * 4);
+        dst
+    }
+
+    #[test]
+    fn solid_colour_stays_exact() {
+        let src = image(37, 23, |_, _| [12, 200, 255, 255]);
+        for filter in FILTERS {
+            for dst_size in [(16, 9), (37, 23), (80, 51)] {
+                for threads in [1, 3] {
+                    let dst = resize(&src, (37, 23), dst_size, filter, threads);
+                    let expected = image(dst_size.0, dst_size.1, |_, _| [12, 200, 255, 255]);
+                    assert_eq!(dst, expected, "{filter:?} to {dst_size:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn downscale_2x_averages_blocks() {
+        // 2x2 blocks of one colour each, so an exact area average is the block colour.
+        let block = |x: usize, y: usize| {
+            let (bx, by) = ((x / 2) as u8, (y / 2) as u8);
+            [bx * 30, by * 40, bx * 10 + by * 20, 255]
+        };
+        let src = image(16, 12, block);
+        let dst = resize(&src, (16, 12), (8, 6), ResampleFilter::Area, 1);
+        let expected = image(8, 6, |x, y| block(x * 2, y * 2));
+        assert_eq!(dst, expected);
+
+        // Lanczos overshoots at the block edges but stays close to the block colour.
+        let dst = resize(&src, (16, 12), (8, 6), ResampleFilter::Lanczos, 1);
+        for (got, want) in dst.iter().zip(&expected) {
+            assert!(got.abs_diff(*want) <= 12, "lanczos {got} vs {want}");
+        }
+    }
+
+    #[test]
+    fn checkerboard_downscale_is_uniform_grey() {
+        let src = image(32, 32, |x, y| {
+            let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+            [v, v, v, 255]
+        });
+        // Lanczos keeps a little of the pattern where its kernel is cut off at the borders.
+        for (filter, tolerance) in [(ResampleFilter::Area, 1), (ResampleFilter::Lanczos, 4)] {
+            let dst = resize(&src, (32, 32), (16, 16), filter, 1);
+            for px in dst.chunks_exact(4) {
+                for c in &px[..3] {
+                    assert!(c.abs_diff(128) <= tolerance, "{filter:?} gave {c}");
+                }
+                assert_eq!(px[3], 255);
+            }
+        }
+    }
+
+    #[test]
+    fn area_upscale_is_bilinear() {
+        let src = image(2, 1, |x, _| {
+            if x == 0 {
+                [0, 0, 0, 255]
+            } else {
+                [200, 200, 200, 255]
+            }
+        });
+        let dst = resize(&src, (2, 1), (4, 1), ResampleFilter::Area, 1);
+        let values: Vec<u8> = dst.chunks_exact(4).map(|px| px[0]).collect();
+        assert_eq!(values, [0, 50, 150, 200]);
+    }
+
+    #[test]
+    fn workers_match_a_single_pass() {
+        let src = image(97, 61, |x, y| {
+            [(x * 7 + y * 3) as u8, (x * y) as u8, (x ^ y) as u8, 255]
+        });
+        for filter in FILTERS {
+            for dst_size in [(40, 25), (130, 77)] {
+                let single = resize(&src, (97, 61), dst_size, filter, 1);
+                for threads in [2, 5, 8] {
+                    let banded = resize(&src, (97, 61), dst_size, filter, threads);
+                    assert_eq!(banded, single, "{filter:?} to {dst_size:?} on {threads}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writes_a_source_window_into_a_padded_canvas() {
+        let src = image(10, 10, |x, y| {
+            let inside = (2..6).contains(&x) && (4..8).contains(&y);
+            if inside {
+                [90, 90, 90, 255]
+            } else {
+                [0, 0, 255, 255]
+            }
+        });
+        let mut resampler = Resampler::with_threads((4, 4), (2, 2), ResampleFilter::Area, 2);
+        // 2x2 output at (1, 1) of a 4x3 canvas.
+        let stride = 4 * 4;
+        let mut canvas = vec![7u8; stride * 3];
+        resampler.resize_bgra(&src, 10 * 4, 2, 4, &mut canvas[stride + 4..], stride);
+        let expected = image(4, 3, |x, y| {
+            if (1..3).contains(&x) && (1..3).contains(&y) {
+                [90, 90, 90, 255]
+            } else {
+                [7, 7, 7, 7]
+            }
+        });
+        assert_eq!(canvas, expected);
+    }
+}
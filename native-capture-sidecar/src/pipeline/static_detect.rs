@@ -0,0 +1,235 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const BLOCK: usize = 32;
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+#[derive(Debug, Clone)]
+pub struct StaticDetectionSettings {
+    // Fraction of 32x32 pixel blocks that may change while a frame still counts as static.
+    pub threshold: f64,
+    pub min_duration: Duration,
+}
+
+// Bounds in ms on the recording timeline (the first captured frame is 0), and in
+// microseconds since the start_capture request, the clock of `monoUs` in the frame
+// timestamp file.
+#[derive(Debug, Clone, Serialize)]
+pub struct StaticSegment {
+    #[serde(rename = "startMs")]
+    pub start_ms: u64,
+    #[serde(rename = "endMs")]
+    pub end_ms: u64,
+    #[serde(rename = "startMonoUs")]
+    pub start_mono_us: u64,
+    #[serde(rename = "endMonoUs")]
+    pub end_mono_us: u64,
+}
+
+// Tracks stretches where the captured picture did not change. Each frame's Y plane (the
+// BGRA picture for lossless recordings) is reduced to one hash per block; a run starts at
+// the last frame that changed more than `threshold` of the blocks and ends at the next one.
+pub struct StaticDetector {
+    settings: StaticDetectionSettings,
+    origin: Instant,
+    width: usize,
+    height: usize,
+    hashes: Vec<u64>,
+    scratch: Vec<u64>,
+    first_frame: Option<Duration>,
+    run_start: Option<Duration>,
+    segments: Vec<StaticSegment>,
+}
+
+impl StaticDetector {
+    pub fn new(
+        settings: StaticDetectionSettings,
+        origin: Instant,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self {
+            settings,
+            origin,
+            width: width as usize,
+            height: height as usize,
+            hashes: Vec::new(),
+            scratch: Vec::new(),
+            first_frame: None,
+            run_start: None,
+            segments: Vec::new(),
+        }
+    }
+
+    // `plane` holds `width` x `height` pixels of one or more bytes each.
+    pub fn observe(&mut self, plane: &[u8], captured_at: Instant) {
+        let mono = captured_at.saturating_duration_since(self.origin);
+        self.first_frame.get_or_insert(mono);
+        block_hashes(plane, self.width, self.height, &mut self.scratch);
+        let changed = if self.hashes.len() == self.scratch.len() {
+            let differing = self
+                .hashes
+                .iter()
+                .zip(&self.scratch)
+                .filter(|(a, b)| a != b)
+                .count();
+            differing as f64 / self.scratch.len().max(1) as f64 > self.settings.threshold
+        } else {
+            true
+        };
+        std::mem::swap(&mut self.hashes, &mut self.scratch);
+
+        if changed {
+            self.close_run(mono);
+            self.run_start = Some(mono);
+        }
+    }
+
+    pub fn finish(mut self, end: Instant) -> Vec<StaticSegment> {
+        self.close_run(end.saturating_duration_since(self.origin));
+        self.segments
+    }
+
+    fn close_run(&mut self, end: Duration) {
+        let Some(start) = self.run_start.take() else {
+            return;
+        };
+        if end.saturating_sub(start) >= self.settings.min_duration {
+            let first = self.first_frame.unwrap_or_default();
+            self.segments.push(StaticSegment {
+                start_ms: start.saturating_sub(first).as_millis() as u64,
+                end_ms: end.saturating_sub(first).as_millis() as u64,
+                start_mono_us: start.as_micros() as u64,
+                end_mono_us: end.as_micros() as u64,
+            });
+        }
+    }
+}
+
+fn block_hashes(plane: &[u8], width: usize, height: usize, out: &mut Vec<u64>) {
+    let cols = width.div_ceil(BLOCK);
+    let rows = height.div_ceil(BLOCK);
+    out.clear();
+    out.resize(cols * rows, FNV_OFFSET);
+    let bytes_per_pixel = (plane.len() / (width * height).max(1)).max(1);
+
+    for (row, line) in plane
+        .chunks_exact(width * bytes_per_pixel)
+        .take(height)
+        .enumerate()
+    {
+        let base = (row / BLOCK) * cols;
+        for (col, span) in line.chunks(BLOCK * bytes_per_pixel).enumerate() {
+            let hash = &mut out[base + col];
+            let mut words = span.chunks_exact(8);
+            for word in &mut words {
+                let value = u64::from_le_bytes(word.try_into().unwrap_or([0; 8]));
+                *hash = (*hash ^ value).wrapping_mul(FNV_PRIME);
+            }
+            for byte in words.remainder() {
+                *hash = (*hash ^ *byte as u64).wrapping_mul(FNV_PRIME);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 128;
+    const H: u32 = 64;
+
+    fn settings(threshold: f64, min_duration_ms: u64) -> StaticDetectionSettings {
+        StaticDetectionSettings {
+            threshold,
+            min_duration: Duration::from_millis(min_duration_ms),
+        }
+    }
+
+    fn at(origin: Instant, ms: u64) -> Instant {
+        origin + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn identical_frames_form_one_segment() {
+        let origin = Instant::now();
+        let mut detector = StaticDetector::new(settings(0.0, 1000), origin, W, H);
+        let frame = vec![40u8; (W * H) as usize];
+        for ms in (100..=3100).step_by(100) {
+            detector.observe(&frame, at(origin, ms));
+        }
+        let segments = detector.finish(at(origin, 3200));
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].start_mono_us, 100_000);
+        assert_eq!(segments[0].end_mono_us, 3_200_000);
+        // The first frame opens the recording timeline.
+        assert_eq!((segments[0].start_ms, segments[0].end_ms), (0, 3100));
+    }
+
+    #[test]
+    fn changes_below_the_threshold_stay_static() {
+        let origin = Instant::now();
+        // 4x2 blocks; one changed block is 12.5%.
+        let mut detector = StaticDetector::new(settings(0.2, 500), origin, W, H);
+        let mut frame = vec![0u8; (W * H) as usize];
+        detector.observe(&frame, at(origin, 0));
+        frame[5] = 255;
+        detector.observe(&frame, at(origin, 400));
+        frame[W as usize * 40 + 70] = 255;
+        detector.observe(&frame, at(origin, 800));
+        let segments = detector.finish(at(origin, 1000));
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].start_mono_us, 0);
+        assert_eq!(segments[0].end_mono_us, 1_000_000);
+    }
+
+    #[test]
+    fn changes_above_the_threshold_split_segments() {
+        let origin = Instant::now();
+        let mut detector = StaticDetector::new(settings(0.2, 500), origin, W, H);
+        let still = vec![10u8; (W * H) as usize];
+        let moved = vec![200u8; (W * H) as usize];
+        detector.observe(&still, at(origin, 0));
+        detector.observe(&still, at(origin, 600));
+        detector.observe(&moved, at(origin, 700));
+        // Too short to report.
+        detector.observe(&still, at(origin, 900));
+        let segments = detector.finish(at(origin, 1500));
+        let bounds: Vec<_> = segments
+            .iter()
+            .map(|s| (s.start_mono_us, s.end_mono_us))
+            .collect();
+        assert_eq!(bounds, [(0, 700_000), (900_000, 1_500_000)]);
+        let ms: Vec<_> = segments.iter().map(|s| (s.start_ms, s.end_ms)).collect();
+        assert_eq!(ms, [(0, 700), (900, 1500)]);
+    }
+
+    #[test]
+    fn block_hashes_cover_partial_edge_blocks() {
+        let mut hashes = Vec::new();
+        let mut plane = vec![0u8; 40 * 40];
+        block_hashes(&plane, 40, 40, &mut hashes);
+        assert_eq!(hashes.len(), 4);
+        let before = hashes.clone();
+        plane[39 * 40 + 39] = 1;
+        block_hashes(&plane, 40, 40, &mut hashes);
+        let changed: Vec<bool> = before.iter().zip(&hashes).map(|(a, b)| a != b).collect();
+        assert_eq!(changed, [false, false, false, true]);
+    }
+
+    #[test]
+    fn bgra_frames_hash_whole_pixels_per_block() {
+        let mut hashes = Vec::new();
+        let mut plane = vec![0u8; 64 * 32 * 4];
+        block_hashes(&plane, 64, 32, &mut hashes);
+        assert_eq!(hashes.len(), 2);
+        let before = hashes.clone();
+        // Alpha byte of the first pixel of the second block.
+        plane[32 * 4 + 3] = 255;
+        block_hashes(&plane, 64, 32, &mut hashes);
+        assert_eq!(hashes[0], before[0]);
+        assert_ne!(hashes[1], before[1]);
+    }
+}
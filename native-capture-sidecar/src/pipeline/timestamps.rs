@@ -0,0 +1,181 @@
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::StaticSegment;
+
+pub fn timestamps_path(output_path: &str) -> PathBuf {
+    Path::new(output_path).with_extension("frames.json")
+}
+
+// Epoch ms of a monotonic instant. Pairing the clocks once per recording keeps every
+// epoch column a fixed offset of the monotonic one, so it cannot drift if the system
+// clock steps, and files written against the same origin line up exactly.
+pub fn origin_epoch_ms(origin: Instant) -> f64 {
+    let now_epoch_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0);
+    now_epoch_ms - origin.elapsed().as_secs_f64() * 1000.0
+}
+
+// One entry per frame handed to the encoder: when its content was captured, on the
+// monotonic clock (relative to the start_capture request) and as epoch ms.
+pub struct FrameTimestampLog {
+    origin: Instant,
+    origin_epoch_ms: f64,
+    fps: u32,
+    vfr: bool,
+    mono_us: Vec<u64>,
+    duplicated: Vec<bool>,
+}
+
+impl FrameTimestampLog {
+    pub fn new(origin: Instant, fps: u32, vfr: bool) -> Self {
+        Self {
+            origin,
+            origin_epoch_ms: origin_epoch_ms(origin),
+            fps,
+            vfr,
+            mono_us: Vec::new(),
+            duplicated: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, captured_at: Instant, duplicated: bool) {
+        let mono = captured_at.saturating_duration_since(self.origin);
+        self.mono_us.push(mono.as_micros() as u64);
+        self.duplicated.push(duplicated);
+    }
+
+    pub fn frames(&self) -> usize {
+        self.mono_us.len()
+    }
+
+    pub fn first_frame_latency_ms(&self) -> Option<f64> {
+        self.mono_us.first().map(|us| *us as f64 / 1000.0)
+    }
+
+    pub fn frame0_epoch_ms(&self) -> Option<u64> {
+        self.mono_us.first().map(|us| self.epoch_ms(*us))
+    }
+
+    pub fn write(
+        &self,
+        path: &Path,
+        static_segments: Option<&[StaticSegment]>,
+    ) -> std::io::Result<()> {
+        let epoch_ms: Vec<u64> = self.mono_us.iter().map(|us| self.epoch_ms(*us)).collect();
+        let duplicated: Vec<u8> = self.duplicated.iter().map(|d| u8::from(*d)).collect();
+        let body = json!({
+            "version": 1,
+            "fps": self.fps,
+            "frameRateMode": if self.vfr { "vfr" } else { "cfr" },
+            "originEpochMs": self.origin_epoch_ms.round() as u64,
+            "frame0EpochMs": self.frame0_epoch_ms(),
+            "firstFrameLatencyMs": self.first_frame_latency_ms(),
+            "frames": self.frames(),
+            "monoUs": self.mono_us,
+            "epochMs": epoch_ms,
+            "duplicated": duplicated,
+            "staticSegments": static_segments,
+        });
+        std::fs::write(path, body.to_string())
+    }
+
+    fn epoch_ms(&self, mono_us: u64) -> u64 {
+        (self.origin_epoch_ms + mono_us as f64 / 1000.0).round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    fn epoch_now_ms() -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+            * 1000.0
+    }
+
+    #[test]
+    fn origin_epoch_is_the_wall_clock_at_the_instant() {
+        let origin = Instant::now() - Duration::from_millis(1500);
+        let expected = epoch_now_ms() - 1500.0;
+        assert!((origin_epoch_ms(origin) - expected).abs() < 50.0);
+    }
+
+    #[test]
+    fn writes_frames_relative_to_the_origin() {
+        let origin = Instant::now();
+        let mut log = FrameTimestampLog::new(origin, 30, true);
+        log.push(origin + Duration::from_micros(40_000), false);
+        log.push(origin + Duration::from_micros(73_333), false);
+        log.push(origin + Duration::from_micros(106_667), true);
+        // A frame captured before the request clamps to the origin.
+        log.push(
+            origin
+                .checked_sub(Duration::from_millis(5))
+                .unwrap_or(origin),
+            false,
+        );
+
+        let segments = [StaticSegment {
+            start_ms: 33,
+            end_ms: 66,
+            start_mono_us: 73_333,
+            end_mono_us: 106_667,
+        }];
+        let path =
+            std::env::temp_dir().join(format!("timestamps-test-{}.json", std::process::id()));
+        log.write(&path, Some(&segments)).unwrap();
+        let body: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(body["version"], 1);
+        assert_eq!(body["fps"], 30);
+        assert_eq!(body["frameRateMode"], "vfr");
+        assert_eq!(body["frames"], 4);
+        assert_eq!(
+            body["monoUs"],
+            serde_json::json!([40_000, 73_333, 106_667, 0])
+        );
+        assert_eq!(body["duplicated"], serde_json::json!([0, 0, 1, 0]));
+        assert_eq!(body["firstFrameLatencyMs"], 40.0);
+
+        let origin_epoch = body["originEpochMs"].as_u64().unwrap();
+        assert!((origin_epoch as f64 - log.origin_epoch_ms).abs() <= 0.5);
+        let epoch: Vec<u64> = body["epochMs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_u64().unwrap())
+            .collect();
+        for (epoch, mono) in epoch.iter().zip([40_000u64, 73_333, 106_667, 0]) {
+            let expected = log.origin_epoch_ms + mono as f64 / 1000.0;
+            assert_eq!(*epoch, expected.round() as u64);
+        }
+        assert_eq!(body["frame0EpochMs"].as_u64(), epoch.first().copied());
+        assert_eq!(
+            body["staticSegments"],
+            serde_json::json!([{
+                "startMs": 33,
+                "endMs": 66,
+                "startMonoUs": 73_333,
+                "endMonoUs": 106_667,
+            }])
+        );
+    }
+
+    #[test]
+    fn timestamps_file_sits_next_to_the_recording() {
+        assert_eq!(
+            timestamps_path("/tmp/rec/capture.mp4"),
+            Path::new("/tmp/rec/capture.frames.json")
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+// Interval statistics over the pts of frames actually handed to the encoder.
+#[derive(Debug, Clone, Default)]
+pub struct FrameTimingStats {
+    pub frames: u64,
+    pub first_pts: Duration,
+    pub last_pts: Duration,
+    pub min_interval: Option<Duration>,
+    pub max_interval: Option<Duration>,
+}
+
+impl FrameTimingStats {
+    pub fn record(&mut self, pts: Duration) {
+        if self.frames == 0 {
+            self.first_pts = pts;
+        } else {
+            let interval = pts.saturating_sub(self.last_pts);
+            self.min_interval = Some(self.min_interval.map_or(interval, |v| v.min(interval)));
+            self.max_interval = Some(self.max_interval.map_or(interval, |v| v.max(interval)));
+        }
+        self.last_pts = pts;
+        self.frames += 1;
+    }
+
+    pub fn span(&self) -> Duration {
+        self.last_pts.saturating_sub(self.first_pts)
+    }
+
+    pub fn mean_interval_ms(&self) -> Option<f64> {
+        if self.frames < 2 {
+            return None;
+        }
+        Some(self.span().as_secs_f64() * 1000.0 / (self.frames - 1) as f64)
+    }
+
+    pub fn effective_fps(&self) -> Option<f64> {
+        self.mean_interval_ms()
+            .filter(|ms| *ms > 0.0)
+            .map(|ms| 1000.0 / ms)
+    }
+}
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::io::Write;
+
+use crate::error::CaptureError;
 
 #[derive(Debug, Deserialize)]
 pub struct Request {
@@ -16,7 +19,7 @@ pub struct Response {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub payload: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub error: Option<CaptureError>,
 }
 
 impl Response {
@@ -29,17 +32,47 @@ impl Response {
         }
     }
 
-    pub fn err(id: impl Into<String>, msg: impl Into<String>) -> Self {
+    pub fn err(id: impl Into<String>, error: impl Into<CaptureError>) -> Self {
         Self {
             id: id.into(),
             ok: false,
             payload: None,
-            error: Some(msg.into()),
+            error: Some(error.into()),
         }
     }
 }
 
-#[derive(Debug, Deserialize)]
+// Unsolicited `{"event": ...}` line. Callable from any thread: stdout is locked for the
+// whole line, so events never split a response.
+pub fn write_event(event: Value) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{event}");
+    let _ = stdout.flush();
+}
+
+// Reply written after the request loop has moved on, from the thread that did the work.
+pub fn write_response(response: &Response) {
+    let line = response_line(response);
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{line}");
+    let _ = stdout.flush();
+}
+
+// A reply that cannot be serialized still answers its request, with a CAPTURE_FAILED
+// error in the usual shape.
+fn response_line(response: &Response) -> String {
+    serde_json::to_string(response).unwrap_or_else(|err| {
+        log!(
+            "[native-capture][protocol] failed to serialize reply id={} err={}",
+            response.id,
+            err
+        );
+        let error = CaptureError::from(format!("failed to serialize the reply: {err}"));
+        json!(Response::err(response.id.clone(), error)).to_string()
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct StartCapturePayload {
     #[serde(rename = "sessionId")]
     pub session_id: String,
@@ -48,11 +81,29 @@ pub struct StartCapturePayload {
     pub cursor: CursorConfig,
     #[serde(rename = "outputPath")]
     pub output_path: String,
+    // Replace outputPath when it already exists instead of failing the start.
+    #[serde(default)]
+    pub overwrite: bool,
     pub platform: String,
     #[serde(rename = "ffmpegPath")]
     pub ffmpeg_path: Option<String>,
     #[serde(rename = "captureRegion")]
     pub capture_region: Option<CaptureRegion>,
+    // Read by the gdigrab/WGC backend only, like the other frame pipeline settings.
+    #[serde(rename = "staticDetection")]
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub static_detection: Option<StaticDetectionConfig>,
+    #[serde(rename = "inputTelemetry", default)]
+    pub input_telemetry: bool,
+    #[serde(rename = "burnIn")]
+    pub burn_in: Option<BurnInConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub struct BurnInConfig {
+    pub clicks: Option<bool>,
+    pub keys: Option<bool>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,6 +114,34 @@ pub struct EncoderOptionsPayload {
 }
 
 #[derive(Debug, Deserialize)]
+pub struct BenchmarkPayload {
+    pub platform: String,
+    #[serde(rename = "ffmpegPath")]
+    pub ffmpeg_path: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    // Encoders to run, in order; everything get_encoder_options lists when omitted.
+    pub encoders: Option<Vec<String>>,
+    // Planned length of each run; capped by the sidecar.
+    #[serde(rename = "durationMs")]
+    pub duration_ms: Option<u64>,
+    // Rate control as in start_capture; an auto bitrate unless `bitrate` is given.
+    #[serde(default)]
+    pub bitrate: u32,
+    #[serde(rename = "bitrateMode")]
+    pub bitrate_mode: Option<String>,
+    pub quality: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CancelBenchmarkPayload {
+    // Request id of the benchmark_encoders call; any running benchmark when omitted.
+    #[serde(rename = "benchmarkId")]
+    pub benchmark_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct CaptureSource {
     #[serde(rename = "type")]
     pub source_type: String,
@@ -72,22 +151,97 @@ pub struct CaptureSource {
     pub name: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct VideoConfig {
     pub width: u32,
     pub height: u32,
     pub fps: u32,
+    // Bits per second; required unless `bitrateMode` derives it.
+    #[serde(default)]
     pub bitrate: u32,
+    // "manual" (default), "auto" or "quality"; see `ffmpeg::rate_control`.
+    #[serde(rename = "bitrateMode", default)]
+    pub bitrate_mode: Option<String>,
     #[serde(alias = "codec")]
     pub encoder: String,
+    // Tried in order when `encoder` fails while ffmpeg starts up. Opt-in; empty by default.
+    #[serde(rename = "fallbackEncoders", default)]
+    pub fallback_encoders: Vec<String>,
+    #[serde(rename = "frameRateMode", default)]
+    pub frame_rate_mode: Option<String>,
+    // Tier for auto bitrates and quality mode ("draft", "standard", "high"), or "lossless".
+    #[serde(default)]
+    pub quality: Option<String>,
+    #[serde(rename = "losslessCodec", default)]
+    pub lossless_codec: Option<String>,
+    #[serde(rename = "scaleFilter", default)]
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    pub scale_filter: Option<String>,
+    #[serde(default)]
+    pub fit: Option<String>,
+    #[serde(rename = "padColor", default)]
+    pub pad_color: Option<String>,
+    // Lets the sidecar trade quality for speed when the encoder falls behind. Opt-in.
+    #[serde(rename = "adaptiveQuality", default)]
+    pub adaptive_quality: Option<AdaptiveQualityConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+// Lower bounds for adaptive quality. The encoder preset may always step down to
+// `minPreset` (the fastest one when unset); frame rate and resolution only change when
+// their bound is given.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdaptiveQualityConfig {
+    #[serde(rename = "minPreset")]
+    pub min_preset: Option<String>,
+    #[serde(rename = "minFps")]
+    pub min_fps: Option<u32>,
+    #[serde(rename = "minHeight")]
+    pub min_height: Option<u32>,
+}
+
+impl VideoConfig {
+    // Codec to record with when `quality` is "lossless"; ffv1 unless overridden.
+    pub fn lossless_codec(&self) -> Option<&str> {
+        if self.quality.as_deref() != Some("lossless") {
+            return None;
+        }
+        Some(self.lossless_codec.as_deref().unwrap_or("ffv1"))
+    }
+
+    // `encoder` followed by the distinct fallbacks. Lossless recordings ignore the encoder,
+    // so they never fall back.
+    pub fn encoder_chain(&self) -> Vec<String> {
+        let mut chain = vec![self.encoder.clone()];
+        if self.lossless_codec().is_none() {
+            for encoder in &self.fallback_encoders {
+                if !chain.contains(encoder) {
+                    chain.push(encoder.clone());
+                }
+            }
+        }
+        chain
+    }
+
+    // Lossless recordings have nothing to trade, so they never adapt.
+    pub fn adaptive_quality(&self) -> Option<&AdaptiveQualityConfig> {
+        self.adaptive_quality.as_ref().filter(|_| self.lossless_codec().is_none())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct CursorConfig {
     pub mode: String,
+    // Sample a cursor track for custom rendering; defaults to on when the cursor is hidden.
+    pub track: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
+impl CursorConfig {
+    pub fn track_enabled(&self) -> bool {
+        self.track.unwrap_or(self.mode == "hide")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct CaptureRegion {
     pub x: i32,
     pub y: i32,
@@ -95,15 +249,101 @@ pub struct CaptureRegion {
     pub height: u32,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub struct StaticDetectionConfig {
+    pub threshold: Option<f64>,
+    #[serde(rename = "minDurationMs")]
+    pub min_duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateAutoZoomPayload {
+    #[serde(rename = "telemetryPath")]
+    pub telemetry_path: String,
+    pub intensity: Option<String>,
+    // Required: regions are clamped to the recording, like the TS version.
+    #[serde(rename = "durationMs")]
+    pub duration_ms: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InjectInputPayload {
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+    pub events: Vec<InjectedInputEvent>,
+}
+
+// Same shapes as the telemetry file events; `ts` is epoch ms like there, and `label`
+// names the keys of a shortcut.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum InjectedInputEvent {
+    #[serde(rename = "mouseDown")]
+    MouseDown { ts: Option<f64>, x: i32, y: i32 },
+    #[serde(rename = "keyDownCategory")]
+    KeyDownCategory {
+        ts: Option<f64>,
+        category: String,
+        label: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct StopCapturePayload {
     #[serde(rename = "sessionId")]
     pub session_id: String,
-    #[allow(dead_code)]
+    // `false` discards the recording instead of finalizing it.
     pub finalize: Option<bool>,
 }
 
-pub fn init_response(id: impl Into<String>) -> Response {
+#[derive(Debug, Default, Deserialize)]
+pub struct ShutdownPayload {
+    // Budget for finalizing running captures; capped by the sidecar.
+    #[serde(rename = "timeoutMs")]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DiagnosticsPayload {
+    #[serde(rename = "ffmpegPath")]
+    pub ffmpeg_path: Option<String>,
+    // Folder the bundle is written to; the temp folder when omitted.
+    #[serde(rename = "outputDir")]
+    pub output_dir: Option<String>,
+    // How many session journals to include, newest first.
+    pub journals: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecoverSessionPayload {
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+    // Falls back to the ffmpeg the session was started with, then PATH.
+    #[serde(rename = "ffmpegPath")]
+    pub ffmpeg_path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelPayload {
+    // `id` of the request to withdraw.
+    #[serde(rename = "requestId")]
+    pub request_id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct InitPayload {
+    // Checked before VELOCITY_FFMPEG_PATH, the bundled ffmpeg and PATH.
+    #[serde(rename = "ffmpegPath")]
+    pub ffmpeg_path: Option<String>,
+}
+
+pub const SIDECAR_VERSION: &str = "0.2.0";
+
+// `ffmpeg` is the discovery report: the binary later commands will use and why.
+pub fn init_response(id: impl Into<String>, ffmpeg: Value) -> Response {
     let backend = if cfg!(target_os = "windows") {
         "ffmpeg-gdigrab"
     } else if cfg!(target_os = "macos") {
@@ -115,8 +355,9 @@ pub fn init_response(id: impl Into<String>) -> Response {
     Response::ok(
         id,
         json!({
-            "version": "0.2.0",
+            "version": SIDECAR_VERSION,
             "backend": backend,
+            "ffmpeg": ffmpeg,
             "status": "ready"
         }),
     )
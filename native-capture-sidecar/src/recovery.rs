@@ -0,0 +1,245 @@
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{CaptureError, ErrorCode};
+use crate::ffmpeg::resolve_ffmpeg_path;
+use crate::journal::SessionJournal;
+use crate::output::is_mp4_family;
+use crate::protocol::{RecoverSessionPayload, Response};
+use crate::system::{interrupt_process, kill_process, process_image_name};
+
+// Same budget stop_capture gives ffmpeg to write its trailer.
+const STALE_FFMPEG_GRACE: Duration = Duration::from_secs(8);
+
+pub fn handle_list_recoverable_sessions(id: String) -> Response {
+    let sessions: Vec<Value> = SessionJournal::load_all()
+        .into_iter()
+        .filter(SessionJournal::is_recoverable)
+        .map(|journal| {
+            let bytes = std::fs::metadata(journal.recorded_path()).ok().map(|m| m.len());
+            json!({
+                "sessionId": journal.session_id,
+                "state": journal.state,
+                "outputPath": journal.output_path,
+                "recordingPath": journal.recording_path,
+                "outputBytes": bytes,
+                "platform": journal.platform,
+                "encoder": journal.encoder,
+                "settings": journal.settings,
+                "startedAtEpochMs": journal.started_at_epoch_ms,
+                "updatedAtEpochMs": journal.updated_at_epoch_ms,
+                "ffmpegRunning": journal.ffmpeg_running(),
+                "error": journal.error,
+            })
+        })
+        .collect();
+    Response::ok(id, json!({ "sessions": sessions }))
+}
+
+// Stops a leftover ffmpeg, then checks the recording and remuxes it when it is damaged. The
+// journal is removed once nothing is left to salvage, and kept when the recording could not
+// be checked or remuxed so a later attempt (e.g. with another ffmpeg) can retry.
+pub fn handle_recover_session(id: String, payload: Value) -> Response {
+    let recover_payload: RecoverSessionPayload = match serde_json::from_value(payload) {
+        Ok(v) => v,
+        Err(err) => {
+            return Response::err(id, CaptureError::invalid_payload("recover_session", err));
+        }
+    };
+    let journal = match SessionJournal::load(&recover_payload.session_id) {
+        Ok(journal) => journal,
+        Err(err) => return Response::err(id, err),
+    };
+    if !journal.is_recoverable() {
+        return Response::err(
+            id,
+            CaptureError::new(
+                ErrorCode::AlreadyRunning,
+                format!(
+                    "session {} is still owned by a running sidecar (pid {})",
+                    journal.session_id, journal.sidecar_pid
+                ),
+            )
+            .with_details(json!({ "sidecarPid": journal.sidecar_pid })),
+        );
+    }
+
+    let stale_ffmpeg = match journal.ffmpeg_pid.filter(|_| journal.ffmpeg_running()) {
+        Some(pid) => stop_stale_ffmpeg(pid),
+        None => "notRunning",
+    };
+    let ffmpeg_exe = resolve_ffmpeg_path(
+        recover_payload
+            .ffmpeg_path
+            .as_deref()
+            .or(journal.ffmpeg_path.as_deref()),
+    );
+    let salvage = salvage_output(
+        journal.recorded_path(),
+        &journal.output_path,
+        ffmpeg_exe.as_deref(),
+    );
+    log!(
+        "[native-capture][recovery] session={} stale_ffmpeg={} output={} result={}",
+        journal.session_id, stale_ffmpeg, journal.output_path, salvage
+    );
+    let settled = matches!(
+        salvage["status"].as_str(),
+        Some("intact" | "remuxed" | "missing" | "empty")
+    );
+    if settled {
+        journal.remove();
+    }
+
+    Response::ok(
+        id,
+        json!({
+            "sessionId": journal.session_id,
+            "outputPath": journal.output_path,
+            "staleFfmpeg": stale_ffmpeg,
+            "salvage": salvage,
+            "journalRemoved": settled,
+        }),
+    )
+}
+
+// Interrupts first so ffmpeg can still finalize the file, then kills it.
+fn stop_stale_ffmpeg(pid: u32) -> &'static str {
+    log!("[native-capture][recovery] stopping stale ffmpeg pid={pid}");
+    if interrupt_process(pid) {
+        let deadline = Instant::now() + STALE_FFMPEG_GRACE;
+        while Instant::now() < deadline {
+            if process_image_name(pid).is_none() {
+                return "interrupted";
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+    if kill_process(pid) || process_image_name(pid).is_none() {
+        "killed"
+    } else {
+        "killFailed"
+    }
+}
+
+// `recorded_path` is what the session wrote. When that is a temp file, the salvaged
+// recording is moved to `output_path` if the crash left it free.
+fn salvage_output(recorded_path: &str, output_path: &str, ffmpeg_exe: Option<&str>) -> Value {
+    let bytes = match std::fs::metadata(recorded_path) {
+        Ok(meta) => meta.len(),
+        Err(_) => return json!({ "status": "missing" }),
+    };
+    if bytes == 0 {
+        return json!({ "status": "empty", "bytes": 0 });
+    }
+    let Some(ffmpeg_exe) = ffmpeg_exe else {
+        return json!({
+            "status": "unchecked",
+            "bytes": bytes,
+            "error": "ffmpeg not found; output left as is",
+        });
+    };
+
+    let temp = recorded_path != output_path;
+    let recovered_path = if temp && !Path::new(output_path).exists() {
+        output_path.to_string()
+    } else {
+        recovered_output_path(output_path)
+    };
+
+    // A stream copy to nowhere reads every packet, so any damage shows up on stderr.
+    match run_ffmpeg(ffmpeg_exe, &["-i", recorded_path, "-map", "0", "-c", "copy", "-f", "null", "-"]) {
+        Ok(check) if check.success && check.message.is_none() => {
+            if !temp {
+                return json!({ "status": "intact", "bytes": bytes });
+            }
+            return match std::fs::rename(recorded_path, &recovered_path) {
+                Ok(()) => json!({ "status": "intact", "bytes": bytes, "recoveredPath": recovered_path }),
+                Err(err) => json!({
+                    "status": "intact",
+                    "bytes": bytes,
+                    "recoveredPath": recorded_path,
+                    "error": format!("failed to move the recording: {err}"),
+                }),
+            };
+        }
+        Ok(_) => {}
+        Err(err) => return json!({ "status": "unchecked", "bytes": bytes, "error": err }),
+    }
+
+    let mut args = vec![
+        "-y",
+        "-err_detect", "ignore_err",
+        "-fflags", "+genpts+discardcorrupt",
+        "-i", recorded_path,
+        "-map", "0",
+        "-c", "copy",
+    ];
+    if is_mp4_family(output_path) {
+        args.extend(["-movflags", "+faststart"]);
+    }
+    args.push(&recovered_path);
+    let remux = run_ffmpeg(ffmpeg_exe, &args);
+    let recovered_bytes = std::fs::metadata(&recovered_path).map(|m| m.len()).unwrap_or(0);
+    match remux {
+        Ok(remux) if remux.success && recovered_bytes > 0 => {
+            // The damaged temp file has nothing the remux did not keep.
+            if temp {
+                let _ = std::fs::remove_file(recorded_path);
+            }
+            json!({
+                "status": "remuxed",
+                "bytes": bytes,
+                "recoveredPath": recovered_path,
+                "recoveredBytes": recovered_bytes,
+                "warning": remux.message,
+            })
+        }
+        remux => {
+            let _ = std::fs::remove_file(&recovered_path);
+            let error = match remux {
+                Ok(remux) => remux.message.unwrap_or_else(|| "remux produced no output".to_string()),
+                Err(err) => err,
+            };
+            json!({ "status": "unrecoverable", "bytes": bytes, "error": error })
+        }
+    }
+}
+
+struct FfmpegRun {
+    success: bool,
+    // Last line ffmpeg printed at error level, if any.
+    message: Option<String>,
+}
+
+fn run_ffmpeg(ffmpeg_exe: &str, args: &[&str]) -> Result<FfmpegRun, String> {
+    let output = Command::new(ffmpeg_exe)
+        .args(["-hide_banner", "-nostdin", "-v", "error"])
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|e| format!("failed to run ffmpeg: {e}"))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Ok(FfmpegRun {
+        success: output.status.success(),
+        message: stderr
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+            .map(str::to_string),
+    })
+}
+
+fn recovered_output_path(output_path: &str) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.recovered.{}", ext.to_string_lossy()),
+        None => format!("{stem}.recovered"),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
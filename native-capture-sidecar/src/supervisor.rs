@@ -0,0 +1,692 @@
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::{CaptureError, ErrorCode};
+use crate::system::interrupt_process;
+use crate::warnings::{fatal_warning, match_stderr_line, CaptureWarning, WarningKind, WarningLog};
+
+const STDERR_RING_LINES: usize = 30;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// After "q" (or stdin EOF) has had its grace period, SIGINT gets this long before a kill.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(2);
+const READER_JOIN_GRACE: Duration = Duration::from_millis(500);
+
+// Base command for every long-running ffmpeg the sidecar supervises. Progress goes to
+// stdout as key=value blocks, and stderr lines carry ffmpeg's own level tag so they can be
+// classified without guessing from the text.
+pub fn ffmpeg_command(ffmpeg_exe: &str) -> Command {
+    let mut command = Command::new(ffmpeg_exe);
+    command
+        .args(["-hide_banner", "-nostats", "-progress", "pipe:1"])
+        .args(["-loglevel", "level+warning"])
+        .arg("-y")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    command
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Fatal,
+    Error,
+    Warning,
+    Info,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Fatal => "fatal",
+            LogLevel::Error => "error",
+            LogLevel::Warning => "warning",
+            LogLevel::Info => "info",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StderrLine {
+    pub level: LogLevel,
+    pub text: String,
+}
+
+// Splits the "[level] " tag ffmpeg adds under `-loglevel level+...` out of a stderr line
+// ("[h264_nvenc @ 0x1] [error] No capable devices found"). Untagged lines come from plain
+// prints (or an ffmpeg too old for the flag) and are judged by their wording.
+pub fn classify_stderr_line(line: &str) -> StderrLine {
+    const TAGS: [(&str, LogLevel); 8] = [
+        ("[panic] ", LogLevel::Fatal),
+        ("[fatal] ", LogLevel::Fatal),
+        ("[error] ", LogLevel::Error),
+        ("[warning] ", LogLevel::Warning),
+        ("[info] ", LogLevel::Info),
+        ("[verbose] ", LogLevel::Info),
+        ("[debug] ", LogLevel::Info),
+        ("[trace] ", LogLevel::Info),
+    ];
+    let tagged = TAGS
+        .iter()
+        .filter_map(|(tag, level)| line.find(tag).map(|at| (at, *tag, *level)))
+        .min_by_key(|(at, _, _)| *at);
+    if let Some((at, tag, level)) = tagged {
+        let text = format!("{}{}", &line[..at], &line[at + tag.len()..]);
+        return StderrLine { level, text: text.trim().to_string() };
+    }
+    let lower = line.to_lowercase();
+    let level = if lower.contains("error") || lower.contains("failed") || lower.contains("invalid") {
+        LogLevel::Error
+    } else {
+        LogLevel::Info
+    };
+    StderrLine { level, text: line.trim().to_string() }
+}
+
+// Last complete block of `-progress` output.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub frame: u64,
+    pub fps: f64,
+    pub dup_frames: u64,
+    pub drop_frames: u64,
+    // Encode speed relative to real time ("1.02x").
+    pub speed: Option<f64>,
+}
+
+impl Progress {
+    fn apply(&mut self, key: &str, value: &str) {
+        let value = value.trim();
+        match key {
+            "frame" => self.frame = value.parse().unwrap_or(self.frame),
+            "fps" => self.fps = value.parse().unwrap_or(self.fps),
+            "dup_frames" => self.dup_frames = value.parse().unwrap_or(self.dup_frames),
+            "drop_frames" => self.drop_frames = value.parse().unwrap_or(self.drop_frames),
+            "speed" => self.speed = value.trim_end_matches('x').trim().parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+struct Shared {
+    recent: VecDeque<StderrLine>,
+    progress: Option<Progress>,
+    warnings: WarningLog,
+}
+
+// How `wait_ready` decides ffmpeg is up.
+#[derive(Debug, Clone, Copy)]
+pub enum Readiness {
+    // Device inputs (gdigrab, avfoundation) report progress within a couple of frames;
+    // nothing by the deadline means the input is stuck.
+    Progress { timeout: Duration },
+    // Pipe-fed ffmpeg reads nothing and encodes nothing until the first frame arrives, so
+    // only an early exit can be detected before frames flow.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    Settle { window: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopStep {
+    // Exited on its own (stdin EOF for pipe-fed ffmpeg, or a failure).
+    Exited,
+    Quit,
+    Interrupted,
+    Killed,
+}
+
+impl StopStep {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StopStep::Exited => "exited",
+            StopStep::Quit => "quit",
+            StopStep::Interrupted => "interrupted",
+            StopStep::Killed => "killed",
+        }
+    }
+}
+
+pub struct FfmpegExit {
+    pub status: Option<ExitStatus>,
+    pub step: StopStep,
+    pub stderr_excerpt: String,
+    pub progress: Option<Progress>,
+    pub warnings: Vec<CaptureWarning>,
+}
+
+impl FfmpegExit {
+    pub fn describe(&self) -> String {
+        let Some(status) = self.status else {
+            return "exit status unavailable".to_string();
+        };
+        if let Some(code) = status.code() {
+            return format!("exit code {code}");
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return format!("terminated by signal {signal}");
+            }
+        }
+        status.to_string()
+    }
+
+    // ffmpeg exits 0 after "q" or EOF and 255 after SIGINT; both still write the trailer.
+    // A kill or anything else leaves the output in doubt.
+    pub fn check(&self) -> Result<(), CaptureError> {
+        if self.step == StopStep::Killed {
+            return Err(self.error(
+                "ffmpeg did not finish in time and was killed; the output may be incomplete",
+            ));
+        }
+        if !self.succeeded() {
+            return Err(self.error(&format!("ffmpeg failed ({})", self.describe())));
+        }
+        Ok(())
+    }
+
+    fn succeeded(&self) -> bool {
+        if self.step == StopStep::Killed {
+            return false;
+        }
+        match self.status.and_then(|status| status.code()) {
+            Some(0) => true,
+            Some(255) => self.step == StopStep::Interrupted,
+            _ => false,
+        }
+    }
+
+    // Error about this exit. A recognized fatal condition decides the code and adds its
+    // advice to the message; the stderr excerpt follows.
+    pub fn error(&self, summary: &str) -> CaptureError {
+        let fatal = fatal_warning(&self.warnings);
+        let mut message = format!("{summary}.");
+        if let Some(warning) = fatal {
+            message.push(' ');
+            message.push_str(warning.message);
+        }
+        if !self.stderr_excerpt.is_empty() {
+            message.push_str(" stderr=");
+            message.push_str(&self.stderr_excerpt);
+        }
+        let code = fatal.map_or(ErrorCode::FfmpegFailed, |warning| warning.kind.into());
+        CaptureError::new(code, message).with_details(json!({
+            "ffmpegExit": self.to_json(),
+            "warning": fatal.map(|warning| warning.kind),
+            "stderr": self.stderr_excerpt,
+        }))
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "status": self.describe(),
+            "stoppedBy": self.step.as_str(),
+            "ok": self.succeeded(),
+            "framesEncoded": self.progress.as_ref().map(|p| p.frame),
+            "fps": self.progress.as_ref().map(|p| p.fps),
+            "speed": self.progress.as_ref().and_then(|p| p.speed),
+            "dupFrames": self.progress.as_ref().map(|p| p.dup_frames),
+            "dropFrames": self.progress.as_ref().map(|p| p.drop_frames),
+        })
+    }
+}
+
+// One supervised ffmpeg: owns the child, drains stdout (progress) and stderr (classified
+// log lines) on reader threads, and handles startup checks and escalating shutdown.
+pub struct FfmpegProcess {
+    child: Child,
+    label: &'static str,
+    stdin: Option<ChildStdin>,
+    shared: Arc<Mutex<Shared>>,
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl FfmpegProcess {
+    // `label` is the log area ("win", "mac", "wgc"); warnings are reported for `session_id`.
+    pub fn spawn(
+        mut command: Command,
+        label: &'static str,
+        session_id: &str,
+    ) -> Result<Self, CaptureError> {
+        let mut child = command.spawn().map_err(|err| {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                CaptureError::new(ErrorCode::FfmpegNotFound, format!("failed to spawn ffmpeg: {err}"))
+                    .with_details(json!({ "ffmpegPath": command.get_program().to_string_lossy() }))
+            } else {
+                CaptureError::io("failed to spawn ffmpeg", &err)
+            }
+        })?;
+        let shared = Arc::new(Mutex::new(Shared {
+            recent: VecDeque::new(),
+            progress: None,
+            warnings: WarningLog::new(session_id, label),
+        }));
+        let mut readers = Vec::new();
+
+        if let Some(stdout) = child.stdout.take() {
+            let shared = Arc::clone(&shared);
+            readers.push(thread::spawn(move || {
+                let mut block = Progress::default();
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    let Some((key, value)) = line.split_once('=') else {
+                        continue;
+                    };
+                    if key == "progress" {
+                        if let Ok(mut guard) = shared.lock() {
+                            let (dropped, duplicated) = match &guard.progress {
+                                Some(last) => (
+                                    block.drop_frames.saturating_sub(last.drop_frames),
+                                    block.dup_frames.saturating_sub(last.dup_frames),
+                                ),
+                                None => (block.drop_frames, block.dup_frames),
+                            };
+                            guard.warnings.record(WarningKind::DroppedFrames, dropped, None);
+                            guard.warnings.record(WarningKind::DuplicatedFrames, duplicated, None);
+                            guard.progress = Some(block.clone());
+                        }
+                    } else {
+                        block.apply(key, value);
+                    }
+                }
+            }));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let shared = Arc::clone(&shared);
+            readers.push(thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let line = classify_stderr_line(&line);
+                    log!(
+                        "[native-capture][{}][ffmpeg][{}] {}",
+                        label,
+                        line.level.as_str(),
+                        line.text
+                    );
+                    if let Ok(mut guard) = shared.lock() {
+                        if let Some(kind) = match_stderr_line(&line) {
+                            guard.warnings.record(kind, 1, Some(&line.text));
+                        }
+                        if guard.recent.len() >= STDERR_RING_LINES {
+                            guard.recent.pop_front();
+                        }
+                        guard.recent.push_back(line);
+                    }
+                }
+            }));
+        }
+
+        let stdin = child.stdin.take();
+        Ok(Self { child, label, stdin, shared, readers })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    // Hands stdin to a frame writer. Stopping then relies on the writer closing it.
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        self.stdin.take()
+    }
+
+    pub fn running(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    pub fn progress(&self) -> Option<Progress> {
+        self.shared.lock().ok().and_then(|guard| guard.progress.clone())
+    }
+
+    pub fn warnings(&self) -> Vec<CaptureWarning> {
+        self.shared
+            .lock()
+            .map(|guard| guard.warnings.warnings())
+            .unwrap_or_default()
+    }
+
+    pub fn stderr_lines(&self) -> Vec<StderrLine> {
+        self.shared
+            .lock()
+            .map(|guard| guard.recent.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn stderr_excerpt(&self) -> String {
+        self.stderr_lines()
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    // Returns once ffmpeg is known to be running. An exit during startup becomes an error
+    // carrying the exit status and the stderr excerpt; a stuck input is killed.
+    pub fn wait_ready(&mut self, readiness: Readiness) -> Result<(), CaptureError> {
+        let started = Instant::now();
+        let deadline = started
+            + match readiness {
+                Readiness::Progress { timeout } => timeout,
+                Readiness::Settle { window } => window,
+            };
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => {
+                    self.join_readers();
+                    let exit = FfmpegExit {
+                        status: Some(status),
+                        step: StopStep::Exited,
+                        stderr_excerpt: self.stderr_excerpt(),
+                        progress: self.progress(),
+                        warnings: self.warnings(),
+                    };
+                    return Err(exit.error(&format!("ffmpeg exited during startup ({})", exit.describe())));
+                }
+                Ok(None) => {}
+                Err(err) => return Err(CaptureError::io("failed to verify ffmpeg startup", &err)),
+            }
+            if let Readiness::Progress { .. } = readiness {
+                if self.progress().is_some_and(|progress| progress.frame > 0) {
+                    log!(
+                        "[native-capture][{}][ffmpeg] ready after {} ms",
+                        self.label,
+                        started.elapsed().as_millis()
+                    );
+                    return Ok(());
+                }
+            }
+            if Instant::now() >= deadline {
+                return match readiness {
+                    Readiness::Settle { .. } => Ok(()),
+                    Readiness::Progress { timeout } => {
+                        let _ = self.child.kill();
+                        let status = self.child.wait().ok();
+                        self.join_readers();
+                        let exit = FfmpegExit {
+                            status,
+                            step: StopStep::Killed,
+                            stderr_excerpt: self.stderr_excerpt(),
+                            progress: self.progress(),
+                            warnings: self.warnings(),
+                        };
+                        Err(exit.error(&format!(
+                            "ffmpeg produced no frames within {} ms",
+                            timeout.as_millis()
+                        )))
+                    }
+                };
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // Asks ffmpeg to finish: "q" on stdin when we still hold it, otherwise the writer has
+    // already closed the pipe. Escalates to SIGINT (Unix) and finally a kill.
+    pub fn stop(mut self, grace: Duration) -> FfmpegExit {
+        let mut step = StopStep::Exited;
+        if let Some(mut stdin) = self.stdin.take() {
+            let _ = stdin.write_all(b"q\n");
+            let _ = stdin.flush();
+            step = StopStep::Quit;
+        }
+        let mut status = self.wait_until(Instant::now() + grace);
+        if status.is_none() && cfg!(unix) && interrupt_process(self.child.id()) {
+            log!(
+                "[native-capture][{}][ffmpeg] no exit after {} ms, sending SIGINT",
+                self.label,
+                grace.as_millis()
+            );
+            step = StopStep::Interrupted;
+            status = self.wait_until(Instant::now() + INTERRUPT_GRACE);
+        }
+        if status.is_none() {
+            log!("[native-capture][{}][ffmpeg] killing unresponsive ffmpeg", self.label);
+            let _ = self.child.kill();
+            step = StopStep::Killed;
+            status = self.child.wait().ok();
+        }
+        self.join_readers();
+        let exit = self.exit(status, step);
+        log!(
+            "[native-capture][{}][ffmpeg] stopped by={} {}",
+            self.label,
+            step.as_str(),
+            exit.describe()
+        );
+        exit
+    }
+
+    // Kills ffmpeg without letting it flush, for recordings that are being discarded.
+    pub fn abort(mut self) -> FfmpegExit {
+        let _ = self.child.kill();
+        let status = self.child.wait().ok();
+        self.join_readers();
+        let exit = self.exit(status, StopStep::Killed);
+        log!("[native-capture][{}][ffmpeg] aborted {}", self.label, exit.describe());
+        exit
+    }
+
+    fn exit(&self, status: Option<ExitStatus>, step: StopStep) -> FfmpegExit {
+        FfmpegExit {
+            status,
+            step,
+            stderr_excerpt: self.stderr_excerpt(),
+            progress: self.progress(),
+            warnings: self.warnings(),
+        }
+    }
+
+    fn wait_until(&mut self, deadline: Instant) -> Option<ExitStatus> {
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+                Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+
+    // The pipes close when ffmpeg exits, so the readers finish right after; the bound only
+    // matters if something else inherited them.
+    fn join_readers(&mut self) {
+        let deadline = Instant::now() + READER_JOIN_GRACE;
+        while self.readers.iter().any(|reader| !reader.is_finished()) && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        for reader in self.readers.drain(..) {
+            if reader.is_finished() {
+                let _ = reader.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_level_tag_out_of_stderr_lines() {
+        let line = classify_stderr_line("[h264_nvenc @ 0x55d1] [error] No capable devices found");
+        assert_eq!(line.level, LogLevel::Error);
+        assert_eq!(line.text, "[h264_nvenc @ 0x55d1] No capable devices found");
+
+        let line = classify_stderr_line("[fatal] Conversion failed!");
+        assert_eq!(line.level, LogLevel::Fatal);
+        assert_eq!(line.text, "Conversion failed!");
+
+        // The first tag wins even when the message quotes another one.
+        let line = classify_stderr_line("[warning] saw [error] in the input name");
+        assert_eq!(line.level, LogLevel::Warning);
+
+        assert_eq!(classify_stderr_line("[verbose] Stream mapping:").level, LogLevel::Info);
+    }
+
+    #[test]
+    fn judges_untagged_lines_by_wording() {
+        assert_eq!(classify_stderr_line("Error opening input files").level, LogLevel::Error);
+        assert_eq!(classify_stderr_line("Invalid argument").level, LogLevel::Error);
+        let line = classify_stderr_line("  Press [q] to stop  ");
+        assert_eq!(line.level, LogLevel::Info);
+        assert_eq!(line.text, "Press [q] to stop");
+    }
+
+    #[test]
+    fn progress_keeps_the_last_parsable_values() {
+        let mut progress = Progress::default();
+        for (key, value) in [
+            ("frame", "120"),
+            ("fps", "59.94"),
+            ("dup_frames", "3"),
+            ("drop_frames", "1"),
+            ("speed", " 1.02x"),
+            ("bitrate", "4000.0kbits/s"),
+        ] {
+            progress.apply(key, value);
+        }
+        assert_eq!(progress.frame, 120);
+        assert_eq!(progress.fps, 59.94);
+        assert_eq!(progress.dup_frames, 3);
+        assert_eq!(progress.drop_frames, 1);
+        assert_eq!(progress.speed, Some(1.02));
+
+        // ffmpeg prints "N/A" before the first frame; counters stay, speed is unknown.
+        progress.apply("frame", "N/A");
+        progress.apply("speed", "N/A");
+        assert_eq!(progress.frame, 120);
+        assert_eq!(progress.speed, None);
+    }
+
+    #[cfg(unix)]
+    fn exit_with(code: Option<i32>, step: StopStep) -> FfmpegExit {
+        use std::os::unix::process::ExitStatusExt;
+        FfmpegExit {
+            // Wait statuses keep the exit code in the second byte and a signal in the first.
+            status: Some(match code {
+                Some(code) => ExitStatus::from_raw(code << 8),
+                None => ExitStatus::from_raw(9),
+            }),
+            step,
+            stderr_excerpt: String::new(),
+            progress: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_accepts_clean_exits_only() {
+        assert!(exit_with(Some(0), StopStep::Quit).check().is_ok());
+        assert!(exit_with(Some(0), StopStep::Exited).check().is_ok());
+        assert!(exit_with(Some(255), StopStep::Interrupted).check().is_ok());
+        // 255 without our SIGINT is ffmpeg reporting its own failure.
+        assert!(exit_with(Some(255), StopStep::Quit).check().is_err());
+        assert!(exit_with(Some(1), StopStep::Exited).check().is_err());
+
+        let killed = exit_with(Some(0), StopStep::Killed).check().unwrap_err();
+        assert_eq!(killed.code, ErrorCode::FfmpegFailed);
+        assert!(killed.message.contains("killed"));
+        let signalled = exit_with(None, StopStep::Interrupted);
+        assert_eq!(signalled.describe(), "terminated by signal 9");
+        assert!(signalled.check().is_err());
+    }
+
+    // Runs `script` under sh in place of ffmpeg, with the pipes `ffmpeg_command` sets up.
+    #[cfg(unix)]
+    fn fake_ffmpeg(script: &str) -> FfmpegProcess {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", script])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        FfmpegProcess::spawn(command, "test", "supervisor-test").unwrap()
+    }
+
+    #[cfg(unix)]
+    const PROGRESS_BLOCK: &str = "printf 'frame=42\\nfps=30\\ndrop_frames=0\\nspeed=1x\\nprogress=continue\\n'";
+
+    #[cfg(unix)]
+    #[test]
+    fn quits_on_q_after_reporting_progress() {
+        let mut ffmpeg =
+            fake_ffmpeg(&format!("{PROGRESS_BLOCK}; read line; [ \"$line\" = q ] && exit 0; exit 3"));
+        ffmpeg.wait_ready(Readiness::Progress { timeout: Duration::from_secs(5) }).unwrap();
+
+        let exit = ffmpeg.stop(Duration::from_secs(5));
+        assert_eq!(exit.step, StopStep::Quit);
+        assert!(exit.check().is_ok());
+        assert_eq!(exit.progress.map(|progress| progress.frame), Some(42));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn turns_progress_counters_into_frame_warnings() {
+        let mut ffmpeg = fake_ffmpeg(
+            "printf 'frame=30\\ndrop_frames=2\\ndup_frames=0\\nprogress=continue\\n'; \
+             printf 'frame=60\\ndrop_frames=5\\ndup_frames=1\\nprogress=end\\n'; read line",
+        );
+        ffmpeg.wait_ready(Readiness::Progress { timeout: Duration::from_secs(5) }).unwrap();
+        let exit = ffmpeg.stop(Duration::from_secs(5));
+        let count = |kind| exit.warnings.iter().find(|w| w.kind == kind).map(|w| w.count);
+        // Counters are cumulative, so the second block only adds its difference.
+        assert_eq!(count(WarningKind::DroppedFrames), Some(5));
+        assert_eq!(count(WarningKind::DuplicatedFrames), Some(1));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn interrupts_when_q_is_ignored() {
+        let ffmpeg = fake_ffmpeg("trap 'exit 255' INT; while :; do sleep 0.05; done");
+        // Give sh time to install the trap before the grace runs out.
+        let exit = ffmpeg.stop(Duration::from_millis(300));
+        assert_eq!(exit.step, StopStep::Interrupted);
+        assert_eq!(exit.describe(), "exit code 255");
+        assert!(exit.check().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_when_sigint_is_ignored() {
+        let ffmpeg = fake_ffmpeg("trap '' INT; while :; do sleep 0.05; done");
+        let started = Instant::now();
+        let exit = ffmpeg.stop(Duration::from_millis(200));
+        assert_eq!(exit.step, StopStep::Killed);
+        assert!(exit.check().is_err());
+        assert!(started.elapsed() >= INTERRUPT_GRACE);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_stuck_input_fails_the_readiness_wait() {
+        let mut ffmpeg = fake_ffmpeg(
+            "echo '[x11grab @ 0x1] [warning] waiting for the display' >&2; while :; do sleep 0.05; done",
+        );
+        let err = ffmpeg
+            .wait_ready(Readiness::Progress { timeout: Duration::from_millis(300) })
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::FfmpegFailed);
+        assert!(err.message.contains("produced no frames within 300 ms"), "{}", err.message);
+        assert!(err.message.contains("waiting for the display"), "{}", err.message);
+        assert_eq!(err.details.unwrap()["ffmpegExit"]["stoppedBy"], "killed");
+        assert!(!ffmpeg.running());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_early_exit_reports_the_fatal_condition() {
+        let mut ffmpeg = fake_ffmpeg(
+            "echo \"[h264_nvenc @ 0x1] [error] OpenEncodeSessionEx failed: unsupported device (2): (no details)\" >&2; exit 1",
+        );
+        let err = ffmpeg
+            .wait_ready(Readiness::Settle { window: Duration::from_secs(5) })
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::EncoderUnavailable);
+        assert!(err.message.contains("exited during startup (exit code 1)"), "{}", err.message);
+    }
+}
@@ -1,6 +1,15 @@
 use std::process::{Command, Stdio};
 
 pub fn detect_gpu_vendors_windows() -> Option<(bool, bool)> {
+    let text = gpu_adapter_names_windows()?;
+    let lower = text.to_lowercase();
+    let has_nvidia = lower.contains("nvidia");
+    let has_amd = lower.contains("amd") || lower.contains("radeon");
+    Some((has_nvidia, has_amd))
+}
+
+// Names of the video controllers as Windows reports them, one per line.
+pub fn gpu_adapter_names_windows() -> Option<String> {
     if !cfg!(target_os = "windows") {
         return None;
     }
@@ -21,9 +30,63 @@ pub fn detect_gpu_vendors_windows() -> Option<(bool, bool)> {
     if text.trim().is_empty() {
         return None;
     }
+    Some(text)
+}
 
-    let lower = text.to_lowercase();
-    let has_nvidia = lower.contains("nvidia");
-    let has_amd = lower.contains("amd") || lower.contains("radeon");
-    Some((has_nvidia, has_amd))
+// Executable name of a running process, or None when nothing runs under that pid. Used to
+// check that a pid left in a session journal still belongs to the program we expect.
+pub fn process_image_name(pid: u32) -> Option<String> {
+    let output = if cfg!(target_os = "windows") {
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?
+    } else {
+        Command::new("ps")
+            .args(["-p", &pid.to_string(), "-o", "comm="])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty())?;
+    if cfg!(target_os = "windows") {
+        // "ffmpeg.exe","1234","Console",...; anything else is the "no tasks" notice.
+        let name = line.strip_prefix('"')?.split('"').next()?;
+        return Some(name.to_string());
+    }
+    Some(line.to_string())
+}
+
+// Asks a process to exit the way Ctrl+C would, so ffmpeg still writes its trailer.
+// Windows has no equivalent for a process on another console, so this is a hard kill there.
+pub fn interrupt_process(pid: u32) -> bool {
+    if cfg!(target_os = "windows") {
+        return kill_process(pid);
+    }
+    Command::new("kill")
+        .args(["-INT", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+pub fn kill_process(pid: u32) -> bool {
+    let status = if cfg!(target_os = "windows") {
+        Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+    } else {
+        Command::new("kill")
+            .args(["-KILL", &pid.to_string()])
+            .stderr(Stdio::null())
+            .status()
+    };
+    status.map(|status| status.success()).unwrap_or(false)
 }
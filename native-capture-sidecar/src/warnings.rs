@@ -0,0 +1,309 @@
+use serde::Serialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use crate::protocol::write_event;
+use crate::supervisor::{LogLevel, StderrLine};
+
+// A condition that keeps recurring is reported again at most this often, with the count
+// accumulated in between.
+const REPEAT_EVENT_INTERVAL: Duration = Duration::from_secs(5);
+const DETAIL_MAX_CHARS: usize = 300;
+
+// Conditions recognized in ffmpeg's output that the app can act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WarningKind {
+    DroppedFrames,
+    DuplicatedFrames,
+    RealtimeBufferFull,
+    NonMonotonicDts,
+    EncoderInitFailed,
+    PermissionDenied,
+    DiskFull,
+}
+
+impl WarningKind {
+    // "error" kinds end the recording; the others only degrade it.
+    pub fn severity(self) -> &'static str {
+        match self {
+            WarningKind::DuplicatedFrames => "info",
+            WarningKind::DroppedFrames
+            | WarningKind::RealtimeBufferFull
+            | WarningKind::NonMonotonicDts => "warning",
+            WarningKind::EncoderInitFailed
+            | WarningKind::PermissionDenied
+            | WarningKind::DiskFull => "error",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            WarningKind::DroppedFrames => {
+                "Frames are being dropped because encoding cannot keep up. Try a faster encoder, a lower resolution or a lower frame rate."
+            }
+            WarningKind::DuplicatedFrames => {
+                "Frames are being duplicated because capture is not delivering the requested frame rate."
+            }
+            WarningKind::RealtimeBufferFull => {
+                "The capture buffer is full and frames are being lost. Try a hardware encoder or a lower frame rate."
+            }
+            WarningKind::NonMonotonicDts => {
+                "Frame timestamps went backwards; playback of the recording may stutter."
+            }
+            WarningKind::EncoderInitFailed => {
+                "The encoder could not be initialized on this GPU. Update the graphics driver or pick another encoder."
+            }
+            WarningKind::PermissionDenied => {
+                "Permission was denied. Check that the output folder is writable and that screen recording is allowed."
+            }
+            WarningKind::DiskFull => "The disk is full. Free some space or record to another drive.",
+        }
+    }
+}
+
+// Matches one stderr line against the known conditions. Frame drops and duplicates are
+// not matched here; they come from the `-progress` counters.
+pub fn match_stderr_line(line: &StderrLine) -> Option<WarningKind> {
+    if line.level == LogLevel::Info {
+        return None;
+    }
+    let text = line.text.to_lowercase();
+    let has = |needle: &str| text.contains(needle);
+    let failed = has("failed") || has("not supported") || has("cannot load") || has("no capable");
+
+    if has("no space left on device")
+        || has("not enough space on the disk")
+        || has("disk quota exceeded")
+        || has("disk full")
+    {
+        Some(WarningKind::DiskFull)
+    } else if has("permission denied")
+        || has("operation not permitted")
+        || has("access is denied")
+        || has("access denied")
+    {
+        Some(WarningKind::PermissionDenied)
+    } else if has("real-time buffer") && (has("too full") || has("frame dropped")) {
+        Some(WarningKind::RealtimeBufferFull)
+    } else if has("non-monotonous dts")
+        || has("non monotonically increasing dts")
+        || has("non-monotonic dts")
+    {
+        Some(WarningKind::NonMonotonicDts)
+    } else if ((has("nvenc") || has("nvcuda") || has("amf") || has("mfx session")) && failed)
+        || has("openencodesessionex failed")
+        || has("error while opening encoder")
+        || has("could not open encoder")
+        || has("error initializing output stream")
+        || has("unknown encoder")
+    {
+        Some(WarningKind::EncoderInitFailed)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureWarning {
+    pub kind: WarningKind,
+    pub severity: &'static str,
+    pub message: &'static str,
+    pub count: u64,
+    // Since ffmpeg was started.
+    #[serde(rename = "firstAtMs")]
+    pub first_at_ms: u64,
+    #[serde(rename = "lastAtMs")]
+    pub last_at_ms: u64,
+    // Latest ffmpeg line that triggered it, when it came from stderr.
+    pub detail: Option<String>,
+    #[serde(skip)]
+    reported_at: Option<Instant>,
+    #[serde(skip)]
+    reported_count: u64,
+}
+
+// Per-process record of the conditions seen so far. Each new condition is reported as a
+// `capture_warning` event right away; repeats are folded into the next report.
+pub struct WarningLog {
+    session_id: String,
+    label: &'static str,
+    started: Instant,
+    warnings: Vec<CaptureWarning>,
+}
+
+impl WarningLog {
+    pub fn new(session_id: &str, label: &'static str) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            label,
+            started: Instant::now(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, kind: WarningKind, count: u64, detail: Option<&str>) {
+        if count == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let at_ms = now.saturating_duration_since(self.started).as_millis() as u64;
+        let index = match self.warnings.iter().position(|warning| warning.kind == kind) {
+            Some(index) => index,
+            None => {
+                self.warnings.push(CaptureWarning {
+                    kind,
+                    severity: kind.severity(),
+                    message: kind.message(),
+                    count: 0,
+                    first_at_ms: at_ms,
+                    last_at_ms: at_ms,
+                    detail: None,
+                    reported_at: None,
+                    reported_count: 0,
+                });
+                self.warnings.len() - 1
+            }
+        };
+        let warning = &mut self.warnings[index];
+        warning.count += count;
+        warning.last_at_ms = at_ms;
+        if let Some(detail) = detail {
+            warning.detail = Some(detail.chars().take(DETAIL_MAX_CHARS).collect());
+        }
+
+        let due = warning
+            .reported_at
+            .is_none_or(|reported_at| now.duration_since(reported_at) >= REPEAT_EVENT_INTERVAL);
+        if !due {
+            return;
+        }
+        log!(
+            "[native-capture][{}][warning] kind={:?} count={} detail={}",
+            self.label,
+            kind,
+            warning.count,
+            warning.detail.as_deref().unwrap_or("-")
+        );
+        write_event(json!({
+            "event": "capture_warning",
+            "sessionId": self.session_id,
+            "kind": kind,
+            "severity": warning.severity,
+            "message": warning.message,
+            "count": warning.count,
+            "newCount": warning.count - warning.reported_count,
+            "atMs": at_ms,
+            "detail": warning.detail,
+        }));
+        warning.reported_at = Some(now);
+        warning.reported_count = warning.count;
+    }
+
+    pub fn warnings(&self) -> Vec<CaptureWarning> {
+        self.warnings.clone()
+    }
+}
+
+// Folds the warnings of one ffmpeg run into those of earlier runs of the same recording.
+// Times stay relative to the run that reported them.
+pub fn merge_warnings(into: &mut Vec<CaptureWarning>, from: Vec<CaptureWarning>) {
+    for warning in from {
+        match into.iter_mut().find(|existing| existing.kind == warning.kind) {
+            Some(existing) => {
+                existing.count += warning.count;
+                existing.last_at_ms = warning.last_at_ms;
+                if warning.detail.is_some() {
+                    existing.detail = warning.detail;
+                }
+            }
+            None => into.push(warning),
+        }
+    }
+}
+
+// The first condition that ended the recording, for error messages and codes.
+pub fn fatal_warning(warnings: &[CaptureWarning]) -> Option<&CaptureWarning> {
+    warnings.iter().find(|warning| warning.severity == "error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supervisor::classify_stderr_line;
+
+    #[test]
+    fn matches_real_ffmpeg_lines() {
+        let table: &[(&str, Option<WarningKind>)] = &[
+            (
+                "[dshow @ 0000020c6c8e8a40] [warning] real-time buffer [screen-capture-recorder] [video input] too full or near too full (101% of size: 3041280 [rtbufsize parameter])! frame dropped!",
+                Some(WarningKind::RealtimeBufferFull),
+            ),
+            (
+                "[mp4 @ 0x55a3c8c0e940] [warning] Non-monotonous DTS in output stream 0:0; previous: 1024, current: 1000; changing to 1025. This may result in incorrect timestamps in the output file.",
+                Some(WarningKind::NonMonotonicDts),
+            ),
+            (
+                "[mp4 @ 0x55a3c8c0e940] [error] Application provided invalid, non monotonically increasing dts to muxer in stream 0: 2048 >= 2048",
+                Some(WarningKind::NonMonotonicDts),
+            ),
+            (
+                "[h264_nvenc @ 0x5581f1c3a2c0] [error] OpenEncodeSessionEx failed: unsupported device (2): (no details)",
+                Some(WarningKind::EncoderInitFailed),
+            ),
+            ("[h264_nvenc @ 0x5581f1c3a2c0] [error] No capable devices found", Some(WarningKind::EncoderInitFailed)),
+            ("[h264_nvenc @ 000001f2] [error] Cannot load nvcuda.dll", Some(WarningKind::EncoderInitFailed)),
+            (
+                "[h264_amf @ 000001f2c3d4e5f0] [error] CreateComponent(AMFVideoEncoderVCE_AVC) failed with error 30",
+                Some(WarningKind::EncoderInitFailed),
+            ),
+            ("[h264_amf @ 000001f2c3d4e5f0] [error] DLL amfrt64.dll failed to open", Some(WarningKind::EncoderInitFailed)),
+            (
+                "[vost#0:0/h264_nvenc @ 0x5581f1c3b000] [error] Error while opening encoder - maybe incorrect parameters such as bit_rate, rate, width or height.",
+                Some(WarningKind::EncoderInitFailed),
+            ),
+            ("[fatal] Unknown encoder 'h264_qsv'", Some(WarningKind::EncoderInitFailed)),
+            (
+                "[out#0/mp4 @ 0x55d0b2f4a7c0] [error] Error opening output /root/Videos/take.mp4: Permission denied",
+                Some(WarningKind::PermissionDenied),
+            ),
+            ("[fatal] C:\\Videos\\take.mp4: Permission denied", Some(WarningKind::PermissionDenied)),
+            (
+                "[mp4 @ 0x55a3c8c0e940] [error] Error writing trailer of /mnt/full/take.mp4: No space left on device",
+                Some(WarningKind::DiskFull),
+            ),
+            ("[error] av_interleaved_write_frame(): No space left on device", Some(WarningKind::DiskFull)),
+            // Drops and duplicates are counted from `-progress`; these lines are not matched.
+            ("*** 1 dup!", None),
+            ("*** dropping frame 3 from stream 0 at ts 2", None),
+            ("[info] Stream mapping:", None),
+            // Info lines never warn, even when they mention a failure.
+            ("[h264_nvenc @ 0x1] [info] Loaded Nvenc version 12.1, init failed fallbacks: none", None),
+        ];
+        for (line, expected) in table {
+            assert_eq!(match_stderr_line(&classify_stderr_line(line)), *expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn folds_repeats_into_one_warning() {
+        let mut log = WarningLog::new("warnings-test", "test");
+        log.record(WarningKind::DroppedFrames, 0, None);
+        assert!(log.warnings().is_empty());
+
+        log.record(WarningKind::DroppedFrames, 2, None);
+        log.record(WarningKind::DiskFull, 1, Some("No space left on device"));
+        log.record(WarningKind::DroppedFrames, 3, None);
+        let warnings = log.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].kind, WarningKind::DroppedFrames);
+        assert_eq!(warnings[0].count, 5);
+        assert_eq!(warnings[0].severity, "warning");
+        assert_eq!(fatal_warning(&warnings).map(|warning| warning.kind), Some(WarningKind::DiskFull));
+
+        let mut merged = warnings.clone();
+        merge_warnings(&mut merged, warnings);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].count, 10);
+    }
+}
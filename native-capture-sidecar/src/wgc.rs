@@ -1,9 +1,10 @@
 #[cfg(target_os = "windows")]
 pub mod inner {
-    use std::io::{BufRead, BufReader, Write};
-    use std::process::{Child, Command, Stdio};
+    use std::io::Write;
+    use std::process::{ChildStdin, Command};
     use std::sync::{
         atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
         Arc, Condvar, Mutex,
     };
     use std::thread::{self, JoinHandle};
@@ -32,7 +33,30 @@ pub mod inner {
         Win32::System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
     };
 
-    type CaptureResult<T> = std::result::Result<T, String>;
+    use crate::adaptive::{
+        with_preset, Adaptation, AdaptiveEncoder, AdaptiveExit, AdaptiveOptions, AdaptivePolicy,
+        EncodeStep, FramePipe,
+    };
+    use crate::embedded::{is_embedded_encoder, Av1Encoder};
+    use crate::error::{CaptureError, ErrorCode};
+    use crate::ffmpeg::{
+        encoder_codec_args, lossless_codec_args, rate_control_args, RateControl, RECORDING_MOVFLAGS,
+    };
+    use crate::pipeline::{
+        timestamps_path, BurnInOverlay, FitPlan, FrameTimestampLog, FrameTimingStats,
+        MkvRawWriter, PipelineOptions, RawFormat, ResampleFilter, Resampler, StaticDetector,
+        StaticSegment,
+    };
+    use crate::supervisor::{ffmpeg_command, FfmpegExit, FfmpegProcess, Readiness};
+    use crate::warnings::CaptureWarning;
+
+    type CaptureResult<T> = std::result::Result<T, CaptureError>;
+
+    // Pipe-fed ffmpeg only fails fast on bad arguments before frames flow; encoder init
+    // errors surface later as a broken pipe.
+    const FFMPEG_STARTUP_WINDOW: Duration = Duration::from_millis(300);
+    // How long ffmpeg may take to flush after the frame pipe closes.
+    const FFMPEG_EXIT_GRACE: Duration = Duration::from_secs(8);
 
     enum CaptureTarget {
         Window(isize),
@@ -44,8 +68,12 @@ pub mod inner {
         pub height: u32,
         pub output_path: String,
         started_at: Instant,
+        // Encoder child of the pipe sink, recorded in the session journal.
+        pub ffmpeg_pid: Option<u32>,
         stop_flag: Arc<AtomicBool>,
-        thread: Option<JoinHandle<CaptureResult<()>>>,
+        // Set before stop_flag when the recording is discarded: no padding, no flush.
+        abort_flag: Arc<AtomicBool>,
+        thread: Option<JoinHandle<CaptureResult<CaptureStats>>>,
     }
 
     pub struct CaptureBounds {
@@ -61,6 +89,48 @@ pub mod inner {
         pub height: u32,
         pub duration_ms: u64,
         pub bytes: u64,
+        pub frames_encoded: u64,
+        pub frames_duplicated: u64,
+        pub frame_timing: Option<FrameTimingStats>,
+        pub timestamps_path: Option<String>,
+        pub frame0_epoch_ms: Option<u64>,
+        pub first_frame_latency_ms: Option<f64>,
+        pub static_segments: Option<Vec<StaticSegment>>,
+        pub warnings: Vec<CaptureWarning>,
+        pub adaptations: Vec<Adaptation>,
+    }
+
+    struct CaptureStats {
+        frames_sent: u64,
+        frames_duplicated: u64,
+        timing: Option<FrameTimingStats>,
+        timestamps: Option<FrameTimestampLog>,
+        static_segments: Option<Vec<StaticSegment>>,
+        warnings: Vec<CaptureWarning>,
+        adaptations: Vec<Adaptation>,
+    }
+
+    // Latest converted frame published by the capture loop. `seq` lets the pacers tell a
+    // fresh capture from the one already sent; `pts` is relative to the first frame and
+    // `captured_at` is the same moment on the sidecar's monotonic clock.
+    struct LatestFrame {
+        seq: u64,
+        pts: Duration,
+        captured_at: Instant,
+        data: Arc<Vec<u8>>,
+    }
+
+    struct OutFrame {
+        pts: Duration,
+        data: Arc<Vec<u8>>,
+    }
+
+    // Where paced frames go: ffmpeg's stdin, the current segment of an adaptive encoder, or
+    // the in-process encoder.
+    enum FrameSink {
+        Pipe(ChildStdin),
+        Adaptive(FramePipe),
+        Embedded(Av1Encoder),
     }
 
     pub fn hwnd_from_source_id(source_id: &str) -> Option<isize> {
@@ -79,21 +149,25 @@ pub mod inner {
         output_path: &str,
         hide_cursor: bool,
         encoder_args: Vec<String>,
+        pipeline: PipelineOptions,
     ) -> CaptureResult<WgcCapture> {
-        eprintln!(
-            "[native-capture][wgc] start hwnd=0x{:x} fps={} encoder={} bitrate={} hide_cursor={} output={}",
+        log!(
+            "[native-capture][wgc] start hwnd=0x{:x} fps={} encoder={} bitrate={} hide_cursor={} vfr={} output={}",
             hwnd_val as usize,
             fps,
             encoder,
             bitrate,
             hide_cursor,
+            pipeline.vfr,
             output_path
         );
         let stop_flag = Arc::new(AtomicBool::new(false));
         let stop_flag_thread = Arc::clone(&stop_flag);
+        let abort_flag = Arc::new(AtomicBool::new(false));
+        let abort_flag_thread = Arc::clone(&abort_flag);
 
         let (setup_tx, setup_rx) =
-            std::sync::mpsc::channel::<CaptureResult<(u32, u32)>>();
+            std::sync::mpsc::channel::<CaptureResult<(u32, u32, Option<u32>)>>();
 
         let encoder = encoder.to_string();
         let ffmpeg_exe = ffmpeg_exe.to_string();
@@ -109,7 +183,9 @@ pub mod inner {
                 output_path_str,
                 hide_cursor,
                 encoder_args,
+                pipeline,
                 stop_flag_thread,
+                abort_flag_thread,
                 setup_tx,
                 0,
                 0,
@@ -118,18 +194,25 @@ pub mod inner {
             )
         });
 
-        let (width, height) = match setup_rx.recv_timeout(Duration::from_secs(8)) {
+        let (width, height, ffmpeg_pid) = match setup_rx.recv_timeout(Duration::from_secs(8)) {
             Ok(Ok(dims)) => dims,
             Ok(Err(e)) => {
                 let _ = thread.join();
                 return Err(e);
             }
-            Err(_) => {
+            // The thread failed before it could report; its own error says why.
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(match thread.join() {
+                    Ok(Err(e)) => e,
+                    _ => "WGC capture thread exited during setup".into(),
+                });
+            }
+            Err(RecvTimeoutError::Timeout) => {
                 let _ = thread.join();
-                return Err("WGC capture setup timed out".to_string());
+                return Err("WGC capture setup timed out".into());
             }
         };
-        eprintln!(
+        log!(
             "[native-capture][wgc] setup complete hwnd=0x{:x} size={}x{}",
             hwnd_val as usize,
             width,
@@ -141,31 +224,57 @@ pub mod inner {
             height,
             output_path: output_path.to_string(),
             started_at: Instant::now(),
+            ffmpeg_pid,
             stop_flag,
+            abort_flag,
             thread: Some(thread),
         })
     }
 
-    pub fn stop(mut capture: WgcCapture) -> CaptureResult<WgcResult> {
-        eprintln!(
+    // Side files are named after `output_path`, the final name, rather than the temp file
+    // the capture records into.
+    pub fn stop(mut capture: WgcCapture, output_path: &str) -> CaptureResult<WgcResult> {
+        log!(
             "[native-capture][wgc] stop requested output={} size={}x{}",
             capture.output_path,
             capture.width,
             capture.height
         );
         capture.stop_flag.store(true, Ordering::SeqCst);
+        let mut stats = None;
         if let Some(handle) = capture.thread.take() {
             match handle.join() {
-                Ok(Ok(())) => {}
+                Ok(Ok(capture_stats)) => stats = Some(capture_stats),
                 Ok(Err(e)) => return Err(e),
-                Err(_) => return Err("WGC capture thread panicked".to_string()),
+                Err(_) => return Err("WGC capture thread panicked".into()),
             }
         }
         let duration_ms = capture.started_at.elapsed().as_millis() as u64;
         let bytes = std::fs::metadata(&capture.output_path)
             .map(|m| m.len())
             .unwrap_or(0);
-        eprintln!(
+        let stats = stats.unwrap_or(CaptureStats {
+            frames_sent: 0,
+            frames_duplicated: 0,
+            timing: None,
+            timestamps: None,
+            static_segments: None,
+            warnings: Vec::new(),
+            adaptations: Vec::new(),
+        });
+        let mut timestamps_file = None;
+        if let Some(log) = stats.timestamps.as_ref().filter(|log| log.frames() > 0) {
+            let path = timestamps_path(output_path);
+            match log.write(&path, stats.static_segments.as_deref()) {
+                Ok(()) => timestamps_file = Some(path.to_string_lossy().to_string()),
+                Err(err) => log!(
+                    "[native-capture][wgc] failed to write frame timestamps path={} err={}",
+                    path.display(),
+                    err
+                ),
+            }
+        }
+        log!(
             "[native-capture][wgc] stop complete output={} duration_ms={} bytes={}",
             capture.output_path,
             duration_ms,
@@ -177,9 +286,40 @@ pub mod inner {
             height: capture.height,
             duration_ms,
             bytes,
+            frames_encoded: stats.frames_sent,
+            frames_duplicated: stats.frames_duplicated,
+            frame0_epoch_ms: stats.timestamps.as_ref().and_then(|log| log.frame0_epoch_ms()),
+            first_frame_latency_ms: stats
+                .timestamps
+                .as_ref()
+                .and_then(|log| log.first_frame_latency_ms()),
+            frame_timing: stats.timing,
+            timestamps_path: timestamps_file,
+            static_segments: stats.static_segments,
+            warnings: stats.warnings,
+            adaptations: stats.adaptations,
         })
     }
 
+    // False once the capture thread has ended on its own (encoder or source failure).
+    pub fn running(capture: &WgcCapture) -> bool {
+        capture.thread.as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    // Tears the capture down without padding or flushing; the caller deletes the output.
+    pub fn abort(mut capture: WgcCapture) {
+        log!("[native-capture][wgc] abort requested output={}", capture.output_path);
+        capture.abort_flag.store(true, Ordering::SeqCst);
+        capture.stop_flag.store(true, Ordering::SeqCst);
+        if let Some(handle) = capture.thread.take() {
+            match handle.join() {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => log!("[native-capture][wgc] abort: capture thread failed: {e}"),
+                Err(_) => log!("[native-capture][wgc] abort: capture thread panicked"),
+            }
+        }
+    }
+
     pub fn source_bounds_from_hwnd(hwnd_val: isize) -> Option<CaptureBounds> {
         use windows::Win32::Foundation::{HWND, RECT};
         use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS};
@@ -243,8 +383,9 @@ pub mod inner {
         output_path: &str,
         hide_cursor: bool,
         encoder_args: Vec<String>,
+        pipeline: PipelineOptions,
     ) -> CaptureResult<WgcCapture> {
-        eprintln!(
+        log!(
             "[native-capture][wgc] start_monitor hmonitor=0x{:x} crop={}x{}+{},{} out={}x{} target={}x{} fps={} encoder={}",
             hmonitor_val as usize,
             out_width, out_height, crop_x, crop_y,
@@ -259,7 +400,9 @@ pub mod inner {
         };
         let stop_flag = Arc::new(AtomicBool::new(false));
         let stop_flag_thread = Arc::clone(&stop_flag);
-        let (setup_tx, setup_rx) = std::sync::mpsc::channel::<CaptureResult<(u32, u32)>>();
+        let abort_flag = Arc::new(AtomicBool::new(false));
+        let abort_flag_thread = Arc::clone(&abort_flag);
+        let (setup_tx, setup_rx) = std::sync::mpsc::channel::<CaptureResult<(u32, u32, Option<u32>)>>();
         let encoder = encoder.to_string();
         let ffmpeg_exe = ffmpeg_exe.to_string();
         let output_path_str = output_path.to_string();
@@ -273,7 +416,9 @@ pub mod inner {
                 output_path_str,
                 hide_cursor,
                 encoder_args,
+                pipeline,
                 stop_flag_thread,
+                abort_flag_thread,
                 setup_tx,
                 crop_x,
                 crop_y,
@@ -281,20 +426,24 @@ pub mod inner {
                 scale_to,
             )
         });
-        let final_w = scale_to.map(|(w, _)| w).unwrap_or(out_width);
-        let final_h = scale_to.map(|(_, h)| h).unwrap_or(out_height);
-        match setup_rx.recv_timeout(Duration::from_secs(8)) {
-            Ok(Ok(_)) => {}
+        let (final_w, final_h, ffmpeg_pid) = match setup_rx.recv_timeout(Duration::from_secs(8)) {
+            Ok(Ok(dims)) => dims,
             Ok(Err(e)) => {
                 let _ = thread.join();
                 return Err(e);
             }
-            Err(_) => {
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(match thread.join() {
+                    Ok(Err(e)) => e,
+                    _ => "WGC monitor capture thread exited during setup".into(),
+                });
+            }
+            Err(RecvTimeoutError::Timeout) => {
                 let _ = thread.join();
-                return Err("WGC monitor capture setup timed out".to_string());
+                return Err("WGC monitor capture setup timed out".into());
             }
-        }
-        eprintln!(
+        };
+        log!(
             "[native-capture][wgc] monitor setup complete size={}x{}",
             final_w, final_h
         );
@@ -303,7 +452,9 @@ pub mod inner {
             height: final_h,
             output_path: output_path.to_string(),
             started_at: Instant::now(),
+            ffmpeg_pid,
             stop_flag,
+            abort_flag,
             thread: Some(thread),
         })
     }
@@ -318,13 +469,15 @@ pub mod inner {
         output_path: String,
         hide_cursor: bool,
         encoder_args: Vec<String>,
+        pipeline: PipelineOptions,
         stop_flag: Arc<AtomicBool>,
-        setup_tx: std::sync::mpsc::Sender<CaptureResult<(u32, u32)>>,
+        abort_flag: Arc<AtomicBool>,
+        setup_tx: std::sync::mpsc::Sender<CaptureResult<(u32, u32, Option<u32>)>>,
         crop_x: u32,
         crop_y: u32,
         out_size: Option<(u32, u32)>,
         scale_to: Option<(u32, u32)>,
-    ) -> CaptureResult<()> {
+    ) -> CaptureResult<CaptureStats> {
         unsafe {
             use windows::Win32::System::Threading::{
                 GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
@@ -358,7 +511,13 @@ pub mod inner {
             CaptureTarget::Window(hwnd) => unsafe {
                 interop
                     .CreateForWindow(HWND(*hwnd as *mut core::ffi::c_void))
-                    .map_err(|e| format!("IGraphicsCaptureItemInterop::CreateForWindow: {e}"))?
+                    .map_err(|e| {
+                        // Fails with E_INVALIDARG once the window has been closed.
+                        CaptureError::new(
+                            ErrorCode::SourceGone,
+                            format!("IGraphicsCaptureItemInterop::CreateForWindow: {e}"),
+                        )
+                    })?
             },
             CaptureTarget::Monitor(hmon) => unsafe {
                 interop
@@ -370,7 +529,42 @@ pub mod inner {
         let item_size = item.Size().map_err(|e| format!("item.Size(): {e}"))?;
         let cap_w = (item_size.Width.max(1)) as u32;
         let cap_h = (item_size.Height.max(1)) as u32;
-        let (out_w, out_h) = out_size.unwrap_or((cap_w, cap_h));
+        // yuv420p needs even sizes; an odd window loses its last column or row.
+        let (out_w, out_h) = out_size.unwrap_or((cap_w.max(2) & !1, cap_h.max(2) & !1));
+        // Scaling happens on the CPU ahead of colour conversion, so conversion, the pipe,
+        // the encoder and the detectors all work at the encoded size.
+        let fit_plan = scale_to.map(|dst| FitPlan::new((out_w, out_h), dst, pipeline.fit));
+        let mut resampler = fit_plan.map(|plan| {
+            let (src, dst) = ((plan.src_w, plan.src_h), (plan.dst_w, plan.dst_h));
+            let filter = pipeline
+                .scale_filter
+                .unwrap_or_else(|| ResampleFilter::auto(src, dst));
+            log!(
+                "[native-capture][wgc] resample {}x{}+{},{} -> {}x{}+{},{} in {}x{} fit={} filter={}",
+                src.0, src.1, plan.src_x, plan.src_y, dst.0, dst.1, plan.dst_x, plan.dst_y,
+                plan.out_w, plan.out_h, pipeline.fit.name(), filter.name()
+            );
+            Resampler::new(src, dst, filter)
+        });
+        let (enc_w, enc_h) = fit_plan.map_or((out_w, out_h), |plan| (plan.out_w, plan.out_h));
+        let mut overlay = pipeline.burn_in.clone().map(|settings| {
+            let plan = fit_plan
+                .unwrap_or_else(|| FitPlan::new((out_w, out_h), (out_w, out_h), pipeline.fit));
+            BurnInOverlay::new(settings, plan)
+        });
+        // BGRA output canvas; the padding of a contain fit is painted once and never overwritten.
+        // With burn-in it also keeps the last clean frame for redrawing effects.
+        let mut scaled_bgra: Vec<u8> = if resampler.is_some() || overlay.is_some() {
+            let pad = pipeline.pad_color;
+            [pad.b, pad.g, pad.r, 255].repeat(enc_w as usize * enc_h as usize)
+        } else {
+            Vec::new()
+        };
+        let mut composited_bgra: Vec<u8> = if overlay.is_some() {
+            vec![0u8; enc_w as usize * enc_h as usize * 4]
+        } else {
+            Vec::new()
+        };
 
         let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
             &winrt_device,
@@ -398,6 +592,13 @@ pub mod inner {
         }
         let mut write_idx: usize = 0;
         let mut frames_queued: u64 = 0;
+        // SystemRelativeTime (100 ns units) and acquisition instant of the frame copied into
+        // each staging slot. Capture instants are derived from the WGC timestamps relative to
+        // the first frame, so they are not skewed by the staging ring's read-back delay.
+        let mut staging_times: [i64; N_STAGING] = [0; N_STAGING];
+        let mut staging_acquired: [Option<Instant>; N_STAGING] = [None; N_STAGING];
+        let mut first_frame: Option<(i64, Instant)> = None;
+        let mut frames_published: u64 = 0;
 
         let frame_signal = Arc::new((Mutex::new(false), Condvar::new()));
         let frame_signal_cb = Arc::clone(&frame_signal);
@@ -416,97 +617,166 @@ pub mod inner {
             .StartCapture()
             .map_err(|e| format!("StartCapture: {e}"))?;
 
-        let mut ffmpeg_child = spawn_ffmpeg_rawvideo(
-            &ffmpeg_exe,
-            &encoder,
-            bitrate,
-            fps,
-            out_w,
-            out_h,
-            &encoder_args,
-            &output_path,
-            scale_to,
-        )?;
-        let ffmpeg_stdin = ffmpeg_child
-            .stdin
-            .take()
-            .ok_or_else(|| "ffmpeg stdin not available".to_string())?;
-        let ffmpeg_stderr = ffmpeg_child.stderr.take();
-        let ffmpeg_stderr_lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
-        if let Some(stderr) = ffmpeg_stderr {
-            let stderr_lines = Arc::clone(&ffmpeg_stderr_lines);
-            thread::spawn(move || {
-                let reader = BufReader::new(stderr);
-                for line in reader.lines().map_while(Result::ok) {
-                    let trimmed = line.trim();
-                    if trimmed.is_empty() {
-                        continue;
+        let embedded = is_embedded_encoder(&encoder);
+        let rate = pipeline
+            .rate_control
+            .clone()
+            .unwrap_or_else(|| RateControl::manual(bitrate));
+        let mut ffmpeg: Option<FfmpegProcess> = None;
+        let mut adaptive: Option<AdaptiveEncoder> = None;
+        let mut base = EncodeStep::new(fps, enc_w, enc_h);
+        // Segments are switched between whole raw frames, so only the CFR pipe adapts.
+        let adaptive_quality = pipeline.adaptive_quality.as_ref().filter(|_| !pipeline.vfr);
+        let sink = if embedded {
+            FrameSink::Embedded(
+                Av1Encoder::create(enc_w, enc_h, fps, bitrate, &output_path)?
+                    .realtime(&pipeline.session_id),
+            )
+        } else if let Some(config) = adaptive_quality {
+            let policy = AdaptivePolicy::new(config, &encoder, &encoder_args, &mut base);
+            let (exe, enc, args) = (ffmpeg_exe.clone(), encoder.clone(), encoder_args.clone());
+            let rate = rate.clone();
+            let options = AdaptiveOptions {
+                session_id: pipeline.session_id.clone(),
+                label: "wgc",
+                ffmpeg_exe: ffmpeg_exe.clone(),
+                readiness: Readiness::Settle { window: FFMPEG_STARTUP_WINDOW },
+                policy,
+                base,
+                piped: true,
+                command: Box::new(move |step| {
+                    ffmpeg_rawvideo_command(&exe, &enc, &rate, fps, enc_w, enc_h, &args, step, false, None)
+                }),
+            };
+            let encoder = AdaptiveEncoder::start(options, &output_path)?;
+            let pipe = encoder
+                .frame_pipe()
+                .ok_or_else(|| "ffmpeg stdin not available".to_string())?;
+            adaptive = Some(encoder);
+            FrameSink::Adaptive(pipe)
+        } else {
+            let mut command = ffmpeg_rawvideo_command(
+                &ffmpeg_exe,
+                &encoder,
+                &rate,
+                fps,
+                enc_w,
+                enc_h,
+                &encoder_args,
+                &base,
+                pipeline.vfr,
+                pipeline.lossless.as_deref(),
+            )?;
+            command.arg(&output_path);
+            let mut process = FfmpegProcess::spawn(command, "wgc", &pipeline.session_id)?;
+            let ffmpeg_stdin = process
+                .take_stdin()
+                .ok_or_else(|| "ffmpeg stdin not available".to_string())?;
+            process.wait_ready(Readiness::Settle { window: FFMPEG_STARTUP_WINDOW })?;
+            ffmpeg = Some(process);
+            FrameSink::Pipe(ffmpeg_stdin)
+        };
+        let ffmpeg_pid = ffmpeg
+            .as_ref()
+            .map(FfmpegProcess::id)
+            .or_else(|| adaptive.as_ref().map(AdaptiveEncoder::id));
+        let _ = setup_tx.send(Ok((enc_w, enc_h, ffmpeg_pid)));
+
+        let vfr = pipeline.vfr;
+        let raw_format = RawFormat::for_options(&pipeline);
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<OutFrame>(256);
+        let writer_abort_flag = Arc::clone(&abort_flag);
+        // Returns the warnings of the in-process encoder; ffmpeg reports its own.
+        let write_thread: JoinHandle<Result<Vec<CaptureWarning>, String>> = thread::spawn(move || {
+            let mut stdin = match sink {
+                FrameSink::Pipe(stdin) => stdin,
+                FrameSink::Adaptive(pipe) => {
+                    let mut result = Ok(());
+                    while let Ok(frame) = frame_rx.recv() {
+                        if writer_abort_flag.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        if let Err(err) = pipe.write_frame(&frame.data) {
+                            result = Err(err.to_string());
+                            break;
+                        }
                     }
-                    eprintln!("[native-capture][wgc][ffmpeg][stderr] {}", trimmed);
-                    if let Ok(mut guard) = stderr_lines.lock() {
-                        if guard.len() >= 30 {
-                            let _ = guard.remove(0);
+                    pipe.close(); // EOF → the current segment finalises
+                    return result.map(|()| Vec::new());
+                }
+                FrameSink::Embedded(mut encoder) => {
+                    while let Ok(frame) = frame_rx.recv() {
+                        if writer_abort_flag.load(Ordering::SeqCst) {
+                            break;
                         }
-                        guard.push(trimmed.to_string());
+                        encoder.push(frame.pts, &frame.data)?;
+                    }
+                    if writer_abort_flag.load(Ordering::SeqCst) {
+                        return Ok(Vec::new());
                     }
+                    return encoder.finish();
                 }
-            });
-        }
-        thread::sleep(Duration::from_millis(300));
-        match ffmpeg_child.try_wait() {
-            Ok(Some(status)) => {
-                let stderr_excerpt = ffmpeg_stderr_lines
-                    .lock()
-                    .ok()
-                    .map(|lines| lines.join(" | "))
-                    .unwrap_or_default();
-                return Err(format!(
-                    "ffmpeg exited immediately during startup (status={status}).{}",
-                    if stderr_excerpt.is_empty() {
-                        String::new()
-                    } else {
-                        format!(" stderr={stderr_excerpt}")
+            };
+            if vfr {
+                let mut writer = MkvRawWriter::new(stdin, enc_w, enc_h, raw_format);
+                while let Ok(frame) = frame_rx.recv() {
+                    if writer_abort_flag.load(Ordering::SeqCst) {
+                        break;
                     }
-                ));
+                    writer
+                        .write_frame(frame.pts, &frame.data)
+                        .map_err(|e| e.to_string())?;
+                }
+                drop(writer); // EOF → ffmpeg finalises
+                return Ok(Vec::new());
             }
-            Ok(None) => {}
-            Err(err) => return Err(format!("failed to verify ffmpeg startup: {err}")),
-        }
-
-        let final_w = scale_to.map(|(w, _)| w).unwrap_or(out_w);
-        let final_h = scale_to.map(|(_, h)| h).unwrap_or(out_h);
-        let _ = setup_tx.send(Ok((final_w, final_h)));
-
-        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<Arc<Vec<u8>>>(256);
-        let write_thread: JoinHandle<bool> = thread::spawn(move || {
-            let mut stdin = ffmpeg_stdin;
             while let Ok(frame) = frame_rx.recv() {
-                if stdin.write_all(&frame).is_err() {
-                    return false;
+                if writer_abort_flag.load(Ordering::SeqCst) {
+                    break;
                 }
+                stdin.write_all(&frame.data).map_err(|e| e.to_string())?;
             }
             drop(stdin); // EOF → ffmpeg finalises
-            true
+            Ok(Vec::new())
         });
 
         // Shared latest converted frame between the GPU capture loop and the pacing thread.
         // The capture loop writes here after each YUV conversion; the pacing thread reads
         // at its own cadence and blocking-sends to the ffmpeg pipe. This separation means
         // pipe back-pressure can never stall the GPU capture loop, preventing choppiness.
-        let shared_latest: Arc<Mutex<Option<Arc<Vec<u8>>>>> = Arc::new(Mutex::new(None));
+        let shared_latest: Arc<Mutex<Option<LatestFrame>>> = Arc::new(Mutex::new(None));
         let shared_latest_pacing = Arc::clone(&shared_latest);
 
         let frame_interval = Duration::from_nanos(1_000_000_000 / fps.max(1) as u64);
         let recording_started_at = Instant::now();
+        // Origin of the timestamp file's monoUs and of the static segments.
+        let timeline_origin = pipeline.requested_at.unwrap_or(recording_started_at);
         let pacing_stop_flag = Arc::clone(&stop_flag);
+        let pacing_abort_flag = Arc::clone(&abort_flag);
+        let timestamps = FrameTimestampLog::new(timeline_origin, fps, vfr);
 
         // Pacing thread: sends frames at the requested fps with correct wall-clock timing.
         // Runs independently from GPU capture so pipe stalls never cause recording gaps.
-        let pacing_thread: JoinHandle<(u64, u64, bool)> = thread::spawn(move || {
+        // In VFR mode it forwards each distinct capture with its own pts instead.
+        let pacing_thread: JoinHandle<(CaptureStats, bool)> = thread::spawn(move || {
+            if vfr {
+                return pace_vfr(
+                    shared_latest_pacing,
+                    frame_tx,
+                    pacing_stop_flag,
+                    pacing_abort_flag,
+                    timestamps,
+                );
+            }
+
+            let mut timestamps = timestamps;
             let mut next_send = Instant::now() + frame_interval;
             let mut frames_sent: u64 = 0;
             let mut frames_duplicated: u64 = 0;
             let mut last_frame: Option<Arc<Vec<u8>>> = None;
+            // (seq, captured_at) of last_frame, and seq of the last frame handed to ffmpeg.
+            let mut last_meta: (u64, Instant) = (0, recording_started_at);
+            let mut last_sent_seq: Option<u64> = None;
             let mut logged_first_frame = false;
 
             loop {
@@ -524,7 +794,8 @@ pub mod inner {
                 // Pull the latest converted frame from the capture thread.
                 if let Ok(guard) = shared_latest_pacing.lock() {
                     if let Some(ref f) = *guard {
-                        last_frame = Some(Arc::clone(f));
+                        last_frame = Some(Arc::clone(&f.data));
+                        last_meta = (f.seq, f.captured_at);
                     }
                 }
 
@@ -535,17 +806,24 @@ pub mod inner {
 
                 let mut emits_this_loop: u32 = 0;
                 while Instant::now() >= next_send {
-                    match frame_tx.send(Arc::clone(frame)) {
+                    let out = OutFrame {
+                        pts: frame_interval * frames_sent as u32,
+                        data: Arc::clone(frame),
+                    };
+                    match frame_tx.send(out) {
                         Ok(_) => {
+                            let (seq, captured_at) = last_meta;
+                            timestamps.push(captured_at, last_sent_seq == Some(seq));
+                            last_sent_seq = Some(seq);
                             frames_sent += 1;
                             if emits_this_loop > 0 {
                                 frames_duplicated += 1;
                             }
                             if !logged_first_frame {
-                                eprintln!("[native-capture][wgc] first-frame sent");
+                                log!("[native-capture][wgc] first-frame sent");
                                 logged_first_frame = true;
                             } else if frames_sent % 120 == 0 {
-                                eprintln!(
+                                log!(
                                     "[native-capture][wgc] frame-progress sent={} dup={}",
                                     frames_sent, frames_duplicated
                                 );
@@ -553,7 +831,16 @@ pub mod inner {
                         }
                         Err(_) => {
                             // ffmpeg pipe disconnected
-                            return (frames_sent, frames_duplicated, false);
+                            let stats = CaptureStats {
+                                frames_sent,
+                                frames_duplicated,
+                                timing: None,
+                                timestamps: Some(timestamps),
+                                static_segments: None,
+                                warnings: Vec::new(),
+                                adaptations: Vec::new(),
+                            };
+                            return (stats, false);
                         }
                     }
                     emits_this_loop += 1;
@@ -567,35 +854,70 @@ pub mod inner {
 
             // Pad with duplicate frames so video duration equals wall-clock recording time.
             // Handles frame drops from any cause (pipe pressure, slow encoder, etc.) and
-            // ensures screen, camera, and microphone tracks all stay in sync.
-            if let Some(ref frame) = last_frame {
+            // ensures screen, camera, and microphone tracks all stay in sync. A discarded
+            // recording is not padded.
+            if let (Some(frame), false) =
+                (last_frame.as_ref(), pacing_abort_flag.load(Ordering::SeqCst))
+            {
                 let elapsed_secs = recording_started_at.elapsed().as_secs_f64();
                 let total_expected = (fps as f64 * elapsed_secs).round() as u64;
                 if frames_sent < total_expected {
                     let pad_count = total_expected - frames_sent;
-                    eprintln!(
+                    log!(
                         "[native-capture][wgc] padding {} frames \
                         (elapsed={:.3}s expected={} sent={})",
                         pad_count, elapsed_secs, total_expected, frames_sent
                     );
                     for _ in 0..pad_count {
-                        if frame_tx.send(Arc::clone(frame)).is_err() {
+                        let out = OutFrame {
+                            pts: frame_interval * frames_sent as u32,
+                            data: Arc::clone(frame),
+                        };
+                        if frame_tx.send(out).is_err() {
                             break;
                         }
+                        timestamps.push(last_meta.1, true);
                         frames_sent += 1;
                     }
                 }
             }
 
-            (frames_sent, frames_duplicated, true)
+            let stats = CaptureStats {
+                frames_sent,
+                frames_duplicated,
+                timing: None,
+                timestamps: Some(timestamps),
+                static_segments: None,
+                warnings: Vec::new(),
+                adaptations: Vec::new(),
+            };
+            (stats, true)
         });
 
-        // GPU capture loop — reads WGC frames, converts to YUV420p, publishes to shared_latest.
-        // Never touches frame_tx so pipe back-pressure cannot block this loop.
-        let mut frame_arc: Arc<Vec<u8>> =
-            Arc::new(vec![0u8; out_w as usize * out_h as usize * 3 / 2]);
+        // GPU capture loop — reads WGC frames, converts to YUV420p (or copies BGRA for
+        // lossless), publishes to shared_latest. Never touches frame_tx so pipe back-pressure
+        // cannot block this loop.
+        let mut frame_arc: Arc<Vec<u8>> = Arc::new(vec![0u8; raw_format.frame_len(enc_w, enc_h)]);
+        // Luma of yuv420p frames, or the whole BGRA picture.
+        let detect_len = match raw_format {
+            RawFormat::Yuv420p => enc_w as usize * enc_h as usize,
+            RawFormat::Bgra => frame_arc.len(),
+        };
         let mut logged_first_frame_cap = false;
         let mut latest_row_pitch: usize = 0;
+        let mut static_detector = pipeline
+            .static_detection
+            .clone()
+            .map(|settings| StaticDetector::new(settings, timeline_origin, enc_w, enc_h));
+        let frame_interval = Duration::from_secs_f64(1.0 / fps.max(1) as f64);
+        // WGC only delivers a frame when the screen changes, so burn-in effects on a static
+        // screen are redrawn from the last clean frame at up to the capture fps.
+        let idle_wait = if overlay.is_some() {
+            frame_interval.min(Duration::from_millis(20))
+        } else {
+            Duration::from_millis(20)
+        };
+        let mut last_published: Option<(Instant, Duration)> = None;
 
         loop {
             if stop_flag.load(Ordering::SeqCst) {
@@ -606,7 +928,7 @@ pub mod inner {
                 let (lock, cvar) = &*frame_signal;
                 let mut ready = lock.lock().unwrap();
                 if !*ready {
-                    let (guard, _) = cvar.wait_timeout(ready, Duration::from_millis(20)).unwrap();
+                    let (guard, _) = cvar.wait_timeout(ready, idle_wait).unwrap();
                     ready = guard;
                 }
                 *ready = false;
@@ -644,6 +966,11 @@ pub mod inner {
                     let dst_res: ID3D11Resource = staging_pool[write_idx].cast().unwrap();
                     d3d_context.CopyResource(&dst_res, &src_res);
                 }
+                staging_times[write_idx] = frame
+                    .SystemRelativeTime()
+                    .map(|t| t.Duration)
+                    .unwrap_or_else(|_| recording_started_at.elapsed().as_nanos() as i64 / 100);
+                staging_acquired[write_idx] = Some(Instant::now());
 
                 let read_idx = (write_idx + 1) % N_STAGING;
                 write_idx = (write_idx + 1) % N_STAGING;
@@ -665,79 +992,303 @@ pub mod inner {
                             )
                         };
                         let buf = Arc::make_mut(&mut frame_arc);
-                        bgra_to_yuv420p(src_slice, pitch, crop_x as usize, crop_y as usize, buf, out_w as usize, out_h as usize);
+                        let canvas_stride = enc_w as usize * 4;
+                        if let (Some(resampler), Some(plan)) = (resampler.as_mut(), fit_plan) {
+                            let canvas_offset = plan.dst_y as usize * canvas_stride + plan.dst_x as usize * 4;
+                            resampler.resize_bgra(
+                                src_slice,
+                                pitch,
+                                (crop_x + plan.src_x) as usize,
+                                (crop_y + plan.src_y) as usize,
+                                &mut scaled_bgra[canvas_offset..],
+                                canvas_stride,
+                            );
+                        } else if overlay.is_some() {
+                            for row in 0..out_h as usize {
+                                let src = (crop_y as usize + row) * pitch + crop_x as usize * 4;
+                                scaled_bgra[row * canvas_stride..(row + 1) * canvas_stride]
+                                    .copy_from_slice(&src_slice[src..src + canvas_stride]);
+                            }
+                        }
+                        if let Some(overlay) = overlay.as_mut() {
+                            // Draw effects as of when the frame was captured, not mapped.
+                            let captured = staging_acquired[read_idx].unwrap_or_else(Instant::now);
+                            overlay.render(captured, &scaled_bgra, &mut composited_bgra);
+                            convert_frame(raw_format, &composited_bgra, canvas_stride, 0, 0, buf, enc_w as usize, enc_h as usize);
+                        } else if resampler.is_some() {
+                            convert_frame(raw_format, &scaled_bgra, canvas_stride, 0, 0, buf, enc_w as usize, enc_h as usize);
+                        } else {
+                            convert_frame(raw_format, src_slice, pitch, crop_x as usize, crop_y as usize, buf, out_w as usize, out_h as usize);
+                        }
                         latest_row_pitch = pitch;
                         unsafe {
                             d3d_context.Unmap(&staging_pool[read_idx].cast::<ID3D11Resource>().unwrap(), 0);
                         }
 
+                        let frame_time = staging_times[read_idx];
+                        let acquired_at = staging_acquired[read_idx].unwrap_or_else(Instant::now);
+                        let (base_time, base_instant) =
+                            *first_frame.get_or_insert((frame_time, acquired_at));
+                        let mut pts = Duration::from_nanos((frame_time - base_time).max(0) as u64 * 100);
+                        // Redrawn overlay frames are stamped on the sidecar clock; keep the
+                        // next captured frame after them.
+                        if let (Some(_), Some((_, last_pts))) = (overlay.as_ref(), last_published) {
+                            pts = pts.max(last_pts + Duration::from_millis(1));
+                        }
+                        last_published = Some((Instant::now(), pts));
+
                         // Publish for the pacing thread (non-blocking)
                         if let Ok(mut guard) = shared_latest.lock() {
-                            *guard = Some(Arc::clone(&frame_arc));
+                            *guard = Some(LatestFrame {
+                                seq: frames_published,
+                                pts,
+                                captured_at: base_instant + pts,
+                                data: Arc::clone(&frame_arc),
+                            });
+                        }
+                        frames_published += 1;
+                        if let Some(detector) = static_detector.as_mut() {
+                            detector.observe(&frame_arc[..detect_len], base_instant + pts);
                         }
                         if !logged_first_frame_cap {
-                            eprintln!(
+                            log!(
                                 "[native-capture][wgc] capture: first frame ready \
-                                cap={}x{} out={}x{} pitch={}",
-                                cap_w, cap_h, out_w, out_h, latest_row_pitch
+                                cap={}x{} out={}x{} enc={}x{} pitch={}",
+                                cap_w, cap_h, out_w, out_h, enc_w, enc_h, latest_row_pitch
                             );
                             logged_first_frame_cap = true;
                         }
                     }
                 }
+            } else if let (Some(overlay), Some((_, base_instant)), Some((published_at, last_pts))) =
+                (overlay.as_mut(), first_frame, last_published)
+            {
+                let now = Instant::now();
+                if now.duration_since(published_at) >= frame_interval && overlay.needs_redraw(now) {
+                    overlay.render(now, &scaled_bgra, &mut composited_bgra);
+                    let buf = Arc::make_mut(&mut frame_arc);
+                    convert_frame(raw_format, &composited_bgra, enc_w as usize * 4, 0, 0, buf, enc_w as usize, enc_h as usize);
+                    let pts = now
+                        .saturating_duration_since(base_instant)
+                        .max(last_pts + Duration::from_millis(1));
+                    if let Ok(mut guard) = shared_latest.lock() {
+                        *guard = Some(LatestFrame {
+                            seq: frames_published,
+                            pts,
+                            captured_at: base_instant + pts,
+                            data: Arc::clone(&frame_arc),
+                        });
+                    }
+                    frames_published += 1;
+                    last_published = Some((now, pts));
+                }
             }
         }
 
-        let (frames_sent, frames_duplicated, pipe_ok) =
+        // Discarding: kill the encoder before joining so a full pipe cannot hold up the
+        // pacer or the writer.
+        let aborted = abort_flag.load(Ordering::SeqCst);
+        let aborted_exit = if aborted {
+            if let Some(encoder) = adaptive.take() {
+                encoder.abort();
+            }
+            ffmpeg.take().map(FfmpegProcess::abort)
+        } else {
+            None
+        };
+
+        let (mut capture_stats, pipe_ok) =
             pacing_thread.join().map_err(|_| "pacing thread panicked".to_string())?;
+        capture_stats.static_segments = static_detector.map(|d| d.finish(Instant::now()));
         let ffmpeg_pipe_broken = !pipe_ok;
-
-        let write_ok = write_thread.join().unwrap_or(false);
-        let ffmpeg_status = ffmpeg_child
-            .wait()
-            .map_err(|e| format!("Failed to wait for ffmpeg process: {e}"))?;
+        let frames_sent = capture_stats.frames_sent;
+        let frames_duplicated = capture_stats.frames_duplicated;
+
+        let write_result = write_thread
+            .join()
+            .unwrap_or_else(|_| Err("frame writer thread panicked".to_string()));
+        let write_ok = write_result.is_ok();
+        // The writer has closed stdin by now, so ffmpeg is already flushing.
+        let (ffmpeg_exit, adaptive_exit) = match adaptive {
+            Some(encoder) => {
+                let mut result = encoder.stop(FFMPEG_EXIT_GRACE);
+                (result.exit.take(), Some(result))
+            }
+            None => (ffmpeg.map(|process| process.stop(FFMPEG_EXIT_GRACE)), None),
+        };
 
         let _ = session.Close();
         let _ = frame_pool.Close();
 
+        if aborted {
+            log!(
+                "[native-capture][wgc] aborted encoder={} sent_frames={} ffmpeg_status={}",
+                encoder,
+                frames_sent,
+                aborted_exit
+                    .as_ref()
+                    .map(FfmpegExit::describe)
+                    .unwrap_or_else(|| "none".to_string())
+            );
+            return Ok(capture_stats);
+        }
         if frames_sent == 0 {
-            return Err("No frames were captured from target window".to_string());
+            return Err(CaptureError::new(
+                ErrorCode::SourceGone,
+                "No frames were captured from target window",
+            ));
         }
-        eprintln!(
-            "[native-capture][wgc] finalize sent_frames={} duplicated_frames={} ffmpeg_pipe_broken={} write_ok={} ffmpeg_status={}",
+        log!(
+            "[native-capture][wgc] finalize encoder={} vfr={} sent_frames={} duplicated_frames={} ffmpeg_pipe_broken={} write_ok={} ffmpeg_status={}",
+            encoder,
+            vfr,
             frames_sent,
             frames_duplicated,
             ffmpeg_pipe_broken,
             write_ok,
-            ffmpeg_status
+            ffmpeg_exit
+                .as_ref()
+                .map(FfmpegExit::describe)
+                .unwrap_or_else(|| "none".to_string())
         );
-        let stderr_excerpt = ffmpeg_stderr_lines
-            .lock()
-            .ok()
-            .map(|lines| lines.join(" | "))
-            .unwrap_or_default();
+        let Some(ffmpeg_exit) = ffmpeg_exit else {
+            adaptive_exit.as_ref().map_or(Ok(()), AdaptiveExit::check)?;
+            return match write_result {
+                Ok(warnings) => {
+                    capture_stats.warnings = warnings;
+                    Ok(capture_stats)
+                }
+                Err(err) => Err(format!("{encoder} encoding failed: {err}").into()),
+            };
+        };
         if ffmpeg_pipe_broken || !write_ok {
-            return Err(format!(
-                "FFmpeg input pipe closed during capture (status={ffmpeg_status}).{}",
-                if stderr_excerpt.is_empty() {
-                    String::new()
-                } else {
-                    format!(" stderr={stderr_excerpt}")
+            return Err(ffmpeg_exit.error(&format!(
+                "FFmpeg input pipe closed during capture ({})",
+                ffmpeg_exit.describe()
+            )));
+        }
+        ffmpeg_exit.check()?;
+        capture_stats.warnings = ffmpeg_exit.warnings;
+        if let Some(result) = adaptive_exit {
+            result.check()?;
+            capture_stats.warnings = result.warnings;
+            capture_stats.adaptations = result.adaptations;
+        }
+
+        Ok(capture_stats)
+    }
+
+    fn pace_vfr(
+        shared_latest: Arc<Mutex<Option<LatestFrame>>>,
+        frame_tx: std::sync::mpsc::SyncSender<OutFrame>,
+        stop_flag: Arc<AtomicBool>,
+        abort_flag: Arc<AtomicBool>,
+        mut timestamps: FrameTimestampLog,
+    ) -> (CaptureStats, bool) {
+        let mut timing = FrameTimingStats::default();
+        let mut last_seq: Option<u64> = None;
+        let mut last_frame: Option<(Instant, Arc<Vec<u8>>)> = None;
+        // (wall-clock instant, capture pts) of the first forwarded frame; pts are rebased so
+        // the first encoded frame sits at zero.
+        let mut anchor: Option<(Instant, Duration)> = None;
+
+        loop {
+            if stop_flag.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let fresh = shared_latest.lock().ok().and_then(|guard| match &*guard {
+                Some(f) if Some(f.seq) != last_seq => {
+                    Some((f.seq, f.pts, f.captured_at, Arc::clone(&f.data)))
                 }
-            ));
+                _ => None,
+            });
+            let Some((seq, pts, captured_at, data)) = fresh else {
+                thread::sleep(Duration::from_millis(2));
+                continue;
+            };
+            last_seq = Some(seq);
+
+            let (_, base_pts) = *anchor.get_or_insert((Instant::now(), pts));
+            let pts = pts.saturating_sub(base_pts);
+            let out = OutFrame {
+                pts,
+                data: Arc::clone(&data),
+            };
+            if frame_tx.send(out).is_err() {
+                let stats = CaptureStats {
+                    frames_sent: timing.frames,
+                    frames_duplicated: 0,
+                    timing: Some(timing),
+                    timestamps: Some(timestamps),
+                    static_segments: None,
+                    warnings: Vec::new(),
+                    adaptations: Vec::new(),
+                };
+                return (stats, false);
+            }
+            timing.record(pts);
+            timestamps.push(captured_at, false);
+            if timing.frames == 1 {
+                log!("[native-capture][wgc] first-frame sent (vfr)");
+            } else if timing.frames % 120 == 0 {
+                log!("[native-capture][wgc] frame-progress sent={} (vfr)", timing.frames);
+            }
+            last_frame = Some((captured_at, data));
         }
-        if !ffmpeg_status.success() {
-            return Err(format!(
-                "FFmpeg exited with non-zero status: {ffmpeg_status}.{}",
-                if stderr_excerpt.is_empty() {
-                    String::new()
-                } else {
-                    format!(" stderr={stderr_excerpt}")
+
+        // Repeat the last frame once at the stop time so the final frame gets a real display
+        // duration and the video length still matches wall-clock recording time.
+        let mut frames_duplicated = 0;
+        if let (Some((captured_at, frame)), Some((first_sent_at, _)), false) =
+            (last_frame, anchor, abort_flag.load(Ordering::SeqCst))
+        {
+            let tail_pts = first_sent_at.elapsed();
+            if tail_pts > timing.last_pts {
+                let out = OutFrame {
+                    pts: tail_pts,
+                    data: frame,
+                };
+                if frame_tx.send(out).is_ok() {
+                    timestamps.push(captured_at, true);
+                    frames_duplicated = 1;
                 }
-            ));
+            }
         }
 
-        Ok(())
+        let stats = CaptureStats {
+            frames_sent: timing.frames + frames_duplicated,
+            frames_duplicated,
+            timing: Some(timing),
+            timestamps: Some(timestamps),
+            static_segments: None,
+            warnings: Vec::new(),
+            adaptations: Vec::new(),
+        };
+        (stats, true)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn convert_frame(
+        format: RawFormat,
+        bgra: &[u8],
+        src_pitch: usize,
+        src_x: usize,
+        src_y: usize,
+        out: &mut [u8],
+        width: usize,
+        height: usize,
+    ) {
+        match format {
+            RawFormat::Yuv420p => bgra_to_yuv420p(bgra, src_pitch, src_x, src_y, out, width, height),
+            RawFormat::Bgra => {
+                let row_len = width * 4;
+                for row in 0..height {
+                    let src = (src_y + row) * src_pitch + src_x * 4;
+                    out[row * row_len..(row + 1) * row_len].copy_from_slice(&bgra[src..src + row_len]);
+                }
+            }
+        }
     }
 
     fn bgra_to_yuv420p(
@@ -829,82 +1380,70 @@ pub mod inner {
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn spawn_ffmpeg_rawvideo(
+    fn ffmpeg_rawvideo_command(
         ffmpeg_exe: &str,
         encoder: &str,
-        bitrate: u32,
+        rate: &RateControl,
         fps: u32,
         width: u32,
         height: u32,
         encoder_args: &[String],
-        output_path: &str,
-        scale_to: Option<(u32, u32)>,
-    ) -> CaptureResult<Child> {
-        let bitrate_str = bitrate.max(1_000_000).to_string();
+        // Output frame rate, size and preset; the input stays at `fps` and `width`x`height`.
+        step: &EncodeStep,
+        vfr: bool,
+        lossless: Option<&str>,
+    ) -> CaptureResult<Command> {
         let fps_str = fps.to_string();
         let size_str = format!("{}x{}", width, height);
-        let bufsize_str = bitrate.saturating_mul(3).to_string();
-        let gop_str = (fps.max(1) * 2).to_string();
-
-        let (video_codec, default_enc_args): (&str, &[&str]) = match encoder {
-            "h264_nvenc" => (
-                "h264_nvenc",
-                &["-preset", "p4", "-tune", "ll", "-rc", "vbr", "-cq", "20"],
-            ),
-            "hevc_nvenc" => (
-                "hevc_nvenc",
-                &["-preset", "p4", "-tune", "ll", "-rc", "vbr", "-cq", "22"],
-            ),
-            "h264_amf" => ("h264_amf", &["-quality", "quality"]),
-            // medium preset produces dramatically sharper text/UI than ultrafast at the
-            // cost of slightly more CPU; still real-time at 1080p60 on any modern CPU.
-            _ => ("libx264", &["-preset", "medium", "-tune", "zerolatency"]),
-        };
+        let raw_format = if lossless.is_some() { RawFormat::Bgra } else { RawFormat::Yuv420p };
+        let gop_str = (step.fps.max(1) * 2).to_string();
 
-        let mut cmd = Command::new(ffmpeg_exe);
-        cmd.arg("-y")
-            .arg("-f")
-            .arg("rawvideo")
-            .arg("-pixel_format").arg("yuv420p")
-            .arg("-video_size").arg(&size_str)
-            .arg("-framerate").arg(&fps_str)
-            .arg("-i").arg("pipe:0")
-            .arg("-r").arg(&fps_str);
-
-        if let Some((tw, th)) = scale_to {
-            // lanczos preserves sharp edges/text when downscaling; bilinear (default) blurs
-            cmd.arg("-vf").arg(format!("scale={}:{}:flags=lanczos", tw, th));
+        let (video_codec, default_enc_args) = encoder_codec_args(encoder);
+
+        let mut cmd = ffmpeg_command(ffmpeg_exe);
+        if vfr {
+            // Frames arrive wrapped in Matroska carrying their capture pts; keep them as-is.
+            cmd.arg("-f").arg("matroska")
+                .arg("-i").arg("pipe:0")
+                .arg("-fps_mode").arg("vfr");
+        } else {
+            cmd.arg("-f")
+                .arg("rawvideo")
+                .arg("-pixel_format").arg(raw_format.ffmpeg_name())
+                .arg("-video_size").arg(&size_str)
+                .arg("-framerate").arg(&fps_str)
+                .arg("-i").arg("pipe:0")
+                .arg("-r").arg(step.fps.to_string());
+            if (step.width, step.height) != (width, height) {
+                cmd.arg("-vf").arg(format!("scale={}:{}", step.width, step.height));
+            }
         }
 
-        cmd.arg("-pix_fmt").arg("yuv420p")
-            .arg("-c:v").arg(video_codec);
+        // Lossless codecs pick their own RGB pixel format.
+        if let Some(codec) = lossless {
+            cmd.args(lossless_codec_args(codec)?);
+            return Ok(cmd);
+        }
+        cmd.arg("-pix_fmt").arg("yuv420p");
 
+        cmd.arg("-c:v").arg(video_codec);
+        let preset = step.preset.as_deref();
         if encoder_args.is_empty() {
-            for arg in default_enc_args {
-                cmd.arg(arg);
-            }
+            cmd.args(with_preset(encoder, default_enc_args, preset));
         } else {
-            for arg in encoder_args {
-                cmd.arg(arg);
-            }
+            cmd.args(with_preset(encoder, encoder_args, preset));
         }
 
-        cmd.arg("-b:v").arg(&bitrate_str)
-            .arg("-maxrate").arg(&bitrate_str)
-            .arg("-bufsize").arg(&bufsize_str)
+        cmd.args(rate_control_args(encoder, rate))
             .arg("-g").arg(&gop_str)
-            .arg("-movflags").arg("+faststart")
-            .arg(output_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped());
+            .arg("-movflags").arg(RECORDING_MOVFLAGS);
 
-        cmd.spawn().map_err(|e| format!("Failed to spawn ffmpeg: {e}"))
+        Ok(cmd)
     }
 }
 
 #[cfg(target_os = "windows")]
 pub use inner::{
-    hmonitor_from_point, hwnd_from_source_id, monitor_rect, source_bounds_from_hwnd, start,
-    start_monitor, stop, WgcCapture,
+    abort, hmonitor_from_point, hwnd_from_source_id, monitor_rect, running,
+    source_bounds_from_hwnd, start, start_monitor, stop, WgcCapture,
 };
@@ -780,6 +780,9 @@ export function useScreenRecorder(): UseScreenRecorderReturn {
         const nativeStart = await window.electronAPI.nativeCaptureStart(await buildNativePayload(selectedEncoder));
         if (nativeStart.success) {
           nativeCaptureActiveRef.current = true;
+          if (nativeStart.preflight?.warning) {
+            setRecordingNotice(nativeStart.preflight.warning);
+          }
           setRecording(true);
           window.electronAPI?.setRecordingState(true);
           return;
@@ -0,0 +1,34 @@
+import { describe, it, expect } from 'vitest';
+import fs from 'node:fs';
+import path from 'node:path';
+import { generateAutoZoomRegions } from './generateAutoZoomRegions';
+import type { AutoZoomGeneratedRegion, AutoZoomIntensity, InputTelemetryFileV1 } from '@/types/inputTelemetry';
+
+// Shared with the sidecar's port (native-capture-sidecar/src/autozoom.rs), which runs
+// the same fixtures; both must produce the expected regions.
+const FIXTURES_DIR = path.join(__dirname, 'fixtures');
+
+interface AutoZoomFixture {
+  description: string;
+  intensity: AutoZoomIntensity;
+  durationMs: number;
+  telemetry: InputTelemetryFileV1;
+  expected: AutoZoomGeneratedRegion[];
+}
+
+const fixtures = fs
+  .readdirSync(FIXTURES_DIR)
+  .filter((name) => name.endsWith('.json'))
+  .sort()
+  .map((name) => [name, JSON.parse(fs.readFileSync(path.join(FIXTURES_DIR, name), 'utf8')) as AutoZoomFixture] as const);
+
+describe('generateAutoZoomRegions fixtures', () => {
+  it.each(fixtures)('%s', (_name, fixture) => {
+    const regions = generateAutoZoomRegions({
+      telemetry: fixture.telemetry,
+      durationMs: fixture.durationMs,
+      intensity: fixture.intensity,
+    });
+    expect(regions).toEqual(fixture.expected);
+  });
+});
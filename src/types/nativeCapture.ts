@@ -1,4 +1,4 @@
-export type RecordingEncoder = "h264_libx264" | "h264_nvenc" | "hevc_nvenc" | "h264_amf";
+export type RecordingEncoder = "h264_libx264" | "h264_nvenc" | "hevc_nvenc" | "h264_amf" | "av1_rav1e";
 
 export type NativeCapturePlatform = "win32" | "darwin" | "linux";
 
@@ -13,12 +13,32 @@ export type NativeCaptureVideoConfig = {
   width: number;
   height: number;
   fps: number;
-  bitrate: number;
+  // Bits per second; required unless `bitrateMode` derives it.
+  bitrate?: number;
+  // "auto" derives the bitrate from size, fps, codec and `quality`; "quality" encodes at a
+  // constant CRF/CQ under a ceiling.
+  bitrateMode?: "manual" | "auto" | "quality";
   encoder: RecordingEncoder;
+  // Tried in order when `encoder` fails while ffmpeg starts up.
+  fallbackEncoders?: RecordingEncoder[];
+  frameRateMode?: "cfr" | "vfr";
+  quality?: "draft" | "standard" | "high" | "lossless";
+  losslessCodec?: "ffv1" | "utvideo" | "x264";
+  scaleFilter?: "auto" | "area" | "lanczos";
+  fit?: "stretch" | "contain" | "cover";
+  padColor?: string;
+  // Step the encoder down when it falls behind. The preset may always drop to `minPreset`
+  // (the fastest when unset); fps and resolution only drop when their bound is given.
+  adaptiveQuality?: {
+    minPreset?: string;
+    minFps?: number;
+    minHeight?: number;
+  };
 };
 
 export type NativeCaptureCursorConfig = {
   mode: "hide" | "system";
+  track?: boolean;
 };
 
 export type NativeCaptureRegion = {
@@ -34,9 +54,20 @@ export type NativeCaptureStartPayload = {
   video: NativeCaptureVideoConfig;
   cursor: NativeCaptureCursorConfig;
   outputPath: string;
+  // Replace outputPath when it already exists; otherwise start fails with OUTPUT_EXISTS.
+  overwrite?: boolean;
   platform: NativeCapturePlatform;
   ffmpegPath?: string;
   captureRegion?: NativeCaptureRegion;
+  staticDetection?: {
+    threshold?: number;
+    minDurationMs?: number;
+  };
+  inputTelemetry?: boolean;
+  burnIn?: {
+    clicks?: boolean;
+    keys?: boolean;
+  };
 };
 
 export type NativeCaptureStopPayload = {
@@ -46,7 +77,141 @@ export type NativeCaptureStopPayload = {
 
 export type NativeCaptureStatus = "idle" | "starting" | "recording" | "stopping" | "error";
 
+export type NativeCaptureErrorCode =
+  | "FFMPEG_NOT_FOUND"
+  | "ENCODER_UNAVAILABLE"
+  | "INVALID_PAYLOAD"
+  | "UNKNOWN_COMMAND"
+  | "UNSUPPORTED_PLATFORM"
+  | "ALREADY_RUNNING"
+  | "NOT_RUNNING"
+  | "SESSION_MISMATCH"
+  | "SESSION_NOT_FOUND"
+  | "OUTPUT_EXISTS"
+  | "OUTPUT_DIR_NOT_FOUND"
+  | "PERMISSION_DENIED"
+  | "SOURCE_GONE"
+  | "DISK_FULL"
+  | "FFMPEG_FAILED"
+  | "CAPTURE_FAILED"
+  | "CANCELLED";
+
+// Error shape of every failed sidecar response; `message` is English and meant for logs.
+export type NativeCaptureError = {
+  code: NativeCaptureErrorCode;
+  message: string;
+  details: Record<string, unknown> | null;
+};
+
+// An encoder start_capture gave up on before `encoderUsed`.
+export type NativeCaptureEncoderFallback = {
+  encoder: RecordingEncoder;
+  code: NativeCaptureErrorCode;
+  reason: string;
+};
+
+// Rate control start_capture resolved for the encoder it used.
+export type NativeCaptureRateControl = {
+  mode: "manual" | "auto" | "quality";
+  tier: "draft" | "standard" | "high";
+  // Target in bits/s; the expected average in quality mode.
+  bitrate: number;
+  maxBitrate: number;
+  bufferSize: number;
+  qualityParam: "crf" | "cq" | "qp" | null;
+  qualityValue: number | null;
+  estimatedBytesPerMinute: number;
+};
+
+// Lossless start check of the projected data rate against the output disk.
+export type NativeCapturePreflight = {
+  estimatedBytesPerSec: number;
+  diskWriteBytesPerSec: number | null;
+  // Set when the disk looked too slow to keep up.
+  warning: string | null;
+};
+
+export type NativeCaptureWarningKind =
+  | "droppedFrames"
+  | "duplicatedFrames"
+  | "realtimeBufferFull"
+  | "nonMonotonicDts"
+  | "encoderInitFailed"
+  | "permissionDenied"
+  | "diskFull";
+
+export type NativeCaptureWarning = {
+  kind: NativeCaptureWarningKind;
+  severity: "info" | "warning" | "error";
+  message: string;
+  count: number;
+  firstAtMs: number;
+  lastAtMs: number;
+  detail: string | null;
+};
+
+export type NativeCaptureEncodeStep = {
+  preset: string | null;
+  fps: number;
+  width: number;
+  height: number;
+};
+
+// One step down made by adaptive quality; also sent as the `quality_adapted` event.
+export type NativeCaptureAdaptation = {
+  atMs: number;
+  change: "preset" | "fps" | "resolution";
+  from: NativeCaptureEncodeStep;
+  to: NativeCaptureEncodeStep;
+  encodeFps: number;
+  targetFps: number;
+  segment: number;
+  // Time not recorded while a device input restarted.
+  gapMs: number;
+};
+
+export type NativeCaptureFrameTiming = {
+  frames: number;
+  spanMs: number;
+  meanIntervalMs: number | null;
+  minIntervalMs: number | null;
+  maxIntervalMs: number | null;
+};
+
+// A stretch where the captured picture did not change.
+export type NativeCaptureStaticSegment = {
+  // On the recording timeline; the first captured frame is 0.
+  startMs: number;
+  endMs: number;
+  // Since the start_capture request, like `monoUs` in the timestamp file.
+  startMonoUs: number;
+  endMonoUs: number;
+};
+
+export type NativeCaptureInputTelemetryStats = {
+  totalEvents: number;
+  mouseDownCount: number;
+  mouseUpCount: number;
+  mouseMoveCount: number;
+  wheelCount: number;
+  keyDownCount: number;
+};
+
+// How the ffmpeg process ended; null counters when it never reported progress.
+export type NativeCaptureFfmpegExit = {
+  status: string;
+  stoppedBy: string;
+  ok: boolean;
+  framesEncoded: number | null;
+  fps: number | null;
+  speed: number | null;
+  dupFrames: number | null;
+  dropFrames: number | null;
+};
+
 export type NativeCaptureSessionResult = {
+  // "aborted" when stopped with `finalize: false`; the output no longer exists.
+  status?: "finalized" | "aborted";
   outputPath: string;
   durationMs?: number;
   width?: number;
@@ -59,11 +224,187 @@ export type NativeCaptureSessionResult = {
     width: number;
     height: number;
   };
+  frameRateMode?: "cfr" | "vfr";
+  // MP4 only: false when the faststart remux failed and the fragmented recording was kept.
+  faststart?: boolean;
+  // WGC only: counted by the sidecar rather than read back from ffmpeg.
+  framesEncoded?: number;
+  framesDuplicated?: number;
+  frameTiming?: NativeCaptureFrameTiming;
+  timestampsPath?: string;
+  frame0EpochMs?: number;
+  firstFrameLatencyMs?: number;
+  staticSegments?: NativeCaptureStaticSegment[];
+  inputTelemetryPath?: string;
+  inputTelemetryStats?: NativeCaptureInputTelemetryStats;
+  cursorTrackPath?: string;
+  cursorShapes?: number;
+  ffmpegExit?: NativeCaptureFfmpegExit;
+  warnings?: NativeCaptureWarning[];
+  adaptations?: NativeCaptureAdaptation[];
+  deletedFiles?: string[];
 };
 
 export type NativeCaptureStatusResult = {
   status: NativeCaptureStatus;
   message?: string;
+  errorCode?: NativeCaptureErrorCode;
   sessionId?: string;
   startedAtMs?: number;
+  warnings?: NativeCaptureWarning[];
+  adaptations?: NativeCaptureAdaptation[];
+};
+
+export type NativeCaptureRecoverableSession = {
+  sessionId: string;
+  state: "recording" | "stopping" | "failed";
+  outputPath: string;
+  recordingPath: string | null;
+  outputBytes: number | null;
+  platform: NativeCapturePlatform;
+  encoder: string;
+  settings: Partial<NativeCaptureVideoConfig> | null;
+  startedAtEpochMs: number;
+  updatedAtEpochMs: number;
+  ffmpegRunning: boolean;
+  error: string | null;
+};
+
+export type NativeCaptureRecoveryResult = {
+  sessionId: string;
+  outputPath: string;
+  staleFfmpeg: "notRunning" | "interrupted" | "killed" | "killFailed";
+  salvage: {
+    status: "intact" | "remuxed" | "unrecoverable" | "unchecked" | "missing" | "empty";
+    bytes?: number;
+    recoveredPath?: string;
+    recoveredBytes?: number;
+    warning?: string | null;
+    error?: string;
+  };
+  journalRemoved: boolean;
+};
+
+export type NativeCaptureBenchmarkPayload = {
+  width: number;
+  height: number;
+  fps: number;
+  // Everything get_encoder_options lists when omitted.
+  encoders?: RecordingEncoder[];
+  // Planned length of each encoder's run; the sidecar caps it at 30 s.
+  durationMs?: number;
+  // Rate control as in start_capture; an auto bitrate unless `bitrate` is given.
+  bitrate?: number;
+  bitrateMode?: "manual" | "auto" | "quality";
+  quality?: "draft" | "standard" | "high";
+};
+
+export type NativeCaptureEncoderBenchmark = {
+  encoder: RecordingEncoder;
+  ok: boolean;
+  frames: number;
+  elapsedMs: number;
+  achievedFps: number;
+  // achievedFps over the requested fps.
+  speed: number;
+  realtime: boolean;
+  // Share of all logical cores; null for the built-in AV1 encoder.
+  cpuPercent: number | null;
+  outputBytes: number;
+  bytesPerMinute: number;
+  timedOut: boolean;
+  error: NativeCaptureError | null;
+};
+
+export type NativeCaptureBenchmarkResult = {
+  benchmarkId: string;
+  cancelled: boolean;
+  width: number;
+  height: number;
+  fps: number;
+  durationMs: number;
+  results: NativeCaptureEncoderBenchmark[];
+  recommended: {
+    encoder: RecordingEncoder;
+    width: number;
+    height: number;
+    fps: number;
+    realtime: boolean;
+    reason: string;
+  } | null;
+};
+
+export type NativeCaptureBenchmarkProgress = {
+  benchmarkId: string;
+  encoder: RecordingEncoder;
+  index: number;
+  count: number;
+  frame: number;
+  frames: number;
+  elapsedMs: number;
+  percent: number;
+  // Set once the encoder has finished.
+  result: NativeCaptureEncoderBenchmark | null;
+};
+
+export type NativeCaptureDiagnosticsResult = {
+  // Where the bundle was written; paths inside it are redacted.
+  path: string;
+  format: "json";
+  bytes: number;
+  elapsedMs: number;
+};
+
+export type NativeCaptureFfmpegCandidate = {
+  source: "payload" | "env" | "bundled" | "path";
+  path: string;
+  version: string | null;
+  usable: boolean;
+  problems: string[];
+  missingEncoder: string | null;
+};
+
+// Which ffmpeg the sidecar picked at init, and why.
+export type NativeCaptureFfmpegDiscovery = {
+  path: string | null;
+  source: NativeCaptureFfmpegCandidate["source"] | null;
+  version: string | null;
+  reason: string;
+  candidates: NativeCaptureFfmpegCandidate[];
+};
+
+export type NativeCaptureSessionHealth = {
+  state: "healthy" | "degraded" | "failing" | "exited";
+  // ffmpeg progress counters; null for WGC, which has none.
+  frames: number | null;
+  encodeFps: number | null;
+  speed: number | null;
+  droppedFrames: number | null;
+  duplicatedFrames: number | null;
+  warnings: Array<Pick<NativeCaptureWarning, "kind" | "severity" | "count">>;
+};
+
+// A session as get_status reports it. Starting sessions only carry their id.
+export type NativeCaptureSidecarSession = {
+  sessionId: string | null;
+  state: "starting" | "recording" | "stopping";
+  // The start or stop request in progress, and for how long.
+  requestId?: string;
+  stateMs?: number;
+  platform?: string;
+  backend?: "wgc" | "gdigrab" | "avfoundation" | "x11grab";
+  adaptive?: boolean;
+  outputPath?: string;
+  elapsedMs?: number;
+  bytesWritten?: number;
+  health?: NativeCaptureSessionHealth;
+};
+
+export type NativeCaptureSidecarStatus = {
+  version: string;
+  pid: number;
+  uptimeMs: number;
+  sessions: NativeCaptureSidecarSession[];
+  requests: Array<{ id: string; cmd: string; elapsedMs: number; cancellable: boolean }>;
+  benchmarkId: string | null;
 };